@@ -31,6 +31,13 @@ class HypervisorVpnService : VpnService() {
         private const val VPN_ADDRESS = "10.0.0.2"
         private const val VPN_ROUTE = "0.0.0.0"
         private const val DNS_SERVER = "8.8.8.8"
+
+        /**
+         * Initialize native inspection. Zeek-style logs are written to [logDir] (null disables
+         * them) as TSV (0) or JSON (1); non-positive limits keep the native defaults.
+         */
+        @JvmStatic
+        external fun nativeInit(logDir: String?, logFormat: Int, maxLogBytes: Long, rotateSecs: Long, maxLogFiles: Int): Int
     }
 
     // Core VPN components
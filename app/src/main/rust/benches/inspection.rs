@@ -0,0 +1,221 @@
+// Inspection throughput over a synthetic mixed-traffic corpus and the captures in
+// benches/corpus/ (classic pcap or pcapng; more can be dropped in). Run with
+// `cargo bench --bench inspection`.
+use std::fs;
+use std::hint::black_box;
+use std::net::IpAddr;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use fortress_hypervisor::bench::*;
+
+fn key(dst: &str, dst_port: u16, protocol: u8) -> FlowKey {
+    let dst: IpAddr = dst.parse().unwrap();
+    let src: IpAddr = if dst.is_ipv4() { "10.0.0.2".parse().unwrap() } else { "2001:db8::2".parse().unwrap() };
+    FlowKey { src, dst, src_port: 40_000 + dst_port % 1000, dst_port, protocol }
+}
+
+fn dns_query(id: u16, name: &str) -> Vec<u8> {
+    let mut msg = id.to_be_bytes().to_vec();
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.extend_from_slice(&[0, 0, 1, 0, 1]);
+    msg
+}
+
+// A Chrome-like ClientHello handshake message
+fn client_hello(sni: &str) -> Vec<u8> {
+    let mut exts = Vec::new();
+    let mut push_ext = |ty: u16, data: &[u8]| {
+        exts.extend_from_slice(&ty.to_be_bytes());
+        exts.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        exts.extend_from_slice(data);
+    };
+    let mut name = vec![0u8];
+    name.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+    name.extend_from_slice(sni.as_bytes());
+    let mut server_name = (name.len() as u16).to_be_bytes().to_vec();
+    server_name.extend_from_slice(&name);
+    push_ext(0x0a0a, &[]);
+    push_ext(0, &server_name);
+    push_ext(10, &[0x00, 0x06, 0x0a, 0x0a, 0x00, 0x1d, 0x00, 0x17]);
+    push_ext(11, &[0x01, 0x00]);
+    push_ext(13, &[0x00, 0x06, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01]);
+    push_ext(16, &[0x00, 0x0c, 0x02, b'h', b'2', 0x08, b'h', b't', b't', b'p', b'/', b'1', b'.', b'1']);
+    push_ext(43, &[0x06, 0x0a, 0x0a, 0x03, 0x04, 0x03, 0x03]);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[0x42; 32]);
+    body.push(32);
+    body.extend_from_slice(&[0x24; 32]);
+    let suites: [u16; 8] = [0x2a2a, 0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030];
+    body.extend_from_slice(&((suites.len() * 2) as u16).to_be_bytes());
+    for suite in suites {
+        body.extend_from_slice(&suite.to_be_bytes());
+    }
+    body.extend_from_slice(&[0x01, 0x00]);
+    body.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+    body.extend_from_slice(&exts);
+
+    let mut msg = vec![0x01];
+    msg.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    msg.extend_from_slice(&body);
+    msg
+}
+
+fn tls_record(content_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut record = vec![content_type, 0x03, 0x03];
+    record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    record.extend_from_slice(body);
+    record
+}
+
+const HTTP_REQUEST: &[u8] = b"GET /assets/app.js?v=20240101 HTTP/1.1\r\nHost: static.example.com\r\n\
+User-Agent: Mozilla/5.0 (Linux; Android 14)\r\nAccept: */*\r\nAccept-Encoding: gzip\r\n\r\n";
+
+// Cleartext HTTP/2 preface, SETTINGS and a literal-header GET
+fn h2c_request() -> Vec<u8> {
+    let mut data = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+    data.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 0, 0]);
+    let mut block = vec![0x82, 0x86, 0x84];
+    block.extend_from_slice(&[0x41, 11]);
+    block.extend_from_slice(b"example.com");
+    data.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+    data.extend_from_slice(&[1, 0x05, 0, 0, 0, 1]);
+    data.extend_from_slice(&block);
+    data
+}
+
+// Packets in the proportions a phone typically sends: mostly encrypted bulk data, some new flows
+fn synthetic_corpus() -> Vec<Vec<u8>> {
+    let mut corpus = Vec::new();
+    let hosts = ["93.184.216.34", "142.250.74.46", "2606:4700::6810:84e5", "151.101.1.69"];
+    for (i, host) in hosts.iter().enumerate() {
+        let https = key(host, 443, 6);
+        let domain = format!("cdn{i}.example.com");
+        corpus.push(udp_packet(&key("10.0.0.1", 53, 17), &dns_query(i as u16, &domain)));
+        corpus.push(tcp_packet(&https, 1, 0, 0x02, 65_535, Some(1460), &[]));
+        corpus.push(tcp_packet(&https, 2, 1, 0x18, 65_535, None, &tls_record(0x16, &client_hello(&domain))));
+        for seq in 0..24 {
+            corpus.push(tcp_packet(&https, 1_000 + seq * 1400, 1, 0x18, 65_535, None, &tls_record(0x17, &[0x5a; 1395])));
+        }
+        let http = key(host, 80, 6);
+        corpus.push(tcp_packet(&http, 1, 1, 0x18, 65_535, None, HTTP_REQUEST));
+        corpus.push(tcp_packet(&http, 200, 1, 0x18, 65_535, None, &[0x20; 1200]));
+        let mut quic_short = vec![0x43];
+        quic_short.extend_from_slice(&[0x7e; 1250]);
+        for _ in 0..8 {
+            corpus.push(udp_packet(&key(host, 443, 17), &quic_short));
+        }
+    }
+    corpus.push(tcp_packet(&key("203.0.113.5", 8080, 6), 1, 1, 0x18, 65_535, None, &h2c_request()));
+    corpus.push(udp_packet(&key("198.51.100.20", 5004, 17), &[0x80; 172]));
+    let mut ping = vec![0x45, 0, 0, 84, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 2, 8, 8, 8, 8, 8, 0, 0, 0, 0, 1, 0, 1];
+    ping.extend_from_slice(&[0x10; 56]);
+    corpus.push(ping);
+    corpus
+}
+
+fn load_captures() -> Vec<(String, Vec<Vec<u8>>)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/corpus");
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut captures: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let packets = read_packets(&fs::read(&path).ok()?)?;
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            Some((name, packets.into_iter().map(|p| p.data).collect::<Vec<_>>()))
+        })
+        .filter(|(_, packets)| !packets.is_empty())
+        .collect();
+    captures.sort();
+    captures
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let mut corpora = vec![("synthetic".to_string(), synthetic_corpus())];
+    corpora.extend(load_captures());
+
+    let mut group = c.benchmark_group("pipeline");
+    for (name, packets) in &corpora {
+        let bytes: usize = packets.iter().map(Vec::len).sum();
+        group.throughput(Throughput::Elements(packets.len() as u64));
+        // Warm flows up once so the measurement is steady-state inspection
+        group.bench_function(name.as_str(), |b| {
+            let mut inspector = PacketInspector::new();
+            for packet in packets {
+                inspector.analyze(packet);
+            }
+            b.iter(|| {
+                for packet in packets {
+                    black_box(inspector.analyze(black_box(packet)));
+                }
+            })
+        });
+
+        let mut inspector = PacketInspector::new();
+        for packet in packets.iter().chain(packets) {
+            inspector.analyze(packet);
+        }
+        let stats = inspector.performance(u64::MAX / 2);
+        println!(
+            "{name}: {} packets ({bytes} bytes), mean {} ns, p99 < {} ns, {:.3} allocations/packet",
+            packets.len(),
+            stats.mean_ns,
+            stats.p99_ns,
+            stats.allocations_per_packet
+        );
+    }
+    group.finish();
+}
+
+fn bench_inspectors(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inspectors");
+
+    let corpus = synthetic_corpus();
+    group.bench_function("flow_parse", |b| {
+        b.iter(|| corpus.iter().filter_map(|p| parse_packet(black_box(p))).count())
+    });
+
+    let query = dns_query(7, "clients4.google.com");
+    group.bench_function("dns", |b| b.iter(|| parse_message(black_box(&query))));
+
+    let hello = client_hello("www.example.com");
+    group.bench_function("tls_client_hello", |b| b.iter(|| parse_client_hello(black_box(&hello))));
+    let parsed = parse_client_hello(&hello).unwrap();
+    group.bench_function("ja3_ja4", |b| b.iter(|| (black_box(&parsed).ja3_hash(), parsed.ja4(HelloTransport::Tcp))));
+
+    group.bench_function("http_request", |b| b.iter(|| parse_request(black_box(HTTP_REQUEST))));
+
+    let h2c = h2c_request();
+    group.bench_function("http2", |b| {
+        b.iter_batched(Http2Connection::new, |mut conn| conn.feed(true, black_box(&h2c)), BatchSize::SmallInput)
+    });
+
+    let url = Url::parse("http://secure-login.paypa1-verify.com/webscr/signin?cmd=_login&session=ab12").unwrap();
+    group.bench_function("url_analysis", |b| b.iter(|| analyze_url(black_box(&url))));
+
+    let mut lookalikes = LookalikeDetector::new();
+    group.bench_function("lookalike", |b| b.iter(|| lookalikes.check(black_box("secure.paypa1.com")).1));
+
+    let mut intel = ThreatIntel::new();
+    let indicators = (0..10_000u32).filter_map(|i| {
+        let value = if i % 2 == 0 { format!("bad{i}.example.net") } else { format!("10.{}.{}.0/24", i >> 8 & 0xff, i & 0xff) };
+        let kind = if i % 2 == 0 { IndicatorKind::Domain } else { IndicatorKind::Ip };
+        Indicator::new(kind, &value, "bench")
+    });
+    intel.import(indicators, 0);
+    group.bench_function("threat_intel_domain", |b| {
+        b.iter(|| intel.match_domain(black_box("a.b.cdn0.example.com"), 1).is_some())
+    });
+    let ip: IpAddr = "10.3.7.99".parse().unwrap();
+    group.bench_function("threat_intel_ip", |b| b.iter(|| intel.match_ip(black_box(ip), 1).is_some()));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipeline, bench_inspectors);
+criterion_main!(benches);
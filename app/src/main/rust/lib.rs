@@ -239,20 +239,3 @@ pub extern "C" fn hypervisor_get_version() -> *mut c_char {
         Err(_) => std::ptr::null_mut(),
     }
 }
-
-// Performance monitoring
-#[no_mangle]
-pub extern "C" fn hypervisor_get_performance_stats() -> *mut c_char {
-    // In a real implementation, this would collect actual performance metrics
-    let stats = r#"{
-        "packet_processing_rate": 125000,
-        "memory_scan_rate": 500,
-        "cpu_usage_percent": 15.5,
-        "memory_usage_mb": 45.2
-    }"#;
-
-    match CString::new(stats) {
-        Ok(c_string) => c_string.into_raw(),
-        Err(_) => std::ptr::null_mut(),
-    }
-}
\ No newline at end of file
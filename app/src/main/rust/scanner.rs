@@ -1,350 +0,0 @@
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
-use std::sync::Mutex;
-use lazy_static::lazy_static;
-
-// Malware signature database
-lazy_static! {
-    static ref MALWARE_SIGNATURES: Mutex<HashMap<String, Vec<u8>>> = Mutex::new(HashMap::new());
-    static ref VIRUS_TOTAL_API_KEY: Mutex<Option<String>> = Mutex::new(None);
-}
-
-// Malware scanning result
-#[derive(Debug, Clone)]
-pub struct ScanResult {
-    pub file_path: String,
-    pub is_malicious: bool,
-    pub threat_name: Option<String>,
-    pub confidence: f32,
-    pub scan_time_ms: u64,
-}
-
-// Initialize the malware scanner
-#[no_mangle]
-pub extern "C" fn hypervisor_scanner_init() -> i32 {
-    let mut signatures = MALWARE_SIGNATURES.lock().unwrap();
-
-    // Load known malware signatures (simplified example)
-    // In a real implementation, this would load from a secure database
-    signatures.insert("trojan_example".to_string(), vec![0x90, 0x90, 0x90]); // NOP sled example
-    signatures.insert("ransomware_pattern".to_string(), vec![0xE8, 0x00, 0x00, 0x00, 0x00]); // CALL pattern
-
-    // Initialize VirusTotal API key (would be loaded from secure storage)
-    *VIRUS_TOTAL_API_KEY.lock().unwrap() = Some("your_api_key_here".to_string());
-
-    0 // Success
-}
-
-// Scan a file for malware
-#[no_mangle]
-pub extern "C" fn hypervisor_scan_file(file_path: *const u8, path_len: usize) -> *mut ScanResult {
-    let path_bytes = unsafe { std::slice::from_raw_parts(file_path, path_len) };
-    let path_str = match std::str::from_utf8(path_bytes) {
-        Ok(s) => s,
-        Err(_) => return std::ptr::null_mut(),
-    };
-
-    let start_time = std::time::Instant::now();
-
-    let result = match scan_file_internal(path_str) {
-        Ok(scan_result) => scan_result,
-        Err(_) => ScanResult {
-            file_path: path_str.to_string(),
-            is_malicious: false,
-            threat_name: None,
-            confidence: 0.0,
-            scan_time_ms: start_time.elapsed().as_millis() as u64,
-        },
-    };
-
-    Box::into_raw(Box::new(result))
-}
-
-// Internal file scanning implementation
-fn scan_file_internal(file_path: &str) -> Result<ScanResult, Box<dyn std::error::Error>> {
-    let start_time = std::time::Instant::now();
-    let path = Path::new(file_path);
-
-    // Check if file exists
-    if !path.exists() {
-        return Ok(ScanResult {
-            file_path: file_path.to_string(),
-            is_malicious: false,
-            threat_name: Some("FILE_NOT_FOUND".to_string()),
-            confidence: 0.0,
-            scan_time_ms: start_time.elapsed().as_millis() as u64,
-        });
-    }
-
-    // Read file content
-    let content = fs::read(path)?;
-
-    // Perform signature-based scanning
-    let signature_result = scan_with_signatures(&content);
-
-    // Perform heuristic analysis
-    let heuristic_result = perform_heuristic_analysis(&content);
-
-    // Perform entropy analysis
-    let entropy_result = analyze_entropy(&content);
-
-    // Combine results
-    let (is_malicious, threat_name, confidence) = combine_scan_results(
-        signature_result,
-        heuristic_result,
-        entropy_result,
-    );
-
-    Ok(ScanResult {
-        file_path: file_path.to_string(),
-        is_malicious,
-        threat_name,
-        confidence,
-        scan_time_ms: start_time.elapsed().as_millis() as u64,
-    })
-}
-
-// Signature-based malware detection
-fn scan_with_signatures(content: &[u8]) -> Option<(String, f32)> {
-    let signatures = MALWARE_SIGNATURES.lock().unwrap();
-
-    for (threat_name, signature) in signatures.iter() {
-        if content.windows(signature.len()).any(|window| window == signature.as_slice()) {
-            return Some((threat_name.clone(), 0.95)); // High confidence for signature match
-        }
-    }
-
-    None
-}
-
-// Heuristic analysis for suspicious patterns
-fn perform_heuristic_analysis(content: &[u8]) -> Vec<(String, f32)> {
-    let mut suspicious_patterns = Vec::new();
-
-    // Check for suspicious strings
-    let suspicious_strings = [
-        "cmd.exe",
-        "powershell.exe",
-        "net user",
-        "reg add",
-        "schtasks",
-        "bitsadmin",
-        "certutil",
-        "mshta",
-        "rundll32",
-        "wscript",
-        "cscript",
-    ];
-
-    let content_str = String::from_utf8_lossy(content);
-    for suspicious in &suspicious_strings {
-        if content_str.contains(suspicious) {
-            suspicious_patterns.push((format!("SUSPICIOUS_COMMAND_{}", suspicious), 0.7));
-        }
-    }
-
-    // Check for encryption-like patterns (high entropy sections)
-    if content.len() > 1024 {
-        let mut high_entropy_count = 0;
-        for chunk in content.chunks(256) {
-            if calculate_entropy(chunk) > 7.5 {
-                high_entropy_count += 1;
-            }
-        }
-
-        if high_entropy_count > content.len() / 1024 {
-            suspicious_patterns.push(("HIGH_ENTROPY_CONTENT".to_string(), 0.6));
-        }
-    }
-
-    // Check for packer signatures
-    let packer_signatures = [
-        &[0x60, 0xE8, 0x00, 0x00, 0x00, 0x00], // UPX
-        &[0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00], // PE header + UPX
-    ];
-
-    for (i, signature) in packer_signatures.iter().enumerate() {
-        if content.windows(signature.len()).any(|window| window == *signature) {
-            suspicious_patterns.push((format!("PACKER_DETECTED_{}", i), 0.8));
-        }
-    }
-
-    suspicious_patterns
-}
-
-// Calculate Shannon entropy of data
-fn calculate_entropy(data: &[u8]) -> f64 {
-    if data.is_empty() {
-        return 0.0;
-    }
-
-    let mut frequencies = [0u32; 256];
-    for &byte in data {
-        frequencies[byte as usize] += 1;
-    }
-
-    let len = data.len() as f64;
-    let mut entropy = 0.0;
-
-    for &freq in &frequencies {
-        if freq > 0 {
-            let p = freq as f64 / len;
-            entropy -= p * p.log2();
-        }
-    }
-
-    entropy
-}
-
-// Analyze overall entropy
-fn analyze_entropy(content: &[u8]) -> f32 {
-    if content.len() < 256 {
-        return 0.0;
-    }
-
-    let entropy = calculate_entropy(content);
-    let normalized_entropy = (entropy / 8.0) as f32; // Normalize to 0-1 range
-
-    // High entropy might indicate encryption or compression
-    if normalized_entropy > 0.8 {
-        0.9 // High confidence of suspicious content
-    } else if normalized_entropy > 0.6 {
-        0.5 // Moderate confidence
-    } else {
-        0.0 // Low confidence
-    }
-}
-
-// Combine multiple scanning results
-fn combine_scan_results(
-    signature_result: Option<(String, f32)>,
-    heuristic_results: Vec<(String, f32)>,
-    entropy_score: f32,
-) -> (bool, Option<String>, f32) {
-    let mut max_confidence = 0.0f32;
-    let mut threat_name = None;
-    let mut is_malicious = false;
-
-    // Signature results have highest priority
-    if let Some((name, confidence)) = signature_result {
-        max_confidence = max_confidence.max(confidence);
-        threat_name = Some(name);
-        is_malicious = true;
-    }
-
-    // Combine heuristic results
-    for (name, confidence) in heuristic_results {
-        if confidence > max_confidence {
-            max_confidence = confidence;
-            if threat_name.is_none() {
-                threat_name = Some(name);
-            }
-            is_malicious = true;
-        }
-    }
-
-    // Factor in entropy analysis
-    if entropy_score > max_confidence {
-        max_confidence = entropy_score;
-        if threat_name.is_none() {
-            threat_name = Some("HIGH_ENTROPY_SUSPICIOUS".to_string());
-        }
-        is_malicious = true;
-    }
-
-    // Apply threshold
-    if max_confidence >= 0.5 {
-        (true, threat_name, max_confidence)
-    } else {
-        (false, None, max_confidence)
-    }
-}
-
-// Scan directory recursively
-#[no_mangle]
-pub extern "C" fn hypervisor_scan_directory(dir_path: *const u8, path_len: usize) -> *mut Vec<ScanResult> {
-    let path_bytes = unsafe { std::slice::from_raw_parts(dir_path, path_len) };
-    let path_str = match std::str::from_utf8(path_bytes) {
-        Ok(s) => s,
-        Err(_) => return std::ptr::null_mut(),
-    };
-
-    let mut results = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(path_str) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    // Only scan executable and script files
-                    let ext_str = ext.to_string_lossy().to_lowercase();
-                    if matches!(ext_str.as_str(), "exe" | "dll" | "bat" | "cmd" | "ps1" | "vbs" | "js" | "jar") {
-                        if let Ok(scan_result) = scan_file_internal(&path.to_string_lossy()) {
-                            results.push(scan_result);
-                        }
-                    }
-                }
-            } else if path.is_dir() {
-                // Recursively scan subdirectories (with depth limit for safety)
-                // Implementation would include depth checking
-            }
-        }
-    }
-
-    Box::into_raw(Box::new(results))
-}
-
-// Update malware signatures
-#[no_mangle]
-pub extern "C" fn hypervisor_update_signatures(signature_data: *const u8, data_len: usize) -> i32 {
-    let data = unsafe { std::slice::from_raw_parts(signature_data, data_len) };
-    let signature_str = match std::str::from_utf8(data) {
-        Ok(s) => s,
-        Err(_) => return -1,
-    };
-
-    // Parse signature data (JSON format expected)
-    match serde_json::from_str::<HashMap<String, Vec<u8>>>(signature_str) {
-        Ok(new_signatures) => {
-            let mut signatures = MALWARE_SIGNATURES.lock().unwrap();
-            signatures.extend(new_signatures);
-            0 // Success
-        }
-        Err(_) => -1, // Parse error
-    }
-}
-
-// Get scanner statistics
-#[no_mangle]
-pub extern "C" fn hypervisor_get_scan_stats() -> *mut HashMap<String, u64> {
-    let mut stats = HashMap::new();
-    stats.insert("total_signatures".to_string(), MALWARE_SIGNATURES.lock().unwrap().len() as u64);
-    stats.insert("scan_engine_version".to_string(), 1);
-
-    Box::into_raw(Box::new(stats))
-}
-
-// Clean up scan result
-#[no_mangle]
-pub extern "C" fn hypervisor_free_scan_result(result: *mut ScanResult) {
-    if !result.is_null() {
-        unsafe { Box::from_raw(result) };
-    }
-}
-
-// Clean up scan results vector
-#[no_mangle]
-pub extern "C" fn hypervisor_free_scan_results(results: *mut Vec<ScanResult>) {
-    if !results.is_null() {
-        unsafe { Box::from_raw(results) };
-    }
-}
-
-// Clean up statistics
-#[no_mangle]
-pub extern "C" fn hypervisor_free_scan_stats(stats: *mut HashMap<String, u64>) {
-    if !stats.is_null() {
-        unsafe { Box::from_raw(stats) };
-    }
-}
\ No newline at end of file
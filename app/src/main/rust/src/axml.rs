@@ -0,0 +1,646 @@
+use serde::Serialize;
+
+// Chunk types of Android's compiled XML
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+
+const UTF8_FLAG: u32 = 1 << 8;
+const NO_INDEX: u32 = u32::MAX;
+const ATTRIBUTE_SIZE: usize = 20;
+
+// Typed value kinds
+const TYPE_NULL: u8 = 0x00;
+const TYPE_REFERENCE: u8 = 0x01;
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const TYPE_INT_BOOLEAN: u8 = 0x12;
+
+// android.R.attr ids; the platform matches attributes by id, so obfuscated names do not matter
+const ATTR_NAME: u32 = 0x0101_0003;
+const ATTR_PERMISSION: u32 = 0x0101_0006;
+const ATTR_PROTECTION_LEVEL: u32 = 0x0101_0009;
+const ATTR_SHARED_USER_ID: u32 = 0x0101_000b;
+const ATTR_ENABLED: u32 = 0x0101_000e;
+const ATTR_DEBUGGABLE: u32 = 0x0101_000f;
+const ATTR_EXPORTED: u32 = 0x0101_0010;
+const ATTR_PRIORITY: u32 = 0x0101_001c;
+const ATTR_VALUE: u32 = 0x0101_0024;
+const ATTR_RESOURCE: u32 = 0x0101_0025;
+const ATTR_MIME_TYPE: u32 = 0x0101_0026;
+const ATTR_SCHEME: u32 = 0x0101_0027;
+const ATTR_HOST: u32 = 0x0101_0028;
+const ATTR_MIN_SDK_VERSION: u32 = 0x0101_020c;
+const ATTR_VERSION_CODE: u32 = 0x0101_021b;
+const ATTR_VERSION_NAME: u32 = 0x0101_021c;
+const ATTR_TARGET_SDK_VERSION: u32 = 0x0101_0270;
+const ATTR_MAX_SDK_VERSION: u32 = 0x0101_0271;
+const ATTR_ALLOW_BACKUP: u32 = 0x0101_0280;
+const ATTR_USES_CLEARTEXT_TRAFFIC: u32 = 0x0101_04ec;
+
+// Providers were exported by default before API 17
+const PROVIDER_EXPORT_DEFAULT_CHANGED: u32 = 17;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxmlError {
+    BadMagic,
+    Truncated,
+    MissingStringPool,
+    NotManifest,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Int(i32),
+    Bool(bool),
+    Reference(u32),
+    Other(u8, u32),
+}
+
+impl Value {
+    // The value as a manifest would spell it; references as "@0x7f010001"
+    pub fn text(&self) -> String {
+        match self {
+            Value::String(s) => s.clone(),
+            Value::Int(i) => i.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Reference(id) => format!("@0x{:08x}", id),
+            Value::Other(_, data) => format!("0x{:08x}", data),
+        }
+    }
+
+    fn int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i as i64),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::String(s) => s.trim().parse().ok(),
+            Value::Int(i) => Some(*i != 0),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub resource_id: Option<u32>,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    // An android: attribute, by resource id when the file maps one and by name otherwise
+    fn attr(&self, id: u32, name: &str) -> Option<&Value> {
+        self.attributes
+            .iter()
+            .find(|a| a.resource_id.map_or(a.name == name, |r| r == id))
+            .map(|a| &a.value)
+    }
+
+    fn text(&self, id: u32, name: &str) -> Option<String> {
+        self.attr(id, name).map(Value::text)
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentKind {
+    Activity,
+    ActivityAlias,
+    Service,
+    Receiver,
+    Provider,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IntentFilter {
+    pub actions: Vec<String>,
+    pub categories: Vec<String>,
+    pub schemes: Vec<String>,
+    pub hosts: Vec<String>,
+    pub mime_types: Vec<String>,
+    pub priority: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetaData {
+    pub name: String,
+    // The literal value, or the resource it points at
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Component {
+    pub kind: ComponentKind,
+    // Fully qualified class name
+    pub name: String,
+    // Whether other apps can reach it, after the platform's defaults
+    pub exported: bool,
+    pub permission: Option<String>,
+    pub enabled: bool,
+    pub intent_filters: Vec<IntentFilter>,
+    pub meta_data: Vec<MetaData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeclaredPermission {
+    pub name: String,
+    pub protection_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Manifest {
+    pub package: Option<String>,
+    pub version_code: Option<i64>,
+    pub version_name: Option<String>,
+    pub min_sdk: Option<u32>,
+    pub target_sdk: Option<u32>,
+    pub max_sdk: Option<u32>,
+    pub shared_user_id: Option<String>,
+    // uses-permission, in declaration order without repeats
+    pub permissions: Vec<String>,
+    pub declared_permissions: Vec<DeclaredPermission>,
+    pub debuggable: bool,
+    pub allow_backup: bool,
+    pub uses_cleartext_traffic: Option<bool>,
+    pub components: Vec<Component>,
+    // meta-data of the application element
+    pub meta_data: Vec<MetaData>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset.checked_add(2)?).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset.checked_add(4)?).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// One string of a pool. Lengths above 0x7f (UTF-8) or 0x7fff (UTF-16) take a second unit.
+fn pool_string(data: &[u8], offset: usize, utf8: bool) -> Option<String> {
+    if utf8 {
+        let length = |at: &mut usize| -> Option<usize> {
+            let first = *data.get(*at)? as usize;
+            *at += 1;
+            if first & 0x80 == 0 {
+                return Some(first);
+            }
+            let second = *data.get(*at)? as usize;
+            *at += 1;
+            Some(((first & 0x7f) << 8) | second)
+        };
+        let mut at = offset;
+        length(&mut at)?; // UTF-16 length, unused
+        let len = length(&mut at)?;
+        let bytes = data.get(at..at.checked_add(len)?)?;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    } else {
+        let mut len = u16_at(data, offset)? as usize;
+        let mut at = offset + 2;
+        if len & 0x8000 != 0 {
+            len = ((len & 0x7fff) << 16) | u16_at(data, at)? as usize;
+            at += 2;
+        }
+        let units: Vec<u16> = (0..len).map(|i| u16_at(data, at + 2 * i)).collect::<Option<_>>()?;
+        Some(String::from_utf16_lossy(&units))
+    }
+}
+
+// Strings that cannot be decoded come back empty so indices stay aligned
+fn string_pool(data: &[u8], chunk: usize, header_size: usize, end: usize) -> Option<Vec<String>> {
+    let count = u32_at(data, chunk + 8)? as usize;
+    let flags = u32_at(data, chunk + 16)?;
+    let strings_start = chunk + u32_at(data, chunk + 20)? as usize;
+    let pool = data.get(..end)?;
+    let offsets = chunk + header_size;
+    if count > pool.len() / 4 {
+        return None;
+    }
+    let strings = (0..count)
+        .map(|i| {
+            u32_at(pool, offsets + 4 * i)
+                .and_then(|offset| pool_string(pool, strings_start.checked_add(offset as usize)?, flags & UTF8_FLAG != 0))
+                .unwrap_or_default()
+        })
+        .collect();
+    Some(strings)
+}
+
+fn attribute(data: &[u8], at: usize, strings: &[String], resource_map: &[u32]) -> Option<Attribute> {
+    let string = |index: u32| strings.get(index as usize).cloned();
+    let name_index = u32_at(data, at + 4)?;
+    let raw = u32_at(data, at + 8)?;
+    let kind = *data.get(at + 15)?;
+    let value_data = u32_at(data, at + 16)?;
+    let value = match kind {
+        TYPE_STRING => Value::String(string(value_data).or_else(|| string(raw)).unwrap_or_default()),
+        TYPE_NULL if raw != NO_INDEX => Value::String(string(raw).unwrap_or_default()),
+        TYPE_NULL => return None,
+        TYPE_INT_DEC | TYPE_INT_HEX => Value::Int(value_data as i32),
+        TYPE_INT_BOOLEAN => Value::Bool(value_data != 0),
+        TYPE_REFERENCE => Value::Reference(value_data),
+        other => Value::Other(other, value_data),
+    };
+    Some(Attribute {
+        name: string(name_index).unwrap_or_default(),
+        resource_id: resource_map.get(name_index as usize).copied(),
+        value,
+    })
+}
+
+// Decode a compiled XML document into its element tree. Unknown chunks are skipped and sizes
+// are bounded by the data rather than trusted, as the platform's parser is lenient and packers
+// lean on that.
+pub fn parse(data: &[u8]) -> Result<Element, AxmlError> {
+    if u16_at(data, 0) != Some(RES_XML_TYPE) {
+        return Err(AxmlError::BadMagic);
+    }
+    let mut offset = u16_at(data, 2).ok_or(AxmlError::Truncated)? as usize;
+    let mut strings: Option<Vec<String>> = None;
+    let mut resource_map = Vec::new();
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+
+    while root.is_none() && offset + 8 <= data.len() {
+        let kind = u16_at(data, offset).unwrap_or(0);
+        let header_size = u16_at(data, offset + 2).unwrap_or(0) as usize;
+        let size = u32_at(data, offset + 4).unwrap_or(0) as usize;
+        if header_size < 8 || size < header_size {
+            break;
+        }
+        let end = offset.saturating_add(size).min(data.len());
+        match kind {
+            RES_STRING_POOL_TYPE if strings.is_none() => {
+                strings = string_pool(data, offset, header_size, end);
+            }
+            RES_XML_RESOURCE_MAP_TYPE => {
+                resource_map = (offset + header_size..end).step_by(4).filter_map(|at| u32_at(data, at)).collect();
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                let strings = strings.as_deref().ok_or(AxmlError::MissingStringPool)?;
+                let ext = offset + header_size;
+                let name = u32_at(data, ext + 4).ok_or(AxmlError::Truncated)?;
+                let attribute_start = u16_at(data, ext + 8).ok_or(AxmlError::Truncated)? as usize;
+                let attribute_size = (u16_at(data, ext + 10).ok_or(AxmlError::Truncated)? as usize).max(ATTRIBUTE_SIZE);
+                let attribute_count = u16_at(data, ext + 12).ok_or(AxmlError::Truncated)? as usize;
+                let attributes = (0..attribute_count)
+                    .map(|i| ext + attribute_start + i * attribute_size)
+                    .take_while(|&at| at + ATTRIBUTE_SIZE <= end)
+                    .filter_map(|at| attribute(data, at, strings, &resource_map))
+                    .collect();
+                stack.push(Element {
+                    name: strings.get(name as usize).cloned().unwrap_or_default(),
+                    attributes,
+                    children: Vec::new(),
+                });
+            }
+            RES_XML_END_ELEMENT_TYPE => {
+                if let Some(element) = stack.pop() {
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => root = Some(element),
+                    }
+                }
+            }
+            _ => {}
+        }
+        offset = offset.saturating_add(size);
+    }
+
+    // A document cut short still yields what was opened
+    while let Some(element) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(element),
+            None => root = Some(element),
+        }
+    }
+    match (root, strings) {
+        (Some(root), _) => Ok(root),
+        (None, None) => Err(AxmlError::MissingStringPool),
+        (None, Some(_)) => Err(AxmlError::Truncated),
+    }
+}
+
+// Decode a compiled AndroidManifest.xml
+pub fn parse_manifest(data: &[u8]) -> Result<Manifest, AxmlError> {
+    let root = parse(data)?;
+    if root.name != "manifest" {
+        return Err(AxmlError::NotManifest);
+    }
+    Ok(Manifest::from_element(&root))
+}
+
+// ".Main" and "Main" are relative to the package
+fn qualify(package: Option<&str>, name: &str) -> String {
+    match package {
+        Some(package) if name.starts_with('.') => format!("{}{}", package, name),
+        Some(package) if !name.contains('.') => format!("{}.{}", package, name),
+        _ => name.to_string(),
+    }
+}
+
+fn meta_data(element: &Element) -> Vec<MetaData> {
+    element
+        .children_named("meta-data")
+        .filter_map(|m| {
+            Some(MetaData {
+                name: m.text(ATTR_NAME, "name")?,
+                value: m.text(ATTR_VALUE, "value").or_else(|| m.text(ATTR_RESOURCE, "resource")),
+            })
+        })
+        .collect()
+}
+
+fn intent_filter(element: &Element) -> IntentFilter {
+    let names = |child: &str, id: u32, attr: &str| -> Vec<String> {
+        element.children_named(child).filter_map(|c| c.text(id, attr)).collect()
+    };
+    IntentFilter {
+        actions: names("action", ATTR_NAME, "name"),
+        categories: names("category", ATTR_NAME, "name"),
+        schemes: names("data", ATTR_SCHEME, "scheme"),
+        hosts: names("data", ATTR_HOST, "host"),
+        mime_types: names("data", ATTR_MIME_TYPE, "mimeType"),
+        priority: element.attr(ATTR_PRIORITY, "priority").and_then(Value::int),
+    }
+}
+
+fn protection_level(value: &Value) -> String {
+    match value.int() {
+        Some(level) => {
+            let base = match level & 0xf {
+                0 => "normal",
+                1 => "dangerous",
+                2 => "signature",
+                3 => "signatureOrSystem",
+                _ => return format!("0x{:x}", level),
+            };
+            if level & !0xf == 0 {
+                base.to_string()
+            } else {
+                format!("{}|0x{:x}", base, level & !0xf)
+            }
+        }
+        None => value.text(),
+    }
+}
+
+impl Manifest {
+    fn from_element(root: &Element) -> Manifest {
+        let sdk = |id: u32, name: &str| {
+            root.children_named("uses-sdk")
+                .find_map(|e| e.attr(id, name))
+                .and_then(Value::int)
+                .and_then(|v| u32::try_from(v).ok())
+        };
+        let mut manifest = Manifest {
+            // package is not an android: attribute and never has a resource id
+            package: root.attributes.iter().find(|a| a.name == "package").map(|a| a.value.text()),
+            version_code: root.attr(ATTR_VERSION_CODE, "versionCode").and_then(Value::int),
+            version_name: root.text(ATTR_VERSION_NAME, "versionName"),
+            min_sdk: sdk(ATTR_MIN_SDK_VERSION, "minSdkVersion"),
+            target_sdk: sdk(ATTR_TARGET_SDK_VERSION, "targetSdkVersion"),
+            max_sdk: sdk(ATTR_MAX_SDK_VERSION, "maxSdkVersion"),
+            shared_user_id: root.text(ATTR_SHARED_USER_ID, "sharedUserId"),
+            allow_backup: true,
+            ..Manifest::default()
+        };
+
+        for element in &root.children {
+            match element.name.as_str() {
+                "uses-permission" | "uses-permission-sdk-23" | "uses-permission-sdk-m" => {
+                    if let Some(name) = element.text(ATTR_NAME, "name") {
+                        if !manifest.permissions.contains(&name) {
+                            manifest.permissions.push(name);
+                        }
+                    }
+                }
+                "permission" => {
+                    if let Some(name) = element.text(ATTR_NAME, "name") {
+                        manifest.declared_permissions.push(DeclaredPermission {
+                            name,
+                            protection_level: element.attr(ATTR_PROTECTION_LEVEL, "protectionLevel").map(protection_level),
+                        });
+                    }
+                }
+                "application" => manifest.read_application(element),
+                _ => {}
+            }
+        }
+        manifest
+    }
+
+    fn read_application(&mut self, application: &Element) {
+        let flag = |id: u32, name: &str| application.attr(id, name).and_then(Value::bool);
+        self.debuggable = flag(ATTR_DEBUGGABLE, "debuggable").unwrap_or(false);
+        self.allow_backup = flag(ATTR_ALLOW_BACKUP, "allowBackup").unwrap_or(true);
+        self.uses_cleartext_traffic = flag(ATTR_USES_CLEARTEXT_TRAFFIC, "usesCleartextTraffic");
+        self.meta_data = meta_data(application);
+
+        for element in &application.children {
+            let kind = match element.name.as_str() {
+                "activity" => ComponentKind::Activity,
+                "activity-alias" => ComponentKind::ActivityAlias,
+                "service" => ComponentKind::Service,
+                "receiver" => ComponentKind::Receiver,
+                "provider" => ComponentKind::Provider,
+                _ => continue,
+            };
+            let Some(name) = element.text(ATTR_NAME, "name") else { continue };
+            let intent_filters: Vec<IntentFilter> = element.children_named("intent-filter").map(intent_filter).collect();
+            // Without an explicit flag, a component is exported if it has intent filters; providers
+            // also were for apps targeting below API 17
+            let exported = element.attr(ATTR_EXPORTED, "exported").and_then(Value::bool).unwrap_or_else(|| {
+                !intent_filters.is_empty()
+                    || (kind == ComponentKind::Provider
+                        && self.target_sdk.or(self.min_sdk).unwrap_or(1) < PROVIDER_EXPORT_DEFAULT_CHANGED)
+            });
+            self.components.push(Component {
+                kind,
+                name: qualify(self.package.as_deref(), &name),
+                exported,
+                permission: element.text(ATTR_PERMISSION, "permission"),
+                enabled: element.attr(ATTR_ENABLED, "enabled").and_then(Value::bool).unwrap_or(true),
+                intent_filters,
+                meta_data: meta_data(element),
+            });
+        }
+    }
+
+    // Components other apps can start or bind without holding a permission
+    pub fn unprotected_exports(&self) -> impl Iterator<Item = &Component> {
+        self.components.iter().filter(|c| c.exported && c.enabled && c.permission.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum V {
+        S(&'static str),
+        I(i32),
+        B(bool),
+    }
+
+    // Attribute names that get resource ids; they lead the string pool, as aapt lays it out
+    const MAPPED: [(&str, u32); 6] = [
+        ("name", ATTR_NAME),
+        ("exported", ATTR_EXPORTED),
+        ("versionCode", ATTR_VERSION_CODE),
+        ("minSdkVersion", ATTR_MIN_SDK_VERSION),
+        ("targetSdkVersion", ATTR_TARGET_SDK_VERSION),
+        ("permission", ATTR_PERMISSION),
+    ];
+
+    #[derive(Default)]
+    struct Writer {
+        strings: Vec<String>,
+        body: Vec<u8>,
+    }
+
+    impl Writer {
+        fn intern(&mut self, s: &str) -> u32 {
+            if let Some(i) = self.strings.iter().position(|x| x == s) {
+                return i as u32;
+            }
+            self.strings.push(s.to_string());
+            self.strings.len() as u32 - 1
+        }
+
+        fn start(&mut self, name: &str, attributes: &[(&str, V)]) {
+            let name = self.intern(name);
+            let mut chunk = Vec::new();
+            for x in [RES_XML_START_ELEMENT_TYPE as u32 | (16 << 16), 36 + 20 * attributes.len() as u32, 1, NO_INDEX, NO_INDEX, name] {
+                chunk.extend_from_slice(&x.to_le_bytes());
+            }
+            for x in [20u16, 20, attributes.len() as u16, 0, 0, 0] {
+                chunk.extend_from_slice(&x.to_le_bytes());
+            }
+            for (attr, value) in attributes {
+                let attr = self.intern(attr);
+                let (raw, kind, data) = match value {
+                    V::S(s) => {
+                        let i = self.intern(s);
+                        (i, TYPE_STRING, i)
+                    }
+                    V::I(i) => (NO_INDEX, TYPE_INT_DEC, *i as u32),
+                    V::B(b) => (NO_INDEX, TYPE_INT_BOOLEAN, if *b { u32::MAX } else { 0 }),
+                };
+                for x in [0, attr, raw, 8 | ((kind as u32) << 24), data] {
+                    chunk.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            self.body.extend_from_slice(&chunk);
+        }
+
+        fn end(&mut self) {
+            for x in [RES_XML_END_ELEMENT_TYPE as u32 | (16 << 16), 24, 1, NO_INDEX, NO_INDEX, 0] {
+                self.body.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+
+        fn finish(self) -> Vec<u8> {
+            let mut pool = Vec::new();
+            let count = self.strings.len() as u32;
+            let mut data = Vec::new();
+            let mut offsets = Vec::new();
+            for s in &self.strings {
+                offsets.push(data.len() as u32);
+                let units: Vec<u16> = s.encode_utf16().collect();
+                data.extend_from_slice(&(units.len() as u16).to_le_bytes());
+                units.iter().for_each(|u| data.extend_from_slice(&u.to_le_bytes()));
+                data.extend_from_slice(&[0, 0]);
+            }
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+            let size = 28 + 4 * count + data.len() as u32;
+            for x in [RES_STRING_POOL_TYPE as u32 | (28 << 16), size, count, 0, 0, 28 + 4 * count, 0] {
+                pool.extend_from_slice(&x.to_le_bytes());
+            }
+            offsets.iter().for_each(|o| pool.extend_from_slice(&o.to_le_bytes()));
+            pool.extend_from_slice(&data);
+
+            let mut map = Vec::new();
+            for x in [RES_XML_RESOURCE_MAP_TYPE as u32 | (8 << 16), 8 + 4 * MAPPED.len() as u32] {
+                map.extend_from_slice(&x.to_le_bytes());
+            }
+            MAPPED.iter().for_each(|(_, id)| map.extend_from_slice(&id.to_le_bytes()));
+
+            let total = 8 + pool.len() + map.len() + self.body.len();
+            let mut out = Vec::new();
+            out.extend_from_slice(&(RES_XML_TYPE as u32 | (8 << 16)).to_le_bytes());
+            out.extend_from_slice(&(total as u32).to_le_bytes());
+            out.extend_from_slice(&pool);
+            out.extend_from_slice(&map);
+            out.extend_from_slice(&self.body);
+            out
+        }
+    }
+
+    #[test]
+    fn decodes_manifest() {
+        let mut w = Writer::default();
+        MAPPED.iter().for_each(|(name, _)| {
+            w.intern(name);
+        });
+        w.start("manifest", &[("package", V::S("com.example.app")), ("versionCode", V::I(42))]);
+        w.start("uses-sdk", &[("minSdkVersion", V::I(21)), ("targetSdkVersion", V::I(33))]);
+        w.end();
+        w.start("uses-permission", &[("name", V::S("android.permission.SEND_SMS"))]);
+        w.end();
+        w.start("uses-permission", &[("name", V::S("android.permission.SEND_SMS"))]);
+        w.end();
+        w.start("application", &[]);
+        w.start("receiver", &[("name", V::S(".SmsReceiver"))]);
+        w.start("intent-filter", &[]);
+        w.start("action", &[("name", V::S("android.provider.Telephony.SMS_RECEIVED"))]);
+        w.end();
+        w.end();
+        w.end();
+        w.start("service", &[("name", V::S("Hidden")), ("exported", V::B(false))]);
+        w.end();
+        w.start("provider", &[("name", V::S("com.other.Files")), ("permission", V::S("com.example.READ"))]);
+        w.end();
+        w.end();
+        w.end();
+
+        let manifest = parse_manifest(&w.finish()).unwrap();
+        assert_eq!(manifest.package.as_deref(), Some("com.example.app"));
+        assert_eq!(manifest.version_code, Some(42));
+        assert_eq!((manifest.min_sdk, manifest.target_sdk), (Some(21), Some(33)));
+        assert_eq!(manifest.permissions, ["android.permission.SEND_SMS"]);
+
+        let [receiver, service, provider] = &manifest.components[..] else { panic!("{:?}", manifest.components) };
+        assert_eq!(receiver.name, "com.example.app.SmsReceiver");
+        assert!(receiver.exported);
+        assert_eq!(receiver.intent_filters[0].actions, ["android.provider.Telephony.SMS_RECEIVED"]);
+        assert_eq!((service.name.as_str(), service.exported), ("com.example.app.Hidden", false));
+        assert_eq!((provider.kind, provider.exported), (ComponentKind::Provider, false));
+        assert_eq!(manifest.unprotected_exports().count(), 1);
+
+        assert_eq!(parse_manifest(b"PK\x03\x04"), Err(AxmlError::BadMagic));
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::{self, Read, Write};
+
+use md5::{Digest, Md5};
+
+// Each slice added to a scalable filter holds twice as many items at half the error rate,
+// which keeps the compound false positive rate below twice the initial one
+const GROWTH: u64 = 2;
+const TIGHTENING: f64 = 0.5;
+const MAX_SLICES: usize = 16;
+
+// Refuse to load absurd dimensions from a corrupt file
+const MAX_BITS: u64 = 1 << 32;
+const MAX_HASHES: u32 = 32;
+
+/// Stable 128-bit hash of a key, split in two for double hashing.
+/// Must not change between releases: filters are persisted with these positions.
+pub fn hash_key(key: &[u8]) -> (u64, u64) {
+    let digest = Md5::digest(key);
+    let h1 = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    let h2 = u64::from_le_bytes(digest[8..].try_into().unwrap());
+    (h1, h2 | 1)
+}
+
+/// Fixed-size Bloom filter sized for a capacity and false positive rate
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    hashes: u32,
+    capacity: u64,
+    count: u64,
+}
+
+impl BloomFilter {
+    pub fn new(capacity: u64, fp_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        let fp_rate = fp_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = ((-(capacity as f64) * fp_rate.ln() / (ln2 * ln2)).ceil() as u64).clamp(64, MAX_BITS);
+        let hashes = ((num_bits as f64 / capacity as f64) * ln2).round().clamp(1.0, MAX_HASHES as f64) as u32;
+        Self { bits: vec![0; num_bits.div_ceil(64) as usize], num_bits, hashes, capacity, count: 0 }
+    }
+
+    fn positions(&self, (h1, h2): (u64, u64)) -> impl Iterator<Item = u64> + '_ {
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    pub fn contains_hash(&self, hash: (u64, u64)) -> bool {
+        self.positions(hash).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn insert_hash(&mut self, hash: (u64, u64)) {
+        let positions: Vec<u64> = self.positions(hash).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.count += 1;
+    }
+
+    /// Items inserted so far (duplicates included)
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count >= self.capacity
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&self.num_bits.to_le_bytes())?;
+        out.write_all(&self.hashes.to_le_bytes())?;
+        out.write_all(&self.capacity.to_le_bytes())?;
+        out.write_all(&self.count.to_le_bytes())?;
+        for word in &self.bits {
+            out.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let num_bits = read_u64(input)?;
+        let hashes = read_u32(input)?;
+        let capacity = read_u64(input)?;
+        let count = read_u64(input)?;
+        if num_bits == 0 || num_bits > MAX_BITS || hashes == 0 || hashes > MAX_HASHES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad bloom filter dimensions"));
+        }
+        let bits = (0..num_bits.div_ceil(64)).map(|_| read_u64(input)).collect::<io::Result<_>>()?;
+        Ok(Self { bits, num_bits, hashes, capacity, count })
+    }
+}
+
+/// Bloom filter that adds larger, tighter slices as it fills instead of degrading
+#[derive(Debug, Clone)]
+pub struct ScalableBloom {
+    slices: Vec<BloomFilter>,
+    initial_capacity: u64,
+    fp_rate: f64,
+}
+
+impl ScalableBloom {
+    pub fn new(initial_capacity: u64, fp_rate: f64) -> Self {
+        Self { slices: vec![BloomFilter::new(initial_capacity, fp_rate * (1.0 - TIGHTENING))], initial_capacity, fp_rate }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let hash = hash_key(key);
+        self.slices.iter().any(|slice| slice.contains_hash(hash))
+    }
+
+    /// Insert a key; returns false if it was (probably) already present
+    pub fn insert(&mut self, key: &[u8]) -> bool {
+        let hash = hash_key(key);
+        if self.slices.iter().any(|slice| slice.contains_hash(hash)) {
+            return false;
+        }
+        if self.slices.last().is_none_or(BloomFilter::is_full) {
+            let n = self.slices.len() as i32;
+            if self.slices.len() >= MAX_SLICES {
+                // Past this size the oldest memories go first
+                self.slices.remove(0);
+            }
+            let capacity = self.initial_capacity.saturating_mul(GROWTH.saturating_pow(n as u32));
+            let fp_rate = self.fp_rate * (1.0 - TIGHTENING) * TIGHTENING.powi(n);
+            self.slices.push(BloomFilter::new(capacity, fp_rate));
+        }
+        self.slices.last_mut().unwrap().insert_hash(hash);
+        true
+    }
+
+    pub fn len(&self) -> u64 {
+        self.slices.iter().map(BloomFilter::len).sum()
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&self.initial_capacity.to_le_bytes())?;
+        out.write_all(&self.fp_rate.to_le_bytes())?;
+        out.write_all(&(self.slices.len() as u32).to_le_bytes())?;
+        self.slices.iter().try_for_each(|slice| slice.write_to(out))
+    }
+
+    pub fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let initial_capacity = read_u64(input)?;
+        let fp_rate = f64::from_le_bytes(read_u64(input)?.to_le_bytes());
+        let count = read_u32(input)? as usize;
+        if count == 0 || count > MAX_SLICES || !(fp_rate > 0.0 && fp_rate < 1.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad scalable bloom header"));
+        }
+        let slices = (0..count).map(|_| BloomFilter::read_from(input)).collect::<io::Result<_>>()?;
+        Ok(Self { slices, initial_capacity, fp_rate })
+    }
+}
+
+pub(crate) fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_fills_to_capacity_and_round_trips() {
+        let mut filter = BloomFilter::new(64, 0.01);
+        for i in 0..64u32 {
+            assert!(!filter.is_full());
+            filter.insert_hash(hash_key(&i.to_le_bytes()));
+        }
+        assert!(filter.is_full());
+        assert_eq!(filter.len(), 64);
+        assert!((0..64u32).all(|i| filter.contains_hash(hash_key(&i.to_le_bytes()))));
+        assert!((1_000..1_100u32).filter(|i| filter.contains_hash(hash_key(&i.to_le_bytes()))).count() < 10);
+
+        let mut bytes = Vec::new();
+        filter.write_to(&mut bytes).unwrap();
+        let restored = BloomFilter::read_from(&mut bytes.as_slice()).unwrap();
+        assert!(restored.is_full() && restored.contains_hash(hash_key(&7u32.to_le_bytes())));
+    }
+
+    #[test]
+    fn test_scalable_bloom_grows_and_round_trips() {
+        let mut filter = ScalableBloom::new(100, 0.01);
+        // A false positive makes insert report the key as already present
+        let inserted = (0..1_000).filter(|i| filter.insert(format!("host{}.example", i).as_bytes())).count();
+        assert!(inserted > 990);
+        assert!(!filter.insert(b"host7.example"));
+        assert!((0..1_000).all(|i| filter.contains(format!("host{}.example", i).as_bytes())));
+
+        let false_positives = (0..10_000).filter(|i| filter.contains(format!("other{}.test", i).as_bytes())).count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+
+        let mut bytes = Vec::new();
+        filter.write_to(&mut bytes).unwrap();
+        let restored = ScalableBloom::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.len(), inserted as u64);
+        assert!(restored.contains(b"host999.example"));
+
+        assert!(ScalableBloom::read_from(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use std::fs;
+use std::path::Path;
+
+use ahash::{AHashMap, AHashSet};
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::Pem;
+use x509_parser::prelude::FromDer;
+use x509_parser::public_key::PublicKey;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+// DER certificates of the public roots shipped with Android and Mozilla, back to back. Used when
+// the system store can't be read.
+const BUNDLED_ROOTS: &[u8] = include_bytes!("../data/bundled_roots.der");
+
+// Signature algorithms no longer acceptable for server certificates (MD2, MD5, SHA-1)
+const WEAK_SIGNATURE_OIDS: &[&str] = &[
+    "1.2.840.113549.1.1.2",
+    "1.2.840.113549.1.1.4",
+    "1.2.840.113549.1.1.5",
+    "1.2.840.10045.4.1",
+    "1.2.840.10040.4.3",
+    "1.3.14.3.2.29",
+];
+
+const MIN_RSA_BITS: usize = 2048;
+const MIN_EC_BITS: usize = 256;
+
+/// Problems found in a server certificate chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateFlag {
+    SelfSigned,
+    Expired,
+    NotYetValid,
+    NameMismatch,
+    KnownBadFingerprint,
+    WeakKey,
+    WeakSignature,
+    // A certificate's issuer is not the next one's subject, or its signature does not verify
+    BrokenChain,
+    UntrustedRoot,
+    Unparseable,
+}
+
+/// Summary of the leaf certificate and everything flagged across the chain
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CertificateReport {
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: i64,
+    pub not_after: i64,
+    pub sha1: String,
+    pub sha256: String,
+    pub chain_len: usize,
+    pub flags: Vec<CertificateFlag>,
+}
+
+impl CertificateReport {
+    fn flag(&mut self, flag: CertificateFlag) {
+        if !self.flags.contains(&flag) {
+            self.flags.push(flag);
+        }
+    }
+
+    /// Flags that mean the peer is known bad rather than merely misconfigured
+    pub fn is_malicious(&self) -> bool {
+        self.flags.contains(&CertificateFlag::KnownBadFingerprint)
+    }
+}
+
+/// Root anchors, the bundled set plus any loaded at runtime, identified by their public key
+pub struct TrustStore {
+    // SHA-256 of each root's SubjectPublicKeyInfo, to the SPKI itself
+    keys: AHashMap<[u8; 32], Vec<u8>>,
+    // Raw subject name to the keys of the roots carrying it
+    subjects: AHashMap<Vec<u8>, Vec<[u8; 32]>>,
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+impl TrustStore {
+    pub fn bundled() -> Self {
+        let mut store = Self::empty();
+        let mut rest = BUNDLED_ROOTS;
+        while let Ok((next, cert)) = X509Certificate::from_der(rest) {
+            store.add_root(&cert);
+            rest = next;
+        }
+        store
+    }
+
+    pub fn empty() -> Self {
+        Self { keys: AHashMap::new(), subjects: AHashMap::new() }
+    }
+
+    /// Add a DER-encoded root certificate
+    pub fn add_root_der(&mut self, der: &[u8]) -> bool {
+        match X509Certificate::from_der(der) {
+            Ok((_, cert)) => {
+                self.add_root(&cert);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn add_root(&mut self, cert: &X509Certificate) {
+        let spki = cert.public_key().raw;
+        let hash: [u8; 32] = Sha256::digest(spki).into();
+        if self.keys.insert(hash, spki.to_vec()).is_none() {
+            self.subjects.entry(cert.subject().as_raw().to_vec()).or_default().push(hash);
+        }
+    }
+
+    /// Load every PEM or DER certificate in a directory such as /system/etc/security/cacerts
+    pub fn load_dir(&mut self, dir: &Path) -> usize {
+        let mut loaded = 0;
+        let Ok(entries) = fs::read_dir(dir) else { return 0 };
+        for entry in entries.flatten() {
+            let Ok(data) = fs::read(entry.path()) else { continue };
+            if data.windows(10).any(|w| w == b"-----BEGIN") {
+                for pem in Pem::iter_from_buffer(&data).flatten() {
+                    if self.add_root_der(&pem.contents) {
+                        loaded += 1;
+                    }
+                }
+            } else if self.add_root_der(&data) {
+                loaded += 1;
+            }
+        }
+        loaded
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // A certificate is anchored if its own key is a root's, or a root with its issuer's name
+    // signed it. Names alone prove nothing; anyone can mint a "ISRG Root X1".
+    fn anchors(&self, cert: &X509Certificate) -> bool {
+        let hash: [u8; 32] = Sha256::digest(cert.public_key().raw).into();
+        if self.keys.contains_key(&hash) {
+            return true;
+        }
+        let Some(candidates) = self.subjects.get(cert.issuer().as_raw()) else { return false };
+        candidates.iter().filter_map(|hash| self.keys.get(hash)).any(|spki| match SubjectPublicKeyInfo::from_der(spki) {
+            Ok((_, key)) => cert.verify_signature(Some(&key)).is_ok(),
+            Err(_) => false,
+        })
+    }
+}
+
+/// Offline checks over the cleartext certificate chain of a TLS 1.2 handshake
+#[derive(Default)]
+pub struct CertificateInspector {
+    pub trust: TrustStore,
+    bad_fingerprints: AHashSet<String>,
+}
+
+impl CertificateInspector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a known-bad certificate SHA-1 or SHA-256 fingerprint (hex, colons allowed)
+    pub fn add_bad_fingerprint(&mut self, fingerprint: &str) {
+        let normalized: String = fingerprint.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+        if matches!(normalized.len(), 40 | 64) {
+            self.bad_fingerprints.insert(normalized.to_ascii_lowercase());
+        }
+    }
+
+    /// Analyze a chain (leaf first) against the SNI the client asked for at `now` (Unix seconds)
+    pub fn analyze_chain(&self, chain: &[Vec<u8>], sni: Option<&str>, now: i64) -> CertificateReport {
+        let mut report = CertificateReport { chain_len: chain.len(), ..Default::default() };
+
+        let mut parsed = Vec::with_capacity(chain.len());
+        for der in chain {
+            let sha1 = hex::encode(Sha1::digest(der));
+            let sha256 = hex::encode(Sha256::digest(der));
+            if self.bad_fingerprints.contains(&sha1) || self.bad_fingerprints.contains(&sha256) {
+                report.flag(CertificateFlag::KnownBadFingerprint);
+            }
+            if parsed.is_empty() {
+                report.sha1 = sha1;
+                report.sha256 = sha256;
+            }
+            match X509Certificate::from_der(der) {
+                Ok((_, cert)) => parsed.push(cert),
+                Err(_) => {
+                    report.flag(CertificateFlag::Unparseable);
+                    return report;
+                }
+            }
+        }
+
+        let Some(leaf) = parsed.first() else {
+            report.flag(CertificateFlag::Unparseable);
+            return report;
+        };
+
+        report.subject = leaf.subject().to_string();
+        report.issuer = leaf.issuer().to_string();
+        report.not_before = leaf.validity().not_before.timestamp();
+        report.not_after = leaf.validity().not_after.timestamp();
+
+        if leaf.subject().as_raw() == leaf.issuer().as_raw() {
+            report.flag(CertificateFlag::SelfSigned);
+        }
+
+        for cert in &parsed {
+            if now < cert.validity().not_before.timestamp() {
+                report.flag(CertificateFlag::NotYetValid);
+            }
+            if now > cert.validity().not_after.timestamp() {
+                report.flag(CertificateFlag::Expired);
+            }
+            if Self::has_weak_key(cert) {
+                report.flag(CertificateFlag::WeakKey);
+            }
+            let self_issued = cert.subject().as_raw() == cert.issuer().as_raw();
+            let sig_oid = cert.signature_algorithm.algorithm.to_id_string();
+            if !self_issued && WEAK_SIGNATURE_OIDS.contains(&sig_oid.as_str()) {
+                report.flag(CertificateFlag::WeakSignature);
+            }
+        }
+
+        // Leaf self-signature with a weak hash is just as bad when it is the whole chain
+        if parsed.len() == 1 && WEAK_SIGNATURE_OIDS.contains(&leaf.signature_algorithm.algorithm.to_id_string().as_str()) {
+            report.flag(CertificateFlag::WeakSignature);
+        }
+
+        if let Some(sni) = sni {
+            if !Self::matches_name(leaf, sni) {
+                report.flag(CertificateFlag::NameMismatch);
+            }
+        }
+
+        // Each certificate must be issued, and signed, by the one after it
+        for pair in parsed.windows(2) {
+            let (child, parent) = (&pair[0], &pair[1]);
+            if child.issuer().as_raw() != parent.subject().as_raw()
+                || child.verify_signature(Some(parent.public_key())).is_err()
+            {
+                report.flag(CertificateFlag::BrokenChain);
+            }
+        }
+
+        // The top of the chain must be, or be signed by, a known root
+        let top = parsed.last().expect("chain is not empty");
+        if !self.trust.anchors(top) {
+            report.flag(CertificateFlag::UntrustedRoot);
+        }
+
+        report
+    }
+
+    fn has_weak_key(cert: &X509Certificate) -> bool {
+        match cert.public_key().parsed() {
+            Ok(PublicKey::RSA(rsa)) => rsa.key_size() < MIN_RSA_BITS,
+            Ok(PublicKey::EC(ec)) => ec.key_size() < MIN_EC_BITS,
+            Ok(PublicKey::DSA(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn matches_name(cert: &X509Certificate, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let mut dns_names = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                if let GeneralName::DNSName(dns) = name {
+                    dns_names.push(dns.to_ascii_lowercase());
+                }
+            }
+        }
+        // Only fall back to the CN when the certificate has no DNS SANs (RFC 6125)
+        if dns_names.is_empty() {
+            dns_names.extend(
+                cert.subject()
+                    .iter_common_name()
+                    .filter_map(|cn| cn.as_str().ok())
+                    .map(|cn| cn.to_ascii_lowercase()),
+            );
+        }
+        dns_names.iter().any(|pattern| Self::hostname_matches(pattern, &host))
+    }
+
+    // A wildcard covers exactly one left-most label
+    fn hostname_matches(pattern: &str, host: &str) -> bool {
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => match host.split_once('.') {
+                Some((label, rest)) => !label.is_empty() && rest == suffix,
+                None => false,
+            },
+            None => pattern == host,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &[u8] = include_bytes!("../testdata/root.der");
+    const LEAF: &[u8] = include_bytes!("../testdata/leaf.der");
+    const WEAK: &[u8] = include_bytes!("../testdata/weak.der");
+    // Self-signed with the exact subject of ISRG Root X1 but its own key, and a leaf it issued
+    const FORGED_ROOT: &[u8] = include_bytes!("../testdata/forged_root.der");
+    const FORGED_LEAF: &[u8] = include_bytes!("../testdata/forged_leaf.der");
+
+    // 2025-06-01, inside the validity window of the test chain
+    const NOW: i64 = 1_748_736_000;
+
+    #[test]
+    fn test_valid_chain_with_loaded_root() {
+        let mut inspector = CertificateInspector::new();
+        let chain = vec![LEAF.to_vec()];
+
+        let report = inspector.analyze_chain(&chain, Some("www.example.com"), NOW);
+        assert_eq!(report.flags, vec![CertificateFlag::UntrustedRoot]);
+
+        assert!(inspector.trust.add_root_der(ROOT));
+        let report = inspector.analyze_chain(&chain, Some("example.com"), NOW);
+        assert!(report.flags.is_empty(), "{:?}", report.flags);
+        assert_eq!(report.subject, "CN=www.example.com");
+    }
+
+    #[test]
+    fn test_expiry_and_name_mismatch() {
+        let mut inspector = CertificateInspector::new();
+        inspector.trust.add_root_der(ROOT);
+        let chain = vec![LEAF.to_vec(), ROOT.to_vec()];
+
+        let report = inspector.analyze_chain(&chain, Some("mail.example.com"), 2_100_000_000);
+        assert!(report.flags.contains(&CertificateFlag::Expired));
+        assert!(report.flags.contains(&CertificateFlag::NameMismatch));
+
+        let report = inspector.analyze_chain(&chain, None, 1_600_000_000);
+        assert_eq!(report.flags, vec![CertificateFlag::NotYetValid]);
+    }
+
+    #[test]
+    fn test_self_signed_weak_and_blocklisted() {
+        let mut inspector = CertificateInspector::new();
+        let chain = vec![WEAK.to_vec()];
+        let sha1 = hex::encode(Sha1::digest(WEAK));
+        inspector.add_bad_fingerprint(&sha1);
+
+        let report = inspector.analyze_chain(&chain, Some("bad.example"), NOW);
+        for flag in [
+            CertificateFlag::SelfSigned,
+            CertificateFlag::WeakKey,
+            CertificateFlag::WeakSignature,
+            CertificateFlag::KnownBadFingerprint,
+            CertificateFlag::UntrustedRoot,
+        ] {
+            assert!(report.flags.contains(&flag), "missing {:?}", flag);
+        }
+        assert!(report.is_malicious());
+    }
+
+    #[test]
+    fn test_forged_root_with_trusted_name_is_rejected() {
+        let inspector = CertificateInspector::new();
+        assert!(inspector.trust.len() > 50);
+        let (_, bundled) = X509Certificate::from_der(BUNDLED_ROOTS).unwrap();
+        let report = inspector.analyze_chain(&[bundled.as_ref().to_vec()], None, NOW);
+        assert!(!report.flags.contains(&CertificateFlag::UntrustedRoot), "{:?}", report.flags);
+
+        for chain in [vec![FORGED_ROOT.to_vec()], vec![FORGED_LEAF.to_vec(), FORGED_ROOT.to_vec()], vec![FORGED_LEAF.to_vec()]] {
+            let report = inspector.analyze_chain(&chain, Some("www.example.com"), NOW);
+            assert!(report.flags.contains(&CertificateFlag::UntrustedRoot), "{:?}", report.flags);
+            assert!(!report.flags.contains(&CertificateFlag::BrokenChain), "{:?}", report.flags);
+        }
+    }
+
+    #[test]
+    fn test_links_and_signatures_are_checked() {
+        let mut inspector = CertificateInspector::new();
+        inspector.trust.add_root_der(ROOT);
+
+        let report = inspector.analyze_chain(&[LEAF.to_vec(), ROOT.to_vec()], None, NOW);
+        assert!(report.flags.is_empty(), "{:?}", report.flags);
+
+        // The forged root has the right name for neither, and the wrong key for both
+        let report = inspector.analyze_chain(&[LEAF.to_vec(), FORGED_ROOT.to_vec()], None, NOW);
+        assert!(report.flags.contains(&CertificateFlag::BrokenChain));
+        assert!(report.flags.contains(&CertificateFlag::UntrustedRoot));
+    }
+
+    #[test]
+    fn test_wildcard_matching() {
+        assert!(CertificateInspector::hostname_matches("*.example.com", "a.example.com"));
+        assert!(!CertificateInspector::hostname_matches("*.example.com", "a.b.example.com"));
+        assert!(!CertificateInspector::hostname_matches("*.example.com", "example.com"));
+    }
+}
@@ -0,0 +1,341 @@
+use serde::Serialize;
+
+use crate::signatures::{
+    BodySignature, CountOp, HashSignature, LogicalExpr, LogicalSignature, PatternError, SignatureOffset, TargetType,
+};
+
+/// A database line that was not loaded, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unsupported {
+    pub line: usize,
+    pub name: String,
+    pub reason: String,
+}
+
+/// Signatures read out of one ClamAV database file
+#[derive(Debug, Default)]
+pub struct ClamAvDatabase {
+    pub hashes: Vec<HashSignature>,
+    pub bodies: Vec<BodySignature>,
+    pub logical: Vec<LogicalSignature>,
+    pub unsupported: Vec<Unsupported>,
+}
+
+impl ClamAvDatabase {
+    pub fn len(&self) -> usize {
+        self.hashes.len() + self.bodies.len() + self.logical.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Parse a ClamAV database by its file extension: `.hdb`/`.hsb` (whole-file hashes), `.ndb`
+/// (extended body signatures) or `.ldb` (logical signatures), and their `.hdu`/`.hsu`/`.ndu`/`.ldu`
+/// variants. None for any other kind of database.
+pub fn parse_database(file_name: &str, text: &str) -> Option<ClamAvDatabase> {
+    let extension = file_name.rsplit_once('.')?.1.to_ascii_lowercase();
+    // The line parser, and which field holds the signature name
+    type ParseLine = fn(&str, &mut ClamAvDatabase) -> Result<(), String>;
+    let (parse_line, name_field): (ParseLine, usize) = match extension.as_str() {
+        "hdb" | "hdu" | "hsb" | "hsu" => (parse_hash_line, 2),
+        "ndb" | "ndu" => (parse_body_line, 0),
+        "ldb" | "ldu" => (parse_logical_line, 0),
+        _ => return None,
+    };
+    let mut database = ClamAvDatabase::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Err(reason) = parse_line(line, &mut database) {
+            let name = line.split([':', ';']).nth(name_field).unwrap_or_default().to_string();
+            database.unsupported.push(Unsupported { line: i + 1, name, reason });
+        }
+    }
+    Some(database)
+}
+
+// HashString:FileSize:MalwareName[:FLEVEL]; the size may be `*`
+fn parse_hash_line(line: &str, database: &mut ClamAvDatabase) -> Result<(), String> {
+    let fields: Vec<&str> = line.split(':').collect();
+    let [hash, size, name, ..] = fields[..] else { return Err("expected hash:size:name".to_string()) };
+    if !matches!(hash.len(), 32 | 40 | 64) {
+        return Err(format!("hash of {} hex digits is not MD5, SHA-1 or SHA-256", hash.len()));
+    }
+    let digest = decode_hex(hash).ok_or("hash is not hex")?;
+    let size = match size {
+        "*" => None,
+        size => Some(size.parse().map_err(|_| format!("invalid file size {size:?}"))?),
+    };
+    database.hashes.push(HashSignature { name: name.to_string(), digest, size });
+    Ok(())
+}
+
+// MalwareName:TargetType:Offset:HexSignature[:MinFL[:MaxFL]]
+fn parse_body_line(line: &str, database: &mut ClamAvDatabase) -> Result<(), String> {
+    let fields: Vec<&str> = line.split(':').collect();
+    let [name, target, offset, hex, ..] = fields[..] else {
+        return Err("expected name:target:offset:signature".to_string());
+    };
+    let target = parse_target(target)?;
+    let mut signature = BodySignature::parse(name, hex).map_err(pattern_error)?;
+    signature.target = target;
+    signature.offset = parse_offset(offset)?;
+    database.bodies.push(signature);
+    Ok(())
+}
+
+// SignatureName;TargetDescriptionBlock;LogicalExpression;Subsig0;Subsig1;...
+fn parse_logical_line(line: &str, database: &mut ClamAvDatabase) -> Result<(), String> {
+    let fields: Vec<&str> = line.split(';').collect();
+    let [name, tdb, expression, ref subsignatures @ ..] = fields[..] else {
+        return Err("expected name;target description;expression;subsignatures".to_string());
+    };
+    if subsignatures.is_empty() {
+        return Err("no subsignatures".to_string());
+    }
+
+    let mut target = TargetType::Any;
+    let mut file_size = None;
+    for attribute in tdb.split(',') {
+        let (key, value) = attribute.split_once(':').ok_or_else(|| format!("invalid attribute {attribute:?}"))?;
+        match key {
+            "Target" => target = parse_target(value)?,
+            // Functionality levels only gate which ClamAV versions load the signature
+            "Engine" => {}
+            "FileSize" => {
+                let (min, max) = value.split_once('-').ok_or_else(|| format!("invalid file size {value:?}"))?;
+                let parse = |n: &str| n.parse::<u64>().map_err(|_| format!("invalid file size {value:?}"));
+                file_size = Some((parse(min)?, parse(max)?));
+            }
+            _ => return Err(format!("target description attribute {key} is not supported")),
+        }
+    }
+
+    let expression = parse_expression(expression)?;
+    if expression.max_sub().is_some_and(|max| max >= subsignatures.len()) {
+        return Err("expression refers to a missing subsignature".to_string());
+    }
+    let subsignatures = subsignatures
+        .iter()
+        .enumerate()
+        .map(|(i, subsignature)| parse_subsignature(&format!("{name}.{i}"), subsignature))
+        .collect::<Result<Vec<_>, _>>()?;
+    database.logical.push(LogicalSignature { name: name.to_string(), target, file_size, subsignatures, expression });
+    Ok(())
+}
+
+// [Offset:]HexSignature[::Modifiers]
+fn parse_subsignature(name: &str, subsignature: &str) -> Result<BodySignature, String> {
+    if subsignature.contains('/') {
+        return Err("PCRE subsignatures are not supported".to_string());
+    }
+    if subsignature.contains('#') {
+        return Err("byte compare subsignatures are not supported".to_string());
+    }
+    let (body, modifiers) = subsignature.split_once("::").unwrap_or((subsignature, ""));
+    let (offset, hex) = match body.rsplit_once(':') {
+        Some((offset, hex)) => (parse_offset(offset)?, hex),
+        None => (SignatureOffset::Any, body),
+    };
+    let mut signature = BodySignature::parse(name, hex).map_err(pattern_error)?;
+    signature.offset = offset;
+    match modifiers {
+        "" => {}
+        "w" => signature = signature.wide(),
+        _ => return Err(format!("subsignature modifiers {modifiers:?} are not supported")),
+    }
+    Ok(signature)
+}
+
+fn parse_target(target: &str) -> Result<TargetType, String> {
+    if target == "*" {
+        return Ok(TargetType::Any);
+    }
+    target
+        .parse()
+        .ok()
+        .and_then(TargetType::from_clamav)
+        .ok_or_else(|| format!("target type {target} is not supported"))
+}
+
+// `*`, `n`, `n,shift`, `EOF-n` or `EOF-n,shift`; executable-relative offsets are not supported
+fn parse_offset(offset: &str) -> Result<SignatureOffset, String> {
+    if offset == "*" {
+        return Ok(SignatureOffset::Any);
+    }
+    let invalid = || format!("offset {offset} is not supported");
+    let (base, shift) = match offset.split_once(',') {
+        Some((base, shift)) => (base, shift.parse().map_err(|_| invalid())?),
+        None => (offset, 0),
+    };
+    if let Some(distance) = base.strip_prefix("EOF-") {
+        return Ok(SignatureOffset::End { distance: distance.parse().map_err(|_| invalid())?, shift });
+    }
+    Ok(SignatureOffset::Start { offset: base.parse().map_err(|_| invalid())?, shift })
+}
+
+fn pattern_error(error: PatternError) -> String {
+    match error {
+        PatternError::Empty => "empty signature".to_string(),
+        PatternError::InvalidHex(at) => format!("invalid hex at {at}"),
+        PatternError::InvalidGap(at) => format!("invalid jump at {at}"),
+        PatternError::MisplacedGap(at) => format!("misplaced jump at {at}"),
+        PatternError::NoAnchor => "no run of two fixed bytes".to_string(),
+        PatternError::Unsupported(at) => format!("alternatives, negations and anchors are not supported (at {at})"),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Logical expressions: subsignature indices combined with `&`, `|` and parentheses, with
+// `=X`, `>X`, `<X` (each optionally `,Y`) match-count conditions after an index or group
+fn parse_expression(expression: &str) -> Result<LogicalExpr, String> {
+    let chars: Vec<char> = expression.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut at = 0;
+    let parsed = parse_or(&chars, &mut at)?;
+    if at != chars.len() {
+        return Err(format!("unexpected {:?} in logical expression", chars[at]));
+    }
+    Ok(parsed)
+}
+
+fn parse_or(chars: &[char], at: &mut usize) -> Result<LogicalExpr, String> {
+    let mut items = vec![parse_and(chars, at)?];
+    while chars.get(*at) == Some(&'|') {
+        *at += 1;
+        items.push(parse_and(chars, at)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { LogicalExpr::Any(items) })
+}
+
+fn parse_and(chars: &[char], at: &mut usize) -> Result<LogicalExpr, String> {
+    let mut items = vec![parse_primary(chars, at)?];
+    while chars.get(*at) == Some(&'&') {
+        *at += 1;
+        items.push(parse_primary(chars, at)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { LogicalExpr::All(items) })
+}
+
+fn parse_primary(chars: &[char], at: &mut usize) -> Result<LogicalExpr, String> {
+    let expr = if chars.get(*at) == Some(&'(') {
+        *at += 1;
+        let inner = parse_or(chars, at)?;
+        if chars.get(*at) != Some(&')') {
+            return Err("unbalanced parentheses in logical expression".to_string());
+        }
+        *at += 1;
+        inner
+    } else {
+        LogicalExpr::Sub(parse_number(chars, at)?)
+    };
+    let op = match chars.get(*at) {
+        Some('=') => CountOp::Eq,
+        Some('>') => CountOp::Gt,
+        Some('<') => CountOp::Lt,
+        _ => return Ok(expr),
+    };
+    *at += 1;
+    let matches = parse_number(chars, at)?;
+    let distinct = if chars.get(*at) == Some(&',') {
+        *at += 1;
+        Some(parse_number(chars, at)?)
+    } else {
+        None
+    };
+    Ok(LogicalExpr::Count { expr: Box::new(expr), op, matches, distinct })
+}
+
+fn parse_number(chars: &[char], at: &mut usize) -> Result<usize, String> {
+    let start = *at;
+    while chars.get(*at).is_some_and(char::is_ascii_digit) {
+        *at += 1;
+    }
+    chars[start..*at]
+        .iter()
+        .collect::<String>()
+        .parse()
+        .map_err(|_| format!("expected a number at {start} in logical expression"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signatures::SignatureDatabase;
+
+    #[test]
+    fn test_loads_hash_body_and_logical_databases() {
+        let mut signatures = SignatureDatabase::new();
+
+        let hdb = "\
+44d88612fea8a8f36de82e1278abb02f:68:Eicar-Test-Signature:73\n\
+# comment\n\
+3395856ce81f2b7382dee72602f798b642f14140:*:Eicar-Sha1\n\
+0123:10:Short-Hash\n";
+        let hashes = parse_database("daily.hdb", hdb).unwrap();
+        assert_eq!(hashes.hashes.len(), 2);
+        assert_eq!(hashes.unsupported, vec![Unsupported {
+            line: 4,
+            name: "Short-Hash".to_string(),
+            reason: "hash of 4 hex digits is not MD5, SHA-1 or SHA-256".to_string(),
+        }]);
+        for hash in hashes.hashes {
+            signatures.insert_hash(hash);
+        }
+        let eicar = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+        assert_eq!(signatures.hashes().lookup(eicar).as_deref(), Some("Eicar-Test-Signature"));
+        assert_eq!(signatures.hashes().lookup(b"clean"), None);
+
+        let ndb = "\
+Elf.Tail:6:EOF-4:deadbeef\n\
+Any.Start:0:2,2:4142{1-2}45\n\
+Pe.Entry:1:EP+0:4d5a\n\
+Html.Script:3:*:3c736372\n\
+Alt.Sig:0:*:41(42|43)44\n";
+        let bodies = parse_database("main.ndb", ndb).unwrap();
+        assert_eq!(bodies.bodies.len(), 2);
+        let reasons: Vec<_> = bodies.unsupported.iter().map(|u| (u.line, u.name.as_str())).collect();
+        assert_eq!(reasons, [(3, "Pe.Entry"), (4, "Html.Script"), (5, "Alt.Sig")]);
+        for body in bodies.bodies {
+            signatures.insert(body);
+        }
+
+        let ldb = "\
+Multi.Count;Engine:51-255,Target:0;0&(1|2)>1,2;6162;6364;6566\n\
+Wide.Sized;Target:0,FileSize:1-64;0;7777::w\n\
+Pcre.Sig;Target:0;0&1;6162;0/abc/i\n\
+Icon.Sig;Target:1,IconGroup1:x;0;6162\n";
+        let logical = parse_database("main.ldb", ldb).unwrap();
+        assert_eq!(logical.logical.len(), 2);
+        assert_eq!(logical.unsupported.len(), 2);
+        for signature in logical.logical {
+            signatures.insert_logical(signature);
+        }
+        assert_eq!(signatures.len(), 6);
+
+        let matcher = signatures.matcher();
+        let names = |data: &[u8]| matcher.scan(data).into_iter().map(|m| (m.name, m.offset)).collect::<Vec<_>>();
+        // Tail signature only within an ELF file, at four bytes from the end
+        assert_eq!(names(b"\x7fELF....\xde\xad\xbe\xef"), [("Elf.Tail".to_string(), 8)]);
+        assert!(names(b"MZ......\xde\xad\xbe\xef").is_empty());
+        // Start offset 2 with a shift of up to 2
+        assert_eq!(names(b"xxxABzEx"), [("Any.Start".to_string(), 3)]);
+        assert!(names(b"xxxxxxABzE").is_empty());
+        // `ab` and two matches spread over both of `cd`/`ef`
+        assert_eq!(names(b"ab cd ef"), [("Multi.Count".to_string(), 0)]);
+        assert!(names(b"ab cd cd").is_empty());
+        assert_eq!(names(b"w\0w\0"), [("Wide.Sized".to_string(), 0)]);
+        assert!(names(&[b"w\0w\0".as_slice(), &[0; 64]].concat()).is_empty());
+
+        assert!(parse_database("main.cvd", "").is_none());
+    }
+}
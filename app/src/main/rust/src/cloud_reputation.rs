@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use serde::Deserialize;
+
+use crate::hash_reputation::{DigestKind, Reputation, Verdict};
+use crate::http;
+
+// Responses larger than this are not a verdict list
+const MAX_RESPONSE_LEN: usize = 1 << 20;
+// A provider that failed is skipped for this long (or for its Retry-After)
+const FAILURE_BACKOFF_MS: u64 = 60_000;
+
+/// Why a provider gave no answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderError {
+    Timeout,
+    Unreachable,
+    /// The service asked us to slow down; retry after this many milliseconds
+    RateLimited(u64),
+    Http(u16),
+    BadResponse,
+}
+
+/// A remote hash reputation service
+pub trait ReputationProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Most hashes one request may carry
+    fn max_batch(&self) -> usize;
+
+    /// Verdicts for a batch of lowercase hex SHA-256 digests. Hashes the service does not know
+    /// are left out of the result; the whole call gives up after `timeout`.
+    fn lookup_batch(&self, hashes: &[String], timeout: Duration) -> Result<HashMap<String, Reputation>, ProviderError>;
+}
+
+/// Limits and cache lifetimes for cloud lookups
+#[derive(Debug, Clone, Copy)]
+pub struct CloudConfig {
+    pub timeout: Duration,
+    /// Per provider; lookups past the limit are answered locally
+    pub requests_per_minute: u32,
+    pub cache_ttl_ms: u64,
+    /// Unknown hashes are cached for less time, as new samples get verdicts quickly
+    pub negative_ttl_ms: u64,
+    pub max_cache_entries: usize,
+}
+
+impl Default for CloudConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(3),
+            requests_per_minute: 60,
+            cache_ttl_ms: 24 * 3_600_000,
+            negative_ttl_ms: 3_600_000,
+            max_cache_entries: 50_000,
+        }
+    }
+}
+
+struct CacheEntry {
+    reputation: Option<Reputation>,
+    expires_ms: u64,
+}
+
+// Token bucket and failure backoff of one provider
+struct ProviderState {
+    tokens: f64,
+    refilled_ms: u64,
+    backoff_until_ms: u64,
+}
+
+struct CloudState {
+    cache: AHashMap<String, CacheEntry>,
+    providers: Vec<ProviderState>,
+}
+
+/// Cloud hash lookups through a list of providers, tried in order, with a shared cache.
+/// Network calls are made without holding the cache lock; when every provider is failing,
+/// rate limited or backed off, lookups simply come back unknown and the scanner goes by its
+/// local verdicts.
+pub struct CloudReputation {
+    pub config: CloudConfig,
+    providers: Vec<Box<dyn ReputationProvider>>,
+    state: Mutex<CloudState>,
+}
+
+impl CloudReputation {
+    pub fn new(config: CloudConfig, providers: Vec<Box<dyn ReputationProvider>>) -> Self {
+        let burst = config.requests_per_minute as f64;
+        let states = providers.iter().map(|_| ProviderState { tokens: burst, refilled_ms: 0, backoff_until_ms: 0 }).collect();
+        Self { config, providers, state: Mutex::new(CloudState { cache: AHashMap::new(), providers: states }) }
+    }
+
+    /// Reputation per SHA-256 (hex); None where no provider knows the hash or none answered
+    pub fn lookup_many(&self, hashes: &[String], now_ms: u64) -> HashMap<String, Option<Reputation>> {
+        let mut results = HashMap::new();
+        let mut missing: Vec<String> = Vec::new();
+        {
+            let state = self.state.lock().unwrap();
+            for hash in hashes {
+                let hash = hash.to_ascii_lowercase();
+                match state.cache.get(&hash).filter(|entry| entry.expires_ms > now_ms) {
+                    Some(entry) => {
+                        results.insert(hash, entry.reputation.clone());
+                    }
+                    None if !missing.contains(&hash) => missing.push(hash),
+                    None => {}
+                }
+            }
+        }
+
+        for (index, provider) in self.providers.iter().enumerate() {
+            if missing.is_empty() {
+                break;
+            }
+            let mut unanswered = Vec::new();
+            for batch in missing.chunks(provider.max_batch().max(1)) {
+                if !self.take_token(index, now_ms) {
+                    unanswered.extend_from_slice(batch);
+                    continue;
+                }
+                match provider.lookup_batch(batch, self.config.timeout) {
+                    Ok(mut found) => {
+                        let mut state = self.state.lock().unwrap();
+                        for hash in batch {
+                            let reputation = found.remove(hash);
+                            self.insert_cached(&mut state, hash.clone(), reputation.clone(), now_ms);
+                            results.insert(hash.clone(), reputation);
+                        }
+                    }
+                    Err(error) => {
+                        let backoff = match error {
+                            ProviderError::RateLimited(retry_ms) => retry_ms.max(1_000),
+                            _ => FAILURE_BACKOFF_MS,
+                        };
+                        self.state.lock().unwrap().providers[index].backoff_until_ms = now_ms + backoff;
+                        unanswered.extend_from_slice(batch);
+                    }
+                }
+            }
+            missing = unanswered;
+        }
+        for hash in missing {
+            results.insert(hash, None);
+        }
+        results
+    }
+
+    pub fn lookup(&self, sha256: &str, now_ms: u64) -> Option<Reputation> {
+        self.lookup_many(&[sha256.to_string()], now_ms).into_values().next().flatten()
+    }
+
+    // Spend one request from the provider's bucket, unless it is out or backing off
+    fn take_token(&self, index: usize, now_ms: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let limit = self.config.requests_per_minute as f64;
+        let provider = &mut state.providers[index];
+        if now_ms < provider.backoff_until_ms {
+            return false;
+        }
+        let elapsed = now_ms.saturating_sub(provider.refilled_ms) as f64;
+        provider.tokens = (provider.tokens + elapsed * limit / 60_000.0).min(limit);
+        provider.refilled_ms = now_ms;
+        if provider.tokens < 1.0 {
+            return false;
+        }
+        provider.tokens -= 1.0;
+        true
+    }
+
+    fn insert_cached(&self, state: &mut CloudState, hash: String, reputation: Option<Reputation>, now_ms: u64) {
+        let ttl = if reputation.is_some() { self.config.cache_ttl_ms } else { self.config.negative_ttl_ms };
+        if state.cache.len() >= self.config.max_cache_entries {
+            state.cache.retain(|_, entry| entry.expires_ms > now_ms);
+            // Still full of live entries: drop the quarter closest to expiry
+            if state.cache.len() >= self.config.max_cache_entries {
+                let mut expiries: Vec<u64> = state.cache.values().map(|entry| entry.expires_ms).collect();
+                let cutoff = *expiries.select_nth_unstable(state.cache.len() / 4).1;
+                state.cache.retain(|_, entry| entry.expires_ms > cutoff);
+            }
+        }
+        state.cache.insert(hash, CacheEntry { reputation, expires_ms: now_ms + ttl });
+    }
+
+    pub fn cached(&self) -> usize {
+        self.state.lock().unwrap().cache.len()
+    }
+}
+
+/// Talks to an HTTP endpoint: POST `{"hashes": [...]}` and receive
+/// `{"results": [{"hash", "verdict", "family", "source"}]}`, verdict being clean, suspicious,
+/// malicious or unknown. Only plain `http://` endpoints are supported, for a local relay or
+/// gateway; there is no TLS client in this library, so an API key is only sent to loopback.
+pub struct HttpJsonProvider {
+    name: String,
+    host: String,
+    port: u16,
+    path: String,
+    api_key: Option<String>,
+    batch_size: usize,
+}
+
+#[derive(Deserialize)]
+struct LookupResponse {
+    results: Vec<LookupResult>,
+}
+
+#[derive(Deserialize)]
+struct LookupResult {
+    hash: String,
+    verdict: String,
+    #[serde(default)]
+    family: String,
+    #[serde(default)]
+    source: String,
+}
+
+impl HttpJsonProvider {
+    /// None if `endpoint` is not an `http://host[:port][/path]` URL, or if an API key would
+    /// leave the device in cleartext
+    pub fn new(endpoint: &str, api_key: Option<String>, batch_size: usize) -> Option<Self> {
+        let rest = endpoint.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            // [IPv6]:port
+            Some(bracketed) => {
+                let (host, port) = bracketed.split_once(']')?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = port.map_or(Some(80), |port| port.parse().ok())?;
+        if host.is_empty() || (api_key.is_some() && !is_loopback(host)) {
+            return None;
+        }
+        Some(Self {
+            name: authority.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            api_key,
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    fn post(&self, body: &[u8], deadline: Instant) -> Result<(http::HttpResponse, Vec<u8>), ProviderError> {
+        let remaining = || deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero());
+        let addresses = resolve(&self.host, self.port, remaining().ok_or(ProviderError::Timeout)?)?;
+        let mut stream = None;
+        for address in addresses {
+            let timeout = remaining().ok_or(ProviderError::Timeout)?;
+            if let Ok(connected) = TcpStream::connect_timeout(&address, timeout) {
+                stream = Some(connected);
+                break;
+            }
+        }
+        let mut stream = stream.ok_or(ProviderError::Unreachable)?;
+        let io_error = |e: io::Error| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProviderError::Timeout,
+            _ => ProviderError::Unreachable,
+        };
+
+        // HTTP/1.0 so the reply is never chunked and ends when the server closes
+        let mut request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nAccept: application/json\r\nContent-Length: {}\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        if let Some(key) = &self.api_key {
+            request.push_str(&format!("Authorization: Bearer {key}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.set_write_timeout(remaining()).map_err(io_error)?;
+        stream.write_all(request.as_bytes()).and_then(|_| stream.write_all(body)).map_err(io_error)?;
+
+        let mut response = Vec::new();
+        let mut buf = [0u8; 8192];
+        loop {
+            stream.set_read_timeout(Some(remaining().ok_or(ProviderError::Timeout)?)).map_err(io_error)?;
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) if response.len() + n <= MAX_RESPONSE_LEN => response.extend_from_slice(&buf[..n]),
+                Ok(_) => return Err(ProviderError::BadResponse),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        let head = http::parse_response(&response).ok_or(ProviderError::BadResponse)?;
+        let body_start = response.windows(4).position(|w| w == b"\r\n\r\n").ok_or(ProviderError::BadResponse)? + 4;
+        Ok((head, response.split_off(body_start)))
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+// The system resolver has no timeout of its own, so it runs on a thread the caller stops
+// waiting for at the deadline
+fn resolve(host: &str, port: u16, timeout: Duration) -> Result<Vec<SocketAddr>, ProviderError> {
+    let (tx, rx) = mpsc::channel();
+    let host = host.to_string();
+    std::thread::spawn(move || {
+        let _ = tx.send((host.as_str(), port).to_socket_addrs().map(Vec::from_iter));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(addresses)) => Ok(addresses),
+        Ok(Err(_)) | Err(mpsc::RecvTimeoutError::Disconnected) => Err(ProviderError::Unreachable),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(ProviderError::Timeout),
+    }
+}
+
+impl ReputationProvider for HttpJsonProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn max_batch(&self) -> usize {
+        self.batch_size
+    }
+
+    fn lookup_batch(&self, hashes: &[String], timeout: Duration) -> Result<HashMap<String, Reputation>, ProviderError> {
+        let body = serde_json::json!({ "hashes": hashes }).to_string();
+        let (head, body) = self.post(body.as_bytes(), Instant::now() + timeout)?;
+        match head.status_code {
+            200..=299 => {}
+            429 | 503 => {
+                let retry_ms = head.header("retry-after").and_then(|s| s.trim().parse::<u64>().ok()).map_or(FAILURE_BACKOFF_MS, |s| s * 1000);
+                return Err(ProviderError::RateLimited(retry_ms));
+            }
+            status => return Err(ProviderError::Http(status)),
+        }
+        let response: LookupResponse = serde_json::from_slice(&body).map_err(|_| ProviderError::BadResponse)?;
+        Ok(response
+            .results
+            .into_iter()
+            .filter_map(|result| {
+                let verdict = match result.verdict.as_str() {
+                    "clean" => Verdict::Clean,
+                    "suspicious" => Verdict::Suspicious,
+                    "malicious" => Verdict::Malicious,
+                    _ => return None,
+                };
+                let source = if result.source.is_empty() { self.name.clone() } else { result.source };
+                let reputation = Reputation { kind: DigestKind::Sha256, verdict, family: result.family, source };
+                Some((result.hash.to_ascii_lowercase(), reputation))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    // Answers each connection with the next canned response, reporting the request bodies
+    fn mock_server(responses: Vec<String>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/lookup", listener.local_addr().unwrap());
+        let (requests, received) = mpsc::channel();
+        thread::spawn(move || {
+            let mut held = Vec::new();
+            for response in responses {
+                let Ok((mut stream, _)) = listener.accept() else { return };
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = stream.read(&mut buf) {
+                    request.extend_from_slice(&buf[..n]);
+                    let end = request.windows(4).position(|w| w == b"\r\n\r\n");
+                    let len = http::parse_request(&request).map_or(0, |head| head.body_len() as usize);
+                    if n == 0 || end.is_some_and(|end| request.len() >= end + 4 + len) {
+                        break;
+                    }
+                }
+                let text = String::from_utf8_lossy(&request).into_owned();
+                let _ = requests.send(text.split("\r\n\r\n").nth(1).unwrap_or_default().to_string());
+                if response.is_empty() {
+                    // Hold the connection open without answering
+                    held.push(stream);
+                } else {
+                    let _ = stream.write_all(response.as_bytes());
+                }
+            }
+        });
+        (endpoint, received)
+    }
+
+    fn ok(body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    #[test]
+    fn test_batches_caches_and_rate_limits_lookups() {
+        let (a, b, c) = ("a".repeat(64), "b".repeat(64), "c".repeat(64));
+        let (endpoint, requests) = mock_server(vec![
+            ok(&format!(r#"{{"results":[{{"hash":"{a}","verdict":"malicious","family":"Trojan.Agent"}},{{"hash":"{b}","verdict":"unknown"}}]}}"#)),
+            ok(&format!(r#"{{"results":[{{"hash":"{c}","verdict":"clean","source":"allowlist"}}]}}"#)),
+        ]);
+        let provider = HttpJsonProvider::new(&endpoint, Some("secret".to_string()), 2).unwrap();
+        let config = CloudConfig { requests_per_minute: 2, ..Default::default() };
+        let cloud = CloudReputation::new(config, vec![Box::new(provider)]);
+
+        let results = cloud.lookup_many(&[a.clone(), b.clone(), c.to_uppercase()], 1_000);
+        assert_eq!(results[&a].as_ref().unwrap().family, "Trojan.Agent");
+        assert_eq!(results[&b], None);
+        let clean = results[&c].as_ref().unwrap();
+        assert_eq!((clean.verdict, clean.source.as_str()), (Verdict::Clean, "allowlist"));
+        assert_eq!(requests.recv().unwrap(), format!(r#"{{"hashes":["{a}","{b}"]}}"#));
+        assert_eq!(requests.recv().unwrap(), format!(r#"{{"hashes":["{c}"]}}"#));
+
+        // Answered from the cache, the unknown hash included
+        assert_eq!(cloud.lookup(&a, 2_000).unwrap().verdict, Verdict::Malicious);
+        assert_eq!(cloud.lookup(&b, 2_000), None);
+        assert_eq!(cloud.cached(), 3);
+        // Both requests of the minute are spent: a new hash comes back unknown without a request
+        assert_eq!(cloud.lookup(&"e".repeat(64), 2_000), None);
+        assert_eq!(cloud.cached(), 3);
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_degrades_when_providers_fail() {
+        let hashes = ["d".repeat(64)];
+        let (endpoint, _requests) = mock_server(vec![String::new(), "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 30\r\n\r\n".to_string()]);
+        let config = CloudConfig { timeout: Duration::from_millis(200), ..Default::default() };
+
+        // Accepts but never answers: gives up at the timeout and backs off
+        let stalled = HttpJsonProvider::new(&endpoint, None, 10).unwrap();
+        assert_eq!(stalled.lookup_batch(&hashes, config.timeout), Err(ProviderError::Timeout));
+        assert_eq!(stalled.lookup_batch(&hashes, config.timeout), Err(ProviderError::RateLimited(30_000)));
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let unreachable = HttpJsonProvider::new(&format!("http://{closed}"), None, 10).unwrap();
+        let cloud = CloudReputation::new(config, vec![Box::new(unreachable)]);
+        let started = Instant::now();
+        assert_eq!(cloud.lookup(&hashes[0], 0), None);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // Failures are not cached, and the provider is skipped while backed off
+        assert_eq!(cloud.cached(), 0);
+        assert!(!cloud.take_token(0, 1_000));
+        assert!(cloud.take_token(0, FAILURE_BACKOFF_MS));
+
+        assert!(HttpJsonProvider::new("https://example.com/lookup", None, 10).is_none());
+        // Name resolution counts against the deadline too
+        let named = HttpJsonProvider::new("http://reputation.invalid/lookup", None, 10).unwrap();
+        assert_eq!(named.lookup_batch(&hashes, Duration::ZERO), Err(ProviderError::Timeout));
+    }
+
+    #[test]
+    fn test_api_key_is_only_sent_to_loopback() {
+        let key = || Some("secret".to_string());
+        assert!(HttpJsonProvider::new("http://reputation.example/lookup", key(), 10).is_none());
+        assert!(HttpJsonProvider::new("http://192.0.2.10:8080/lookup", key(), 10).is_none());
+        assert!(HttpJsonProvider::new("http://reputation.example/lookup", None, 10).is_some());
+        for endpoint in ["http://localhost:8080/lookup", "http://127.0.0.1/lookup", "http://[::1]:8080/lookup"] {
+            assert!(HttpJsonProvider::new(endpoint, key(), 10).is_some(), "{endpoint}");
+        }
+    }
+}
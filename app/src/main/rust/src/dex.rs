@@ -0,0 +1,478 @@
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+
+const HEADER_SIZE: usize = 0x70;
+const ENDIAN_CONSTANT: u32 = 0x1234_5678;
+// Versions ART has shipped: 035 through 041
+const MIN_VERSION: u16 = 35;
+const MAX_VERSION: u16 = 41;
+
+// Classes that come from the platform rather than the app, in dotted form
+const FRAMEWORK_PREFIXES: [&str; 11] = [
+    "android.",
+    "com.android.",
+    "dalvik.",
+    "java.",
+    "javax.",
+    "libcore.",
+    "org.apache.http.",
+    "org.json.",
+    "org.w3c.dom.",
+    "org.xml.sax.",
+    "sun.misc.",
+];
+
+// Most an API profile alone can count for, short of a signature match
+const MAX_API_CONFIDENCE: f32 = 0.9;
+const CHECKSUM_MISMATCH_CONFIDENCE: f32 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DexError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    BadEndianTag,
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiCategory {
+    DynamicCodeLoading,
+    CommandExecution,
+    SmsSending,
+    DeviceAdmin,
+    Accessibility,
+    Overlay,
+    Reflection,
+    DeviceIdentifiers,
+    NativeCode,
+    Crypto,
+}
+
+impl ApiCategory {
+    // How much one category adds to a file's static score; categories are listed heaviest first
+    fn weight(self) -> f32 {
+        match self {
+            ApiCategory::DynamicCodeLoading => 0.35,
+            ApiCategory::CommandExecution | ApiCategory::SmsSending => 0.3,
+            ApiCategory::DeviceAdmin | ApiCategory::Accessibility => 0.25,
+            ApiCategory::Overlay => 0.2,
+            ApiCategory::Reflection | ApiCategory::DeviceIdentifiers => 0.15,
+            ApiCategory::NativeCode | ApiCategory::Crypto => 0.1,
+        }
+    }
+
+    fn finding(self) -> &'static str {
+        match self {
+            ApiCategory::DynamicCodeLoading => "DEX_DYNAMIC_CODE_LOADING",
+            ApiCategory::CommandExecution => "DEX_COMMAND_EXECUTION",
+            ApiCategory::SmsSending => "DEX_SMS_SENDING",
+            ApiCategory::DeviceAdmin => "DEX_DEVICE_ADMIN",
+            ApiCategory::Accessibility => "DEX_ACCESSIBILITY_ABUSE",
+            ApiCategory::Overlay => "DEX_OVERLAY",
+            ApiCategory::Reflection => "DEX_REFLECTION",
+            ApiCategory::DeviceIdentifiers => "DEX_DEVICE_IDENTIFIERS",
+            ApiCategory::NativeCode => "DEX_NATIVE_CODE",
+            ApiCategory::Crypto => "DEX_CRYPTO",
+        }
+    }
+}
+
+// Framework methods worth scoring, by declaring class as the method reference names it
+const SUSPICIOUS_APIS: [(&str, &[&str], ApiCategory); 17] = [
+    ("dalvik.system.DexClassLoader", &["<init>"], ApiCategory::DynamicCodeLoading),
+    ("dalvik.system.InMemoryDexClassLoader", &["<init>"], ApiCategory::DynamicCodeLoading),
+    ("dalvik.system.PathClassLoader", &["<init>"], ApiCategory::DynamicCodeLoading),
+    ("dalvik.system.DexFile", &["<init>", "loadDex", "loadClass"], ApiCategory::DynamicCodeLoading),
+    ("java.lang.Runtime", &["exec"], ApiCategory::CommandExecution),
+    ("java.lang.ProcessBuilder", &["start"], ApiCategory::CommandExecution),
+    ("android.telephony.SmsManager", &["sendTextMessage", "sendMultipartTextMessage", "sendDataMessage"], ApiCategory::SmsSending),
+    ("android.telephony.gsm.SmsManager", &["sendTextMessage", "sendMultipartTextMessage", "sendDataMessage"], ApiCategory::SmsSending),
+    ("android.app.admin.DevicePolicyManager", &["lockNow", "resetPassword", "wipeData"], ApiCategory::DeviceAdmin),
+    ("android.accessibilityservice.AccessibilityService", &["performGlobalAction", "dispatchGesture"], ApiCategory::Accessibility),
+    ("android.view.accessibility.AccessibilityNodeInfo", &["performAction"], ApiCategory::Accessibility),
+    ("android.view.WindowManager", &["addView"], ApiCategory::Overlay),
+    ("java.lang.Class", &["forName", "getDeclaredMethod", "getDeclaredField"], ApiCategory::Reflection),
+    ("java.lang.reflect.Method", &["invoke"], ApiCategory::Reflection),
+    ("android.telephony.TelephonyManager", &["getDeviceId", "getImei", "getSubscriberId", "getLine1Number", "getSimSerialNumber"], ApiCategory::DeviceIdentifiers),
+    ("java.lang.System", &["load", "loadLibrary"], ApiCategory::NativeCode),
+    ("javax.crypto.Cipher", &["getInstance", "doFinal"], ApiCategory::Crypto),
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProtoId {
+    pub shorty_idx: u32,
+    pub return_type_idx: u32,
+    pub parameters_off: u32,
+}
+
+// Field and method references share a layout: declaring class, type or prototype, name
+#[derive(Debug, Clone, Copy)]
+pub struct MemberId {
+    pub class_idx: u16,
+    pub type_idx: u16,
+    pub name_idx: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClassDef {
+    pub class_idx: u32,
+    pub access_flags: u32,
+    pub superclass_idx: u32,
+    pub interfaces_off: u32,
+    pub source_file_idx: u32,
+    pub annotations_off: u32,
+    pub class_data_off: u32,
+    pub static_values_off: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dex {
+    pub version: u16,
+    pub file_size: u32,
+    pub checksum: u32,
+    pub signature: [u8; 20],
+    // Whether the stored Adler-32 and SHA-1 match the file; a mismatch means it was modified
+    // after being built
+    pub checksum_valid: bool,
+    pub signature_valid: bool,
+    pub strings: Vec<String>,
+    // String index of each type descriptor
+    pub types: Vec<u32>,
+    pub protos: Vec<ProtoId>,
+    pub fields: Vec<MemberId>,
+    pub methods: Vec<MemberId>,
+    pub classes: Vec<ClassDef>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset.checked_add(2)?)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset.checked_add(4)?)?.try_into().ok()?))
+}
+
+fn uleb128(data: &[u8], offset: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// Modified UTF-8: NUL is encoded as C0 80 and supplementary characters as surrogate pairs,
+// so it decodes to UTF-16 code units first
+fn decode_mutf8(data: &[u8], mut offset: usize) -> Option<String> {
+    let mut units = Vec::new();
+    loop {
+        let a = *data.get(offset)? as u16;
+        let unit = match a {
+            0 => break,
+            0x01..=0x7f => {
+                offset += 1;
+                a
+            }
+            0xc0..=0xdf => {
+                let b = *data.get(offset + 1)? as u16;
+                offset += 2;
+                (a & 0x1f) << 6 | (b & 0x3f)
+            }
+            0xe0..=0xef => {
+                let (b, c) = (*data.get(offset + 1)? as u16, *data.get(offset + 2)? as u16);
+                offset += 3;
+                (a & 0x0f) << 12 | (b & 0x3f) << 6 | (c & 0x3f)
+            }
+            _ => {
+                offset += 1;
+                0xfffd
+            }
+        };
+        units.push(unit);
+    }
+    Some(String::from_utf16_lossy(&units))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65_521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before the u32 accumulators could overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+// The fixed-size entries of one ID table, all of which must lie within the file
+fn table(data: &[u8], size_at: usize, item_size: usize) -> Result<impl Iterator<Item = usize> + '_, DexError> {
+    let count = u32_at(data, size_at).ok_or(DexError::Truncated)? as usize;
+    let offset = u32_at(data, size_at + 4).ok_or(DexError::Truncated)? as usize;
+    let end = count.checked_mul(item_size).and_then(|len| len.checked_add(offset)).ok_or(DexError::Truncated)?;
+    if count > 0 && end > data.len() {
+        return Err(DexError::Truncated);
+    }
+    Ok((0..count).map(move |i| offset + i * item_size))
+}
+
+pub fn is_dex(data: &[u8]) -> bool {
+    data.starts_with(b"dex\n") && data.get(7) == Some(&0)
+}
+
+pub fn parse(data: &[u8]) -> Result<Dex, DexError> {
+    if !is_dex(data) {
+        return Err(DexError::BadMagic);
+    }
+    if data.len() < HEADER_SIZE {
+        return Err(DexError::Truncated);
+    }
+    let version = std::str::from_utf8(&data[4..7]).ok().and_then(|v| v.parse::<u16>().ok()).unwrap_or(0);
+    if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+        return Err(DexError::UnsupportedVersion(version));
+    }
+    let header = |offset| u32_at(data, offset).ok_or(DexError::Truncated);
+    if header(40)? != ENDIAN_CONSTANT {
+        return Err(DexError::BadEndianTag);
+    }
+    let checksum = header(8)?;
+    let signature: [u8; 20] = data[12..32].try_into().unwrap();
+    let file_size = header(32)?;
+
+    // Both digests cover the declared file size, which a truncated file cannot match
+    let (checksum_valid, signature_valid) = match data.get(..file_size as usize) {
+        Some(file) if file.len() >= HEADER_SIZE => {
+            (adler32(&file[12..]) == checksum, Sha1::digest(&file[32..])[..] == signature)
+        }
+        _ => (false, false),
+    };
+
+    let strings = table(data, 56, 4)?
+        .map(|at| {
+            let mut offset = u32_at(data, at)? as usize;
+            uleb128(data, &mut offset)?;
+            decode_mutf8(data, offset)
+        })
+        .collect::<Option<Vec<String>>>()
+        .ok_or(DexError::Truncated)?;
+    let types = table(data, 64, 4)?.map(|at| u32_at(data, at).unwrap()).collect();
+    let protos = table(data, 72, 12)?
+        .map(|at| ProtoId {
+            shorty_idx: u32_at(data, at).unwrap(),
+            return_type_idx: u32_at(data, at + 4).unwrap(),
+            parameters_off: u32_at(data, at + 8).unwrap(),
+        })
+        .collect();
+    let member = |at| MemberId {
+        class_idx: u16_at(data, at).unwrap(),
+        type_idx: u16_at(data, at + 2).unwrap(),
+        name_idx: u32_at(data, at + 4).unwrap(),
+    };
+    let fields = table(data, 80, 8)?.map(member).collect();
+    let methods = table(data, 88, 8)?.map(member).collect();
+    let classes = table(data, 96, 32)?
+        .map(|at| {
+            let word = |i: usize| u32_at(data, at + i * 4).unwrap();
+            ClassDef {
+                class_idx: word(0),
+                access_flags: word(1),
+                superclass_idx: word(2),
+                interfaces_off: word(3),
+                source_file_idx: word(4),
+                annotations_off: word(5),
+                class_data_off: word(6),
+                static_values_off: word(7),
+            }
+        })
+        .collect();
+
+    Ok(Dex {
+        version,
+        file_size,
+        checksum,
+        signature,
+        checksum_valid,
+        signature_valid,
+        strings,
+        types,
+        protos,
+        fields,
+        methods,
+        classes,
+    })
+}
+
+// "Ljava/lang/Runtime;" to "java.lang.Runtime"; arrays and primitives have no class name
+fn class_name(descriptor: &str) -> Option<String> {
+    let name = descriptor.strip_prefix('L')?.strip_suffix(';')?;
+    Some(name.replace('/', "."))
+}
+
+impl Dex {
+    pub fn string(&self, idx: u32) -> Option<&str> {
+        self.strings.get(idx as usize).map(String::as_str)
+    }
+
+    pub fn type_descriptor(&self, idx: u32) -> Option<&str> {
+        self.string(*self.types.get(idx as usize)?)
+    }
+
+    // Classes this file defines, in dotted form
+    pub fn class_names(&self) -> Vec<String> {
+        self.classes.iter().filter_map(|class| class_name(self.type_descriptor(class.class_idx)?)).collect()
+    }
+
+    // Every platform method referenced from this file as "package.Class.method", sorted.
+    // Classes the file defines itself are left out even under a framework package name.
+    pub fn framework_methods(&self) -> Vec<String> {
+        let defined: std::collections::HashSet<u32> = self.classes.iter().map(|class| class.class_idx).collect();
+        let mut methods: Vec<String> = self
+            .methods
+            .iter()
+            .filter(|method| !defined.contains(&(method.class_idx as u32)))
+            .filter_map(|method| {
+                let class = class_name(self.type_descriptor(method.class_idx as u32)?)?;
+                if !FRAMEWORK_PREFIXES.iter().any(|prefix| class.starts_with(prefix)) {
+                    return None;
+                }
+                Some(format!("{}.{}", class, self.string(method.name_idx)?))
+            })
+            .collect();
+        methods.sort_unstable();
+        methods.dedup();
+        methods
+    }
+
+    // Referenced framework methods that fall into a scored category
+    pub fn suspicious_apis(&self) -> Vec<(String, ApiCategory)> {
+        self.framework_methods()
+            .into_iter()
+            .filter_map(|api| {
+                let (class, method) = api.rsplit_once('.')?;
+                let (_, _, category) = SUSPICIOUS_APIS
+                    .iter()
+                    .find(|(name, methods, _)| *name == class && methods.contains(&method))?;
+                Some((api, *category))
+            })
+            .collect()
+    }
+
+    // Heuristic findings for the scanner, as (name, confidence). The API categories add up to
+    // one finding named after the heaviest of them.
+    pub fn heuristics(&self) -> Vec<(String, f32)> {
+        let mut findings = Vec::new();
+        let mut categories: Vec<ApiCategory> = self.suspicious_apis().into_iter().map(|(_, category)| category).collect();
+        categories.sort_unstable();
+        categories.dedup();
+        if let Some(heaviest) = categories.first() {
+            let score: f32 = categories.iter().map(|category| category.weight()).sum();
+            findings.push((heaviest.finding().to_string(), score.min(MAX_API_CONFIDENCE)));
+        }
+        if !self.checksum_valid || !self.signature_valid {
+            findings.push(("DEX_CHECKSUM_MISMATCH".to_string(), CHECKSUM_MISMATCH_CONFIDENCE));
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal DEX file defining one class that references three framework methods
+    fn sample_dex() -> Vec<u8> {
+        let strings = [
+            "<init>",
+            "Landroid/telephony/SmsManager;",
+            "Lcom/example/Payload;",
+            "Ldalvik/system/DexClassLoader;",
+            "Ljava/lang/Object;",
+            "Ljava/lang/Runtime;",
+            "V",
+            "exec",
+            "run",
+            "sendTextMessage",
+        ];
+        // Type i is string i + 1 for the six descriptors
+        let types = [1u32, 2, 3, 4, 5, 6];
+        // (class type, name string): DexClassLoader.<init>, Runtime.exec, SmsManager.sendTextMessage, Payload.run
+        let methods = [(2u16, 0u32), (4, 7), (0, 9), (1, 8)];
+
+        let string_ids = HEADER_SIZE;
+        let type_ids = string_ids + 4 * strings.len();
+        let proto_ids = type_ids + 4 * types.len();
+        let method_ids = proto_ids + 12;
+        let class_defs = method_ids + 8 * methods.len();
+        let string_data = class_defs + 32;
+
+        let mut dex = vec![0u8; string_data];
+        let put = |dex: &mut Vec<u8>, at: usize, value: u32| dex[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        dex[..8].copy_from_slice(b"dex\n039\0");
+        put(&mut dex, 36, HEADER_SIZE as u32);
+        put(&mut dex, 40, ENDIAN_CONSTANT);
+        for (size_at, count, offset) in [(56, strings.len(), string_ids), (64, types.len(), type_ids), (72, 1, proto_ids), (88, methods.len(), method_ids), (96, 1, class_defs)] {
+            put(&mut dex, size_at, count as u32);
+            put(&mut dex, size_at + 4, offset as u32);
+        }
+        for (i, string) in strings.iter().enumerate() {
+            let offset = dex.len() as u32;
+            put(&mut dex, string_ids + 4 * i, offset);
+            dex.push(string.len() as u8);
+            dex.extend_from_slice(string.as_bytes());
+            dex.push(0);
+        }
+        for (i, &descriptor) in types.iter().enumerate() {
+            put(&mut dex, type_ids + 4 * i, descriptor);
+        }
+        put(&mut dex, proto_ids, 6);
+        put(&mut dex, proto_ids + 4, 5);
+        for (i, &(class, name)) in methods.iter().enumerate() {
+            dex[method_ids + 8 * i..method_ids + 8 * i + 2].copy_from_slice(&class.to_le_bytes());
+            put(&mut dex, method_ids + 8 * i + 4, name);
+        }
+        put(&mut dex, class_defs, 1);
+        put(&mut dex, class_defs + 8, 3);
+
+        let size = dex.len() as u32;
+        put(&mut dex, 32, size);
+        let signature = Sha1::digest(&dex[32..]);
+        dex[12..32].copy_from_slice(&signature);
+        let checksum = adler32(&dex[12..]);
+        put(&mut dex, 8, checksum);
+        dex
+    }
+
+    #[test]
+    fn test_parses_dex_and_scores_framework_apis() {
+        let mut data = sample_dex();
+        let dex = parse(&data).unwrap();
+        assert_eq!(dex.version, 39);
+        assert!(dex.checksum_valid && dex.signature_valid);
+        assert_eq!(dex.class_names(), ["com.example.Payload"]);
+        assert_eq!(
+            dex.framework_methods(),
+            ["android.telephony.SmsManager.sendTextMessage", "dalvik.system.DexClassLoader.<init>", "java.lang.Runtime.exec"]
+        );
+        assert_eq!(dex.heuristics(), [("DEX_DYNAMIC_CODE_LOADING".to_string(), MAX_API_CONFIDENCE)]);
+
+        // Any modification after the build shows in both digests
+        let last = data.len() - 2;
+        data[last] ^= 0x20;
+        let dex = parse(&data).unwrap();
+        assert!(!dex.checksum_valid && !dex.signature_valid);
+        assert_eq!(dex.heuristics()[1].0, "DEX_CHECKSUM_MISMATCH");
+
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(parse(&data[..0x60]).unwrap_err(), DexError::Truncated);
+        data[6] = b'4';
+        assert_eq!(parse(&data).unwrap_err(), DexError::UnsupportedVersion(34));
+    }
+}
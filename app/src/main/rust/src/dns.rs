@@ -0,0 +1,267 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Parsing limits for hostile messages
+const MAX_RECORDS: usize = 64;
+const MAX_POINTER_JUMPS: usize = 16;
+const MAX_NAME_LEN: usize = 255;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+
+#[derive(Debug, Clone)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsRecord {
+    pub name: String,
+    pub rtype: u16,
+    pub ttl: u32,
+    /// Presentation form of the record data (address, target name, text...)
+    pub data: String,
+}
+
+/// A decoded DNS query or response
+#[derive(Debug, Clone)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub response: bool,
+    pub opcode: u8,
+    pub authoritative: bool,
+    pub truncated: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub rcode: u8,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+    /// Name of the first question, which is all real resolvers ever send
+    pub fn query_name(&self) -> Option<&str> {
+        self.questions.first().map(|q| q.name.as_str())
+    }
+}
+
+/// Parse a DNS message as carried over UDP (no TCP length prefix)
+pub fn parse_message(data: &[u8]) -> Option<DnsMessage> {
+    if data.len() < 12 {
+        return None;
+    }
+    let id = u16::from_be_bytes([data[0], data[1]]);
+    let flags = u16::from_be_bytes([data[2], data[3]]);
+    let qdcount = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let ancount = u16::from_be_bytes([data[6], data[7]]) as usize;
+
+    let mut offset = 12;
+    let mut questions = Vec::new();
+    for _ in 0..qdcount.min(MAX_RECORDS) {
+        let (name, next) = read_name(data, offset)?;
+        let fixed = data.get(next..next + 4)?;
+        questions.push(DnsQuestion {
+            name,
+            qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+            qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+        });
+        offset = next + 4;
+    }
+
+    // A truncated answer section still yields the records read so far
+    let mut answers = Vec::new();
+    for _ in 0..ancount.min(MAX_RECORDS) {
+        let Some((name, next)) = read_name(data, offset) else { break };
+        let Some(fixed) = data.get(next..next + 10) else { break };
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata_offset = next + 10;
+        let Some(rdata) = data.get(rdata_offset..rdata_offset + rdlen) else { break };
+        answers.push(DnsRecord { name, rtype, ttl, data: render_rdata(data, rdata_offset, rtype, rdata) });
+        offset = rdata_offset + rdlen;
+    }
+
+    Some(DnsMessage {
+        id,
+        response: flags & 0x8000 != 0,
+        opcode: ((flags >> 11) & 0x0F) as u8,
+        authoritative: flags & 0x0400 != 0,
+        truncated: flags & 0x0200 != 0,
+        recursion_desired: flags & 0x0100 != 0,
+        recursion_available: flags & 0x0080 != 0,
+        rcode: (flags & 0x000F) as u8,
+        questions,
+        answers,
+    })
+}
+
+// Read a possibly compressed name; returns it lowercased and the offset after it
+fn read_name(data: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut offset = start;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *data.get(offset)? as usize;
+        match len & 0xC0 {
+            0x00 if len == 0 => {
+                end.get_or_insert(offset + 1);
+                break;
+            }
+            0x00 => {
+                let label = data.get(offset + 1..offset + 1 + len)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                for &b in label {
+                    // Keep names printable; binary labels are a tunneling tell anyway
+                    if b.is_ascii_graphic() && b != b'.' {
+                        name.push(b.to_ascii_lowercase() as char);
+                    } else {
+                        name.push_str(&format!("\\{:03}", b));
+                    }
+                }
+                if name.len() > MAX_NAME_LEN * 4 {
+                    return None;
+                }
+                offset += 1 + len;
+            }
+            0xC0 => {
+                let pointer = (u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]) & 0x3FFF) as usize;
+                end.get_or_insert(offset + 2);
+                jumps += 1;
+                if jumps > MAX_POINTER_JUMPS || pointer >= data.len() {
+                    return None;
+                }
+                offset = pointer;
+            }
+            _ => return None, // reserved label types
+        }
+    }
+
+    Some((name, end?))
+}
+
+fn render_rdata(message: &[u8], offset: usize, rtype: u16, rdata: &[u8]) -> String {
+    match rtype {
+        TYPE_A if rdata.len() == 4 => Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]).to_string(),
+        TYPE_AAAA if rdata.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(rdata);
+            Ipv6Addr::from(octets).to_string()
+        }
+        TYPE_NS | TYPE_CNAME | TYPE_PTR => read_name(message, offset).map(|(n, _)| n).unwrap_or_default(),
+        TYPE_MX if rdata.len() > 2 => read_name(message, offset + 2).map(|(n, _)| n).unwrap_or_default(),
+        TYPE_TXT => {
+            let mut text = String::new();
+            let mut rest = rdata;
+            while let Some((&len, tail)) = rest.split_first() {
+                let chunk = &tail[..(len as usize).min(tail.len())];
+                text.push_str(&String::from_utf8_lossy(chunk));
+                rest = &tail[chunk.len()..];
+            }
+            text
+        }
+        _ => format!("<unknown type={}>", rtype),
+    }
+}
+
+/// Mnemonic for common query types
+pub fn qtype_name(qtype: u16) -> Option<&'static str> {
+    Some(match qtype {
+        TYPE_A => "A",
+        TYPE_NS => "NS",
+        TYPE_CNAME => "CNAME",
+        6 => "SOA",
+        TYPE_PTR => "PTR",
+        TYPE_MX => "MX",
+        TYPE_TXT => "TXT",
+        TYPE_AAAA => "AAAA",
+        33 => "SRV",
+        41 => "OPT",
+        43 => "DS",
+        46 => "RRSIG",
+        48 => "DNSKEY",
+        64 => "SVCB",
+        65 => "HTTPS",
+        255 => "*",
+        _ => return None,
+    })
+}
+
+pub fn rcode_name(rcode: u8) -> &'static str {
+    match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        _ => "UNKNOWN",
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a single-question query, or a response with the given A records
+    pub(crate) fn build_message(id: u16, name: &str, qtype: u16, answers: &[[u8; 4]]) -> Vec<u8> {
+        let flags: u16 = if answers.is_empty() { 0x0100 } else { 0x8180 };
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&id.to_be_bytes());
+        msg.extend_from_slice(&flags.to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        msg.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        for addr in answers {
+            msg.extend_from_slice(&[0xC0, 12]); // pointer to the question name
+            msg.extend_from_slice(&TYPE_A.to_be_bytes());
+            msg.extend_from_slice(&1u16.to_be_bytes());
+            msg.extend_from_slice(&300u32.to_be_bytes());
+            msg.extend_from_slice(&4u16.to_be_bytes());
+            msg.extend_from_slice(addr);
+        }
+        msg
+    }
+
+    #[test]
+    fn test_query_and_compressed_response() {
+        let query = parse_message(&build_message(0x1234, "WWW.Example.com", TYPE_A, &[])).unwrap();
+        assert!(!query.response);
+        assert!(query.recursion_desired);
+        assert_eq!(query.query_name(), Some("www.example.com"));
+
+        let response = parse_message(&build_message(0x1234, "www.example.com", TYPE_A, &[[93, 184, 216, 34], [93, 184, 216, 35]])).unwrap();
+        assert!(response.response);
+        assert_eq!(rcode_name(response.rcode), "NOERROR");
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[0].name, "www.example.com");
+        assert_eq!(response.answers[1].data, "93.184.216.35");
+        assert_eq!(response.answers[0].ttl, 300);
+    }
+
+    #[test]
+    fn test_pointer_loop_is_rejected() {
+        let mut msg = build_message(1, "a.example", TYPE_A, &[]);
+        // Make the question name point at itself
+        msg[12] = 0xC0;
+        msg[13] = 12;
+        assert!(parse_message(&msg).is_none());
+    }
+}
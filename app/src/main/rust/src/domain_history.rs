@@ -0,0 +1,206 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::bloom::{read_u64, ScalableBloom};
+use crate::lookalike;
+
+const MAGIC: &[u8; 4] = b"FDH1";
+
+// Unsaved observations are flushed to disk at most this often
+const SAVE_INTERVAL_MS: u64 = 60_000;
+
+/// Sizing and aging of the newly observed domain filter
+#[derive(Debug, Clone, Copy)]
+pub struct DomainHistoryConfig {
+    /// Domains the first filter slice holds before a larger one is added
+    pub initial_capacity: u64,
+    pub fp_rate: f64,
+    /// A domain not seen for between one and two generations is forgotten
+    pub generation_ms: u64,
+    /// After a fresh start, domains are learned silently for this long
+    pub learning_ms: u64,
+}
+
+impl Default for DomainHistoryConfig {
+    fn default() -> Self {
+        Self {
+            initial_capacity: 16_384,
+            fp_rate: 0.001,
+            generation_ms: 30 * 24 * 3_600_000,
+            learning_ms: 24 * 3_600_000,
+        }
+    }
+}
+
+/// Every registered domain the device has contacted, kept in two aging Bloom generations
+pub struct DomainHistory {
+    pub config: DomainHistoryConfig,
+    current: ScalableBloom,
+    previous: ScalableBloom,
+    created_ms: u64,
+    generation_started_ms: u64,
+    path: Option<PathBuf>,
+    dirty: bool,
+    last_save_ms: u64,
+}
+
+impl DomainHistory {
+    /// An empty, memory-only history
+    pub fn new(config: DomainHistoryConfig, now_ms: u64) -> Self {
+        Self {
+            config,
+            current: ScalableBloom::new(config.initial_capacity, config.fp_rate),
+            previous: ScalableBloom::new(config.initial_capacity, config.fp_rate),
+            created_ms: now_ms,
+            generation_started_ms: now_ms,
+            path: None,
+            dirty: false,
+            last_save_ms: now_ms,
+        }
+    }
+
+    /// Reload the history saved at `path`, or start a new one there if the file is missing
+    pub fn open(path: &Path, config: DomainHistoryConfig, now_ms: u64) -> io::Result<Self> {
+        let mut history = match File::open(path) {
+            Ok(file) => Self::read_from(&mut BufReader::new(file), config, now_ms)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::new(config, now_ms),
+            Err(e) => return Err(e),
+        };
+        history.path = Some(path.to_path_buf());
+        Ok(history)
+    }
+
+    /// Record a contacted domain. Returns its registered domain if this device had never
+    /// contacted it before and the learning period is over.
+    pub fn observe(&mut self, domain: &str, now_ms: u64) -> Option<String> {
+        self.age(now_ms);
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let registered = lookalike::registrable_domain(&domain)?;
+        let key = registered.as_bytes();
+
+        if self.current.contains(key) {
+            return None;
+        }
+        self.current.insert(key);
+        self.dirty = true;
+        // Seen last generation: carried forward, not new
+        if self.previous.contains(key) {
+            return None;
+        }
+        let learning = now_ms.saturating_sub(self.created_ms) < self.config.learning_ms;
+        (!learning).then(|| registered.to_string())
+    }
+
+    pub fn contains(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        lookalike::registrable_domain(&domain)
+            .is_some_and(|d| self.current.contains(d.as_bytes()) || self.previous.contains(d.as_bytes()))
+    }
+
+    /// Approximate number of distinct domains remembered
+    pub fn len(&self) -> u64 {
+        self.current.len() + self.previous.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Start a new generation once the current one is old enough
+    fn age(&mut self, now_ms: u64) {
+        if now_ms.saturating_sub(self.generation_started_ms) < self.config.generation_ms {
+            return;
+        }
+        let fresh = ScalableBloom::new(self.config.initial_capacity, self.config.fp_rate);
+        self.previous = std::mem::replace(&mut self.current, fresh);
+        self.generation_started_ms = now_ms;
+        self.dirty = true;
+    }
+
+    /// Write to the backing file if there are changes and the save interval has passed
+    pub fn maybe_save(&mut self, now_ms: u64) -> io::Result<()> {
+        if self.dirty && now_ms.saturating_sub(self.last_save_ms) >= SAVE_INTERVAL_MS {
+            self.last_save_ms = now_ms;
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Write to the backing file through a temporary so a crash never leaves it half written
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let tmp = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        self.write_to(&mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&self.created_ms.to_le_bytes())?;
+        out.write_all(&self.generation_started_ms.to_le_bytes())?;
+        self.current.write_to(out)?;
+        self.previous.write_to(out)
+    }
+
+    fn read_from(input: &mut impl Read, config: DomainHistoryConfig, now_ms: u64) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a domain history file"));
+        }
+        let created_ms = read_u64(input)?;
+        let generation_started_ms = read_u64(input)?;
+        Ok(Self {
+            config,
+            current: ScalableBloom::read_from(input)?,
+            previous: ScalableBloom::read_from(input)?,
+            created_ms,
+            generation_started_ms,
+            path: None,
+            dirty: false,
+            last_save_ms: now_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_seen_learning_aging_and_reload() {
+        let config = DomainHistoryConfig { learning_ms: 1_000, generation_ms: 10_000, ..Default::default() };
+        let path = std::env::temp_dir().join(format!("domain_history_{}.bin", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut history = DomainHistory::open(&path, config, 0).unwrap();
+        // Learned silently while the history is new
+        assert_eq!(history.observe("www.example.com", 10), None);
+        assert!(history.contains("cdn.example.com"));
+
+        assert_eq!(history.observe("api.new-site.co.uk.", 2_000).as_deref(), Some("new-site.co.uk"));
+        assert_eq!(history.observe("www.new-site.co.uk", 2_001), None);
+        assert_eq!(history.observe("localhost", 2_002), None);
+        history.save().unwrap();
+
+        // Survives a restart
+        let mut history = DomainHistory::open(&path, config, 3_000).unwrap();
+        assert_eq!(history.observe("example.com", 3_000), None);
+        assert_eq!(history.observe("another.example", 3_001).as_deref(), Some("another.example"));
+
+        // Seen during the previous generation: still known. Unseen for two: forgotten.
+        assert_eq!(history.observe("example.com", 12_000), None);
+        assert_eq!(history.observe("new-site.co.uk", 25_000).as_deref(), Some("new-site.co.uk"));
+        assert_eq!(history.observe("example.com", 25_001), None);
+
+        let _ = fs::remove_file(&path);
+        fs::write(&path, b"garbage").unwrap();
+        assert!(DomainHistory::open(&path, config, 0).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,573 @@
+use serde::Serialize;
+
+use crate::scanner::calculate_entropy;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+
+// Program header types and flags
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+// Section header types
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_NOBITS: u32 = 8;
+const SHT_DYNSYM: u32 = 11;
+const SHF_EXECINSTR: u64 = 4;
+
+// Dynamic table tags
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_SONAME: u64 = 14;
+const DT_GNU_HASH: u64 = 0x6fff_fef5;
+
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+
+// Bounds on what a hostile header can make us walk
+const MAX_SYMBOLS: usize = 1 << 20;
+const MAX_NAME_LEN: usize = 4096;
+
+// Entropy above which executable code is taken to be compressed or encrypted
+const PACKED_ENTROPY: f64 = 7.2;
+
+// Strings left behind by packers and protectors common on Android
+const UPX_MARKER: &[u8] = b"UPX!";
+const UPX_INFO: &[u8] = b"$Info: This file is packed with the UPX";
+const JIAGU_MARKERS: [&[u8]; 3] = [b"libjiagu", b"com/qihoo/util/StubApp", b"com.qihoo.util.StubApp"];
+const BANGCLE_MARKERS: [&[u8]; 5] = [b"libsecexe", b"libsecmain", b"libSecShell", b"libDexHelper", b"com/secneo/apkwrapper"];
+
+// Process-launching imports, the su paths they are pointed at, and anti-debugging tells
+const EXEC_IMPORTS: [&str; 7] = ["execve", "execv", "execvp", "execl", "execlp", "system", "popen"];
+const SU_STRINGS: [&[u8]; 4] = [b"/system/bin/su", b"/system/xbin/su", b"/sbin/su", b"su -c"];
+const TRACER_PID: &[u8] = b"TracerPid";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    BadMagic,
+    UnsupportedClass(u8),
+    UnsupportedEncoding(u8),
+    Truncated,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    // Shannon entropy of the section's bytes in the file; 0 for sections without any
+    pub entropy: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub entropy: f64,
+}
+
+impl Segment {
+    pub fn is_rwx(&self) -> bool {
+        self.flags & (PF_R | PF_W | PF_X) == PF_R | PF_W | PF_X
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Elf {
+    // 32 or 64
+    pub bits: u8,
+    pub big_endian: bool,
+    pub elf_type: u16,
+    pub machine: u16,
+    pub entry: u64,
+    pub interpreter: Option<String>,
+    pub soname: Option<String>,
+    // DT_NEEDED libraries, in load order
+    pub needed: Vec<String>,
+    pub sections: Vec<Section>,
+    pub segments: Vec<Segment>,
+    // Undefined dynamic symbols, and defined global or weak ones
+    pub imports: Vec<String>,
+    pub exports: Vec<String>,
+    // No static symbol table
+    pub stripped: bool,
+}
+
+// Bounds-checked reads in the file's class and byte order
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, offset: u64) -> Option<[u8; N]> {
+        let start = usize::try_from(offset).ok()?;
+        self.data.get(start..start.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u8(&self, offset: u64) -> Option<u8> {
+        self.data.get(usize::try_from(offset).ok()?).copied()
+    }
+
+    fn u16(&self, offset: u64) -> Option<u16> {
+        let bytes = self.bytes(offset)?;
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: u64) -> Option<u32> {
+        let bytes = self.bytes(offset)?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64(&self, offset: u64) -> Option<u64> {
+        let bytes = self.bytes(offset)?;
+        Some(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    // An address, offset or size field: 4 bytes in ELF32, 8 in ELF64
+    fn word(&self, offset: u64) -> Option<u64> {
+        if self.is_64 {
+            self.u64(offset)
+        } else {
+            self.u32(offset).map(u64::from)
+        }
+    }
+
+    fn slice(&self, offset: u64, size: u64) -> Option<&[u8]> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(size).ok()?)?;
+        self.data.get(start..end)
+    }
+
+    fn string(&self, offset: u64) -> Option<String> {
+        let start = usize::try_from(offset).ok()?;
+        let rest = self.data.get(start..)?;
+        let rest = &rest[..rest.len().min(MAX_NAME_LEN)];
+        let end = rest.iter().position(|&b| b == 0)?;
+        Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+}
+
+// Where the dynamic linker would find the symbol and string tables
+struct DynamicInfo {
+    needed: Vec<u64>,
+    soname: Option<u64>,
+    strtab: Option<u64>,
+    strsz: Option<u64>,
+    symtab: Option<u64>,
+    hash: Option<u64>,
+    gnu_hash: Option<u64>,
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(ELF_MAGIC)
+}
+
+pub fn parse(data: &[u8]) -> Result<Elf, ElfError> {
+    if !is_elf(data) {
+        return Err(ElfError::BadMagic);
+    }
+    let class = *data.get(4).ok_or(ElfError::Truncated)?;
+    let encoding = *data.get(5).ok_or(ElfError::Truncated)?;
+    let is_64 = match class {
+        1 => false,
+        2 => true,
+        other => return Err(ElfError::UnsupportedClass(other)),
+    };
+    let big_endian = match encoding {
+        1 => false,
+        2 => true,
+        other => return Err(ElfError::UnsupportedEncoding(other)),
+    };
+    let r = Reader { data, is_64, big_endian };
+    let truncated = ElfError::Truncated;
+
+    let (entry, phoff, shoff, sizes) = if is_64 { (24, 32, 40, 54) } else { (24, 28, 32, 42) };
+    let elf_type = r.u16(16).ok_or(truncated)?;
+    let machine = r.u16(18).ok_or(truncated)?;
+    let entry = r.word(entry).ok_or(truncated)?;
+    let phoff = r.word(phoff).ok_or(truncated)?;
+    let shoff = r.word(shoff).ok_or(truncated)?;
+    let phentsize = r.u16(sizes).ok_or(truncated)? as u64;
+    let phnum = r.u16(sizes + 2).ok_or(truncated)? as u64;
+    let shentsize = r.u16(sizes + 4).ok_or(truncated)? as u64;
+    let shnum = r.u16(sizes + 6).ok_or(truncated)? as u64;
+    let shstrndx = r.u16(sizes + 8).ok_or(truncated)? as usize;
+
+    // Headers that run past the end of the file are dropped rather than failing the parse:
+    // packers routinely leave stale or truncated section tables behind
+    let mut segments = Vec::new();
+    if phentsize >= if is_64 { 56 } else { 32 } {
+        for i in 0..phnum {
+            let at = phoff.saturating_add(i * phentsize);
+            if let Some(segment) = read_segment(&r, at) {
+                segments.push(segment);
+            }
+        }
+    }
+    let mut raw_sections = Vec::new();
+    if shentsize >= if is_64 { 64 } else { 40 } {
+        for i in 0..shnum {
+            let at = shoff.saturating_add(i * shentsize);
+            match read_section(&r, at) {
+                Some(section) => raw_sections.push(section),
+                None => break,
+            }
+        }
+    }
+    let section_names = raw_sections.get(shstrndx).map(|(_, section, _, _)| section.offset);
+    let sections: Vec<Section> = raw_sections
+        .iter()
+        .map(|(name, section, _, _)| Section {
+            name: section_names.and_then(|names| r.string(names.saturating_add(*name as u64))).unwrap_or_default(),
+            ..section.clone()
+        })
+        .collect();
+
+    let interpreter = segments
+        .iter()
+        .find(|segment| segment.kind == PT_INTERP)
+        .and_then(|segment| r.string(segment.offset));
+
+    // The dynamic table from its segment, or from its section when program headers are damaged
+    let dynamic = segments
+        .iter()
+        .find(|segment| segment.kind == PT_DYNAMIC)
+        .map(|segment| (segment.offset, segment.file_size))
+        .or_else(|| sections.iter().find(|section| section.kind == SHT_DYNAMIC).map(|s| (s.offset, s.size)))
+        .map(|(offset, size)| read_dynamic(&r, offset, size))
+        .unwrap_or(DynamicInfo { needed: Vec::new(), soname: None, strtab: None, strsz: None, symtab: None, hash: None, gnu_hash: None });
+
+    let dynstr = dynamic.strtab.and_then(|addr| file_offset(&segments, addr));
+    let dynstr_name = |offset: u64| -> Option<String> {
+        if dynamic.strsz.is_some_and(|size| offset >= size) {
+            return None;
+        }
+        r.string(dynstr?.checked_add(offset)?)
+    };
+    let needed = dynamic.needed.iter().filter_map(|&offset| dynstr_name(offset)).collect();
+    let soname = dynamic.soname.and_then(dynstr_name);
+
+    // Dynamic symbols from .dynsym when section headers survive, else through the hash tables
+    let dynsym = raw_sections.iter().find(|(_, section, _, _)| section.kind == SHT_DYNSYM);
+    let symbols = match dynsym {
+        Some((_, section, link, entsize)) => {
+            let strings = raw_sections.get(*link as usize).map(|(_, strtab, _, _)| strtab.offset);
+            let entsize = if *entsize == 0 { symbol_size(is_64) } else { *entsize };
+            let count = (section.size / entsize) as usize;
+            read_symbols(&r, section.offset, entsize, count, strings)
+        }
+        None => {
+            let table = dynamic.symtab.and_then(|addr| file_offset(&segments, addr));
+            let count = dynamic
+                .hash
+                .and_then(|addr| file_offset(&segments, addr))
+                .and_then(|offset| r.u32(offset + 4))
+                .map(|nchain| nchain as usize)
+                .or_else(|| gnu_hash_symbol_count(&r, dynamic.gnu_hash.and_then(|addr| file_offset(&segments, addr))?));
+            match (table, count) {
+                (Some(table), Some(count)) => read_symbols(&r, table, symbol_size(is_64), count, dynstr),
+                _ => Vec::new(),
+            }
+        }
+    };
+    let mut imports = Vec::new();
+    let mut exports = Vec::new();
+    for (name, binding, defined) in symbols {
+        if name.is_empty() {
+            continue;
+        }
+        if !defined {
+            imports.push(name);
+        } else if binding == STB_GLOBAL || binding == STB_WEAK {
+            exports.push(name);
+        }
+    }
+
+    Ok(Elf {
+        bits: if is_64 { 64 } else { 32 },
+        big_endian,
+        elf_type,
+        machine,
+        entry,
+        interpreter,
+        soname,
+        needed,
+        stripped: !raw_sections.iter().any(|(_, section, _, _)| section.kind == SHT_SYMTAB),
+        sections,
+        segments,
+        imports,
+        exports,
+    })
+}
+
+fn read_segment(r: &Reader, at: u64) -> Option<Segment> {
+    let kind = r.u32(at)?;
+    let (flags, offset, vaddr, file_size, mem_size) = if r.is_64 {
+        (r.u32(at + 4)?, r.u64(at + 8)?, r.u64(at + 16)?, r.u64(at + 32)?, r.u64(at + 40)?)
+    } else {
+        (r.u32(at + 24)?, r.word(at + 4)?, r.word(at + 8)?, r.word(at + 16)?, r.word(at + 20)?)
+    };
+    let entropy = if kind == PT_LOAD { r.slice(offset, file_size).map_or(0.0, calculate_entropy) } else { 0.0 };
+    Some(Segment { kind, flags, offset, vaddr, file_size, mem_size, entropy })
+}
+
+// A section with its name offset, sh_link and sh_entsize, which only the parser needs
+fn read_section(r: &Reader, at: u64) -> Option<(u32, Section, u32, u64)> {
+    let name = r.u32(at)?;
+    let kind = r.u32(at + 4)?;
+    let (flags, addr, offset, size, link, entsize) = if r.is_64 {
+        (r.u64(at + 8)?, r.u64(at + 16)?, r.u64(at + 24)?, r.u64(at + 32)?, r.u32(at + 40)?, r.u64(at + 56)?)
+    } else {
+        (r.word(at + 8)?, r.word(at + 12)?, r.word(at + 16)?, r.word(at + 20)?, r.u32(at + 24)?, r.word(at + 36)?)
+    };
+    let entropy = if kind == SHT_NOBITS { 0.0 } else { r.slice(offset, size).map_or(0.0, calculate_entropy) };
+    let section = Section { name: String::new(), kind, flags, addr, offset, size, entropy };
+    Some((name, section, link, entsize))
+}
+
+fn read_dynamic(r: &Reader, offset: u64, size: u64) -> DynamicInfo {
+    let mut info = DynamicInfo { needed: Vec::new(), soname: None, strtab: None, strsz: None, symtab: None, hash: None, gnu_hash: None };
+    let entry = if r.is_64 { 16 } else { 8 };
+    for at in (offset..offset.saturating_add(size)).step_by(entry) {
+        let (Some(tag), Some(value)) = (r.word(at), r.word(at + entry as u64 / 2)) else { break };
+        match tag {
+            DT_NULL => break,
+            DT_NEEDED => info.needed.push(value),
+            DT_SONAME => info.soname = Some(value),
+            DT_STRTAB => info.strtab = Some(value),
+            DT_STRSZ => info.strsz = Some(value),
+            DT_SYMTAB => info.symtab = Some(value),
+            DT_HASH => info.hash = Some(value),
+            DT_GNU_HASH => info.gnu_hash = Some(value),
+            _ => {}
+        }
+    }
+    info
+}
+
+// Translate a virtual address to a file offset through the loadable segments
+fn file_offset(segments: &[Segment], addr: u64) -> Option<u64> {
+    segments
+        .iter()
+        .filter(|segment| segment.kind == PT_LOAD)
+        .find(|segment| addr >= segment.vaddr && addr - segment.vaddr < segment.file_size)
+        .map(|segment| segment.offset + (addr - segment.vaddr))
+}
+
+fn symbol_size(is_64: bool) -> u64 {
+    if is_64 { 24 } else { 16 }
+}
+
+// DT_GNU_HASH does not store the symbol count: it is one past the last symbol reachable from
+// the highest bucket, whose chain ends at the first value with the low bit set
+fn gnu_hash_symbol_count(r: &Reader, offset: u64) -> Option<usize> {
+    let buckets = r.u32(offset)? as u64;
+    let symbol_offset = r.u32(offset + 4)? as u64;
+    let bloom_words = r.u32(offset + 8)? as u64;
+    let word_size = if r.is_64 { 8 } else { 4 };
+    let buckets_at = offset + 16 + bloom_words.checked_mul(word_size)?;
+    let mut last = 0;
+    for i in 0..buckets.min(MAX_SYMBOLS as u64) {
+        last = last.max(r.u32(buckets_at + i * 4)? as u64);
+    }
+    if last < symbol_offset {
+        return Some(symbol_offset as usize);
+    }
+    let chains_at = buckets_at + buckets * 4;
+    while last < MAX_SYMBOLS as u64 {
+        if r.u32(chains_at + (last - symbol_offset) * 4)? & 1 == 1 {
+            return Some(last as usize + 1);
+        }
+        last += 1;
+    }
+    None
+}
+
+// (name, binding, defined) for each symbol in a table
+fn read_symbols(r: &Reader, offset: u64, entsize: u64, count: usize, strings: Option<u64>) -> Vec<(String, u8, bool)> {
+    let mut symbols = Vec::new();
+    for i in 0..count.min(MAX_SYMBOLS) as u64 {
+        let at = offset.saturating_add(i * entsize);
+        let (name, info, shndx) = if r.is_64 {
+            (r.u32(at), r.u8(at + 4), r.u16(at + 6))
+        } else {
+            (r.u32(at), r.u8(at + 12), r.u16(at + 14))
+        };
+        let (Some(name), Some(info), Some(shndx)) = (name, info, shndx) else { break };
+        let name = strings.and_then(|strings| r.string(strings.checked_add(name as u64)?)).unwrap_or_default();
+        symbols.push((name, info >> 4, shndx != 0));
+    }
+    symbols
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+impl Elf {
+    // JNI_OnLoad and the Java_* functions the runtime binds native methods to
+    pub fn jni_exports(&self) -> Vec<&str> {
+        self.exports
+            .iter()
+            .map(String::as_str)
+            .filter(|name| *name == "JNI_OnLoad" || name.starts_with("Java_"))
+            .collect()
+    }
+
+    pub fn imports_any(&self, names: &[&str]) -> bool {
+        self.imports.iter().any(|import| names.contains(&import.as_str()))
+    }
+
+    // Executable code that looks compressed or encrypted, judged by section where the section
+    // table survives and by loadable segment otherwise
+    fn has_packed_code(&self) -> bool {
+        let packed_section = self
+            .sections
+            .iter()
+            .any(|section| section.flags & SHF_EXECINSTR != 0 && section.entropy > PACKED_ENTROPY);
+        let packed_segment = self
+            .segments
+            .iter()
+            .any(|segment| segment.kind == PT_LOAD && segment.flags & PF_X != 0 && segment.entropy > PACKED_ENTROPY);
+        packed_section || (self.sections.is_empty() && packed_segment)
+    }
+
+    // Heuristic findings for the scanner, as (name, confidence)
+    pub fn heuristics(&self, data: &[u8]) -> Vec<(String, f32)> {
+        let mut findings = Vec::new();
+        if self.segments.iter().any(|segment| segment.kind == PT_LOAD && segment.is_rwx()) {
+            findings.push(("ELF_RWX_SEGMENT".to_string(), 0.7));
+        }
+        if self.segments.iter().any(|segment| segment.kind == PT_GNU_STACK && segment.flags & PF_X != 0) {
+            findings.push(("ELF_EXECUTABLE_STACK".to_string(), 0.5));
+        }
+        if self.stripped && self.has_packed_code() {
+            findings.push(("ELF_STRIPPED_PACKED".to_string(), 0.75));
+        }
+
+        if contains(data, UPX_MARKER) && (contains(data, UPX_INFO) || self.sections.is_empty()) {
+            findings.push(("ELF_PACKER_UPX".to_string(), 0.8));
+        }
+        let names: Vec<&[u8]> = self.needed.iter().chain(&self.soname).map(|name| name.as_bytes()).collect();
+        let marked = |markers: &[&[u8]]| {
+            markers.iter().any(|marker| names.iter().any(|name| contains(name, marker)) || contains(data, marker))
+        };
+        if marked(&JIAGU_MARKERS) {
+            findings.push(("ELF_PACKER_JIAGU".to_string(), 0.6));
+        }
+        if marked(&BANGCLE_MARKERS) {
+            findings.push(("ELF_PACKER_BANGCLE".to_string(), 0.6));
+        }
+
+        if self.imports_any(&["ptrace"]) || contains(data, TRACER_PID) {
+            findings.push(("ELF_ANTI_DEBUG".to_string(), 0.6));
+        }
+        if SU_STRINGS.iter().any(|su| contains(data, su)) {
+            if self.imports_any(&EXEC_IMPORTS) {
+                findings.push(("ELF_SU_EXEC".to_string(), 0.75));
+            } else {
+                findings.push(("ELF_SU_REFERENCE".to_string(), 0.5));
+            }
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stripped ELF64 shared object with only program headers, the way packers leave them:
+    // an RWX load segment covering the file, a dynamic table, and symbols found through DT_HASH
+    fn stripped_library() -> Vec<u8> {
+        let mut strings = vec![0u8];
+        let mut add = |s: &str| {
+            let offset = strings.len() as u64;
+            strings.extend_from_slice(s.as_bytes());
+            strings.push(0);
+            offset
+        };
+        let libc = add("libc.so");
+        let liblog = add("liblog.so");
+        let soname = add("libpayload.so");
+        let names = [add("ptrace"), add("execve"), add("JNI_OnLoad"), add("Java_com_example_Native_run")];
+        strings.extend_from_slice(b"/system/xbin/su\0");
+
+        let (phoff, dynamic, symtab, hash) = (64u64, 64 + 2 * 56, 64 + 2 * 56 + 7 * 16, 64 + 2 * 56 + 7 * 16 + 5 * 24);
+        let strtab = hash + 8 + 4 * 6;
+        let total = strtab + strings.len() as u64;
+
+        let mut elf = vec![0u8; total as usize];
+        elf[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+        let mut put = |at: u64, bytes: &[u8]| elf[at as usize..at as usize + bytes.len()].copy_from_slice(bytes);
+        put(16, &3u16.to_le_bytes()); // ET_DYN
+        put(18, &183u16.to_le_bytes()); // EM_AARCH64
+        put(32, &phoff.to_le_bytes());
+        put(54, &56u16.to_le_bytes());
+        put(56, &2u16.to_le_bytes());
+        put(58, &64u16.to_le_bytes());
+
+        let mut phdr = |at: u64, kind: u32, flags: u32, offset: u64, size: u64| {
+            put(at, &kind.to_le_bytes());
+            put(at + 4, &flags.to_le_bytes());
+            put(at + 8, &offset.to_le_bytes());
+            put(at + 16, &offset.to_le_bytes());
+            put(at + 32, &size.to_le_bytes());
+            put(at + 40, &size.to_le_bytes());
+        };
+        phdr(phoff, PT_LOAD, PF_R | PF_W | PF_X, 0, total);
+        phdr(phoff + 56, PT_DYNAMIC, PF_R | PF_W, dynamic, 7 * 16);
+        let entries = [(DT_NEEDED, libc), (DT_NEEDED, liblog), (DT_SONAME, soname), (DT_STRTAB, strtab), (DT_SYMTAB, symtab), (DT_HASH, hash)];
+        for (i, (tag, value)) in entries.iter().enumerate() {
+            put(dynamic + i as u64 * 16, &tag.to_le_bytes());
+            put(dynamic + i as u64 * 16 + 8, &value.to_le_bytes());
+        }
+        // Symbol 0 is the null symbol; two imports, then two defined global functions
+        for (i, &name) in names.iter().enumerate() {
+            let at = symtab + (i as u64 + 1) * 24;
+            put(at, &(name as u32).to_le_bytes());
+            put(at + 4, &[(STB_GLOBAL << 4) | 2]);
+            put(at + 6, &(if i < 2 { 0u16 } else { 9 }).to_le_bytes());
+        }
+        put(hash, &1u32.to_le_bytes());
+        put(hash + 4, &5u32.to_le_bytes());
+        put(strtab, &strings);
+        elf
+    }
+
+    #[test]
+    fn test_parses_stripped_library_and_flags_it() {
+        let data = stripped_library();
+        let elf = parse(&data).unwrap();
+        assert_eq!((elf.bits, elf.machine, elf.elf_type), (64, 183, 3));
+        assert_eq!(elf.needed, ["libc.so", "liblog.so"]);
+        assert_eq!(elf.soname.as_deref(), Some("libpayload.so"));
+        assert_eq!(elf.imports, ["ptrace", "execve"]);
+        assert_eq!(elf.jni_exports(), ["JNI_OnLoad", "Java_com_example_Native_run"]);
+        assert!(elf.stripped && elf.sections.is_empty());
+
+        let findings: Vec<String> = elf.heuristics(&data).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(findings, ["ELF_RWX_SEGMENT", "ELF_ANTI_DEBUG", "ELF_SU_EXEC"]);
+
+        assert_eq!(parse(&data[..30]).unwrap_err(), ElfError::Truncated);
+        assert_eq!(parse(b"MZ\x90\x00").unwrap_err(), ElfError::BadMagic);
+    }
+}
@@ -0,0 +1,299 @@
+use std::net::IpAddr;
+
+use ahash::AHashSet;
+use serde::Serialize;
+
+use crate::flow_table::FlowRecord;
+
+// Public DoH/DoT resolvers; extended at runtime through `add_resolver`
+const KNOWN_RESOLVER_DOMAINS: &[&str] = &[
+    "dns.google",
+    "dns.google.com",
+    "cloudflare-dns.com",
+    "one.one.one.one",
+    "1dot1dot1dot1.cloudflare-dns.com",
+    "security.cloudflare-dns.com",
+    "family.cloudflare-dns.com",
+    "dns.quad9.net",
+    "dns9.quad9.net",
+    "dns10.quad9.net",
+    "dns11.quad9.net",
+    "doh.opendns.com",
+    "dns.umbrella.com",
+    "dns.adguard.com",
+    "dns.adguard-dns.com",
+    "dns-family.adguard.com",
+    "dns.nextdns.io",
+    "doh.cleanbrowsing.org",
+    "doh.mullvad.net",
+    "dns.mullvad.net",
+    "doh.libredns.gr",
+    "dns.switch.ch",
+    "doh.dns.sb",
+    "dns.controld.com",
+    "freedns.controld.com",
+    "dns.alidns.com",
+    "doh.pub",
+    "dot.pub",
+    "doh.360.cn",
+    "chrome.cloudflare-dns.com",
+    "mozilla.cloudflare-dns.com",
+];
+
+const KNOWN_RESOLVER_IPS: &[&str] = &[
+    "8.8.8.8",
+    "8.8.4.4",
+    "1.1.1.1",
+    "1.0.0.1",
+    "1.1.1.2",
+    "1.0.0.2",
+    "1.1.1.3",
+    "1.0.0.3",
+    "9.9.9.9",
+    "149.112.112.112",
+    "9.9.9.10",
+    "9.9.9.11",
+    "208.67.222.222",
+    "208.67.220.220",
+    "94.140.14.14",
+    "94.140.15.15",
+    "185.228.168.168",
+    "185.228.168.9",
+    "76.76.2.0",
+    "76.76.10.0",
+    "45.90.28.0",
+    "45.90.30.0",
+    "223.5.5.5",
+    "223.6.6.6",
+    "2001:4860:4860::8888",
+    "2001:4860:4860::8844",
+    "2606:4700:4700::1111",
+    "2606:4700:4700::1001",
+    "2620:fe::fe",
+    "2620:fe::9",
+    "2620:119:35::35",
+    "2a10:50c0::ad1:ff",
+];
+
+// Detections below this combined confidence are not acted on
+const CLASSIFY_THRESHOLD: f32 = 0.6;
+
+// Minimum outbound payload packets before the size pattern is judged
+const SIZE_PATTERN_MIN_PACKETS: u32 = 6;
+
+/// Which encrypted DNS protocol a flow carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsKind {
+    Dot,
+    Doh,
+    Doq,
+}
+
+/// Individual evidence that a flow is encrypted DNS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsSignal {
+    Port853,
+    ResolverSni,
+    ResolverIp,
+    DnsAlpn,
+    SizePattern,
+}
+
+impl EncryptedDnsSignal {
+    // A well-known resolver address on 443/853 alone is enough to classify; these anycast
+    // addresses serve little besides DNS. The size pattern alone never is.
+    fn weight(self) -> f32 {
+        match self {
+            EncryptedDnsSignal::Port853 => 0.9,
+            EncryptedDnsSignal::ResolverSni => 0.9,
+            EncryptedDnsSignal::DnsAlpn => 0.9,
+            EncryptedDnsSignal::ResolverIp => 0.75,
+            EncryptedDnsSignal::SizePattern => 0.3,
+        }
+    }
+}
+
+/// What to do with flows classified as encrypted DNS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsPolicy {
+    /// Report only
+    Monitor,
+    /// Drop the flow silently
+    Block,
+    /// Reject the flow so the client falls back to port 53, where the blocklist applies
+    ForcePlainDns,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptedDnsDetection {
+    pub kind: EncryptedDnsKind,
+    pub resolver: String,
+    pub signals: Vec<EncryptedDnsSignal>,
+    pub confidence: f32,
+}
+
+/// Classifies flows as DoT, DoH or DoQ from port, resolver, ALPN and size evidence
+pub struct EncryptedDnsDetector {
+    resolver_domains: AHashSet<String>,
+    resolver_ips: AHashSet<IpAddr>,
+    pub policy: EncryptedDnsPolicy,
+}
+
+impl Default for EncryptedDnsDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EncryptedDnsDetector {
+    pub fn new() -> Self {
+        let mut detector = Self {
+            resolver_domains: AHashSet::new(),
+            resolver_ips: AHashSet::new(),
+            policy: EncryptedDnsPolicy::Monitor,
+        };
+        for entry in KNOWN_RESOLVER_DOMAINS.iter().chain(KNOWN_RESOLVER_IPS) {
+            detector.add_resolver(entry);
+        }
+        detector
+    }
+
+    /// Add a resolver hostname or IP address to the known list
+    pub fn add_resolver(&mut self, resolver: &str) {
+        let resolver = resolver.trim();
+        match resolver.parse::<IpAddr>() {
+            Ok(ip) => {
+                self.resolver_ips.insert(ip);
+            }
+            Err(_) => {
+                self.resolver_domains.insert(resolver.trim_end_matches('.').to_ascii_lowercase());
+            }
+        }
+    }
+
+    fn is_resolver_domain(&self, domain: &str) -> bool {
+        let mut candidate = domain;
+        loop {
+            if self.resolver_domains.contains(candidate) {
+                return true;
+            }
+            match candidate.find('.') {
+                Some(dot) => candidate = &candidate[dot + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    /// Evaluate the evidence gathered on a flow so far
+    pub fn classify(&self, flow: &FlowRecord) -> Option<EncryptedDnsDetection> {
+        let key = &flow.key;
+        let mut signals = Vec::new();
+
+        if key.dst_port == 853 {
+            signals.push(EncryptedDnsSignal::Port853);
+        }
+
+        let resolver_sni = flow.sni.as_deref().filter(|sni| self.is_resolver_domain(sni));
+        if resolver_sni.is_some() {
+            signals.push(EncryptedDnsSignal::ResolverSni);
+        }
+        if self.resolver_ips.contains(&key.dst) && matches!(key.dst_port, 443 | 853) {
+            signals.push(EncryptedDnsSignal::ResolverIp);
+        }
+        if matches!(flow.alpn.as_deref(), Some("dot") | Some("doq")) {
+            signals.push(EncryptedDnsSignal::DnsAlpn);
+        }
+        if key.dst_port == 443
+            && flow.payload_packets_out >= SIZE_PATTERN_MIN_PACKETS
+            && flow.small_payloads_out * 4 >= flow.payload_packets_out * 3
+        {
+            signals.push(EncryptedDnsSignal::SizePattern);
+        }
+
+        // Independent signals combine as 1 - prod(1 - w)
+        let confidence = 1.0 - signals.iter().fold(1.0f32, |acc, s| acc * (1.0 - s.weight()));
+        if confidence < CLASSIFY_THRESHOLD {
+            return None;
+        }
+
+        let kind = match (key.protocol, key.dst_port, flow.alpn.as_deref()) {
+            (_, _, Some("doq")) | (17, 853, _) => EncryptedDnsKind::Doq,
+            (_, 853, _) | (_, _, Some("dot")) => EncryptedDnsKind::Dot,
+            _ => EncryptedDnsKind::Doh,
+        };
+
+        let resolver = match resolver_sni.or(flow.sni.as_deref()) {
+            Some(sni) => sni.to_string(),
+            None => key.dst.to_string(),
+        };
+
+        Some(EncryptedDnsDetection { kind, resolver, signals, confidence })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_table::FlowKey;
+
+    fn flow(dst: &str, protocol: u8, dst_port: u16) -> FlowRecord {
+        let key = FlowKey {
+            src: "10.0.0.2".parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            src_port: 41000,
+            dst_port,
+            protocol,
+        };
+        let mut record = FlowRecord::new(key, 0);
+        record.uid = Some(10100);
+        record
+    }
+
+    #[test]
+    fn test_dot_port_and_doh_sni() {
+        let detector = EncryptedDnsDetector::new();
+
+        let dot = detector.classify(&flow("203.0.113.9", 6, 853)).unwrap();
+        assert_eq!(dot.kind, EncryptedDnsKind::Dot);
+        assert_eq!(dot.resolver, "203.0.113.9");
+
+        let mut doh = flow("203.0.113.10", 6, 443);
+        doh.sni = Some("dns.nextdns.io".to_string());
+        doh.alpn = Some("h2".to_string());
+        let detection = detector.classify(&doh).unwrap();
+        assert_eq!(detection.kind, EncryptedDnsKind::Doh);
+        assert_eq!(detection.resolver, "dns.nextdns.io");
+    }
+
+    #[test]
+    fn test_size_pattern_alone_is_not_enough() {
+        let detector = EncryptedDnsDetector::new();
+        let mut web = flow("203.0.113.20", 6, 443);
+        web.payload_packets_out = 10;
+        web.small_payloads_out = 10;
+        assert!(detector.classify(&web).is_none());
+
+        // ...but it tips a resolver IP over HTTPS into a confident detection
+        let mut doh = flow("1.1.1.1", 6, 443);
+        doh.payload_packets_out = 10;
+        doh.small_payloads_out = 9;
+        let detection = detector.classify(&doh).unwrap();
+        assert!(detection.confidence > 0.7);
+        assert!(detection.signals.contains(&EncryptedDnsSignal::SizePattern));
+    }
+
+    #[test]
+    fn test_resolver_ip_alone_is_enough() {
+        let detector = EncryptedDnsDetector::new();
+        let detection = detector.classify(&flow("9.9.9.9", 6, 443)).unwrap();
+        assert_eq!(detection.kind, EncryptedDnsKind::Doh);
+        assert_eq!(detection.signals, [EncryptedDnsSignal::ResolverIp]);
+        assert!(detection.confidence > CLASSIFY_THRESHOLD + 0.1);
+
+        // Plain HTTP to the same address is not
+        assert!(detector.classify(&flow("9.9.9.9", 6, 80)).is_none());
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::certificate::CertificateReport;
+use crate::encrypted_dns::{EncryptedDnsDetection, EncryptedDnsPolicy};
+use crate::geoip::{GeoInfo, GeoRule};
+use crate::icmp_tunnel::IcmpTunnelDetection;
+use crate::lookalike::LookalikeMatch;
+use crate::scan_detection::ScanVerdict;
+use crate::threat_intel::Indicator;
+use crate::url_analysis::UrlAnalysis;
+
+// Oldest events are dropped once the host stops polling
+const MAX_PENDING_EVENTS: usize = 1024;
+
+/// Detections raised by the inspectors, polled by the VPN service as JSON
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum InspectionEvent {
+    EncryptedDns {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        #[serde(flatten)]
+        detection: EncryptedDnsDetection,
+        action: EncryptedDnsPolicy,
+    },
+    CertificateAnomaly {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        sni: Option<String>,
+        ja3s: Option<String>,
+        certificate: CertificateReport,
+    },
+    Scan {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        #[serde(flatten)]
+        scan: ScanVerdict,
+    },
+    IcmpTunnel {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        #[serde(flatten)]
+        detection: IcmpTunnelDetection,
+    },
+    GeoPolicy {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        remote: IpAddr,
+        #[serde(flatten)]
+        geo: GeoInfo,
+        bytes_out: u64,
+        rule: GeoRule,
+    },
+    NewDomain {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        source: &'static str,
+        /// Registered domain never contacted before by this device
+        domain: String,
+        /// Full name that was looked up or connected to
+        query: String,
+        /// Protected brand the domain imitates, if any
+        lookalike_of: Option<String>,
+    },
+    UrlThreat {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        #[serde(flatten)]
+        analysis: UrlAnalysis,
+    },
+    LookalikeDomain {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        /// Where the name was seen: "dns", "tls" or "quic"
+        source: &'static str,
+        #[serde(flatten)]
+        lookalike: LookalikeMatch,
+    },
+    ThreatIntel {
+        timestamp_ms: u64,
+        uid: Option<u32>,
+        /// Address, domain, URL or fingerprint seen in the traffic
+        observed: String,
+        #[serde(flatten)]
+        indicator: Indicator,
+    },
+}
+
+/// Bounded FIFO of pending events
+#[derive(Default)]
+pub struct EventQueue {
+    events: VecDeque<InspectionEvent>,
+    dropped: u64,
+}
+
+impl EventQueue {
+    pub fn push(&mut self, event: InspectionEvent) {
+        if self.events.len() >= MAX_PENDING_EVENTS {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn drain(&mut self) -> Vec<InspectionEvent> {
+        self.events.drain(..).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Number of events discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
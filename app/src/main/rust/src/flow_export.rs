@@ -0,0 +1,657 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+use crate::flow_table::FlowRecord;
+
+// Keep messages inside a typical path MTU so UDP export never fragments
+const MAX_MESSAGE_LEN: usize = 1400;
+
+// Templates are resent this often so a collector that restarts can resync (RFC 7011 §8.4)
+const TEMPLATE_REFRESH_MESSAGES: u64 = 32;
+
+const TEMPLATE_ID_V4: u16 = 256;
+const TEMPLATE_ID_V6: u16 = 257;
+
+const IPFIX_VERSION: u16 = 10;
+const IPFIX_TEMPLATE_SET: u16 = 2;
+const V9_VERSION: u16 = 9;
+const V9_TEMPLATE_FLOWSET: u16 = 0;
+
+// IPFIX variable-length marker
+const VARIABLE_LENGTH: u16 = 65535;
+
+// NetFlow v9 has no enterprise bit; private field types live above the Cisco range
+const V9_PRIVATE_BASE: u16 = 40000;
+
+// NetFlow v9 has no variable-length encoding, so strings are zero padded
+const V9_SNI_LEN: u16 = 64;
+const V9_JA3_LEN: u16 = 32;
+const V9_JA4_LEN: u16 = 36;
+
+/// RFC 5612 documentation PEN; deployments should configure their own
+pub const DEFAULT_ENTERPRISE_NUMBER: u32 = 32473;
+
+/// Enterprise-specific information elements carried with every record
+pub mod enterprise {
+    pub const UID: u16 = 1;
+    pub const SNI: u16 = 2;
+    pub const JA3: u16 = 3;
+    pub const VERDICT: u16 = 4;
+    pub const JA4: u16 = 5;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Ipfix,
+    NetflowV9,
+}
+
+/// Where export messages go
+#[derive(Debug, Clone)]
+pub enum ExportTarget {
+    /// Messages appended back to back, as in RFC 5655 IPFIX files
+    File(PathBuf),
+    /// A collector reached over UDP. On Android the socket must be excluded
+    /// from the VPN or export traffic loops back through the tunnel, which is
+    /// what the `protect` callback of [`FlowExporter::new`] is for.
+    Udp(SocketAddr),
+}
+
+impl ExportTarget {
+    /// Parse `udp://host:port` or a plain file path
+    pub fn parse(target: &str) -> Option<ExportTarget> {
+        let target = target.trim();
+        if let Some(addr) = target.strip_prefix("udp://") {
+            return addr.to_socket_addrs().ok()?.next().map(ExportTarget::Udp);
+        }
+        if target.is_empty() {
+            return None;
+        }
+        Some(ExportTarget::File(PathBuf::from(target.strip_prefix("file://").unwrap_or(target))))
+    }
+}
+
+/// flowEndReason (IANA IE 136)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEndReason {
+    IdleTimeout = 1,
+    ActiveTimeout = 2,
+    EndOfFlow = 3,
+    ForcedEnd = 4,
+    LackOfResources = 5,
+}
+
+// Every field of the data record, in template order
+#[derive(Debug, Clone, Copy)]
+enum Element {
+    SrcAddr,
+    DstAddr,
+    SrcPort,
+    DstPort,
+    Protocol,
+    TcpFlags,
+    FlowStart,
+    FlowEnd,
+    InitiatorOctets,
+    InitiatorPackets,
+    ResponderOctets,
+    ResponderPackets,
+    EndReason,
+    Uid,
+    Verdict,
+    Sni,
+    Ja3,
+    Ja4,
+}
+
+const ELEMENTS: &[Element] = &[
+    Element::SrcAddr,
+    Element::DstAddr,
+    Element::SrcPort,
+    Element::DstPort,
+    Element::Protocol,
+    Element::TcpFlags,
+    Element::FlowStart,
+    Element::FlowEnd,
+    Element::InitiatorOctets,
+    Element::InitiatorPackets,
+    Element::ResponderOctets,
+    Element::ResponderPackets,
+    Element::EndReason,
+    Element::Uid,
+    Element::Verdict,
+    Element::Sni,
+    Element::Ja3,
+    Element::Ja4,
+];
+
+// Field specifier: (element id, length, enterprise-specific)
+fn field_spec(format: ExportFormat, element: Element, v6: bool) -> (u16, u16, bool) {
+    use Element::*;
+    let addr = |v4_id: u16, v6_id: u16| if v6 { (v6_id, 16, false) } else { (v4_id, 4, false) };
+    match (format, element) {
+        (_, SrcAddr) => addr(8, 27),
+        (_, DstAddr) => addr(12, 28),
+        (_, SrcPort) => (7, 2, false),
+        (_, DstPort) => (11, 2, false),
+        (_, Protocol) => (4, 1, false),
+        (ExportFormat::Ipfix, TcpFlags) => (6, 2, false),
+        (ExportFormat::NetflowV9, TcpFlags) => (6, 1, false),
+        // flowStartMilliseconds / flowEndMilliseconds
+        (ExportFormat::Ipfix, FlowStart) => (152, 8, false),
+        (ExportFormat::Ipfix, FlowEnd) => (153, 8, false),
+        // FIRST_SWITCHED / LAST_SWITCHED, relative to exporter uptime
+        (ExportFormat::NetflowV9, FlowStart) => (22, 4, false),
+        (ExportFormat::NetflowV9, FlowEnd) => (21, 4, false),
+        // initiator/responder counters (RFC 5103 style biflow totals)
+        (ExportFormat::Ipfix, InitiatorOctets) => (231, 8, false),
+        (ExportFormat::Ipfix, InitiatorPackets) => (298, 8, false),
+        (ExportFormat::Ipfix, ResponderOctets) => (232, 8, false),
+        (ExportFormat::Ipfix, ResponderPackets) => (299, 8, false),
+        // IN_BYTES/IN_PKTS count the initiator side, OUT_BYTES/OUT_PKTS the responder
+        (ExportFormat::NetflowV9, InitiatorOctets) => (1, 8, false),
+        (ExportFormat::NetflowV9, InitiatorPackets) => (2, 8, false),
+        (ExportFormat::NetflowV9, ResponderOctets) => (23, 8, false),
+        (ExportFormat::NetflowV9, ResponderPackets) => (24, 8, false),
+        (_, EndReason) => (136, 1, false),
+        (ExportFormat::Ipfix, Uid) => (enterprise::UID, 4, true),
+        (ExportFormat::Ipfix, Verdict) => (enterprise::VERDICT, 1, true),
+        (ExportFormat::Ipfix, Sni) => (enterprise::SNI, VARIABLE_LENGTH, true),
+        (ExportFormat::Ipfix, Ja3) => (enterprise::JA3, VARIABLE_LENGTH, true),
+        (ExportFormat::Ipfix, Ja4) => (enterprise::JA4, VARIABLE_LENGTH, true),
+        (ExportFormat::NetflowV9, Uid) => (V9_PRIVATE_BASE + enterprise::UID, 4, false),
+        (ExportFormat::NetflowV9, Verdict) => (V9_PRIVATE_BASE + enterprise::VERDICT, 1, false),
+        (ExportFormat::NetflowV9, Sni) => (V9_PRIVATE_BASE + enterprise::SNI, V9_SNI_LEN, false),
+        (ExportFormat::NetflowV9, Ja3) => (V9_PRIVATE_BASE + enterprise::JA3, V9_JA3_LEN, false),
+        (ExportFormat::NetflowV9, Ja4) => (V9_PRIVATE_BASE + enterprise::JA4, V9_JA4_LEN, false),
+    }
+}
+
+enum ExportSink {
+    File(BufWriter<File>),
+    Udp(UdpSocket),
+}
+
+/// Encodes flow records as IPFIX or NetFlow v9 and ships them to a file or collector
+pub struct FlowExporter {
+    format: ExportFormat,
+    sink: ExportSink,
+    pub enterprise_number: u32,
+    pub observation_domain: u32,
+    // Reference point for NetFlow v9 sysUptime
+    started_ms: u64,
+    messages_sent: u64,
+    // IPFIX counts data records, NetFlow v9 counts export packets
+    sequence: u32,
+    records_exported: u64,
+}
+
+impl FlowExporter {
+    /// `protect` is called with a UDP collector's socket before it connects and must exclude
+    /// it from the VPN (`VpnService.protect`); returning false fails the export setup
+    pub fn new(
+        format: ExportFormat,
+        target: &ExportTarget,
+        now_ms: u64,
+        protect: &mut dyn FnMut(RawFd) -> bool,
+    ) -> io::Result<Self> {
+        let sink = match target {
+            ExportTarget::File(path) => {
+                ExportSink::File(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?))
+            }
+            ExportTarget::Udp(addr) => {
+                let bind: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().expect("literal");
+                let socket = UdpSocket::bind(bind)?;
+                if !protect(socket.as_raw_fd()) {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "socket protection refused"));
+                }
+                socket.connect(addr)?;
+                ExportSink::Udp(socket)
+            }
+        };
+        Ok(Self {
+            format,
+            sink,
+            enterprise_number: DEFAULT_ENTERPRISE_NUMBER,
+            observation_domain: 0,
+            started_ms: now_ms,
+            messages_sent: 0,
+            sequence: 0,
+            records_exported: 0,
+        })
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Total data records written so far
+    pub fn records_exported(&self) -> u64 {
+        self.records_exported
+    }
+
+    /// Encode and send `flows`; returns the number of messages written
+    pub fn export<'a, I>(&mut self, flows: I, reason: FlowEndReason, now_ms: u64) -> io::Result<usize>
+    where
+        I: IntoIterator<Item = &'a FlowRecord>,
+    {
+        let messages = self.encode(flows, reason, now_ms);
+        for message in &messages {
+            match &mut self.sink {
+                ExportSink::File(writer) => writer.write_all(message)?,
+                ExportSink::Udp(socket) => {
+                    socket.send(message)?;
+                }
+            }
+        }
+        if let ExportSink::File(writer) = &mut self.sink {
+            writer.flush()?;
+        }
+        Ok(messages.len())
+    }
+
+    /// Encode `flows` into as many messages as needed
+    pub(crate) fn encode<'a, I>(&mut self, flows: I, reason: FlowEndReason, now_ms: u64) -> Vec<Vec<u8>>
+    where
+        I: IntoIterator<Item = &'a FlowRecord>,
+    {
+        let mut messages = Vec::new();
+        let mut builder: Option<MessageBuilder> = None;
+        let mut record = Vec::with_capacity(256);
+
+        for flow in flows {
+            let v6 = flow.key.src.is_ipv6();
+            let template_id = if v6 { TEMPLATE_ID_V6 } else { TEMPLATE_ID_V4 };
+            record.clear();
+            self.encode_record(flow, reason, v6, &mut record);
+
+            if let Some(current) = &builder {
+                if !current.fits(template_id, record.len()) {
+                    messages.push(self.finish(builder.take().expect("checked above"), now_ms));
+                }
+            }
+            let current = builder.get_or_insert_with(|| self.start_message());
+            current.push_record(template_id, &record);
+        }
+
+        if let Some(current) = builder {
+            messages.push(self.finish(current, now_ms));
+        }
+        messages
+    }
+
+    fn start_message(&self) -> MessageBuilder {
+        let mut builder = MessageBuilder::new(self.format);
+        if self.messages_sent.is_multiple_of(TEMPLATE_REFRESH_MESSAGES) {
+            builder.push_templates(self.enterprise_number);
+        }
+        builder
+    }
+
+    fn finish(&mut self, mut builder: MessageBuilder, now_ms: u64) -> Vec<u8> {
+        builder.close_set();
+        let export_secs = (now_ms / 1000) as u32;
+        let mut header = Vec::with_capacity(20);
+        match self.format {
+            ExportFormat::Ipfix => {
+                let length = (16 + builder.body.len()) as u16;
+                header.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+                header.extend_from_slice(&length.to_be_bytes());
+                header.extend_from_slice(&export_secs.to_be_bytes());
+                header.extend_from_slice(&self.sequence.to_be_bytes());
+                header.extend_from_slice(&self.observation_domain.to_be_bytes());
+                self.sequence = self.sequence.wrapping_add(builder.data_records);
+            }
+            ExportFormat::NetflowV9 => {
+                let uptime = now_ms.saturating_sub(self.started_ms) as u32;
+                header.extend_from_slice(&V9_VERSION.to_be_bytes());
+                header.extend_from_slice(&(builder.data_records as u16 + builder.template_records).to_be_bytes());
+                header.extend_from_slice(&uptime.to_be_bytes());
+                header.extend_from_slice(&export_secs.to_be_bytes());
+                header.extend_from_slice(&self.sequence.to_be_bytes());
+                header.extend_from_slice(&self.observation_domain.to_be_bytes());
+                self.sequence = self.sequence.wrapping_add(1);
+            }
+        }
+        self.messages_sent += 1;
+        self.records_exported += builder.data_records as u64;
+        header.extend_from_slice(&builder.body);
+        header
+    }
+
+    fn encode_record(&self, flow: &FlowRecord, reason: FlowEndReason, v6: bool, out: &mut Vec<u8>) {
+        let uptime = |ms: u64| ms.saturating_sub(self.started_ms) as u32;
+        for &element in ELEMENTS {
+            let (_, len, _) = field_spec(self.format, element, v6);
+            match element {
+                Element::SrcAddr => put_addr(out, flow.key.src),
+                Element::DstAddr => put_addr(out, flow.key.dst),
+                Element::SrcPort => out.extend_from_slice(&flow.key.src_port.to_be_bytes()),
+                Element::DstPort => out.extend_from_slice(&flow.key.dst_port.to_be_bytes()),
+                Element::Protocol => out.push(flow.key.protocol),
+                Element::TcpFlags => {
+                    let flags = flow.tcp_flags_out | flow.tcp_flags_in;
+                    if len == 2 {
+                        out.push(0);
+                    }
+                    out.push(flags);
+                }
+                Element::FlowStart if len == 8 => out.extend_from_slice(&flow.first_seen_ms.to_be_bytes()),
+                Element::FlowStart => out.extend_from_slice(&uptime(flow.first_seen_ms).to_be_bytes()),
+                Element::FlowEnd if len == 8 => out.extend_from_slice(&flow.last_seen_ms.to_be_bytes()),
+                Element::FlowEnd => out.extend_from_slice(&uptime(flow.last_seen_ms).to_be_bytes()),
+                Element::InitiatorOctets => out.extend_from_slice(&flow.bytes_out.to_be_bytes()),
+                Element::InitiatorPackets => out.extend_from_slice(&flow.packets_out.to_be_bytes()),
+                Element::ResponderOctets => out.extend_from_slice(&flow.bytes_in.to_be_bytes()),
+                Element::ResponderPackets => out.extend_from_slice(&flow.packets_in.to_be_bytes()),
+                Element::EndReason => out.push(reason as u8),
+                // Android reports unknown owners as -1
+                Element::Uid => out.extend_from_slice(&flow.uid.unwrap_or(u32::MAX).to_be_bytes()),
+                Element::Verdict => out.push(flow.verdict),
+                Element::Sni => put_string(out, flow.sni.as_deref(), len),
+                Element::Ja3 => put_string(out, flow.ja3.as_deref(), len),
+                Element::Ja4 => put_string(out, flow.ja4.as_deref(), len),
+            }
+        }
+    }
+}
+
+fn put_addr(out: &mut Vec<u8>, addr: IpAddr) {
+    match addr {
+        IpAddr::V4(v4) => out.extend_from_slice(&v4.octets()),
+        IpAddr::V6(v6) => out.extend_from_slice(&v6.octets()),
+    }
+}
+
+// Variable-length (RFC 7011 §7) or zero padded fixed-length string
+fn put_string(out: &mut Vec<u8>, value: Option<&str>, len: u16) {
+    let bytes = value.unwrap_or("").as_bytes();
+    if len == VARIABLE_LENGTH {
+        let bytes = &bytes[..bytes.len().min(MAX_MESSAGE_LEN / 4)];
+        if bytes.len() < 255 {
+            out.push(bytes.len() as u8);
+        } else {
+            out.push(255);
+            out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(bytes);
+    } else {
+        let len = len as usize;
+        let take = bytes.len().min(len);
+        out.extend_from_slice(&bytes[..take]);
+        out.resize(out.len() + len - take, 0);
+    }
+}
+
+// Accumulates sets for one message; the header is prepended in `FlowExporter::finish`
+struct MessageBuilder {
+    format: ExportFormat,
+    body: Vec<u8>,
+    open_set: Option<(u16, usize)>,
+    data_records: u32,
+    template_records: u16,
+}
+
+impl MessageBuilder {
+    fn new(format: ExportFormat) -> Self {
+        Self { format, body: Vec::with_capacity(MAX_MESSAGE_LEN), open_set: None, data_records: 0, template_records: 0 }
+    }
+
+    fn header_len(&self) -> usize {
+        match self.format {
+            ExportFormat::Ipfix => 16,
+            ExportFormat::NetflowV9 => 20,
+        }
+    }
+
+    fn push_templates(&mut self, enterprise_number: u32) {
+        let set_id = match self.format {
+            ExportFormat::Ipfix => IPFIX_TEMPLATE_SET,
+            ExportFormat::NetflowV9 => V9_TEMPLATE_FLOWSET,
+        };
+        self.open(set_id);
+        for (template_id, v6) in [(TEMPLATE_ID_V4, false), (TEMPLATE_ID_V6, true)] {
+            self.body.extend_from_slice(&template_id.to_be_bytes());
+            self.body.extend_from_slice(&(ELEMENTS.len() as u16).to_be_bytes());
+            for &element in ELEMENTS {
+                let (id, len, is_enterprise) = field_spec(self.format, element, v6);
+                if is_enterprise {
+                    self.body.extend_from_slice(&(id | 0x8000).to_be_bytes());
+                    self.body.extend_from_slice(&len.to_be_bytes());
+                    self.body.extend_from_slice(&enterprise_number.to_be_bytes());
+                } else {
+                    self.body.extend_from_slice(&id.to_be_bytes());
+                    self.body.extend_from_slice(&len.to_be_bytes());
+                }
+            }
+            self.template_records += 1;
+        }
+        self.close_set();
+    }
+
+    fn fits(&self, template_id: u16, record_len: usize) -> bool {
+        let set_header = if self.open_set.is_some_and(|(id, _)| id == template_id) { 0 } else { 4 };
+        // Worst-case v9 padding of 3 bytes when the set is closed
+        self.header_len() + self.body.len() + set_header + record_len + 3 <= MAX_MESSAGE_LEN
+            || self.data_records == 0
+    }
+
+    fn push_record(&mut self, template_id: u16, record: &[u8]) {
+        if self.open_set.is_none_or(|(id, _)| id != template_id) {
+            self.close_set();
+            self.open(template_id);
+        }
+        self.body.extend_from_slice(record);
+        self.data_records += 1;
+    }
+
+    fn open(&mut self, set_id: u16) {
+        self.open_set = Some((set_id, self.body.len()));
+        self.body.extend_from_slice(&set_id.to_be_bytes());
+        self.body.extend_from_slice(&[0, 0]);
+    }
+
+    fn close_set(&mut self) {
+        let Some((_, start)) = self.open_set.take() else { return };
+        // NetFlow v9 flowsets are padded to a 32-bit boundary
+        if self.format == ExportFormat::NetflowV9 {
+            while !(self.body.len() - start).is_multiple_of(4) {
+                self.body.push(0);
+            }
+        }
+        let len = (self.body.len() - start) as u16;
+        self.body[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_table::FlowKey;
+    use ahash::AHashMap;
+    use std::time::Duration;
+
+    // (enterprise number, element id) -> raw value
+    type DecodedRecord = AHashMap<(u32, u16), Vec<u8>>;
+    type Templates = AHashMap<u16, Vec<(u32, u16, u16)>>;
+
+    fn be16(b: &[u8], at: usize) -> u16 {
+        u16::from_be_bytes([b[at], b[at + 1]])
+    }
+
+    fn be32(b: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+    }
+
+    // Minimal collector: decodes one IPFIX or NetFlow v9 message, returns its length and records
+    fn decode(message: &[u8], templates: &mut Templates) -> (usize, Vec<DecodedRecord>) {
+        let version = be16(message, 0);
+        let (header_len, total_len, template_set) = match version {
+            10 => (16, be16(message, 2) as usize, 2),
+            9 => (20, message.len(), 0),
+            other => panic!("unexpected version {other}"),
+        };
+
+        let mut records = Vec::new();
+        let mut at = header_len;
+        while at + 4 <= total_len {
+            let set_id = be16(message, at);
+            let set_len = be16(message, at + 2) as usize;
+            let set = &message[at + 4..at + set_len];
+            let mut pos = 0;
+            if set_id == template_set {
+                while pos + 4 <= set.len() {
+                    let id = be16(set, pos);
+                    let count = be16(set, pos + 2);
+                    pos += 4;
+                    let mut fields = Vec::new();
+                    for _ in 0..count {
+                        let raw = be16(set, pos);
+                        let len = be16(set, pos + 2);
+                        pos += 4;
+                        // Only IPFIX has the enterprise bit; v9 uses the full 16-bit type
+                        if version == 10 && raw & 0x8000 != 0 {
+                            fields.push((be32(set, pos), raw & 0x7fff, len));
+                            pos += 4;
+                        } else {
+                            fields.push((0, raw, len));
+                        }
+                    }
+                    templates.insert(id, fields);
+                }
+            } else {
+                let fields = templates.get(&set_id).expect("template announced first").clone();
+                let min_len: usize = fields.iter().map(|f| if f.2 == VARIABLE_LENGTH { 1 } else { f.2 as usize }).sum();
+                while pos + min_len <= set.len() {
+                    let mut record = DecodedRecord::new();
+                    for &(pen, id, len) in &fields {
+                        let len = if len == VARIABLE_LENGTH {
+                            let short = set[pos] as usize;
+                            pos += 1;
+                            if short == 255 {
+                                pos += 2;
+                                be16(set, pos - 2) as usize
+                            } else {
+                                short
+                            }
+                        } else {
+                            len as usize
+                        };
+                        record.insert((pen, id), set[pos..pos + len].to_vec());
+                        pos += len;
+                    }
+                    records.push(record);
+                }
+            }
+            at += set_len;
+        }
+        (total_len, records)
+    }
+
+    fn sample_flow(src: &str, dst: &str, sni: Option<&str>) -> FlowRecord {
+        let key = FlowKey { src: src.parse().unwrap(), dst: dst.parse().unwrap(), src_port: 40000, dst_port: 443, protocol: 6 };
+        let mut flow = FlowRecord::new(key, 1_700_000_000_000);
+        flow.last_seen_ms = 1_700_000_004_000;
+        flow.uid = Some(10123);
+        flow.packets_out = 12;
+        flow.bytes_out = 2_400;
+        flow.packets_in = 20;
+        flow.bytes_in = 30_000;
+        flow.tcp_flags_out = 0x1a;
+        flow.tcp_flags_in = 0x13;
+        flow.sni = sni.map(str::to_string);
+        flow.ja3 = Some("e7d705a3286e19ea42f587b344ee6865".to_string());
+        flow.verdict = 1;
+        flow
+    }
+
+    #[test]
+    fn test_ipfix_file_export_round_trips() {
+        let path = std::env::temp_dir().join(format!("fortress-ipfix-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut flows = vec![sample_flow("10.0.0.2", "93.184.216.34", Some("www.example.com"))];
+        flows.push(sample_flow("2001:db8::2", "2001:db8::443", None));
+        // Enough records to need several messages
+        for i in 0..40 {
+            flows.push(sample_flow("10.0.0.2", &format!("198.51.100.{i}"), Some("cdn.example.net")));
+        }
+
+        let target = ExportTarget::File(path.clone());
+        let mut exporter = FlowExporter::new(ExportFormat::Ipfix, &target, 0, &mut |_| true).unwrap();
+        let written = exporter.export(&flows, FlowEndReason::IdleTimeout, 1_700_000_130_000).unwrap();
+        assert!(written > 1);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let mut templates = Templates::new();
+        let mut records = Vec::new();
+        let mut at = 0;
+        while at < bytes.len() {
+            let (len, mut decoded) = decode(&bytes[at..], &mut templates);
+            assert!(len <= MAX_MESSAGE_LEN);
+            records.append(&mut decoded);
+            at += len;
+        }
+        assert_eq!(records.len(), flows.len());
+
+        let pen = DEFAULT_ENTERPRISE_NUMBER;
+        let first = &records[0];
+        assert_eq!(first[&(0, 8)], vec![10, 0, 0, 2]);
+        assert_eq!(first[&(0, 231)], 2_400u64.to_be_bytes());
+        assert_eq!(first[&(0, 299)], 20u64.to_be_bytes());
+        assert_eq!(first[&(0, 152)], 1_700_000_000_000u64.to_be_bytes());
+        assert_eq!(first[&(0, 6)], vec![0, 0x1b]);
+        assert_eq!(first[&(0, 136)], vec![FlowEndReason::IdleTimeout as u8]);
+        assert_eq!(first[&(pen, enterprise::UID)], 10123u32.to_be_bytes());
+        assert_eq!(first[&(pen, enterprise::SNI)], b"www.example.com");
+        assert_eq!(first[&(pen, enterprise::JA3)], b"e7d705a3286e19ea42f587b344ee6865");
+        assert_eq!(first[&(pen, enterprise::VERDICT)], vec![1]);
+
+        let v6 = &records[1];
+        assert_eq!(v6[&(0, 27)], "2001:db8::2".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        assert!(v6[&(pen, enterprise::SNI)].is_empty());
+    }
+
+    #[test]
+    fn test_netflow_v9_to_udp_collector() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let target = ExportTarget::parse(&format!("udp://{}", collector.local_addr().unwrap())).unwrap();
+
+        let refused = FlowExporter::new(ExportFormat::NetflowV9, &target, 0, &mut |_| false);
+        assert_eq!(refused.err().map(|e| e.kind()), Some(io::ErrorKind::PermissionDenied));
+        let mut protected = Vec::new();
+        let mut protect = |fd| {
+            protected.push(fd);
+            true
+        };
+        let mut exporter = FlowExporter::new(ExportFormat::NetflowV9, &target, 1_699_999_990_000, &mut protect).unwrap();
+        assert_eq!(protected.len(), 1);
+        let flows = [sample_flow("10.0.0.2", "93.184.216.34", Some("a-very-long-hostname.example.com"))];
+        assert_eq!(exporter.export(&flows, FlowEndReason::ActiveTimeout, 1_700_000_005_000).unwrap(), 1);
+
+        let mut buf = [0u8; 2048];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = &buf[..len];
+        assert_eq!(be16(message, 0), 9);
+        assert_eq!(be16(message, 2), 3); // two template records and one data record
+        assert_eq!(be32(message, 4), 15_000); // sysUptime
+
+        let mut templates = Templates::new();
+        let (_, records) = decode(message, &mut templates);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record[&(0, 22)], 10_000u32.to_be_bytes()); // FIRST_SWITCHED
+        assert_eq!(record[&(0, 1)], 2_400u64.to_be_bytes());
+        assert_eq!(record[&(0, 24)], 20u64.to_be_bytes());
+        let sni = &record[&(0, V9_PRIVATE_BASE + enterprise::SNI)];
+        assert_eq!(sni.len(), V9_SNI_LEN as usize);
+        assert!(sni.starts_with(b"a-very-long-hostname.example.com\0"));
+        assert_eq!(record[&(0, V9_PRIVATE_BASE + enterprise::UID)], 10123u32.to_be_bytes());
+    }
+}
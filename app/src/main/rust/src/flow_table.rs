@@ -0,0 +1,458 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use ahash::AHashMap;
+
+use crate::certificate::CertificateReport;
+use crate::encrypted_dns::EncryptedDnsKind;
+
+// Bounds on tracked state; the oldest idle flows are dropped first
+const DEFAULT_MAX_FLOWS: usize = 16_384;
+const DEFAULT_IDLE_TIMEOUT_MS: u64 = 120_000;
+
+/// Five-tuple in the direction of the packet that opened the flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+}
+
+impl FlowKey {
+    pub fn reversed(&self) -> FlowKey {
+        FlowKey {
+            src: self.dst,
+            dst: self.src,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+            protocol: self.protocol,
+        }
+    }
+}
+
+/// Addressing and transport details pulled out of an IP packet
+#[derive(Debug, Clone, Copy)]
+pub struct PacketMeta {
+    pub key: FlowKey,
+    /// Offset of the transport header
+    pub transport_offset: usize,
+    /// Offset of the transport payload
+    pub payload_offset: usize,
+    pub tcp_flags: u8,
+}
+
+impl PacketMeta {
+    pub fn payload<'a>(&self, packet: &'a [u8]) -> &'a [u8] {
+        packet.get(self.payload_offset..).unwrap_or(&[])
+    }
+
+    /// Sequence number of a TCP segment
+    pub fn tcp_seq(&self, packet: &[u8]) -> Option<u32> {
+        if self.key.protocol != 6 {
+            return None;
+        }
+        let at = self.transport_offset + 4;
+        Some(u32::from_be_bytes(packet.get(at..at + 4)?.try_into().ok()?))
+    }
+}
+
+/// In-order delivery of one direction of a TCP stream: retransmitted bytes are trimmed, and
+/// segments that arrive early wait for the gap before them to fill
+#[derive(Debug, Default)]
+pub struct TcpReassembly {
+    next_seq: Option<u32>,
+    // Stream offset of `next_seq`, so held segments keep their place across wraparound
+    offset: u64,
+    early: BTreeMap<u64, Vec<u8>>,
+    early_bytes: usize,
+}
+
+impl TcpReassembly {
+    /// Add a segment and return the bytes that are now in order, or None once more than
+    /// `max_early` bytes are waiting on a gap
+    pub fn push(&mut self, seq: u32, payload: &[u8], max_early: usize) -> Option<Vec<u8>> {
+        let Some(next_seq) = self.next_seq else {
+            self.next_seq = Some(seq.wrapping_add(payload.len() as u32));
+            self.offset = payload.len() as u64;
+            return Some(payload.to_vec());
+        };
+        let start = self.offset as i64 + seq.wrapping_sub(next_seq) as i32 as i64;
+        if start > self.offset as i64 {
+            if !payload.is_empty() && self.early.insert(start as u64, payload.to_vec()).is_none() {
+                self.early_bytes += payload.len();
+            }
+            return (self.early_bytes <= max_early).then(Vec::new);
+        }
+
+        let mut ready = Vec::new();
+        self.take(start, payload, &mut ready);
+        while let Some(entry) = self.early.first_entry() {
+            if *entry.key() > self.offset {
+                break;
+            }
+            let (start, segment) = entry.remove_entry();
+            self.early_bytes -= segment.len();
+            self.take(start as i64, &segment, &mut ready);
+        }
+        Some(ready)
+    }
+
+    // Append what of a segment starting at or before the current offset is new
+    fn take(&mut self, start: i64, segment: &[u8], ready: &mut Vec<u8>) {
+        let skip = (self.offset as i64 - start) as usize;
+        let Some(new) = segment.get(skip..).filter(|new| !new.is_empty()) else { return };
+        ready.extend_from_slice(new);
+        self.offset += new.len() as u64;
+        self.next_seq = self.next_seq.map(|seq| seq.wrapping_add(new.len() as u32));
+    }
+}
+
+/// Parse the IPv4/IPv6 and TCP/UDP/ICMP headers of a raw packet
+pub fn parse_packet(packet: &[u8]) -> Option<PacketMeta> {
+    let version = packet.first()? >> 4;
+    let (src, dst, mut protocol, mut offset) = match version {
+        4 => {
+            if packet.len() < 20 {
+                return None;
+            }
+            let ihl = (packet[0] & 0x0F) as usize * 4;
+            let src = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+            let dst = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+            (IpAddr::V4(src), IpAddr::V4(dst), packet[9], ihl)
+        }
+        6 => {
+            if packet.len() < 40 {
+                return None;
+            }
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&packet[8..24]);
+            dst.copy_from_slice(&packet[24..40]);
+            (IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)), packet[6], 40)
+        }
+        _ => return None,
+    };
+
+    // Skip IPv6 hop-by-hop, routing and destination options headers
+    if version == 6 {
+        while matches!(protocol, 0 | 43 | 60) {
+            let next = *packet.get(offset)?;
+            let len = (*packet.get(offset + 1)? as usize + 1) * 8;
+            protocol = next;
+            offset += len;
+        }
+    }
+
+    let (src_port, dst_port, payload_offset, tcp_flags) = match protocol {
+        6 => {
+            let header = packet.get(offset..offset + 20)?;
+            let data_offset = (header[12] >> 4) as usize * 4;
+            (
+                u16::from_be_bytes([header[0], header[1]]),
+                u16::from_be_bytes([header[2], header[3]]),
+                offset + data_offset.max(20),
+                header[13],
+            )
+        }
+        17 => {
+            let header = packet.get(offset..offset + 8)?;
+            (u16::from_be_bytes([header[0], header[1]]), u16::from_be_bytes([header[2], header[3]]), offset + 8, 0)
+        }
+        // ICMP type/code stand in for ports so echo flows stay distinct from errors
+        1 | 58 => {
+            let header = packet.get(offset..offset + 4)?;
+            (header[0] as u16, header[1] as u16, offset + 8, 0)
+        }
+        _ => (0, 0, offset, 0),
+    };
+
+    Some(PacketMeta {
+        key: FlowKey { src, dst, src_port, dst_port, protocol },
+        transport_offset: offset,
+        payload_offset: payload_offset.min(packet.len()),
+        tcp_flags,
+    })
+}
+
+/// Per-flow state shared by the inspectors
+#[derive(Debug, Clone)]
+pub struct FlowRecord {
+    pub key: FlowKey,
+    pub uid: Option<u32>,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+    pub packets_out: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub bytes_in: u64,
+    /// TCP flags seen in each direction, OR-ed together
+    pub tcp_flags_out: u8,
+    pub tcp_flags_in: u8,
+    pub sni: Option<String>,
+    pub alpn: Option<String>,
+    pub ja3: Option<String>,
+    pub ja4: Option<String>,
+    pub ja3s: Option<String>,
+    /// Negotiated protocol version and cipher suite from the ServerHello
+    pub tls_version: Option<u16>,
+    pub tls_cipher: Option<u16>,
+    pub certificate: Option<CertificateReport>,
+    /// Set once the server's cleartext handshake flight has been examined
+    pub tls_server_inspected: bool,
+    pub verdict: u8,
+    /// Country (ISO code) and network owner of the responder, when GeoIP data is loaded
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+    /// Set once a geo policy rule has alerted on this flow
+    pub geo_alerted: bool,
+    pub encrypted_dns: Option<EncryptedDnsKind>,
+    /// Outbound packets that carried payload, and how many of those were DNS-message sized
+    pub payload_packets_out: u32,
+    pub small_payloads_out: u32,
+}
+
+impl FlowRecord {
+    pub(crate) fn new(key: FlowKey, now_ms: u64) -> Self {
+        Self {
+            key,
+            uid: None,
+            first_seen_ms: now_ms,
+            last_seen_ms: now_ms,
+            packets_out: 0,
+            bytes_out: 0,
+            packets_in: 0,
+            bytes_in: 0,
+            tcp_flags_out: 0,
+            tcp_flags_in: 0,
+            sni: None,
+            alpn: None,
+            ja3: None,
+            ja4: None,
+            ja3s: None,
+            tls_version: None,
+            tls_cipher: None,
+            certificate: None,
+            tls_server_inspected: false,
+            verdict: 0,
+            country: None,
+            asn: None,
+            as_org: None,
+            geo_alerted: false,
+            encrypted_dns: None,
+            payload_packets_out: 0,
+            small_payloads_out: 0,
+        }
+    }
+}
+
+/// Flows dropped to make room for a new one: those already idle, else the least recently seen
+#[derive(Debug, Default)]
+pub struct Evicted {
+    pub idle: Vec<FlowRecord>,
+    pub oldest: Option<FlowRecord>,
+}
+
+/// Bidirectional flow table keyed by the originating five-tuple
+pub struct FlowTable {
+    flows: AHashMap<FlowKey, FlowRecord>,
+    // One entry per flow, ordered by the last_seen_ms it had when queued. Packets only bump
+    // last_seen_ms; an entry found out of date at the top is requeued, so finding the oldest flow
+    // never scans the table.
+    by_age: BinaryHeap<Reverse<(u64, FlowKey)>>,
+    max_flows: usize,
+    idle_timeout_ms: u64,
+}
+
+impl Default for FlowTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlowTable {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_FLOWS, DEFAULT_IDLE_TIMEOUT_MS)
+    }
+
+    pub fn with_limits(max_flows: usize, idle_timeout_ms: u64) -> Self {
+        Self { flows: AHashMap::new(), by_age: BinaryHeap::new(), max_flows, idle_timeout_ms }
+    }
+
+    /// Account one packet and return the key of the flow it belongs to, along with any flows a
+    /// full table dropped to fit it
+    pub fn record(&mut self, meta: &PacketMeta, packet_len: usize, uid: Option<u32>, now_ms: u64) -> (FlowKey, Evicted) {
+        let reverse = meta.key.reversed();
+        let mut evicted = Evicted::default();
+        let (key, outbound) = if self.flows.contains_key(&meta.key) {
+            (meta.key, true)
+        } else if self.flows.contains_key(&reverse) {
+            (reverse, false)
+        } else {
+            if self.flows.len() >= self.max_flows {
+                evicted.idle = self.expire(now_ms);
+                if self.flows.len() >= self.max_flows {
+                    evicted.oldest = self.pop_oldest();
+                }
+            }
+            self.flows.insert(meta.key, FlowRecord::new(meta.key, now_ms));
+            self.by_age.push(Reverse((now_ms, meta.key)));
+            (meta.key, true)
+        };
+
+        let flow = self.flows.get_mut(&key).expect("flow inserted above");
+        flow.last_seen_ms = now_ms;
+        if uid.is_some() {
+            flow.uid = uid;
+        }
+        if outbound {
+            flow.packets_out += 1;
+            flow.bytes_out += packet_len as u64;
+            flow.tcp_flags_out |= meta.tcp_flags;
+            let payload_len = packet_len.saturating_sub(meta.payload_offset);
+            if payload_len > 0 {
+                flow.payload_packets_out += 1;
+                if (40..=600).contains(&payload_len) {
+                    flow.small_payloads_out += 1;
+                }
+            }
+        } else {
+            flow.packets_in += 1;
+            flow.bytes_in += packet_len as u64;
+            flow.tcp_flags_in |= meta.tcp_flags;
+        }
+        (key, evicted)
+    }
+
+    pub fn get(&self, key: &FlowKey) -> Option<&FlowRecord> {
+        self.flows.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &FlowKey) -> Option<&mut FlowRecord> {
+        self.flows.get_mut(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FlowRecord> {
+        self.flows.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// Drop flows idle for longer than the timeout and return them
+    pub fn expire(&mut self, now_ms: u64) -> Vec<FlowRecord> {
+        let mut expired = Vec::new();
+        while let Some(last_seen_ms) = self.oldest_last_seen() {
+            if now_ms.saturating_sub(last_seen_ms) <= self.idle_timeout_ms {
+                break;
+            }
+            expired.extend(self.pop_oldest());
+        }
+        expired
+    }
+
+    // When the least recently seen flow was last seen, requeueing entries that have gone stale
+    fn oldest_last_seen(&mut self) -> Option<u64> {
+        while let Some(&Reverse((queued_ms, key))) = self.by_age.peek() {
+            let last_seen_ms = self.flows.get(&key).expect("every queued flow is in the table").last_seen_ms;
+            if last_seen_ms == queued_ms {
+                return Some(queued_ms);
+            }
+            self.by_age.pop();
+            self.by_age.push(Reverse((last_seen_ms, key)));
+        }
+        None
+    }
+
+    fn pop_oldest(&mut self) -> Option<FlowRecord> {
+        self.oldest_last_seen()?;
+        let Reverse((_, key)) = self.by_age.pop()?;
+        self.flows.remove(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp_packet(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+        let mut p = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0];
+        p.extend_from_slice(&src);
+        p.extend_from_slice(&dst);
+        p.extend_from_slice(&sport.to_be_bytes());
+        p.extend_from_slice(&dport.to_be_bytes());
+        p.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        p.extend_from_slice(payload);
+        p
+    }
+
+    #[test]
+    fn test_tcp_reassembly_delivers_bytes_in_order() {
+        let mut stream = TcpReassembly::default();
+        assert_eq!(stream.push(u32::MAX - 2, b"abc", 16).unwrap(), b"abc");
+        // Early segments wait for the gap, which here spans the sequence wraparound
+        assert_eq!(stream.push(2, b"fgh", 16).unwrap(), b"");
+        assert_eq!(stream.push(u32::MAX - 2, b"abcd", 16).unwrap(), b"d");
+        assert_eq!(stream.push(1, b"ef", 16).unwrap(), b"efgh");
+        assert_eq!(stream.push(4, b"hij", 16).unwrap(), b"ij");
+        assert_eq!(stream.push(1, b"e", 16).unwrap(), b"");
+
+        // Bytes held past the limit give up on the stream
+        assert!(stream.push(20, &[0; 16], 16).is_some());
+        assert!(stream.push(40, &[0; 1], 16).is_none());
+    }
+
+    #[test]
+    fn test_both_directions_share_a_flow() {
+        let mut table = FlowTable::new();
+        let out = tcp_packet([10, 0, 0, 2], [1, 1, 1, 1], 40000, 443, &[0u8; 100]);
+        let back = tcp_packet([1, 1, 1, 1], [10, 0, 0, 2], 443, 40000, &[0u8; 1000]);
+
+        let (k1, _) = table.record(&parse_packet(&out).unwrap(), out.len(), Some(10123), 1_000);
+        let (k2, _) = table.record(&parse_packet(&back).unwrap(), back.len(), None, 1_010);
+        assert_eq!(k1, k2);
+
+        let flow = table.get(&k1).unwrap();
+        assert_eq!((flow.packets_out, flow.packets_in), (1, 1));
+        assert_eq!(flow.uid, Some(10123));
+        assert_eq!(flow.small_payloads_out, 1);
+    }
+
+    #[test]
+    fn test_idle_flows_expire() {
+        let mut table = FlowTable::with_limits(4, 1_000);
+        let p = tcp_packet([10, 0, 0, 2], [8, 8, 8, 8], 40001, 853, &[]);
+        table.record(&parse_packet(&p).unwrap(), p.len(), None, 0);
+        assert_eq!(table.expire(500).len(), 0);
+        assert_eq!(table.expire(2_000).len(), 1);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_full_table_evicts_least_recently_seen() {
+        let mut table = FlowTable::with_limits(2, 10_000);
+        let packets: Vec<Vec<u8>> = (0..3).map(|i| tcp_packet([10, 0, 0, 2], [1, 1, 1, 1], 40000 + i, 443, &[])).collect();
+        let record = |table: &mut FlowTable, i: usize, now: u64| table.record(&parse_packet(&packets[i]).unwrap(), packets[i].len(), None, now);
+
+        record(&mut table, 0, 0);
+        record(&mut table, 1, 10);
+        // The first flow is older on insertion but seen again since
+        record(&mut table, 0, 20);
+        let (_, evicted) = record(&mut table, 2, 30);
+        assert!(evicted.idle.is_empty());
+        assert_eq!(evicted.oldest.map(|f| f.key.src_port), Some(40001));
+        assert_eq!(table.len(), 2);
+
+        let (_, evicted) = record(&mut table, 1, 20_000);
+        assert_eq!(evicted.idle.len(), 2);
+        assert!(evicted.oldest.is_none());
+    }
+}
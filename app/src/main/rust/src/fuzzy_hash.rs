@@ -0,0 +1,691 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use ahash::AHashMap;
+use serde::{Serialize, Serializer};
+
+// ssdeep (context-triggered piecewise hashing), as computed and compared by ssdeep 2.14
+const ROLLING_WINDOW: usize = 7;
+const MIN_BLOCKSIZE: u64 = 3;
+const HASH_PRIME: u32 = 0x0100_0193;
+const HASH_INIT: u32 = 0x2802_1967;
+const SPAMSUM_LENGTH: usize = 64;
+const NUM_BLOCKHASHES: usize = 31;
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// TLSH with the reference defaults: 128 buckets, 1-byte checksum, 5-byte window, "T1" prefix
+const TLSH_BUCKETS: usize = 128;
+const TLSH_CODE_LEN: usize = TLSH_BUCKETS / 4;
+const TLSH_MIN_LENGTH: usize = 50;
+const TLSH_WINDOW: usize = 5;
+const TLSH_LENGTH_MULT: u32 = 12;
+const TLSH_QRATIO_MULT: u32 = 12;
+
+// Pearson permutation used by every TLSH bucket and checksum mapping
+const V_TABLE: [u8; 256] = [
+    1, 87, 49, 12, 176, 178, 102, 166, 121, 193, 6, 84, 249, 230, 44, 163,
+    14, 197, 213, 181, 161, 85, 218, 80, 64, 239, 24, 226, 236, 142, 38, 200,
+    110, 177, 104, 103, 141, 253, 255, 50, 77, 101, 81, 18, 45, 96, 31, 222,
+    25, 107, 190, 70, 86, 237, 240, 34, 72, 242, 20, 214, 244, 227, 149, 235,
+    97, 234, 57, 22, 60, 250, 82, 175, 208, 5, 127, 199, 111, 62, 135, 248,
+    174, 169, 211, 58, 66, 154, 106, 195, 245, 171, 17, 187, 182, 179, 0, 243,
+    132, 56, 148, 75, 128, 133, 158, 100, 130, 126, 91, 13, 153, 246, 216, 219,
+    119, 68, 223, 78, 83, 88, 201, 99, 122, 11, 92, 32, 136, 114, 52, 10,
+    138, 30, 48, 183, 156, 35, 61, 26, 143, 74, 251, 94, 129, 162, 63, 152,
+    170, 7, 115, 167, 241, 206, 3, 150, 55, 59, 151, 220, 90, 53, 23, 131,
+    125, 173, 15, 238, 79, 95, 89, 16, 105, 137, 225, 224, 217, 160, 37, 123,
+    118, 73, 2, 157, 46, 116, 9, 145, 134, 228, 207, 212, 202, 215, 69, 229,
+    27, 188, 67, 124, 168, 252, 42, 4, 29, 108, 21, 247, 19, 205, 39, 203,
+    233, 40, 186, 147, 198, 192, 155, 33, 164, 191, 98, 204, 165, 180, 117, 76,
+    140, 36, 210, 172, 41, 54, 159, 8, 185, 232, 113, 196, 231, 47, 146, 120,
+    51, 65, 28, 144, 254, 221, 93, 189, 194, 139, 112, 43, 71, 109, 184, 209,
+];
+
+// Largest input length for each TLSH length byte (the reference's topval table)
+const TLSH_TOPVAL: [u32; 170] = [
+    1, 2, 3, 5, 7, 11, 17, 25, 38, 57, 86, 129, 194, 291, 437, 656, 854, 1110, 1443, 1876, 2439, 3171,
+    3475, 3823, 4205, 4626, 5088, 5597, 6157, 6772, 7450, 8195, 9014, 9916, 10907, 11998, 13198,
+    14518, 15970, 17567, 19323, 21256, 23382, 25720, 28292, 31121, 34233, 37656, 41422, 45564,
+    50121, 55133, 60646, 66711, 73382, 80721, 88793, 97672, 107439, 118183, 130002, 143002,
+    157302, 173032, 190335, 209369, 230306, 253337, 278670, 306538, 337191, 370911, 408002,
+    448802, 493682, 543050, 597356, 657091, 722800, 795081, 874589, 962048, 1058252, 1164078,
+    1280486, 1408534, 1549388, 1704327, 1874759, 2062236, 2268459, 2495305, 2744836, 3019320,
+    3321252, 3653374, 4018711, 4420582, 4862641, 5348905, 5883796, 6472176, 7119394, 7831333,
+    8614467, 9475909, 10423501, 11465851, 12612437, 13873681, 15261050, 16787154, 18465870,
+    20312458, 22343706, 24578077, 27035886, 29739474, 32713425, 35984770, 39583245, 43541573,
+    47895730, 52685306, 57953837, 63749221, 70124148, 77136564, 84850228, 93335252, 102668779,
+    112935659, 124229227, 136652151, 150317384, 165349128, 181884040, 200072456, 220079703,
+    242087671, 266296456, 292926096, 322218735, 354440623, 389884688, 428873168, 471760495,
+    518936559, 570830240, 627913311, 690704607, 759775136, 835752671, 919327967, 1011260767,
+    1112386880, 1223623232, 1345985727, 1480584256, 1628642751, 1791507135, 1970657856,
+    2167723648, 2384496256, 2622945920, 2885240448, 3173764736, 3491141248, 3840255616,
+    4224281216,
+];
+
+// Thresholds for feed entries that do not carry their own
+pub const DEFAULT_SSDEEP_MIN_SCORE: u32 = 60;
+pub const DEFAULT_TLSH_MAX_DISTANCE: u32 = 70;
+// TLSH distance treated as unrelated when ranking TLSH matches against ssdeep ones
+const TLSH_UNRELATED_DISTANCE: u32 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FuzzyKind {
+    Ssdeep,
+    Tlsh,
+}
+
+#[derive(Default)]
+struct RollingHash {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl RollingHash {
+    fn update(&mut self, c: u8) {
+        let slot = self.n % ROLLING_WINDOW;
+        self.h2 = self.h2.wrapping_sub(self.h1).wrapping_add(ROLLING_WINDOW as u32 * c as u32);
+        self.h1 = self.h1.wrapping_add(c as u32).wrapping_sub(self.window[slot] as u32);
+        self.window[slot] = c;
+        self.n += 1;
+        self.h3 = (self.h3 << 5) ^ c as u32;
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+// One candidate block size: its digest so far, and the running piece hashes for the full and
+// half-length digests. A digest char at `len` is only ever set once the digest is full.
+#[derive(Clone, Copy)]
+struct BlockHash {
+    digest: [u8; SPAMSUM_LENGTH],
+    len: usize,
+    h: u32,
+    half_h: u32,
+    half_digest: u8,
+}
+
+impl BlockHash {
+    fn new(h: u32, half_h: u32) -> BlockHash {
+        BlockHash { digest: [0; SPAMSUM_LENGTH], len: 0, h, half_h, half_digest: 0 }
+    }
+}
+
+fn block_size(index: usize) -> u64 {
+    MIN_BLOCKSIZE << index
+}
+
+fn piece_hash(h: u32, c: u8) -> u32 {
+    h.wrapping_mul(HASH_PRIME) ^ c as u32
+}
+
+fn b64(h: u32) -> u8 {
+    B64[(h % 64) as usize]
+}
+
+// A parsed ssdeep digest: "blocksize:digest:digest at twice the block size"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsdeepHash {
+    pub block_size: u64,
+    first: Vec<u8>,
+    second: Vec<u8>,
+}
+
+impl SsdeepHash {
+    pub fn compute(data: &[u8]) -> SsdeepHash {
+        let total = data.len() as u64;
+        let mut roll = RollingHash::default();
+        let mut blocks = vec![BlockHash::new(HASH_INIT, HASH_INIT)];
+        // Block sizes below `start` can no longer be chosen and are not updated
+        let mut start = 0;
+
+        for &c in data {
+            roll.update(c);
+            let sum = roll.sum() as u64;
+            for block in &mut blocks[start..] {
+                block.h = piece_hash(block.h, c);
+                block.half_h = piece_hash(block.half_h, c);
+            }
+
+            // A trigger point for one block size is one for every smaller size too
+            let mut i = start;
+            while i < blocks.len() && sum % block_size(i) == block_size(i) - 1 {
+                if blocks[i].len == 0 && blocks.len() < NUM_BLOCKHASHES {
+                    let last = blocks[blocks.len() - 1];
+                    blocks.push(BlockHash::new(last.h, last.half_h));
+                }
+                let block = &mut blocks[i];
+                block.digest[block.len] = b64(block.h);
+                block.half_digest = b64(block.half_h);
+                if block.len < SPAMSUM_LENGTH - 1 {
+                    block.len += 1;
+                    block.h = HASH_INIT;
+                    if block.len < SPAMSUM_LENGTH / 2 {
+                        block.half_h = HASH_INIT;
+                        block.half_digest = 0;
+                    }
+                } else if blocks.len() - start >= 2
+                    && block_size(start) * (SPAMSUM_LENGTH as u64) < total
+                    && blocks[start + 1].len >= SPAMSUM_LENGTH / 2
+                {
+                    start += 1;
+                }
+                i += 1;
+            }
+        }
+
+        // The smallest block size expected to fill the digest, then down while it came out short
+        let mut index = start;
+        while block_size(index) * (SPAMSUM_LENGTH as u64) < total && index < NUM_BLOCKHASHES - 1 {
+            index += 1;
+        }
+        index = index.min(blocks.len() - 1);
+        while index > start && blocks[index].len < SPAMSUM_LENGTH / 2 {
+            index -= 1;
+        }
+
+        // The unfinished last piece contributes one more char unless the input ended on a trigger
+        let ended_mid_piece = roll.sum() != 0;
+        let block = &blocks[index];
+        let mut first = block.digest[..block.len].to_vec();
+        if ended_mid_piece {
+            first.push(b64(block.h));
+        } else if block.digest[block.len] != 0 {
+            first.push(block.digest[block.len]);
+        }
+
+        let mut second = Vec::new();
+        if let Some(next) = blocks.get(index + 1) {
+            second.extend_from_slice(&next.digest[..next.len.min(SPAMSUM_LENGTH / 2 - 1)]);
+            if ended_mid_piece {
+                second.push(b64(next.half_h));
+            } else if next.half_digest != 0 {
+                second.push(next.half_digest);
+            }
+        } else if ended_mid_piece {
+            second.push(b64(block.h));
+        }
+
+        SsdeepHash { block_size: block_size(index), first, second }
+    }
+
+    // Accepts the digest alone or an ssdeep CSV value with a trailing ",filename"
+    pub fn parse(input: &str) -> Option<SsdeepHash> {
+        let input = input.split(',').next()?;
+        let mut parts = input.splitn(3, ':');
+        let block_size = parts.next()?.parse::<u64>().ok().filter(|&size| size >= MIN_BLOCKSIZE)?;
+        let first = parts.next()?.as_bytes();
+        let second = parts.next()?.as_bytes();
+        let valid = |digest: &[u8]| digest.len() <= SPAMSUM_LENGTH && digest.iter().all(|c| B64.contains(c));
+        if !valid(first) || !valid(second) {
+            return None;
+        }
+        Some(SsdeepHash { block_size, first: first.to_vec(), second: second.to_vec() })
+    }
+
+    // Similarity from 0 (unrelated) to 100 (identical), as ssdeep's fuzzy_compare reports it.
+    // Digests only compare when their block sizes are equal or a factor of two apart.
+    pub fn compare(&self, other: &SsdeepHash) -> u32 {
+        let (size1, size2) = (self.block_size, other.block_size);
+        if size1 != size2 && size1.checked_mul(2) != Some(size2) && (size1 % 2 == 1 || size1 / 2 != size2) {
+            return 0;
+        }
+        let (first1, second1) = (eliminate_sequences(&self.first), eliminate_sequences(&self.second));
+        let (first2, second2) = (eliminate_sequences(&other.first), eliminate_sequences(&other.second));
+        if size1 == size2 && first1 == first2 && second1 == second2 {
+            return 100;
+        }
+
+        if size1 == size2 {
+            score_strings(&first1, &first2, size1).max(score_strings(&second1, &second2, size1.saturating_mul(2)))
+        } else if size1.checked_mul(2) == Some(size2) {
+            score_strings(&first2, &second1, size2)
+        } else {
+            score_strings(&first1, &second2, size1)
+        }
+    }
+}
+
+impl fmt::Display for SsdeepHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Digests only ever hold base64 characters
+        let text = |digest: &[u8]| String::from_utf8_lossy(digest).into_owned();
+        write!(f, "{}:{}:{}", self.block_size, text(&self.first), text(&self.second))
+    }
+}
+
+impl Serialize for SsdeepHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// Runs of more than three identical characters carry little information; ssdeep trims them
+// before comparing
+fn eliminate_sequences(digest: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(digest.len());
+    for (i, &c) in digest.iter().enumerate() {
+        if i < 3 || c != digest[i - 1] || c != digest[i - 2] || c != digest[i - 3] {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn has_common_substring(s1: &[u8], s2: &[u8]) -> bool {
+    if s1.len() < ROLLING_WINDOW || s2.len() < ROLLING_WINDOW {
+        return false;
+    }
+    let windows: HashSet<&[u8]> = s1.windows(ROLLING_WINDOW).collect();
+    s2.windows(ROLLING_WINDOW).any(|window| windows.contains(window))
+}
+
+// Edit distance with insertions and deletions costing 1 and substitutions 2
+fn edit_distance(s1: &[u8], s2: &[u8]) -> u32 {
+    let mut previous: Vec<u32> = (0..=s2.len() as u32).collect();
+    let mut current = vec![0; s2.len() + 1];
+    for (i, &a) in s1.iter().enumerate() {
+        current[0] = i as u32 + 1;
+        for (j, &b) in s2.iter().enumerate() {
+            let replace = previous[j] + if a == b { 0 } else { 2 };
+            current[j + 1] = replace.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[s2.len()]
+}
+
+fn score_strings(s1: &[u8], s2: &[u8], block_size: u64) -> u32 {
+    if s1.len() > SPAMSUM_LENGTH || s2.len() > SPAMSUM_LENGTH || !has_common_substring(s1, s2) {
+        return 0;
+    }
+    let distance = edit_distance(s1, s2) as u64;
+    let score = distance * SPAMSUM_LENGTH as u64 / (s1.len() + s2.len()) as u64;
+    let score = 100 * score / SPAMSUM_LENGTH as u64;
+    if score >= 100 {
+        return 0;
+    }
+    let score = 100 - score;
+    // Small block sizes see few pieces, so a high score there is not trusted
+    let trusted_block_size = (99 + ROLLING_WINDOW as u64) / ROLLING_WINDOW as u64 * MIN_BLOCKSIZE;
+    if block_size >= trusted_block_size {
+        return score as u32;
+    }
+    score.min(block_size / MIN_BLOCKSIZE * s1.len().min(s2.len()) as u64) as u32
+}
+
+// A TLSH digest: quartile-coded bucket counts plus the checksum, length and quartile ratios
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlsh {
+    checksum: u8,
+    l_value: u8,
+    q1_ratio: u8,
+    q2_ratio: u8,
+    code: [u8; TLSH_CODE_LEN],
+}
+
+fn pearson(salt: u8, a: u8, b: u8, c: u8) -> u8 {
+    let h = V_TABLE[salt as usize];
+    let h = V_TABLE[(h ^ a) as usize];
+    let h = V_TABLE[(h ^ b) as usize];
+    V_TABLE[(h ^ c) as usize]
+}
+
+fn swap_nibbles(b: u8) -> u8 {
+    b.rotate_left(4)
+}
+
+fn mod_diff(x: u32, y: u32, range: u32) -> u32 {
+    let direct = x.abs_diff(y);
+    direct.min(range - direct)
+}
+
+impl Tlsh {
+    // None for inputs under 50 bytes or too uniform to fill half the buckets, which the
+    // reference implementation also refuses to hash
+    pub fn compute(data: &[u8]) -> Option<Tlsh> {
+        if data.len() < TLSH_MIN_LENGTH {
+            return None;
+        }
+        let mut buckets = [0u32; 256];
+        let mut checksum = 0u8;
+        for window in data.windows(TLSH_WINDOW) {
+            // Newest byte first, as the reference reads its sliding window
+            let [b4, b3, b2, b1, b0] = [window[0], window[1], window[2], window[3], window[4]];
+            checksum = pearson(0, b0, b1, checksum);
+            for (salt, x, y) in [(2, b1, b2), (3, b1, b3), (5, b2, b3), (7, b2, b4), (11, b1, b4), (13, b3, b4)] {
+                buckets[pearson(salt, b0, x, y) as usize] += 1;
+            }
+        }
+
+        let mut sorted = buckets;
+        sorted[..TLSH_BUCKETS].sort_unstable();
+        let (q1, q2, q3) = (sorted[TLSH_BUCKETS / 4 - 1], sorted[TLSH_BUCKETS / 2 - 1], sorted[TLSH_BUCKETS * 3 / 4 - 1]);
+        if buckets[..TLSH_BUCKETS].iter().filter(|&&count| count > 0).count() <= TLSH_BUCKETS / 2 {
+            return None;
+        }
+
+        let mut code = [0u8; TLSH_CODE_LEN];
+        for (i, byte) in code.iter_mut().enumerate() {
+            for j in 0..4 {
+                let count = buckets[4 * i + j];
+                let quartile = if q3 < count {
+                    3
+                } else if q2 < count {
+                    2
+                } else if q1 < count {
+                    1
+                } else {
+                    0
+                };
+                *byte |= quartile << (j * 2);
+            }
+        }
+
+        let length = data.len().min(u32::MAX as usize) as u32;
+        // The reference computes the ratios in single precision, from 32-bit products
+        let ratio = |q: u32| (q.wrapping_mul(100) as f32 / q3 as f32) as u32 % 16;
+        Some(Tlsh {
+            checksum,
+            l_value: TLSH_TOPVAL.partition_point(|&top| top < length) as u8,
+            q1_ratio: ratio(q1) as u8,
+            q2_ratio: ratio(q2) as u8,
+            code,
+        })
+    }
+
+    // Accepts the 72-character "T1" form and the 70-character form older tools print
+    pub fn parse(input: &str) -> Option<Tlsh> {
+        let digits = input.strip_prefix("T1").unwrap_or(input);
+        if digits.len() != 2 * (3 + TLSH_CODE_LEN) {
+            return None;
+        }
+        let bytes = hex::decode(digits).ok()?;
+        let mut code = [0u8; TLSH_CODE_LEN];
+        for (i, byte) in code.iter_mut().enumerate() {
+            *byte = bytes[3 + TLSH_CODE_LEN - 1 - i];
+        }
+        Some(Tlsh {
+            checksum: swap_nibbles(bytes[0]),
+            l_value: swap_nibbles(bytes[1]),
+            q1_ratio: bytes[2] >> 4,
+            q2_ratio: bytes[2] & 0x0f,
+            code,
+        })
+    }
+
+    // Distance including the length term: 0 for identical files, growing without a fixed bound
+    pub fn diff(&self, other: &Tlsh) -> u32 {
+        let mut diff = match mod_diff(self.l_value as u32, other.l_value as u32, 256) {
+            small @ (0 | 1) => small,
+            large => large * TLSH_LENGTH_MULT,
+        };
+        for (a, b) in [(self.q1_ratio, other.q1_ratio), (self.q2_ratio, other.q2_ratio)] {
+            diff += match mod_diff(a as u32, b as u32, 16) {
+                small @ (0 | 1) => small,
+                large => (large - 1) * TLSH_QRATIO_MULT,
+            };
+        }
+        if self.checksum != other.checksum {
+            diff += 1;
+        }
+        for (&a, &b) in self.code.iter().zip(&other.code) {
+            for shift in (0..8).step_by(2) {
+                diff += match ((a >> shift) & 3).abs_diff((b >> shift) & 3) {
+                    3 => 6,
+                    d => d as u32,
+                };
+            }
+        }
+        diff
+    }
+}
+
+impl fmt::Display for Tlsh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "T1{:02X}{:02X}{:02X}",
+            swap_nibbles(self.checksum),
+            swap_nibbles(self.l_value),
+            self.q1_ratio << 4 | self.q2_ratio
+        )?;
+        self.code.iter().rev().try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+impl Serialize for Tlsh {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// Both digests of one file; TLSH is absent for small or near-uniform content
+#[derive(Debug, Clone, Serialize)]
+pub struct FuzzyHashes {
+    pub ssdeep: SsdeepHash,
+    pub tlsh: Option<Tlsh>,
+}
+
+impl FuzzyHashes {
+    pub fn of(data: &[u8]) -> FuzzyHashes {
+        FuzzyHashes { ssdeep: SsdeepHash::compute(data), tlsh: Tlsh::compute(data) }
+    }
+}
+
+// The closest known-bad digest within its threshold. `score` is the ssdeep similarity (higher
+// is closer) or the TLSH distance (lower is closer), depending on `kind`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FuzzyMatch {
+    pub family: String,
+    pub kind: FuzzyKind,
+    pub hash: String,
+    pub score: u32,
+}
+
+impl FuzzyMatch {
+    // Rough common scale for picking between an ssdeep and a TLSH match
+    fn closeness(&self) -> u32 {
+        match self.kind {
+            FuzzyKind::Ssdeep => self.score,
+            FuzzyKind::Tlsh => TLSH_UNRELATED_DISTANCE.saturating_sub(self.score) * 100 / TLSH_UNRELATED_DISTANCE,
+        }
+    }
+}
+
+// Known-bad fuzzy digests. ssdeep entries are indexed by block size, since a digest only
+// compares against the same size or one a factor of two away.
+#[derive(Debug, Clone, Default)]
+pub struct FuzzyDatabase {
+    ssdeep: Vec<(SsdeepHash, String, u32)>,
+    ssdeep_by_block_size: AHashMap<u64, Vec<usize>>,
+    tlsh: Vec<(Tlsh, String, u32)>,
+}
+
+// Fields of one feed line: tab separated, or comma separated with optional double quotes
+fn split_fields(line: &str) -> Vec<String> {
+    if line.contains('\t') {
+        return line.split('\t').map(|field| field.trim().to_string()).collect();
+    }
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields.iter().map(|field| field.trim().to_string()).collect()
+}
+
+impl FuzzyDatabase {
+    pub fn new() -> FuzzyDatabase {
+        FuzzyDatabase::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ssdeep.len() + self.tlsh.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert_ssdeep(&mut self, hash: SsdeepHash, family: &str, min_score: u32) {
+        self.ssdeep_by_block_size.entry(hash.block_size).or_default().push(self.ssdeep.len());
+        self.ssdeep.push((hash, family.to_string(), min_score));
+    }
+
+    pub fn insert_tlsh(&mut self, hash: Tlsh, family: &str, max_distance: u32) {
+        self.tlsh.push((hash, family.to_string(), max_distance));
+    }
+
+    // Load a feed: one "digest, family[, threshold]" per line, tab or comma separated, with
+    // the digest type recognised from its shape. ssdeep's own CSV output (`ssdeep -c`) loads
+    // as is, the file name column standing in for the family. The threshold is the minimum
+    // ssdeep score or the maximum TLSH distance. Returns (loaded, rejected) line counts.
+    pub fn load(&mut self, text: &str) -> (usize, usize) {
+        let (mut loaded, mut rejected) = (0, 0);
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with("ssdeep,") {
+                continue;
+            }
+            let fields = split_fields(line);
+            let family = fields.get(1).filter(|family| !family.is_empty()).map_or("unknown", |family| family.as_str());
+            let threshold = fields.get(2).and_then(|threshold| threshold.parse::<u32>().ok());
+            if let Some(hash) = SsdeepHash::parse(&fields[0]) {
+                self.insert_ssdeep(hash, family, threshold.unwrap_or(DEFAULT_SSDEEP_MIN_SCORE));
+            } else if let Some(hash) = Tlsh::parse(&fields[0]) {
+                self.insert_tlsh(hash, family, threshold.unwrap_or(DEFAULT_TLSH_MAX_DISTANCE));
+            } else {
+                rejected += 1;
+                continue;
+            }
+            loaded += 1;
+        }
+        (loaded, rejected)
+    }
+
+    pub fn best_match(&self, hashes: &FuzzyHashes) -> Option<FuzzyMatch> {
+        let size = hashes.ssdeep.block_size;
+        let candidates = [size / 2, size, size.saturating_mul(2)]
+            .into_iter()
+            .filter_map(|size| self.ssdeep_by_block_size.get(&size))
+            .flatten()
+            .map(|&index| &self.ssdeep[index]);
+        let ssdeep = candidates
+            .map(|(hash, family, min_score)| (hashes.ssdeep.compare(hash), hash, family, *min_score))
+            .filter(|(score, _, _, min_score)| *score > 0 && score >= min_score)
+            .max_by_key(|(score, ..)| *score)
+            .map(|(score, hash, family, _)| FuzzyMatch {
+                family: family.clone(),
+                kind: FuzzyKind::Ssdeep,
+                hash: hash.to_string(),
+                score,
+            });
+
+        let tlsh = hashes.tlsh.as_ref().and_then(|digest| {
+            self.tlsh
+                .iter()
+                .map(|(hash, family, max_distance)| (digest.diff(hash), hash, family, *max_distance))
+                .filter(|(distance, _, _, max_distance)| distance <= max_distance)
+                .min_by_key(|(distance, ..)| *distance)
+                .map(|(distance, hash, family, _)| FuzzyMatch {
+                    family: family.clone(),
+                    kind: FuzzyKind::Tlsh,
+                    hash: hash.to_string(),
+                    score: distance,
+                })
+        });
+
+        match (ssdeep, tlsh) {
+            (Some(a), Some(b)) => Some(if b.closeness() > a.closeness() { b } else { a }),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic pseudo-random bytes so the test does not depend on a fixture file
+    fn sample(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_digests_match_reference_vectors() {
+        // The example pair from the ssdeep library documentation, one letter case apart
+        let ctph = SsdeepHash::compute(b"Also called fuzzy hashes, Ctph can match inputs that have homologies.");
+        let upper = SsdeepHash::compute(b"Also called fuzzy hashes, CTPH can match inputs that have homologies.");
+        assert_eq!(ctph.to_string(), "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C");
+        assert_eq!(upper.to_string(), "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C");
+        assert_eq!(ctph.compare(&upper), 22);
+        assert_eq!(ctph.compare(&ctph), 100);
+
+        let pangrams = "The quick brown fox jumps over the lazy dog. Pack my box with five dozen liquor jugs. \
+            How vexingly quick daft zebras jump! Sphinx of black quartz, judge my vow. The five boxing wizards \
+            jump quickly. Jackdaws love my big sphinx of quartz.\n";
+        let edited = pangrams.replace("lazy dog", "sleepy cat");
+        let counting: Vec<u8> = (0..=255).collect();
+        let inputs = [pangrams.as_bytes(), edited.as_bytes(), &counting, &counting.repeat(4)];
+        let [text, edited, counting, counting_x4] = inputs.map(|data| Tlsh::compute(data).unwrap());
+        assert_eq!(text.to_string(), "T1CED0A7C4FB58119607E9444E93AAB6B2D3EC9A049323F6305A786193209827748F56B5");
+        assert_eq!(edited.to_string(), "T160D0A7D0FB59118A07A9544A91BAB6F2D2FC9B049317F6306A38A19320AC27748F56B6");
+        assert_eq!(counting.to_string(), "T1DBD09524A6514D7D1F175ADC504E44DF554FCDE301C5002517F146D1C510194440ED1D");
+        assert_eq!(counting_x4.to_string(), "T16D119524E6514D7D1F175ADCD04E44DF554FCDE302C5002517F186D1C510294440ED1D");
+        assert_eq!(text.diff(&edited), 24);
+        assert_eq!(counting.diff(&counting_x4), 55);
+        assert_eq!(text.diff(&counting), 227);
+    }
+
+    #[test]
+    fn test_variants_match_the_closest_family() {
+        assert_eq!(SsdeepHash::compute(b"").to_string(), "3::");
+        assert!(Tlsh::compute(&[0x41; 4096]).is_none());
+
+        let original = sample(64 * 1024, 1);
+        let mut variant = original.clone();
+        variant[20_000..20_040].copy_from_slice(&sample(40, 2));
+        let unrelated = sample(64 * 1024, 3);
+
+        let hashes = FuzzyHashes::of(&original);
+        let ssdeep = hashes.ssdeep.to_string();
+        let tlsh = hashes.tlsh.clone().unwrap();
+        assert_eq!(SsdeepHash::parse(&ssdeep), Some(hashes.ssdeep.clone()));
+        assert_eq!(tlsh.to_string().len(), 72);
+        assert_eq!(Tlsh::parse(&tlsh.to_string()), Some(tlsh.clone()));
+        assert_eq!(Tlsh::parse(&tlsh.to_string()[2..]), Some(tlsh.clone()));
+
+        let variant_hashes = FuzzyHashes::of(&variant);
+        assert!(hashes.ssdeep.compare(&variant_hashes.ssdeep) >= 60);
+        assert!(tlsh.diff(variant_hashes.tlsh.as_ref().unwrap()) < 30);
+        assert_eq!(hashes.ssdeep.compare(&FuzzyHashes::of(&unrelated).ssdeep), 0);
+        assert!(tlsh.diff(FuzzyHashes::of(&unrelated).tlsh.as_ref().unwrap()) > 100);
+
+        let mut database = FuzzyDatabase::new();
+        let feed = format!(
+            "ssdeep,1.1--blocksize:hash:hash,filename\n\"{}\",\"Android.Banker\"\n{}\tAndroid.Dropper\t40\nnot-a-hash\tX\n",
+            ssdeep, tlsh
+        );
+        assert_eq!(database.load(&feed), (2, 1));
+        let found = database.best_match(&variant_hashes).unwrap();
+        assert!(found.family == "Android.Banker" || found.family == "Android.Dropper");
+        assert_eq!(database.best_match(&hashes).unwrap().kind, FuzzyKind::Ssdeep);
+        assert_eq!(database.best_match(&hashes).unwrap().score, 100);
+        assert!(database.best_match(&FuzzyHashes::of(&unrelated)).is_none());
+    }
+}
@@ -0,0 +1,634 @@
+use std::fs::File;
+use std::net::IpAddr;
+use std::path::Path;
+
+use ahash::AHashMap;
+use memmap2::Mmap;
+use serde::Serialize;
+
+// Metadata sits after this marker within the last 128 KiB of the file
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+const METADATA_SEARCH_LEN: usize = 128 * 1024;
+
+// Gap between the search tree and the data section
+const DATA_SECTION_SEPARATOR: usize = 16;
+
+// Guard against malicious nesting in the data section
+const MAX_DECODE_DEPTH: usize = 32;
+
+// Recently resolved addresses kept per lookup table
+const CACHE_SIZE: usize = 4096;
+
+/// A decoded value from the MMDB data section
+#[derive(Debug, Clone, PartialEq)]
+pub enum MmdbValue {
+    String(String),
+    Double(f64),
+    Bytes(Vec<u8>),
+    Uint(u128),
+    Int(i32),
+    Map(Vec<(String, MmdbValue)>),
+    Array(Vec<MmdbValue>),
+    Bool(bool),
+    Float(f32),
+}
+
+impl MmdbValue {
+    /// Field of a map value
+    pub fn get(&self, key: &str) -> Option<&MmdbValue> {
+        match self {
+            MmdbValue::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Follow a path of map keys, e.g. `["country", "iso_code"]`
+    pub fn path(&self, keys: &[&str]) -> Option<&MmdbValue> {
+        keys.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MmdbValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            MmdbValue::Uint(n) => u64::try_from(*n).ok(),
+            MmdbValue::Int(n) => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+}
+
+enum Source {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Source {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Source::Mapped(map) => map,
+            Source::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Reader for MaxMind DB (MMDB v2) files such as GeoLite2-Country and GeoLite2-ASN
+pub struct MmdbReader {
+    source: Source,
+    node_count: u32,
+    record_size: u16,
+    ip_version: u16,
+    pub database_type: String,
+    data_section: usize,
+    // Node reached after the 96 zero bits of ::/96, where IPv4 lookups start
+    ipv4_start: u32,
+}
+
+impl MmdbReader {
+    /// Memory-map a database file
+    pub fn open(path: &Path) -> Option<MmdbReader> {
+        let file = File::open(path).ok()?;
+        // Safety: the file is opened read-only; a database replaced underneath us
+        // is swapped in by rename, which leaves this mapping intact.
+        let map = unsafe { Mmap::map(&file) }.ok()?;
+        Self::from_source(Source::Mapped(map))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Option<MmdbReader> {
+        Self::from_source(Source::Owned(bytes))
+    }
+
+    fn from_source(source: Source) -> Option<MmdbReader> {
+        let bytes = source.bytes();
+        let window_start = bytes.len().saturating_sub(METADATA_SEARCH_LEN);
+        let marker = bytes[window_start..]
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)?
+            + window_start;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let (metadata, _) = Decoder { data: &bytes[metadata_start..], base: 0 }.decode(0, 0)?;
+
+        let node_count = metadata.get("node_count")?.as_u64()? as u32;
+        let record_size = metadata.get("record_size")?.as_u64()? as u16;
+        let ip_version = metadata.get("ip_version")?.as_u64()? as u16;
+        let database_type = metadata.get("database_type").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        if !matches!(record_size, 24 | 28 | 32) || !matches!(ip_version, 4 | 6) {
+            return None;
+        }
+
+        let tree_size = node_count as usize * record_size as usize / 4;
+        let data_section = tree_size + DATA_SECTION_SEPARATOR;
+        if data_section > marker {
+            return None;
+        }
+
+        let mut reader = MmdbReader { source, node_count, record_size, ip_version, database_type, data_section, ipv4_start: 0 };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = reader.read_record(node, 0)?;
+            }
+            reader.ipv4_start = node;
+        }
+        Some(reader)
+    }
+
+    fn read_record(&self, node: u32, bit: u8) -> Option<u32> {
+        let bytes = self.source.bytes();
+        let node_len = self.record_size as usize / 4;
+        let base = node as usize * node_len;
+        let n = bytes.get(base..base + node_len)?;
+        let value = match (self.record_size, bit) {
+            (24, 0) => u32::from_be_bytes([0, n[0], n[1], n[2]]),
+            (24, _) => u32::from_be_bytes([0, n[3], n[4], n[5]]),
+            // 28-bit records share the middle byte's nibbles
+            (28, 0) => u32::from_be_bytes([(n[3] & 0xF0) >> 4, n[0], n[1], n[2]]),
+            (28, _) => u32::from_be_bytes([n[3] & 0x0F, n[4], n[5], n[6]]),
+            (_, 0) => u32::from_be_bytes([n[0], n[1], n[2], n[3]]),
+            (_, _) => u32::from_be_bytes([n[4], n[5], n[6], n[7]]),
+        };
+        Some(value)
+    }
+
+    /// Look up an address; returns the record and the matched prefix length
+    pub fn lookup(&self, ip: IpAddr) -> Option<(MmdbValue, u8)> {
+        let (bits, start): (Vec<u8>, u32) = match ip {
+            IpAddr::V4(v4) if self.ip_version == 6 => (v4.octets().to_vec(), self.ipv4_start),
+            IpAddr::V4(v4) => (v4.octets().to_vec(), 0),
+            IpAddr::V6(v6) => match (self.ip_version, v6.to_ipv4_mapped()) {
+                (4, Some(v4)) => (v4.octets().to_vec(), 0),
+                (4, None) => return None,
+                _ => (v6.octets().to_vec(), 0),
+            },
+        };
+
+        let mut node = start;
+        let mut depth = 0u8;
+        for i in 0..bits.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bits[i / 8] >> (7 - i % 8)) & 1;
+            node = self.read_record(node, bit)?;
+            depth += 1;
+        }
+        if node <= self.node_count {
+            return None; // empty record
+        }
+
+        let offset = ((node - self.node_count) as usize).checked_sub(DATA_SECTION_SEPARATOR)?;
+        let decoder = Decoder { data: self.source.bytes().get(self.data_section..)?, base: 0 };
+        let (value, _) = decoder.decode(offset, 0)?;
+        Some((value, depth))
+    }
+}
+
+// Decoder over the data section (or the metadata block); pointers are relative to `base`
+struct Decoder<'a> {
+    data: &'a [u8],
+    base: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&self, at: usize) -> Option<u8> {
+        self.data.get(at).copied()
+    }
+
+    fn uint(&self, at: usize, len: usize) -> Option<u128> {
+        let bytes = self.data.get(at..at + len)?;
+        if len > 16 {
+            return None;
+        }
+        Some(bytes.iter().fold(0u128, |acc, &b| (acc << 8) | b as u128))
+    }
+
+    // Returns the value and the offset just past it
+    fn decode(&self, offset: usize, depth: usize) -> Option<(MmdbValue, usize)> {
+        if depth > MAX_DECODE_DEPTH {
+            return None;
+        }
+        let ctrl = self.byte(offset)?;
+        let mut at = offset + 1;
+        let mut kind = ctrl >> 5;
+
+        if kind == 1 {
+            // Pointer: the value lives elsewhere, decoding resumes after the pointer
+            let ss = (ctrl >> 3) & 0x3;
+            let vvv = (ctrl & 0x7) as usize;
+            let (target, len) = match ss {
+                0 => ((vvv << 8) | self.uint(at, 1)? as usize, 1),
+                1 => (((vvv << 16) | self.uint(at, 2)? as usize) + 2048, 2),
+                2 => (((vvv << 24) | self.uint(at, 3)? as usize) + 526_336, 3),
+                _ => (self.uint(at, 4)? as usize, 4),
+            };
+            let (value, _) = self.decode(self.base + target, depth + 1)?;
+            return Some((value, at + len));
+        }
+
+        if kind == 0 {
+            kind = 7 + self.byte(at)?;
+            at += 1;
+        }
+
+        let mut size = (ctrl & 0x1F) as usize;
+        match size {
+            29 => {
+                size = 29 + self.uint(at, 1)? as usize;
+                at += 1;
+            }
+            30 => {
+                size = 285 + self.uint(at, 2)? as usize;
+                at += 2;
+            }
+            31 => {
+                size = 65_821 + self.uint(at, 3)? as usize;
+                at += 3;
+            }
+            _ => {}
+        }
+
+        match kind {
+            2 => {
+                let s = std::str::from_utf8(self.data.get(at..at + size)?).ok()?;
+                Some((MmdbValue::String(s.to_string()), at + size))
+            }
+            3 if size == 8 => {
+                let bits = self.uint(at, 8)? as u64;
+                Some((MmdbValue::Double(f64::from_bits(bits)), at + 8))
+            }
+            4 => Some((MmdbValue::Bytes(self.data.get(at..at + size)?.to_vec()), at + size)),
+            5 | 6 | 9 | 10 => Some((MmdbValue::Uint(self.uint(at, size)?), at + size)),
+            7 => {
+                let mut entries = Vec::with_capacity(size.min(64));
+                let mut next = at;
+                for _ in 0..size {
+                    let (key, after_key) = self.decode(next, depth + 1)?;
+                    let MmdbValue::String(key) = key else { return None };
+                    let (value, after_value) = self.decode(after_key, depth + 1)?;
+                    entries.push((key, value));
+                    next = after_value;
+                }
+                Some((MmdbValue::Map(entries), next))
+            }
+            8 => {
+                let raw = self.uint(at, size)? as u32;
+                Some((MmdbValue::Int(raw as i32), at + size))
+            }
+            11 => {
+                let mut items = Vec::with_capacity(size.min(64));
+                let mut next = at;
+                for _ in 0..size {
+                    let (item, after) = self.decode(next, depth + 1)?;
+                    items.push(item);
+                    next = after;
+                }
+                Some((MmdbValue::Array(items), next))
+            }
+            14 => Some((MmdbValue::Bool(size != 0), at)),
+            15 if size == 4 => {
+                let bits = self.uint(at, 4)? as u32;
+                Some((MmdbValue::Float(f32::from_bits(bits)), at + 4))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Country and network owner of an address
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 code
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+impl GeoInfo {
+    pub fn is_empty(&self) -> bool {
+        self.country.is_none() && self.asn.is_none()
+    }
+}
+
+/// Country and ASN databases with a small lookup cache
+#[derive(Default)]
+pub struct GeoIp {
+    country_db: Option<MmdbReader>,
+    asn_db: Option<MmdbReader>,
+    cache: AHashMap<IpAddr, GeoInfo>,
+}
+
+impl GeoIp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_country_db(&mut self, db: Option<MmdbReader>) {
+        self.country_db = db;
+        self.cache.clear();
+    }
+
+    pub fn set_asn_db(&mut self, db: Option<MmdbReader>) {
+        self.asn_db = db;
+        self.cache.clear();
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.country_db.is_some() || self.asn_db.is_some()
+    }
+
+    pub fn lookup(&mut self, ip: IpAddr) -> GeoInfo {
+        if let Some(info) = self.cache.get(&ip) {
+            return info.clone();
+        }
+
+        let mut info = GeoInfo::default();
+        if let Some((record, _)) = self.country_db.as_ref().and_then(|db| db.lookup(ip)) {
+            // Anycast and satellite ranges only carry the registered country
+            info.country = record
+                .path(&["country", "iso_code"])
+                .or_else(|| record.path(&["registered_country", "iso_code"]))
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+        }
+        if let Some((record, _)) = self.asn_db.as_ref().and_then(|db| db.lookup(ip)) {
+            info.asn = record.get("autonomous_system_number").and_then(|v| v.as_u64()).map(|n| n as u32);
+            info.as_org = record.get("autonomous_system_organization").and_then(|v| v.as_str()).map(str::to_string);
+        }
+
+        if self.cache.len() >= CACHE_SIZE {
+            self.cache.clear();
+        }
+        self.cache.insert(ip, info.clone());
+        info
+    }
+}
+
+/// What a geo rule matches on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeoMatch {
+    Country(String),
+    Asn(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeoAction {
+    Alert,
+    Block,
+}
+
+/// Policy rule over the remote end's country or ASN, optionally scoped to one app
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GeoRule {
+    pub action: GeoAction,
+    pub matcher: GeoMatch,
+    pub uid: Option<u32>,
+    /// Only fire once the flow has sent this many bytes
+    pub min_upload_bytes: Option<u64>,
+}
+
+impl GeoRule {
+    /// Parse `<alert|block> <country=CC|asn=N> [uid=N] [upload>=BYTES]`
+    pub fn parse(rule: &str) -> Option<GeoRule> {
+        let mut words = rule.split_whitespace();
+        let action = match words.next()?.to_ascii_lowercase().as_str() {
+            "alert" => GeoAction::Alert,
+            "block" => GeoAction::Block,
+            _ => return None,
+        };
+        let mut matcher = None;
+        let mut uid = None;
+        let mut min_upload_bytes = None;
+        for word in words {
+            if let Some(bytes) = word.strip_prefix("upload>=") {
+                min_upload_bytes = Some(bytes.parse().ok()?);
+                continue;
+            }
+            let (key, value) = word.split_once('=')?;
+            match key.to_ascii_lowercase().as_str() {
+                "country" if value.len() == 2 => matcher = Some(GeoMatch::Country(value.to_ascii_uppercase())),
+                "asn" => matcher = Some(GeoMatch::Asn(value.trim_start_matches("AS").parse().ok()?)),
+                "uid" => uid = Some(value.parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(GeoRule { action, matcher: matcher?, uid, min_upload_bytes })
+    }
+
+    pub fn matches(&self, info: &GeoInfo, uid: Option<u32>, bytes_out: u64) -> bool {
+        if self.uid.is_some() && self.uid != uid {
+            return false;
+        }
+        if self.min_upload_bytes.is_some_and(|min| bytes_out < min) {
+            return false;
+        }
+        match &self.matcher {
+            GeoMatch::Country(cc) => info.country.as_deref() == Some(cc.as_str()),
+            GeoMatch::Asn(asn) => info.asn == Some(*asn),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Minimal MMDB writer for fixtures: IPv6 tree, 24-bit records, no pointers
+    fn encode(value: &MmdbValue, out: &mut Vec<u8>) {
+        fn header(kind: u8, size: usize, out: &mut Vec<u8>) {
+            let (short, extra): (u8, Vec<u8>) = match size {
+                0..=28 => (size as u8, vec![]),
+                29..=284 => (29, vec![(size - 29) as u8]),
+                _ => (30, ((size - 285) as u16).to_be_bytes().to_vec()),
+            };
+            if kind <= 7 {
+                out.push((kind << 5) | short);
+            } else {
+                out.push(short);
+                out.push(kind - 7);
+            }
+            out.extend_from_slice(&extra);
+        }
+        match value {
+            MmdbValue::String(s) => {
+                header(2, s.len(), out);
+                out.extend_from_slice(s.as_bytes());
+            }
+            MmdbValue::Uint(n) => {
+                let bytes = n.to_be_bytes();
+                let skip = bytes.iter().take_while(|&&b| b == 0).count();
+                header(if bytes.len() - skip <= 4 { 6 } else { 9 }, bytes.len() - skip, out);
+                out.extend_from_slice(&bytes[skip..]);
+            }
+            MmdbValue::Map(entries) => {
+                header(7, entries.len(), out);
+                for (k, v) in entries {
+                    encode(&MmdbValue::String(k.clone()), out);
+                    encode(v, out);
+                }
+            }
+            MmdbValue::Array(items) => {
+                header(11, items.len(), out);
+                for item in items {
+                    encode(item, out);
+                }
+            }
+            other => panic!("fixture writer doesn't encode {other:?}"),
+        }
+    }
+
+    pub(crate) fn map(entries: &[(&str, MmdbValue)]) -> MmdbValue {
+        MmdbValue::Map(entries.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+    }
+
+    pub(crate) fn string(s: &str) -> MmdbValue {
+        MmdbValue::String(s.to_string())
+    }
+
+    /// Build a database mapping CIDR prefixes (IPv4 or IPv6) to records
+    pub(crate) fn build_mmdb(database_type: &str, entries: &[(&str, MmdbValue)]) -> Vec<u8> {
+        const EMPTY: u32 = u32::MAX;
+        // Trie nodes; children are node indexes, or DATA_BIT | data index
+        const DATA_BIT: u32 = 1 << 31;
+        let mut nodes: Vec<[u32; 2]> = vec![[EMPTY, EMPTY]];
+        let mut data = Vec::new();
+        let mut data_offsets = Vec::new();
+
+        for (index, (cidr, value)) in entries.iter().enumerate() {
+            data_offsets.push(data.len());
+            encode(value, &mut data);
+
+            let (addr, len) = cidr.split_once('/').unwrap();
+            let len: usize = len.parse().unwrap();
+            let (bits, len) = match addr.parse::<IpAddr>().unwrap() {
+                IpAddr::V4(v4) => {
+                    let mut b = [0u8; 16];
+                    b[12..].copy_from_slice(&v4.octets());
+                    (b, len + 96)
+                }
+                IpAddr::V6(v6) => (v6.octets(), len),
+            };
+            let mut node = 0usize;
+            for i in 0..len {
+                let bit = ((bits[i / 8] >> (7 - i % 8)) & 1) as usize;
+                if i == len - 1 {
+                    nodes[node][bit] = DATA_BIT | index as u32;
+                } else if nodes[node][bit] == EMPTY {
+                    nodes.push([EMPTY, EMPTY]);
+                    nodes[node][bit] = (nodes.len() - 1) as u32;
+                    node = nodes.len() - 1;
+                } else {
+                    node = nodes[node][bit] as usize;
+                }
+            }
+        }
+
+        let node_count = nodes.len() as u32;
+        let mut out = Vec::new();
+        for node in &nodes {
+            for &child in node {
+                let record = match child {
+                    EMPTY => node_count,
+                    c if c & DATA_BIT != 0 => {
+                        node_count + DATA_SECTION_SEPARATOR as u32 + data_offsets[(c & !DATA_BIT) as usize] as u32
+                    }
+                    c => c,
+                };
+                out.extend_from_slice(&record.to_be_bytes()[1..]);
+            }
+        }
+        out.extend_from_slice(&[0u8; DATA_SECTION_SEPARATOR]);
+        out.extend_from_slice(&data);
+        out.extend_from_slice(METADATA_MARKER);
+        encode(
+            &map(&[
+                ("node_count", MmdbValue::Uint(node_count as u128)),
+                ("record_size", MmdbValue::Uint(24)),
+                ("ip_version", MmdbValue::Uint(6)),
+                ("database_type", string(database_type)),
+                ("binary_format_major_version", MmdbValue::Uint(2)),
+                ("binary_format_minor_version", MmdbValue::Uint(0)),
+                ("languages", MmdbValue::Array(vec![string("en")])),
+            ]),
+            &mut out,
+        );
+        out
+    }
+
+    pub(crate) fn country_db() -> MmdbReader {
+        MmdbReader::from_bytes(build_mmdb(
+            "GeoLite2-Country",
+            &[
+                ("93.184.216.0/24", map(&[("country", map(&[("iso_code", string("US"))]))])),
+                ("203.0.113.0/24", map(&[("registered_country", map(&[("iso_code", string("RU"))]))])),
+                ("2001:db8::/32", map(&[("country", map(&[("iso_code", string("DE"))]))])),
+            ],
+        ))
+        .unwrap()
+    }
+
+    pub(crate) fn asn_db() -> MmdbReader {
+        MmdbReader::from_bytes(build_mmdb(
+            "GeoLite2-ASN",
+            &[
+                (
+                    "93.184.216.0/24",
+                    map(&[
+                        ("autonomous_system_number", MmdbValue::Uint(15133)),
+                        ("autonomous_system_organization", string("Edgecast")),
+                    ]),
+                ),
+                ("203.0.113.0/24", map(&[("autonomous_system_number", MmdbValue::Uint(64500))])),
+            ],
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_country_and_asn_lookup() {
+        let mut geo = GeoIp::new();
+        geo.set_country_db(Some(country_db()));
+        geo.set_asn_db(Some(asn_db()));
+
+        let info = geo.lookup("93.184.216.34".parse().unwrap());
+        assert_eq!(info.country.as_deref(), Some("US"));
+        assert_eq!(info.asn, Some(15133));
+        assert_eq!(info.as_org.as_deref(), Some("Edgecast"));
+
+        assert_eq!(geo.lookup("203.0.113.9".parse().unwrap()).country.as_deref(), Some("RU"));
+        assert_eq!(geo.lookup("2001:db8::1".parse().unwrap()).country.as_deref(), Some("DE"));
+        assert!(geo.lookup("198.51.100.1".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_mapped_file_and_rule_parsing() {
+        let path = std::env::temp_dir().join(format!("fortress-geo-{}.mmdb", std::process::id()));
+        std::fs::write(&path, build_mmdb("GeoLite2-ASN", &[("10.0.0.0/8", map(&[("autonomous_system_number", MmdbValue::Uint(1))]))]))
+            .unwrap();
+        let reader = MmdbReader::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(reader.database_type, "GeoLite2-ASN");
+        let (record, _) = reader.lookup("10.1.2.3".parse().unwrap()).unwrap();
+        assert_eq!(record.get("autonomous_system_number").and_then(|v| v.as_u64()), Some(1));
+
+        let rule = GeoRule::parse("block country=ru uid=10123").unwrap();
+        let russia = GeoInfo { country: Some("RU".to_string()), ..Default::default() };
+        assert!(rule.matches(&russia, Some(10123), 0));
+        assert!(!rule.matches(&russia, Some(10124), 0));
+
+        let upload = GeoRule::parse("alert asn=AS15133 upload>=1048576").unwrap();
+        let edgecast = GeoInfo { asn: Some(15133), ..Default::default() };
+        assert!(!upload.matches(&edgecast, None, 1000));
+        assert!(upload.matches(&edgecast, None, 2 << 20));
+        assert!(GeoRule::parse("block planet=mars").is_none());
+    }
+}
@@ -0,0 +1,540 @@
+use std::cmp::Ordering;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use ed25519_dalek::{Signature, VerifyingKey};
+use md5::Md5;
+use memmap2::Mmap;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::bloom::BloomFilter;
+
+// Base store: header, then records sorted by key, then the strings they point into.
+// Header: magic, reserved u32, sequence of the last delta folded in, record count.
+const MAGIC: &[u8; 4] = b"FHR1";
+const HEADER_LEN: usize = 24;
+// Record: key (digest kind, digest zero-padded to 32 bytes), verdict, 2 reserved bytes,
+// family and source as offsets into the string section
+const KEY_LEN: usize = 33;
+const RECORD_LEN: usize = KEY_LEN + 1 + 2 + 4 + 4;
+
+const DELTA_MAGIC: &str = "FHRDELTA 1";
+const SIGNATURE_LEN: usize = 64;
+
+// Journaled delta entries folded into a new base store past this many
+const COMPACT_THRESHOLD: usize = 100_000;
+const BLOOM_FP_RATE: f64 = 0.001;
+// Room in the Bloom filter for deltas applied before the next rebuild
+const BLOOM_HEADROOM: u64 = 4_096;
+
+const BASE_FILE: &str = "reputation.db";
+const JOURNAL_FILE: &str = "reputation.journal";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestKind {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl DigestKind {
+    pub fn of(digest: &[u8]) -> Option<DigestKind> {
+        match digest.len() {
+            16 => Some(DigestKind::Md5),
+            20 => Some(DigestKind::Sha1),
+            32 => Some(DigestKind::Sha256),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Clean,
+    Suspicious,
+    Malicious,
+}
+
+impl Verdict {
+    fn from_byte(byte: u8) -> Option<Verdict> {
+        Some(match byte {
+            0 => Verdict::Clean,
+            1 => Verdict::Suspicious,
+            2 => Verdict::Malicious,
+            _ => return None,
+        })
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Verdict::Clean => 0,
+            Verdict::Suspicious => 1,
+            Verdict::Malicious => 2,
+        }
+    }
+}
+
+/// What the store knows about a file hash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Reputation {
+    pub kind: DigestKind,
+    pub verdict: Verdict,
+    pub family: String,
+    pub source: String,
+}
+
+/// Why a delta file was not applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaError {
+    Truncated,
+    BadSignature,
+    /// Not a delta, or not valid UTF-8
+    BadHeader,
+    /// Deltas apply one after another; `expected` is the sequence number the store needs next
+    OutOfSequence { expected: u64, found: u64 },
+    /// Line number of an entry that does not parse
+    InvalidEntry(usize),
+    Io(io::ErrorKind),
+}
+
+type Key = [u8; KEY_LEN];
+
+fn key(digest: &[u8]) -> Option<Key> {
+    let kind = DigestKind::of(digest)?;
+    let mut key = [0; KEY_LEN];
+    key[0] = kind as u8;
+    key[1..1 + digest.len()].copy_from_slice(digest);
+    Some(key)
+}
+
+fn kind_of_key(key: &Key) -> DigestKind {
+    match key[0] {
+        0 => DigestKind::Md5,
+        1 => DigestKind::Sha1,
+        _ => DigestKind::Sha256,
+    }
+}
+
+// Digests are uniformly distributed already, so their bytes serve as the Bloom hash pair
+fn bloom_hash(key: &Key) -> (u64, u64) {
+    let h1 = u64::from_le_bytes(key[1..9].try_into().unwrap()) ^ key[0] as u64;
+    let h2 = u64::from_le_bytes(key[9..17].try_into().unwrap());
+    (h1, h2 | 1)
+}
+
+/// SHA-256, SHA-1 and MD5 of `data`, in the order the store is queried
+pub fn file_digests(data: &[u8]) -> [Vec<u8>; 3] {
+    [Sha256::digest(data).to_vec(), Sha1::digest(data).to_vec(), Md5::digest(data).to_vec()]
+}
+
+/// A parsed delta: its sequence number, and per key the new entry or None for a removal
+struct Delta {
+    sequence: u64,
+    entries: Vec<(Key, Option<Reputation>)>,
+}
+
+// "FHRDELTA 1 <sequence>" then one "<hex digest>\t<verdict>\t<family>\t<source>" line per entry,
+// with verdict clean, suspicious, malicious or remove
+fn parse_delta(payload: &[u8]) -> Result<Delta, DeltaError> {
+    let text = std::str::from_utf8(payload).map_err(|_| DeltaError::BadHeader)?;
+    let mut lines = text.lines();
+    let sequence = lines
+        .next()
+        .and_then(|header| header.strip_prefix(DELTA_MAGIC))
+        .and_then(|sequence| sequence.trim().parse().ok())
+        .ok_or(DeltaError::BadHeader)?;
+    let mut entries = Vec::new();
+    for (i, line) in lines.enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = DeltaError::InvalidEntry(i + 2);
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (hex, verdict) = (fields[0], fields.get(1).copied().unwrap_or_default());
+        let key = hex::decode(hex).ok().and_then(|digest| key(&digest)).ok_or(invalid)?;
+        let verdict = match verdict {
+            "remove" => {
+                entries.push((key, None));
+                continue;
+            }
+            "clean" => Verdict::Clean,
+            "suspicious" => Verdict::Suspicious,
+            "malicious" => Verdict::Malicious,
+            _ => return Err(invalid),
+        };
+        let field = |i: usize| fields.get(i).map_or(String::new(), |s| s.to_string());
+        entries.push((key, Some(Reputation { kind: kind_of_key(&key), verdict, family: field(2), source: field(3) })));
+    }
+    Ok(Delta { sequence, entries })
+}
+
+/// Local file hash reputation: a memory-mapped base store sorted for binary search, signed
+/// deltas journaled on top of it, and a Bloom filter over both so unknown hashes (nearly
+/// every lookup) never touch either.
+pub struct HashReputation {
+    dir: PathBuf,
+    public_key: VerifyingKey,
+    base: Option<Mmap>,
+    base_count: usize,
+    sequence: u64,
+    // Journaled entries; None marks a removal of a base entry
+    overlay: AHashMap<Key, Option<Reputation>>,
+    bloom: BloomFilter,
+}
+
+impl HashReputation {
+    /// Open the store in `dir`, creating an empty one if there is none. Deltas must be signed
+    /// with the Ed25519 key whose public half is `public_key`.
+    pub fn open(dir: &Path, public_key: &[u8; 32]) -> io::Result<Self> {
+        let public_key = VerifyingKey::from_bytes(public_key)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid Ed25519 public key"))?;
+        fs::create_dir_all(dir)?;
+        let mut store = Self {
+            dir: dir.to_path_buf(),
+            public_key,
+            base: None,
+            base_count: 0,
+            sequence: 0,
+            overlay: AHashMap::new(),
+            bloom: BloomFilter::new(1, BLOOM_FP_RATE),
+        };
+        store.map_base()?;
+        store.replay_journal()?;
+        store.rebuild_bloom();
+        Ok(store)
+    }
+
+    fn map_base(&mut self) -> io::Result<()> {
+        let file = match File::open(self.dir.join(BASE_FILE)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // Only this store replaces the file, by renaming a new one over it, so the mapping
+        // never changes underneath
+        let map = unsafe { Mmap::map(&file)? };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt reputation store");
+        if map.len() < HEADER_LEN || &map[..4] != MAGIC {
+            return Err(invalid());
+        }
+        let sequence = u64::from_le_bytes(map[8..16].try_into().unwrap());
+        let count = u64::from_le_bytes(map[16..24].try_into().unwrap()) as usize;
+        if count.checked_mul(RECORD_LEN).is_none_or(|len| HEADER_LEN + len > map.len()) {
+            return Err(invalid());
+        }
+        self.base = Some(map);
+        self.base_count = count;
+        self.sequence = sequence;
+        Ok(())
+    }
+
+    // Journal: length-prefixed delta payloads, already verified when they were appended
+    fn replay_journal(&mut self) -> io::Result<()> {
+        let journal = match fs::read(self.dir.join(JOURNAL_FILE)) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut at = 0;
+        while let Some(len) = journal.get(at..at + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize) {
+            // A torn final write leaves a short or unparseable tail; everything before it stands
+            let Some(delta) = journal.get(at + 4..at + 4 + len).and_then(|payload| parse_delta(payload).ok()) else {
+                break;
+            };
+            if delta.sequence > self.sequence {
+                self.sequence = delta.sequence;
+                self.overlay.extend(delta.entries);
+            }
+            at += 4 + len;
+        }
+        Ok(())
+    }
+
+    fn rebuild_bloom(&mut self) {
+        let capacity = (self.base_count + self.overlay.len()) as u64 + BLOOM_HEADROOM;
+        self.bloom = BloomFilter::new(capacity, BLOOM_FP_RATE);
+        for i in 0..self.base_count {
+            let key: Key = self.record(i)[..KEY_LEN].try_into().unwrap();
+            self.bloom.insert_hash(bloom_hash(&key));
+        }
+        for key in self.overlay.keys() {
+            self.bloom.insert_hash(bloom_hash(key));
+        }
+    }
+
+    fn record(&self, index: usize) -> &[u8] {
+        let at = HEADER_LEN + index * RECORD_LEN;
+        &self.base.as_ref().unwrap()[at..at + RECORD_LEN]
+    }
+
+    // Strings follow the records as u16 length + UTF-8 bytes
+    fn string(&self, offset: u32) -> String {
+        let map = self.base.as_ref().unwrap();
+        let at = HEADER_LEN + self.base_count * RECORD_LEN + offset as usize;
+        let Some(len) = map.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize) else {
+            return String::new();
+        };
+        map.get(at + 2..at + 2 + len).map_or(String::new(), |s| String::from_utf8_lossy(s).into_owned())
+    }
+
+    fn base_entry(&self, index: usize) -> Option<Reputation> {
+        let record = self.record(index);
+        let key: Key = record[..KEY_LEN].try_into().unwrap();
+        let offset = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        Some(Reputation {
+            kind: kind_of_key(&key),
+            verdict: Verdict::from_byte(record[KEY_LEN])?,
+            family: self.string(offset(KEY_LEN + 3)),
+            source: self.string(offset(KEY_LEN + 7)),
+        })
+    }
+
+    fn search_base(&self, key: &Key) -> Option<usize> {
+        let (mut low, mut high) = (0, self.base_count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.record(mid)[..KEY_LEN].cmp(key) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// The reputation of an MD5, SHA-1 or SHA-256 digest
+    pub fn lookup(&self, digest: &[u8]) -> Option<Reputation> {
+        let key = key(digest)?;
+        if !self.bloom.contains_hash(bloom_hash(&key)) {
+            return None;
+        }
+        if let Some(entry) = self.overlay.get(&key) {
+            return entry.clone();
+        }
+        self.base_entry(self.search_base(&key)?)
+    }
+
+    /// The first known of a file's SHA-256, SHA-1 and MD5 digests (see `file_digests`)
+    pub fn lookup_digests(&self, digests: &[Vec<u8>]) -> Option<Reputation> {
+        digests.iter().find_map(|digest| self.lookup(digest))
+    }
+
+    /// Sequence number of the last delta applied
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Entries in the base store plus journaled additions (removals not subtracted)
+    pub fn len(&self) -> usize {
+        self.base_count + self.overlay.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Verify a delta file (payload followed by its Ed25519 signature), append it to the
+    /// journal and apply it. Returns the number of entries it added, changed or removed.
+    pub fn apply_delta(&mut self, file: &[u8]) -> Result<usize, DeltaError> {
+        let split = file.len().checked_sub(SIGNATURE_LEN).ok_or(DeltaError::Truncated)?;
+        let (payload, signature) = file.split_at(split);
+        let signature = Signature::from_bytes(signature.try_into().unwrap());
+        self.public_key.verify_strict(payload, &signature).map_err(|_| DeltaError::BadSignature)?;
+        let delta = parse_delta(payload)?;
+        if delta.sequence != self.sequence + 1 {
+            return Err(DeltaError::OutOfSequence { expected: self.sequence + 1, found: delta.sequence });
+        }
+
+        let io_error = |e: io::Error| DeltaError::Io(e.kind());
+        let mut journal =
+            OpenOptions::new().create(true).append(true).open(self.dir.join(JOURNAL_FILE)).map_err(io_error)?;
+        journal.write_all(&[&(payload.len() as u32).to_le_bytes(), payload].concat()).map_err(io_error)?;
+        journal.sync_data().map_err(io_error)?;
+
+        let applied = delta.entries.len();
+        self.sequence = delta.sequence;
+        for (key, entry) in delta.entries {
+            self.bloom.insert_hash(bloom_hash(&key));
+            self.overlay.insert(key, entry);
+        }
+        if self.overlay.len() >= COMPACT_THRESHOLD {
+            self.compact().map_err(io_error)?;
+        } else if self.bloom.is_full() {
+            self.rebuild_bloom();
+        }
+        Ok(applied)
+    }
+
+    /// Fold the journal into a new base store, written through a temporary file. On failure
+    /// the store is left as it was, journal and overlay included.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut overlay: Vec<(&Key, &Option<Reputation>)> = self.overlay.iter().collect();
+        overlay.sort_unstable_by_key(|(key, _)| *key);
+
+        let mut records = Vec::new();
+        let mut strings = Vec::new();
+        let mut string_offsets: AHashMap<String, u32> = AHashMap::new();
+        let mut push = |key: &Key, entry: &Reputation| {
+            let mut intern = |s: &str| {
+                *string_offsets.entry(s.to_string()).or_insert_with(|| {
+                    let offset = strings.len() as u32;
+                    let bytes = &s.as_bytes()[..s.len().min(u16::MAX as usize)];
+                    strings.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+                    strings.extend_from_slice(bytes);
+                    offset
+                })
+            };
+            let (family, source) = (intern(&entry.family), intern(&entry.source));
+            records.extend_from_slice(key);
+            records.extend_from_slice(&[entry.verdict.to_byte(), 0, 0]);
+            records.extend_from_slice(&family.to_le_bytes());
+            records.extend_from_slice(&source.to_le_bytes());
+        };
+
+        // Merge the sorted base with the sorted overlay; the overlay wins on equal keys
+        let (mut i, mut j) = (0, 0);
+        while i < self.base_count || j < overlay.len() {
+            let base_key: Option<Key> = (i < self.base_count).then(|| self.record(i)[..KEY_LEN].try_into().unwrap());
+            let order = match (base_key, overlay.get(j)) {
+                (Some(base_key), Some((key, _))) => base_key.cmp(*key),
+                (Some(_), None) => Ordering::Less,
+                _ => Ordering::Greater,
+            };
+            if order == Ordering::Less {
+                if let Some(entry) = self.base_entry(i) {
+                    push(&base_key.unwrap(), &entry);
+                }
+                i += 1;
+                continue;
+            }
+            if order == Ordering::Equal {
+                i += 1;
+            }
+            if let (key, Some(entry)) = &overlay[j] {
+                push(key, entry);
+            }
+            j += 1;
+        }
+
+        let path = self.dir.join(BASE_FILE);
+        let tmp = path.with_extension("tmp");
+        let write = || -> io::Result<()> {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            out.write_all(MAGIC)?;
+            out.write_all(&0u32.to_le_bytes())?;
+            out.write_all(&self.sequence.to_le_bytes())?;
+            out.write_all(&((records.len() / RECORD_LEN) as u64).to_le_bytes())?;
+            out.write_all(&records)?;
+            out.write_all(&strings)?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            fs::rename(&tmp, &path)
+        };
+        if let Err(e) = write() {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        self.overlay.clear();
+        // Deltas at or below the base sequence are skipped on replay, so a crash before the
+        // journal is removed is harmless
+        let _ = fs::remove_file(self.dir.join(JOURNAL_FILE));
+
+        self.base = None;
+        self.base_count = 0;
+        self.map_base()?;
+        self.rebuild_bloom();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn signed(key: &SigningKey, payload: &str) -> Vec<u8> {
+        let signature = key.sign(payload.as_bytes()).to_bytes();
+        [payload.as_bytes(), &signature].concat()
+    }
+
+    #[test]
+    fn test_signed_deltas_compaction_and_lookup() {
+        let dir = std::env::temp_dir().join(format!("hash_reputation_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let signing = SigningKey::from_bytes(&[7; 32]);
+        let public = signing.verifying_key().to_bytes();
+
+        let eicar = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+        let [sha256, sha1, md5] = file_digests(eicar);
+        let mut store = HashReputation::open(&dir, &public).unwrap();
+        let first = format!(
+            "FHRDELTA 1 1\n{}\tmalicious\tEICAR\tvendor-a\n{}\tclean\tsystem-app\tplay\n{}\tsuspicious\tdropper\tvendor-b\n",
+            hex::encode(&sha1),
+            "00".repeat(32),
+            hex::encode(&md5)
+        );
+        assert_eq!(store.apply_delta(&signed(&signing, &first)), Ok(3));
+        // Replayed, re-signed by someone else, or skipping ahead
+        assert_eq!(
+            store.apply_delta(&signed(&signing, &first)),
+            Err(DeltaError::OutOfSequence { expected: 2, found: 1 })
+        );
+        let forged = signed(&SigningKey::from_bytes(&[8; 32]), &first.replace(" 1\n", " 2\n"));
+        assert_eq!(store.apply_delta(&forged), Err(DeltaError::BadSignature));
+        assert_eq!(store.apply_delta(&signed(&signing, "FHRDELTA 1 2\nzz\tclean\n")), Err(DeltaError::InvalidEntry(2)));
+
+        // SHA-256 is queried first but unknown, so the SHA-1 entry answers
+        let found = store.lookup_digests(&[sha256.clone(), sha1.clone(), md5.clone()]).unwrap();
+        assert_eq!((found.kind, found.verdict, found.family.as_str()), (DigestKind::Sha1, Verdict::Malicious, "EICAR"));
+        assert_eq!(store.lookup(&[0; 32]).unwrap().verdict, Verdict::Clean);
+        assert_eq!(store.lookup(&[1; 32]), None);
+
+        // The journal survives a restart; compaction moves it into the mapped base store
+        let mut store = HashReputation::open(&dir, &public).unwrap();
+        assert_eq!(store.sequence(), 1);
+        store.compact().unwrap();
+        assert!(!dir.join(JOURNAL_FILE).exists());
+        let second = format!("FHRDELTA 1 2\n{}\tremove\n{}\tmalicious\tEICAR\tvendor-c\n", hex::encode(&sha1), hex::encode(&sha256));
+        assert_eq!(store.apply_delta(&signed(&signing, &second)), Ok(2));
+
+        let store = HashReputation::open(&dir, &public).unwrap();
+        assert_eq!(store.sequence(), 2);
+        assert_eq!(store.lookup(&sha1), None);
+        assert_eq!(store.lookup(&sha256).unwrap().source, "vendor-c");
+        assert_eq!(store.lookup(&md5).unwrap().family, "dropper");
+        assert_eq!(store.lookup(&[0; 32]).unwrap().source, "play");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_compaction_keeps_journaled_entries() {
+        let dir = std::env::temp_dir().join(format!("hash_reputation_unwritable_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let signing = SigningKey::from_bytes(&[7; 32]);
+        let public = signing.verifying_key().to_bytes();
+        let mut store = HashReputation::open(&dir, &public).unwrap();
+        let delta = format!("FHRDELTA 1 1\n{}\tmalicious\tEICAR\tvendor-a\n", "ab".repeat(32));
+        assert_eq!(store.apply_delta(&signed(&signing, &delta)), Ok(1));
+
+        // A directory in the way of the base file makes the final rename fail
+        fs::create_dir_all(dir.join(BASE_FILE).join("blocker")).unwrap();
+        assert!(store.compact().is_err());
+        assert_eq!(store.lookup(&[0xab; 32]).unwrap().family, "EICAR");
+        assert!(dir.join(JOURNAL_FILE).exists());
+        assert!(!dir.join(BASE_FILE).with_extension("tmp").exists());
+
+        fs::remove_dir_all(dir.join(BASE_FILE)).unwrap();
+        store.compact().unwrap();
+        assert_eq!(store.lookup(&[0xab; 32]).unwrap().family, "EICAR");
+        let store = HashReputation::open(&dir, &public).unwrap();
+        assert_eq!(store.lookup(&[0xab; 32]).unwrap().source, "vendor-a");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
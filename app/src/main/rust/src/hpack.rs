@@ -0,0 +1,406 @@
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+// Decoded header lists are capped the way SETTINGS_MAX_HEADER_LIST_SIZE would cap them,
+// counting each field as name + value + 32 (RFC 7541 4.1). This is what stops a few bytes
+// of indexed references to one large table entry from expanding without bound.
+pub const MAX_HEADER_LIST_SIZE: usize = 64 * 1024;
+const MAX_HEADER_FIELDS: usize = 256;
+
+// Largest dynamic table we allow a peer to negotiate, whatever its SETTINGS say
+pub const MAX_TABLE_SIZE: usize = 64 * 1024;
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+const ENTRY_OVERHEAD: usize = 32;
+const EOS: u16 = 256;
+
+/// Why a header block could not be decoded. Any of these desynchronises the
+/// dynamic table, so the connection can't be decoded any further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpackError {
+    Truncated,
+    IntegerOverflow,
+    InvalidIndex,
+    InvalidHuffman,
+    /// A dynamic table size update above the negotiated limit
+    TableSizeExceeded,
+    HeaderListTooLarge,
+}
+
+// RFC 7541 Appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Code lengths of the RFC 7541 Appendix B Huffman code, symbols 0..=256 (EOS).
+// The code is canonical, so the codes themselves follow from the lengths.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+const MAX_CODE_LEN: usize = 30;
+
+// Canonical decoding tables: per code length, the first code, how many codes
+// have that length, and where their symbols start in `symbols`
+struct HuffmanTable {
+    first: [u32; MAX_CODE_LEN + 1],
+    count: [u32; MAX_CODE_LEN + 1],
+    offset: [usize; MAX_CODE_LEN + 1],
+    symbols: Vec<u16>,
+}
+
+fn huffman_table() -> &'static HuffmanTable {
+    static TABLE: OnceLock<HuffmanTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..=EOS).collect();
+        symbols.sort_by_key(|&s| (HUFFMAN_LENGTHS[s as usize], s));
+        let mut count = [0u32; MAX_CODE_LEN + 1];
+        for &len in &HUFFMAN_LENGTHS {
+            count[len as usize] += 1;
+        }
+        let mut first = [0u32; MAX_CODE_LEN + 1];
+        let mut offset = [0usize; MAX_CODE_LEN + 1];
+        for len in 1..=MAX_CODE_LEN {
+            first[len] = (first[len - 1] + count[len - 1]) << 1;
+            offset[len] = offset[len - 1] + count[len - 1] as usize;
+        }
+        HuffmanTable { first, count, offset, symbols }
+    })
+}
+
+/// Decode a Huffman-coded string literal
+pub fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let table = huffman_table();
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code = 0u32;
+    let mut len = 0usize;
+    for &byte in data {
+        for shift in (0..8).rev() {
+            code = (code << 1) | ((byte >> shift) & 1) as u32;
+            len += 1;
+            if len > MAX_CODE_LEN {
+                return Err(HpackError::InvalidHuffman);
+            }
+            let index = code.wrapping_sub(table.first[len]);
+            if code >= table.first[len] && index < table.count[len] {
+                let symbol = table.symbols[table.offset[len] + index as usize];
+                if symbol == EOS {
+                    return Err(HpackError::InvalidHuffman);
+                }
+                out.push(symbol as u8);
+                code = 0;
+                len = 0;
+            }
+        }
+    }
+    // Padding is a prefix of EOS: fewer than eight bits, all ones
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(HpackError::InvalidHuffman);
+    }
+    Ok(out)
+}
+
+/// Header block decoder for one direction of an HTTP/2 connection
+pub struct HpackDecoder {
+    table: VecDeque<(String, String)>,
+    table_size: usize,
+    /// Size set by the encoder's most recent table size update
+    max_size: usize,
+    /// Ceiling the encoder may raise it to, from the peer's SETTINGS
+    allowed_size: usize,
+}
+
+impl Default for HpackDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HpackDecoder {
+    pub fn new() -> Self {
+        Self { table: VecDeque::new(), table_size: 0, max_size: DEFAULT_TABLE_SIZE, allowed_size: DEFAULT_TABLE_SIZE }
+    }
+
+    /// Apply SETTINGS_HEADER_TABLE_SIZE; values above MAX_TABLE_SIZE are clamped, so an
+    /// encoder that actually uses them fails with TableSizeExceeded
+    pub fn set_allowed_table_size(&mut self, size: usize) {
+        self.allowed_size = size.min(MAX_TABLE_SIZE);
+        if self.max_size > self.allowed_size {
+            self.max_size = self.allowed_size;
+            self.evict();
+        }
+    }
+
+    /// Bytes currently held by the dynamic table, as RFC 7541 counts them
+    pub fn table_size(&self) -> usize {
+        self.table_size
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+        let mut pos = 0;
+
+        while pos < block.len() {
+            let first = block[pos];
+            let (name, value) = if first & 0x80 != 0 {
+                let index = read_integer(block, &mut pos, 7)?;
+                let (name, value) = self.entry(index)?;
+                // Check before copying so repeated references can't allocate past the limit
+                if list_size + name.len() + value.len() + ENTRY_OVERHEAD > MAX_HEADER_LIST_SIZE {
+                    return Err(HpackError::HeaderListTooLarge);
+                }
+                (name.to_string(), value.to_string())
+            } else if first & 0x40 != 0 {
+                let (name, value) = self.read_literal(block, &mut pos, 6)?;
+                self.insert(name.clone(), value.clone());
+                (name, value)
+            } else if first & 0x20 != 0 {
+                let size = read_integer(block, &mut pos, 5)?;
+                if size > self.allowed_size {
+                    return Err(HpackError::TableSizeExceeded);
+                }
+                self.max_size = size;
+                self.evict();
+                continue;
+            } else {
+                // Without indexing (0000) and never indexed (0001) differ only for proxies
+                self.read_literal(block, &mut pos, 4)?
+            };
+
+            list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+            if list_size > MAX_HEADER_LIST_SIZE || headers.len() >= MAX_HEADER_FIELDS {
+                return Err(HpackError::HeaderListTooLarge);
+            }
+            headers.push((name, value));
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(&str, &str), HpackError> {
+        match index {
+            0 => Err(HpackError::InvalidIndex),
+            1..=61 => Ok(STATIC_TABLE[index - 1]),
+            _ => self
+                .table
+                .get(index - 62)
+                .map(|(n, v)| (n.as_str(), v.as_str()))
+                .ok_or(HpackError::InvalidIndex),
+        }
+    }
+
+    fn read_literal(&self, block: &[u8], pos: &mut usize, prefix: u8) -> Result<(String, String), HpackError> {
+        let index = read_integer(block, pos, prefix)?;
+        let name = if index == 0 { read_string(block, pos)? } else { self.entry(index)?.0.to_string() };
+        let value = read_string(block, pos)?;
+        Ok((name, value))
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        // An entry larger than the whole table empties it (RFC 7541 4.4)
+        if size > self.max_size {
+            self.table.clear();
+            self.table_size = 0;
+            return;
+        }
+        self.table.push_front((name, value));
+        self.table_size += size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.table_size > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else { break };
+            self.table_size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+// Prefix-coded integer (RFC 7541 5.1); anything past 2^28 is treated as hostile
+fn read_integer(block: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, HpackError> {
+    let mask = (1u16 << prefix) as usize - 1;
+    let mut value = *block.get(*pos).ok_or(HpackError::Truncated)? as usize & mask;
+    *pos += 1;
+    if value < mask {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let byte = *block.get(*pos).ok_or(HpackError::Truncated)?;
+        *pos += 1;
+        value += ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        if shift > 21 {
+            return Err(HpackError::IntegerOverflow);
+        }
+    }
+}
+
+fn read_string(block: &[u8], pos: &mut usize) -> Result<String, HpackError> {
+    let huffman = block.get(*pos).ok_or(HpackError::Truncated)? & 0x80 != 0;
+    let len = read_integer(block, pos, 7)?;
+    let end = pos.checked_add(len).ok_or(HpackError::Truncated)?;
+    let raw = block.get(*pos..end).ok_or(HpackError::Truncated)?;
+    *pos = end;
+    let bytes = if huffman { huffman_decode(raw)? } else { raw.to_vec() };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        hex::decode(s.replace(' ', "")).unwrap()
+    }
+
+    /// Literal header field with incremental indexing and a new, uncompressed name
+    pub(crate) fn literal(name: &str, value: &str) -> Vec<u8> {
+        let mut out = vec![0x40];
+        for s in [name, value] {
+            assert!(s.len() < 127);
+            out.push(s.len() as u8);
+            out.extend_from_slice(s.as_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn test_rfc7541_huffman_requests() {
+        // RFC 7541 C.4: three requests on one connection sharing the dynamic table
+        let mut decoder = HpackDecoder::new();
+        let first = decoder.decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff")).unwrap();
+        assert_eq!(first[3], (":authority".to_string(), "www.example.com".to_string()));
+        assert_eq!(decoder.table_size(), 57);
+
+        let second = decoder.decode(&hex("8286 84be 5886 a8eb 1064 9cbf")).unwrap();
+        assert_eq!(second[3].1, "www.example.com");
+        assert_eq!(second[4], ("cache-control".to_string(), "no-cache".to_string()));
+
+        let third = decoder.decode(&hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf")).unwrap();
+        assert_eq!(third[1].1, "https");
+        assert_eq!(third[2].1, "/index.html");
+        assert_eq!(third[4], ("custom-key".to_string(), "custom-value".to_string()));
+        assert_eq!(decoder.table_size(), 164);
+
+        assert_eq!(huffman_decode(&hex("d07a be94 1054 d444 a820 0595 040b 8166 e082 a62d 1bff")).unwrap(), b"Mon, 21 Oct 2013 20:13:21 GMT");
+        // Padding that isn't all ones, and a full EOS symbol
+        assert!(huffman_decode(&[0x00]).is_err());
+        assert!(huffman_decode(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_bombs_and_bad_input_are_bounded() {
+        // One 4000-byte entry, then thousands of one-byte references to it
+        let mut decoder = HpackDecoder::new();
+        let mut block = vec![0x40, 0x01, b'x', 0x7F];
+        let mut length = Vec::new();
+        let mut n = 4000 - 127;
+        while n >= 128 {
+            length.push((n % 128) as u8 | 0x80);
+            n /= 128;
+        }
+        length.push(n as u8);
+        block.extend_from_slice(&length);
+        block.extend(std::iter::repeat_n(b'a', 4000));
+        block.extend(std::iter::repeat_n(0xBE, 5000)); // index 62
+        assert_eq!(decoder.decode(&block), Err(HpackError::HeaderListTooLarge));
+
+        // Table size updates beyond what SETTINGS allowed
+        let mut decoder = HpackDecoder::new();
+        decoder.set_allowed_table_size(1 << 30);
+        assert_eq!(decoder.decode(&[0x3F, 0xE1, 0xFF, 0x07]), Err(HpackError::TableSizeExceeded));
+
+        let mut decoder = HpackDecoder::new();
+        assert_eq!(decoder.decode(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]), Err(HpackError::IntegerOverflow));
+        assert_eq!(decoder.decode(&[0xBE]), Err(HpackError::InvalidIndex));
+        assert_eq!(decoder.decode(&[0x40, 0x05, b'a']), Err(HpackError::Truncated));
+
+        // Size update to zero evicts everything
+        let mut decoder = HpackDecoder::new();
+        decoder.decode(&literal("x-a", "1")).unwrap();
+        assert!(decoder.table_size() > 0);
+        decoder.decode(&[0x20]).unwrap();
+        assert_eq!(decoder.table_size(), 0);
+    }
+}
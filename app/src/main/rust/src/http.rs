@@ -0,0 +1,159 @@
+// Request methods recognised at the start of a TCP payload
+const METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE"];
+
+// Header bytes examined per message
+const MAX_HEAD_LEN: usize = 16 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// Request line and headers of an HTTP/1.x request
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    /// Body bytes present in the same segment as the head
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// First header with this name, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.header("host")
+    }
+
+    /// Declared body length, falling back to what arrived with the head
+    pub fn body_len(&self) -> u64 {
+        content_length(&self.headers).unwrap_or(self.body.len() as u64)
+    }
+
+    /// HTTP/1.1 wire form, so byte-level rules see requests from any HTTP version alike
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut out = format!("{} {} HTTP/1.1\r\n", self.method, self.uri).into_bytes();
+        for (name, value) in &self.headers {
+            out.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
+
+/// Status line and headers of an HTTP/1.x response
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub version: String,
+    pub status_code: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body_len: u64,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+}
+
+fn content_length(headers: &[(String, String)]) -> Option<u64> {
+    find_header(headers, "content-length").and_then(|v| v.trim().parse().ok())
+}
+
+// Split the head into lines; returns the lines and where the body starts
+fn split_head(payload: &[u8]) -> (Vec<&[u8]>, usize) {
+    let head = &payload[..payload.len().min(MAX_HEAD_LEN)];
+    let (head, body_start) = match head.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => (&head[..end], end + 4),
+        None => (head, payload.len()),
+    };
+    let lines = head.split(|&b| b == b'\n').map(|l| l.strip_suffix(b"\r").unwrap_or(l)).collect();
+    (lines, body_start)
+}
+
+fn parse_headers(lines: &[&[u8]]) -> Vec<(String, String)> {
+    lines
+        .iter()
+        .take(MAX_HEADERS)
+        .filter_map(|line| {
+            let colon = line.iter().position(|&b| b == b':')?;
+            let name = String::from_utf8_lossy(&line[..colon]).trim().to_string();
+            let value = String::from_utf8_lossy(&line[colon + 1..]).trim().to_string();
+            (!name.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+/// Parse a request head from the start of a TCP payload
+pub fn parse_request(payload: &[u8]) -> Option<HttpRequest> {
+    let first_space = payload.iter().take(8).position(|&b| b == b' ')?;
+    let method = std::str::from_utf8(&payload[..first_space]).ok()?;
+    if !METHODS.contains(&method) {
+        return None;
+    }
+
+    let (lines, body_start) = split_head(payload);
+    let request_line = String::from_utf8_lossy(lines.first()?);
+    let mut parts = request_line.split(' ');
+    let method = parts.next()?.to_string();
+    let uri = parts.next()?.to_string();
+    let version = parts.next().unwrap_or("").trim_start_matches("HTTP/").to_string();
+
+    Some(HttpRequest {
+        method,
+        uri,
+        version,
+        headers: parse_headers(&lines[1..]),
+        body: payload.get(body_start..).unwrap_or(&[]).to_vec(),
+    })
+}
+
+/// Parse a response head from the start of a TCP payload
+pub fn parse_response(payload: &[u8]) -> Option<HttpResponse> {
+    if !payload.starts_with(b"HTTP/") {
+        return None;
+    }
+    let (lines, body_start) = split_head(payload);
+    let status_line = String::from_utf8_lossy(lines.first()?);
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next()?.trim_start_matches("HTTP/").to_string();
+    let status_code = parts.next()?.parse().ok()?;
+    let reason = parts.next().unwrap_or("").to_string();
+    let headers = parse_headers(&lines[1..]);
+    let body_len = content_length(&headers).unwrap_or(payload.len().saturating_sub(body_start) as u64);
+
+    Some(HttpResponse { version, status_code, reason, headers, body_len })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_response_heads() {
+        let request = parse_request(
+            b"POST /api/login?next=%2F HTTP/1.1\r\nHost: Example.com\r\nUser-Agent: okhttp/4.12\r\nContent-Length: 27\r\n\r\nuser=alice&password=hunter2",
+        )
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.uri, "/api/login?next=%2F");
+        assert_eq!(request.version, "1.1");
+        assert_eq!(request.host(), Some("Example.com"));
+        assert_eq!(request.header("user-agent"), Some("okhttp/4.12"));
+        assert_eq!(request.body_len(), 27);
+
+        let response = parse_response(b"HTTP/1.1 302 Found\r\nLocation: /home\r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert_eq!(response.status_code, 302);
+        assert_eq!(response.reason, "Found");
+        assert_eq!(response.header("location"), Some("/home"));
+
+        assert!(parse_request(b"\x16\x03\x01\x02\x00").is_none());
+    }
+}
@@ -0,0 +1,409 @@
+use ahash::AHashMap;
+
+use crate::hpack::{HpackDecoder, HpackError, MAX_HEADER_LIST_SIZE};
+use crate::http::{HttpRequest, HttpResponse};
+
+/// First bytes a client sends on every HTTP/2 connection (RFC 9113 3.4)
+pub const CLIENT_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_LEN: usize = 9;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PUSH_PROMISE: u8 = 0x5;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+
+// Memory bounds per connection. Peers may announce frames up to 16 MiB; we stop decoding
+// a connection rather than buffer one that large.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+const MAX_OPEN_STREAMS: usize = 128;
+const MAX_BODY_CAPTURE: usize = 64 * 1024;
+
+/// Why decoding of a connection stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Http2Error {
+    BadPreface,
+    FrameTooLarge,
+    HeaderBlockTooLarge,
+    /// A frame that makes no sense where it appeared, e.g. an interleaved CONTINUATION
+    Protocol,
+    Compression(HpackError),
+}
+
+/// Headers (pseudo-headers included) and captured body of one side of a stream
+#[derive(Debug, Clone, Default)]
+pub struct Http2Message {
+    pub stream_id: u32,
+    pub headers: Vec<(String, String)>,
+    /// First MAX_BODY_CAPTURE bytes of DATA
+    pub body: Vec<u8>,
+    /// All DATA bytes, padding excluded
+    pub body_len: u64,
+}
+
+impl Http2Message {
+    /// First header with this name; pseudo-headers are looked up with their colon
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    fn regular_headers(&self) -> Vec<(String, String)> {
+        self.headers.iter().filter(|(n, _)| !n.starts_with(':')).cloned().collect()
+    }
+
+    /// The request as HTTP/1.x would have carried it: `:authority` becomes Host
+    pub fn to_request(&self) -> Option<HttpRequest> {
+        let method = self.header(":method")?.to_string();
+        let uri = match method.as_str() {
+            "CONNECT" => self.header(":authority")?.to_string(),
+            _ => self.header(":path")?.to_string(),
+        };
+        let mut headers = self.regular_headers();
+        if let Some(authority) = self.header(":authority") {
+            if !headers.iter().any(|(n, _)| n == "host") {
+                headers.insert(0, ("host".to_string(), authority.to_string()));
+            }
+        }
+        Some(HttpRequest { method, uri, version: "2".to_string(), headers, body: self.body.clone() })
+    }
+
+    pub fn to_response(&self) -> Option<HttpResponse> {
+        let status_code = self.header(":status")?.parse().ok()?;
+        let headers = self.regular_headers();
+        let body_len = headers
+            .iter()
+            .find(|(n, _)| n == "content-length")
+            .and_then(|(_, v)| v.parse().ok())
+            .unwrap_or(self.body_len);
+        Some(HttpResponse { version: "2".to_string(), status_code, reason: String::new(), headers, body_len })
+    }
+}
+
+/// Stream halves handed back once they end or are reset
+#[derive(Debug, Clone)]
+pub enum Http2Event {
+    Request(Http2Message),
+    Response(Http2Message),
+}
+
+// A header block waiting for its CONTINUATION frames
+struct PendingBlock {
+    stream_id: u32,
+    end_stream: bool,
+    /// PUSH_PROMISE blocks are decoded to keep HPACK in sync, then dropped
+    promise: bool,
+    block: Vec<u8>,
+}
+
+// Frame reassembly and HPACK state for one direction
+#[derive(Default)]
+struct Half {
+    buffer: Vec<u8>,
+    decoder: HpackDecoder,
+    pending: Option<PendingBlock>,
+}
+
+/// Decoder for both directions of one HTTP/2 connection
+pub struct Http2Connection {
+    client: Half,
+    server: Half,
+    preface_seen: bool,
+    // Keyed by (sent by client, stream id); a stream ends independently in each direction
+    open: AHashMap<(bool, u32), Http2Message>,
+    error: Option<Http2Error>,
+}
+
+impl Default for Http2Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Http2Connection {
+    pub fn new() -> Self {
+        Self {
+            client: Half::default(),
+            server: Half::default(),
+            preface_seen: false,
+            open: AHashMap::new(),
+            error: None,
+        }
+    }
+
+    /// Set once the connection can't be decoded; later data is ignored
+    pub fn error(&self) -> Option<Http2Error> {
+        self.error
+    }
+
+    /// Feed bytes in one direction; returns every stream half that completed
+    pub fn feed(&mut self, from_client: bool, data: &[u8]) -> Vec<Http2Event> {
+        let mut events = Vec::new();
+        if self.error.is_some() {
+            return events;
+        }
+        if let Err(error) = self.process(from_client, data, &mut events) {
+            self.error = Some(error);
+            self.client = Half::default();
+            self.server = Half::default();
+            // Whatever was captured so far is still worth inspecting
+            for ((client, _), message) in self.open.drain() {
+                events.push(if client { Http2Event::Request(message) } else { Http2Event::Response(message) });
+            }
+        }
+        events
+    }
+
+    fn process(&mut self, from_client: bool, data: &[u8], events: &mut Vec<Http2Event>) -> Result<(), Http2Error> {
+        let half = if from_client { &mut self.client } else { &mut self.server };
+        half.buffer.extend_from_slice(data);
+
+        if from_client && !self.preface_seen {
+            let len = half.buffer.len().min(CLIENT_PREFACE.len());
+            if half.buffer[..len] != CLIENT_PREFACE[..len] {
+                return Err(Http2Error::BadPreface);
+            }
+            if len < CLIENT_PREFACE.len() {
+                return Ok(());
+            }
+            half.buffer.drain(..CLIENT_PREFACE.len());
+            self.preface_seen = true;
+        }
+
+        loop {
+            let half = if from_client { &self.client } else { &self.server };
+            let Some(header) = half.buffer.get(..FRAME_HEADER_LEN) else { return Ok(()) };
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            if length > MAX_FRAME_LEN {
+                return Err(Http2Error::FrameTooLarge);
+            }
+            if half.buffer.len() < FRAME_HEADER_LEN + length {
+                return Ok(());
+            }
+            let kind = header[3];
+            let flags = header[4];
+            let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF;
+
+            let half = if from_client { &mut self.client } else { &mut self.server };
+            let frame: Vec<u8> = half.buffer.drain(..FRAME_HEADER_LEN + length).skip(FRAME_HEADER_LEN).collect();
+            self.frame(from_client, kind, flags, stream_id, &frame, events)?;
+        }
+    }
+
+    fn frame(
+        &mut self,
+        from_client: bool,
+        kind: u8,
+        flags: u8,
+        stream_id: u32,
+        payload: &[u8],
+        events: &mut Vec<Http2Event>,
+    ) -> Result<(), Http2Error> {
+        let half = if from_client { &mut self.client } else { &mut self.server };
+
+        // Nothing may come between a header block and its continuations
+        if let Some(pending) = &mut half.pending {
+            if kind != FRAME_CONTINUATION || stream_id != pending.stream_id {
+                return Err(Http2Error::Protocol);
+            }
+            if pending.block.len() + payload.len() > MAX_HEADER_BLOCK {
+                return Err(Http2Error::HeaderBlockTooLarge);
+            }
+            pending.block.extend_from_slice(payload);
+            if flags & FLAG_END_HEADERS != 0 {
+                let pending = half.pending.take().expect("pending block");
+                self.header_block(from_client, pending, events)?;
+            }
+            return Ok(());
+        }
+
+        match kind {
+            FRAME_DATA => {
+                let data = strip_padding(payload, flags)?;
+                let key = (from_client, stream_id);
+                if let Some(message) = self.open.get_mut(&key) {
+                    let room = MAX_BODY_CAPTURE.saturating_sub(message.body.len());
+                    message.body.extend_from_slice(&data[..data.len().min(room)]);
+                    message.body_len += data.len() as u64;
+                }
+                if flags & FLAG_END_STREAM != 0 {
+                    self.finish(key, events);
+                }
+            }
+            FRAME_HEADERS | FRAME_PUSH_PROMISE => {
+                let mut fragment = strip_padding(payload, flags)?;
+                if kind == FRAME_HEADERS && flags & FLAG_PRIORITY != 0 {
+                    fragment = fragment.get(5..).ok_or(Http2Error::Protocol)?;
+                }
+                if kind == FRAME_PUSH_PROMISE {
+                    fragment = fragment.get(4..).ok_or(Http2Error::Protocol)?;
+                }
+                let pending = PendingBlock {
+                    stream_id,
+                    end_stream: kind == FRAME_HEADERS && flags & FLAG_END_STREAM != 0,
+                    promise: kind == FRAME_PUSH_PROMISE,
+                    block: fragment.to_vec(),
+                };
+                if flags & FLAG_END_HEADERS != 0 {
+                    self.header_block(from_client, pending, events)?;
+                } else {
+                    half.pending = Some(pending);
+                }
+            }
+            FRAME_CONTINUATION => return Err(Http2Error::Protocol),
+            FRAME_RST_STREAM => {
+                self.finish((true, stream_id), events);
+                self.finish((false, stream_id), events);
+            }
+            FRAME_SETTINGS if flags & FLAG_ACK == 0 => {
+                // A table size limit constrains the other side's encoder
+                let peer = if from_client { &mut self.server } else { &mut self.client };
+                for setting in payload.chunks_exact(6) {
+                    let id = u16::from_be_bytes([setting[0], setting[1]]);
+                    let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                    if id == SETTINGS_HEADER_TABLE_SIZE {
+                        peer.decoder.set_allowed_table_size(value as usize);
+                    }
+                }
+            }
+            // PING, GOAWAY, WINDOW_UPDATE, PRIORITY and extensions carry nothing to inspect
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn header_block(&mut self, from_client: bool, pending: PendingBlock, events: &mut Vec<Http2Event>) -> Result<(), Http2Error> {
+        let half = if from_client { &mut self.client } else { &mut self.server };
+        let headers = half.decoder.decode(&pending.block).map_err(Http2Error::Compression)?;
+        if pending.promise {
+            return Ok(());
+        }
+
+        let key = (from_client, pending.stream_id);
+        let at_capacity = self.open.len() >= MAX_OPEN_STREAMS;
+        match self.open.get_mut(&key) {
+            // Informational responses are replaced by the final one
+            Some(message) if message.header(":status").is_some_and(|s| s.starts_with('1')) => {
+                message.headers = headers;
+            }
+            // Trailers
+            Some(message) => {
+                let size: usize = message.headers.iter().chain(&headers).map(|(n, v)| n.len() + v.len()).sum();
+                if size <= MAX_HEADER_LIST_SIZE {
+                    message.headers.extend(headers);
+                }
+            }
+            None if at_capacity => {}
+            None => {
+                self.open.insert(key, Http2Message { stream_id: pending.stream_id, headers, ..Default::default() });
+            }
+        }
+        if pending.end_stream {
+            self.finish(key, events);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, key: (bool, u32), events: &mut Vec<Http2Event>) {
+        let Some(message) = self.open.remove(&key) else { return };
+        events.push(if key.0 { Http2Event::Request(message) } else { Http2Event::Response(message) });
+    }
+}
+
+// Payload of a DATA, HEADERS or PUSH_PROMISE frame without its padding
+fn strip_padding(payload: &[u8], flags: u8) -> Result<&[u8], Http2Error> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    let (&pad, rest) = payload.split_first().ok_or(Http2Error::Protocol)?;
+    rest.get(..rest.len().checked_sub(pad as usize).ok_or(Http2Error::Protocol)?).ok_or(Http2Error::Protocol)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::hpack::tests::literal;
+
+    pub(crate) fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        out.extend_from_slice(&[kind, flags]);
+        out.extend_from_slice(&stream_id.to_be_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// Preface, SETTINGS and a POST on stream 1 with the given body, as a client sends it
+    pub(crate) fn post_request(authority: &str, path: &str, body: &[u8]) -> Vec<u8> {
+        let mut block = vec![0x83, 0x86]; // :method POST, :scheme http
+        block.extend(literal(":authority", authority));
+        block.extend(literal(":path", path));
+        let mut out = CLIENT_PREFACE.to_vec();
+        out.extend(frame(FRAME_SETTINGS, 0, 0, &[0, 1, 0, 0, 0x10, 0]));
+        out.extend(frame(FRAME_HEADERS, FLAG_END_HEADERS, 1, &block));
+        out.extend(frame(FRAME_DATA, FLAG_END_STREAM, 1, body));
+        out
+    }
+
+    #[test]
+    fn test_request_and_response_across_segments() {
+        let mut connection = Http2Connection::new();
+        let client = post_request("api.example", "/v1/upload?id=7", b"secret=1");
+
+        // Split mid-frame, as TCP is free to
+        let (first, second) = client.split_at(40);
+        assert!(connection.feed(true, first).is_empty());
+        let events = connection.feed(true, second);
+        assert_eq!(events.len(), 1);
+        let Http2Event::Request(message) = &events[0] else { panic!("expected request") };
+        let request = message.to_request().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.uri, "/v1/upload?id=7");
+        assert_eq!(request.host(), Some("api.example"));
+        assert_eq!(request.body, b"secret=1");
+
+        // Response headers split over HEADERS + CONTINUATION with a 103 first, then padded DATA
+        let mut server = frame(FRAME_HEADERS, FLAG_END_HEADERS, 1, &literal(":status", "103"));
+        let mut block = vec![0x88]; // :status 200
+        block.extend(literal("content-type", "text/plain"));
+        server.extend(frame(FRAME_HEADERS, 0, 1, &block[..3]));
+        server.extend(frame(FRAME_CONTINUATION, FLAG_END_HEADERS, 1, &block[3..]));
+        server.extend(frame(FRAME_DATA, FLAG_PADDED | FLAG_END_STREAM, 1, &[2, b'o', b'k', 0, 0]));
+        let events = connection.feed(false, &server);
+        let Http2Event::Response(message) = &events[0] else { panic!("expected response") };
+        let response = message.to_response().unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.body_len, 2);
+    }
+
+    #[test]
+    fn test_malformed_connections_stop_decoding() {
+        let mut connection = Http2Connection::new();
+        assert!(connection.feed(true, b"GET / HTTP/1.1\r\n").is_empty());
+        assert_eq!(connection.error(), Some(Http2Error::BadPreface));
+
+        let mut connection = Http2Connection::new();
+        let mut data = CLIENT_PREFACE.to_vec();
+        data.extend(frame(FRAME_HEADERS, 0, 1, &[0x82]));
+        data.extend(frame(FRAME_DATA, 0, 1, b"x"));
+        connection.feed(true, &data);
+        assert_eq!(connection.error(), Some(Http2Error::Protocol));
+
+        let mut connection = Http2Connection::new();
+        let mut data = CLIENT_PREFACE.to_vec();
+        data.extend_from_slice(&[0xFF, 0xFF, 0xFF, FRAME_DATA, 0, 0, 0, 0, 1]);
+        connection.feed(true, &data);
+        assert_eq!(connection.error(), Some(Http2Error::FrameTooLarge));
+        assert!(connection.feed(true, &post_request("a", "/", b"")).is_empty());
+    }
+}
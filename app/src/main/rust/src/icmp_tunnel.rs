@@ -0,0 +1,401 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+
+use ahash::{AHashMap, AHashSet};
+use serde::Serialize;
+
+// ICMPv4 and ICMPv6 echo types
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+// Bounds on tracked echo sessions and outstanding requests per session
+const MAX_SESSIONS: usize = 1024;
+const MAX_PENDING_REQUESTS: usize = 64;
+const SESSION_IDLE_MS: u64 = 60_000;
+
+// Echo messages seen before a session is judged at all
+const MIN_MESSAGES: u32 = 3;
+const TUNNEL_THRESHOLD: f32 = 0.85;
+
+// Anything above the classic 56-byte ping plus generous headroom
+const OVERSIZED_PAYLOAD: usize = 128;
+const HIGH_RATE_PER_SEC: u32 = 10;
+
+/// Evidence that an echo session carries something other than ping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IcmpTunnelSignal {
+    /// Payload doesn't follow any ping implementation's fill pattern
+    NonStandardPayload,
+    /// Payload looks compressed or encrypted
+    HighEntropy,
+    OversizedPayload,
+    /// Reply data differs from the request, which RFC 792 and 4443 forbid
+    ReplyMismatch,
+    /// Payload size changes between requests; ping keeps it constant
+    VaryingSizes,
+    HighRate,
+}
+
+impl IcmpTunnelSignal {
+    fn weight(self) -> f32 {
+        match self {
+            IcmpTunnelSignal::ReplyMismatch => 0.8,
+            IcmpTunnelSignal::HighEntropy => 0.6,
+            IcmpTunnelSignal::NonStandardPayload => 0.5,
+            IcmpTunnelSignal::VaryingSizes => 0.4,
+            IcmpTunnelSignal::OversizedPayload => 0.3,
+            IcmpTunnelSignal::HighRate => 0.3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IcmpTunnelDetection {
+    /// Host sending the echo requests
+    pub requester: IpAddr,
+    pub responder: IpAddr,
+    pub identifier: u16,
+    pub signals: Vec<IcmpTunnelSignal>,
+    pub confidence: f32,
+    pub requests: u32,
+    pub replies: u32,
+    pub request_bytes: u64,
+    pub reply_bytes: u64,
+}
+
+/// Outcome of feeding one ICMP message to the detector
+#[derive(Debug)]
+pub enum IcmpAssessment {
+    /// Not an echo, or an echo session that looks like ping
+    Clean,
+    /// Part of a session already reported as a tunnel
+    Tunnel,
+    /// This message tipped the session over the threshold
+    NewTunnel(IcmpTunnelDetection),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct SessionKey {
+    requester: IpAddr,
+    responder: IpAddr,
+    identifier: u16,
+}
+
+#[derive(Default)]
+struct EchoSession {
+    first_ms: u64,
+    last_ms: u64,
+    requests: u32,
+    replies: u32,
+    request_bytes: u64,
+    reply_bytes: u64,
+    nonstandard: u32,
+    high_entropy: u32,
+    oversized: u32,
+    mismatched: u32,
+    sizes: AHashSet<usize>,
+    // (sequence, payload hash) of requests still waiting for a reply
+    pending: VecDeque<(u16, u64)>,
+    reported: bool,
+}
+
+impl EchoSession {
+    fn signals(&self) -> Vec<IcmpTunnelSignal> {
+        let messages = self.requests + self.replies;
+        let mut signals = Vec::new();
+        if self.mismatched > 0 {
+            signals.push(IcmpTunnelSignal::ReplyMismatch);
+        }
+        if self.high_entropy * 2 >= messages {
+            signals.push(IcmpTunnelSignal::HighEntropy);
+        }
+        if self.nonstandard * 2 >= messages {
+            signals.push(IcmpTunnelSignal::NonStandardPayload);
+        }
+        if self.sizes.len() >= 4 {
+            signals.push(IcmpTunnelSignal::VaryingSizes);
+        }
+        if self.oversized * 2 >= messages {
+            signals.push(IcmpTunnelSignal::OversizedPayload);
+        }
+        let elapsed_ms = self.last_ms.saturating_sub(self.first_ms).max(1);
+        if self.requests >= 20 && self.requests as u64 * 1000 / elapsed_ms > HIGH_RATE_PER_SEC as u64 {
+            signals.push(IcmpTunnelSignal::HighRate);
+        }
+        signals
+    }
+}
+
+/// Shannon entropy of a byte string in bits per byte
+pub fn shannon_entropy(data: &[u8]) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u32; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f32;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f32 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Whether an echo payload matches what ping tools put on the wire
+pub fn is_standard_ping_payload(payload: &[u8]) -> bool {
+    if payload.len() <= 16 {
+        return true;
+    }
+    // iputils/BSD prefix an 8 or 16 byte timestamp, then fill with incrementing bytes;
+    // Windows repeats "abcdefghijklmnopqrstuvw"; `ping -p` and busybox repeat a short pattern
+    let windows_alphabet = payload
+        .windows(2)
+        .all(|w| (b'a'..=b'w').contains(&w[0]) && w[1] == if w[0] == b'w' { b'a' } else { w[0] + 1 });
+    if windows_alphabet {
+        return true;
+    }
+    [0usize, 8, 16].iter().any(|&skip| {
+        let body = &payload[skip..];
+        body.len() >= 8 && (body.windows(2).all(|w| w[1] == w[0].wrapping_add(1)) || is_periodic(body, 32))
+    })
+}
+
+fn is_periodic(body: &[u8], max_period: usize) -> bool {
+    (1..=max_period.min(body.len() / 2)).any(|period| body[period..].iter().zip(body).all(|(a, b)| a == b))
+}
+
+// Entropy close to the maximum the sample size allows
+fn is_high_entropy(payload: &[u8]) -> bool {
+    if payload.len() < 64 {
+        return false;
+    }
+    let max_bits = (payload.len().min(256) as f32).log2();
+    shannon_entropy(payload) > max_bits * 0.8
+}
+
+fn payload_hash(payload: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    payload.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Tracks ICMP/ICMPv6 echo sessions and flags ptunnel/icmpsh style covert channels
+#[derive(Default)]
+pub struct IcmpTunnelDetector {
+    sessions: AHashMap<SessionKey, EchoSession>,
+}
+
+impl IcmpTunnelDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one ICMP message (header included) travelling from `src` to `dst`
+    pub fn observe(&mut self, src: IpAddr, dst: IpAddr, icmp: &[u8], now_ms: u64) -> IcmpAssessment {
+        if icmp.len() < 8 {
+            return IcmpAssessment::Clean;
+        }
+        // Only echo carries free-form data. Errors, neighbor discovery, router
+        // advertisements and MLD have fixed layouts and are never judged here.
+        let request = match (src, icmp[0]) {
+            (IpAddr::V4(_), ICMP_ECHO_REQUEST) | (IpAddr::V6(_), ICMPV6_ECHO_REQUEST) => true,
+            (IpAddr::V4(_), ICMP_ECHO_REPLY) | (IpAddr::V6(_), ICMPV6_ECHO_REPLY) => false,
+            _ => return IcmpAssessment::Clean,
+        };
+        let identifier = u16::from_be_bytes([icmp[4], icmp[5]]);
+        let sequence = u16::from_be_bytes([icmp[6], icmp[7]]);
+        let payload = &icmp[8..];
+
+        let key = if request {
+            SessionKey { requester: src, responder: dst, identifier }
+        } else {
+            SessionKey { requester: dst, responder: src, identifier }
+        };
+
+        if !self.sessions.contains_key(&key) {
+            if self.sessions.len() >= MAX_SESSIONS {
+                self.sessions.retain(|_, s| now_ms.saturating_sub(s.last_ms) <= SESSION_IDLE_MS);
+            }
+            if self.sessions.len() >= MAX_SESSIONS {
+                let oldest = self.sessions.iter().min_by_key(|(_, s)| s.last_ms).map(|(k, _)| *k);
+                if let Some(oldest) = oldest {
+                    self.sessions.remove(&oldest);
+                }
+            }
+        }
+
+        let session = self.sessions.entry(key).or_insert_with(|| EchoSession { first_ms: now_ms, ..Default::default() });
+        session.last_ms = now_ms;
+
+        if !is_standard_ping_payload(payload) {
+            session.nonstandard += 1;
+        }
+        if is_high_entropy(payload) {
+            session.high_entropy += 1;
+        }
+        if payload.len() > OVERSIZED_PAYLOAD {
+            session.oversized += 1;
+        }
+
+        let hash = payload_hash(payload);
+        if request {
+            session.requests += 1;
+            session.request_bytes += payload.len() as u64;
+            if session.sizes.len() < 16 {
+                session.sizes.insert(payload.len());
+            }
+            session.pending.push_back((sequence, hash));
+            if session.pending.len() > MAX_PENDING_REQUESTS {
+                session.pending.pop_front();
+            }
+        } else {
+            session.replies += 1;
+            session.reply_bytes += payload.len() as u64;
+            match session.pending.iter().position(|(seq, _)| *seq == sequence) {
+                Some(index) => {
+                    let (_, expected) = session.pending.remove(index).expect("index from position");
+                    if expected != hash {
+                        session.mismatched += 1;
+                    }
+                }
+                // Unsolicited replies are how some tunnels push data to the client
+                None => session.mismatched += 1,
+            }
+        }
+
+        if session.reported {
+            return IcmpAssessment::Tunnel;
+        }
+        if session.requests + session.replies < MIN_MESSAGES {
+            return IcmpAssessment::Clean;
+        }
+
+        let signals = session.signals();
+        let confidence = 1.0 - signals.iter().fold(1.0f32, |acc, s| acc * (1.0 - s.weight()));
+        if confidence < TUNNEL_THRESHOLD {
+            return IcmpAssessment::Clean;
+        }
+
+        session.reported = true;
+        IcmpAssessment::NewTunnel(IcmpTunnelDetection {
+            requester: key.requester,
+            responder: key.responder,
+            identifier,
+            signals,
+            confidence,
+            requests: session.requests,
+            replies: session.replies,
+            request_bytes: session.request_bytes,
+            reply_bytes: session.reply_bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(ty: u8, id: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
+        let mut icmp = vec![ty, 0, 0, 0];
+        icmp.extend_from_slice(&id.to_be_bytes());
+        icmp.extend_from_slice(&seq.to_be_bytes());
+        icmp.extend_from_slice(payload);
+        icmp
+    }
+
+    // iputils layout: 16-byte timeval followed by bytes equal to their offset
+    fn linux_ping_payload(seq: u16) -> Vec<u8> {
+        let mut payload: Vec<u8> = (0..56u8).collect();
+        payload[..8].copy_from_slice(&(1_700_000_000u64 + seq as u64).to_le_bytes());
+        payload
+    }
+
+    fn pseudo_random(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ping_sessions_stay_clean() {
+        let phone: IpAddr = "10.0.0.2".parse().unwrap();
+        let server: IpAddr = "8.8.8.8".parse().unwrap();
+        let mut detector = IcmpTunnelDetector::new();
+
+        for seq in 0..30 {
+            let payload = linux_ping_payload(seq);
+            let now = seq as u64 * 1000;
+            let request = detector.observe(phone, server, &echo(ICMP_ECHO_REQUEST, 0x1234, seq, &payload), now);
+            let reply = detector.observe(server, phone, &echo(ICMP_ECHO_REPLY, 0x1234, seq, &payload), now + 20);
+            assert!(matches!(request, IcmpAssessment::Clean));
+            assert!(matches!(reply, IcmpAssessment::Clean));
+        }
+
+        // Windows fills with the alphabet
+        let windows = b"abcdefghijklmnopqrstuvwabcdefghi";
+        for seq in 0..5 {
+            let result = detector.observe(phone, server, &echo(ICMP_ECHO_REQUEST, 1, seq, windows), seq as u64);
+            assert!(matches!(result, IcmpAssessment::Clean));
+        }
+    }
+
+    #[test]
+    fn test_icmpsh_style_tunnel_is_flagged() {
+        let phone: IpAddr = "10.0.0.2".parse().unwrap();
+        let master: IpAddr = "198.51.100.66".parse().unwrap();
+        let mut detector = IcmpTunnelDetector::new();
+
+        let mut detection = None;
+        for seq in 0..10u16 {
+            // Requests carry command output, replies carry the next command
+            let output = pseudo_random(seq as u64 + 1, 200 + seq as usize * 37);
+            let command = pseudo_random(seq as u64 + 100, 90);
+            let now = seq as u64 * 500;
+            for (src, dst, packet) in [
+                (phone, master, echo(ICMP_ECHO_REQUEST, 0x0001, seq, &output)),
+                (master, phone, echo(ICMP_ECHO_REPLY, 0x0001, seq, &command)),
+            ] {
+                if let IcmpAssessment::NewTunnel(found) = detector.observe(src, dst, &packet, now) {
+                    detection = Some(found);
+                }
+            }
+        }
+
+        let detection = detection.expect("tunnel should be detected");
+        assert_eq!(detection.requester, phone);
+        assert!(detection.signals.contains(&IcmpTunnelSignal::ReplyMismatch));
+        assert!(detection.signals.contains(&IcmpTunnelSignal::HighEntropy));
+        assert!(detection.confidence >= TUNNEL_THRESHOLD);
+    }
+
+    #[test]
+    fn test_neighbor_discovery_is_ignored() {
+        let router: IpAddr = "fe80::1".parse().unwrap();
+        let phone: IpAddr = "fe80::2".parse().unwrap();
+        let mut detector = IcmpTunnelDetector::new();
+
+        // Router advertisements carry prefix/RDNSS options that look random and vary in size
+        for (i, ty) in [133u8, 134, 135, 136, 137, 134, 134, 135].iter().enumerate() {
+            let mut icmp = vec![*ty, 0, 0, 0, 0x40, 0, 0, 0];
+            icmp.extend_from_slice(&pseudo_random(i as u64 + 7, 120 + i * 24));
+            assert!(matches!(detector.observe(router, phone, &icmp, i as u64), IcmpAssessment::Clean));
+        }
+        assert!(detector.sessions.is_empty());
+    }
+}
@@ -1,6 +1,87 @@
 #![allow(unused)]
 use std::ffi::{CStr, CString};
+use std::net::Ipv4Addr;
+use std::os::fd::{FromRawFd, OwnedFd};
 use std::os::raw::{c_char, c_int};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+use jni::objects::{JClass, JString};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+use lazy_static::lazy_static;
+
+mod axml;
+mod bloom;
+mod certificate;
+mod dex;
+mod clamav;
+mod cloud_reputation;
+mod dns;
+mod domain_history;
+mod elf;
+mod encrypted_dns;
+mod events;
+mod flow_export;
+mod flow_table;
+mod fuzzy_hash;
+mod geoip;
+mod hash_reputation;
+mod hpack;
+mod http;
+mod http2;
+mod icmp_tunnel;
+mod lookalike;
+mod memory_analysis;
+mod netstack;
+mod packet_inspection;
+mod pcap;
+mod perf;
+mod quic;
+mod scan_detection;
+mod scanner;
+mod signatures;
+mod threat_intel;
+mod tls;
+mod tun;
+mod url_analysis;
+mod yara;
+mod zip;
+mod zeek_log;
+
+use domain_history::{DomainHistory, DomainHistoryConfig};
+use encrypted_dns::EncryptedDnsPolicy;
+use flow_export::{ExportFormat, ExportTarget, FlowExporter};
+use geoip::{GeoRule, MmdbReader};
+use netstack::{NetStack, NetStackConfig};
+use packet_inspection::PacketInspector;
+use scan_detection::ScanThresholds;
+use tun::TunDevice;
+use zeek_log::{LogConfig, LogFormat, ZeekLogger};
+
+lazy_static! {
+    static ref INSPECTOR: Mutex<PacketInspector> = Mutex::new(PacketInspector::new());
+    static ref NETSTACK: Mutex<Option<(Arc<AtomicBool>, JoinHandle<()>)>> = Mutex::new(None);
+}
+
+// Counts allocations per thread so inspection can report allocations per packet
+#[global_allocator]
+static GLOBAL: perf::CountingAllocator = perf::CountingAllocator;
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+// Split a comma or newline separated C string list; `ptr` must be null or NUL-terminated
+unsafe fn c_str_list(ptr: *const c_char) -> Vec<String> {
+    if ptr.is_null() { return Vec::new(); }
+    let s = unsafe { CStr::from_ptr(ptr) }.to_string_lossy();
+    s.split([',', '\n'])
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
 
 #[no_mangle]
 pub extern "C" fn rust_scan_system(_paths: *const c_char) -> c_int {
@@ -8,13 +89,405 @@ pub extern "C" fn rust_scan_system(_paths: *const c_char) -> c_int {
     0
 }
 
+/// # Safety
+///
+/// `packet` must be null or point to `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rust_inspect_packet(packet: *const u8, length: c_int) -> c_int {
+    if packet.is_null() || length <= 0 { return 0; }
+    let packet = unsafe { std::slice::from_raw_parts(packet, length as usize) };
+    INSPECTOR.lock().unwrap().analyze(packet) as c_int
+}
+
+/// `uid` is the owning app's UID, or -1 when the VPN service could not attribute the packet
+///
+/// # Safety
+///
+/// `packet` must be null or point to `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rust_inspect_packet_for_uid(packet: *const u8, length: c_int, uid: c_int) -> c_int {
+    if packet.is_null() || length <= 0 { return 0; }
+    let packet = unsafe { std::slice::from_raw_parts(packet, length as usize) };
+    let uid = if uid >= 0 { Some(uid as u32) } else { None };
+    INSPECTOR.lock().unwrap().analyze_for_uid(packet, uid) as c_int
+}
+
+// 0 = monitor, 1 = block, 2 = reject so apps fall back to plain DNS
+#[no_mangle]
+pub extern "C" fn rust_set_encrypted_dns_policy(policy: c_int) -> c_int {
+    let policy = match policy {
+        0 => EncryptedDnsPolicy::Monitor,
+        1 => EncryptedDnsPolicy::Block,
+        2 => EncryptedDnsPolicy::ForcePlainDns,
+        _ => return -1,
+    };
+    INSPECTOR.lock().unwrap().set_encrypted_dns_policy(policy);
+    0
+}
+
+/// # Safety
+///
+/// `resolvers` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_add_encrypted_dns_resolvers(resolvers: *const c_char) {
+    let mut inspector = INSPECTOR.lock().unwrap();
+    for resolver in c_str_list(resolvers) {
+        inspector.add_encrypted_dns_resolver(&resolver);
+    }
+}
+
+// Configure port scan / host sweep detection; zero or negative values keep the default
+#[no_mangle]
+pub extern "C" fn rust_set_scan_thresholds(
+    window_ms: c_int,
+    ports_per_host: c_int,
+    hosts: c_int,
+    min_failed_percent: c_int,
+) {
+    let defaults = ScanThresholds::default();
+    let pick = |value: c_int, default: usize| if value > 0 { value as usize } else { default };
+    let thresholds = ScanThresholds {
+        window_ms: pick(window_ms, defaults.window_ms as usize) as u64,
+        ports_per_host: pick(ports_per_host, defaults.ports_per_host),
+        hosts: pick(hosts, defaults.hosts),
+        min_failed_ratio: if (1..=100).contains(&min_failed_percent) {
+            min_failed_percent as f32 / 100.0
+        } else {
+            defaults.min_failed_ratio
+        },
+        ..defaults
+    };
+    INSPECTOR.lock().unwrap().set_scan_thresholds(thresholds);
+}
+
+/// Memory-map GeoLite2-Country / GeoLite2-ASN compatible databases; either path may be null.
+/// Returns how many databases were loaded.
+///
+/// # Safety
+///
+/// `country_db` and `asn_db` must each be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_load_geoip(country_db: *const c_char, asn_db: *const c_char) -> c_int {
+    let open = |path: *const c_char| {
+        if path.is_null() { return None; }
+        let path = unsafe { CStr::from_ptr(path) }.to_string_lossy().into_owned();
+        MmdbReader::open(std::path::Path::new(&path))
+    };
+    let (country, asn) = (open(country_db), open(asn_db));
+    let loaded = country.is_some() as c_int + asn.is_some() as c_int;
+    INSPECTOR.lock().unwrap().set_geoip_databases(country, asn);
+    loaded
+}
+
+/// Replace the geo policy with newline separated rules such as
+/// "block country=RU uid=10123" or "alert asn=64500 upload>=1048576".
+/// Returns the number of rules accepted, or -1 if any rule is malformed.
+///
+/// # Safety
+///
+/// `rules` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_set_geo_rules(rules: *const c_char) -> c_int {
+    let parsed: Option<Vec<GeoRule>> = c_str_list(rules).iter().map(|r| GeoRule::parse(r)).collect();
+    let Some(parsed) = parsed else { return -1 };
+    let count = parsed.len() as c_int;
+    INSPECTOR.lock().unwrap().set_geo_rules(parsed);
+    count
+}
+
+/// Replace the brand domains protected from homograph and typosquat lookalikes with a
+/// newline separated list such as "paypal.com\nmybank.co.uk". Returns how many were usable.
+///
+/// # Safety
+///
+/// `domains` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_set_protected_domains(domains: *const c_char) -> c_int {
+    let domains = c_str_list(domains);
+    INSPECTOR.lock().unwrap().set_protected_domains(domains.iter().map(String::as_str)) as c_int
+}
+
+/// Track first-seen domains in a Bloom filter persisted at `path`, reloading what an earlier
+/// run saved. New domains are learned without events for `learning_secs` after the file is
+/// first created (negative keeps the one day default). A null path turns tracking off.
+/// Returns the number of remembered domains, or -1 if the file can't be read.
+///
+/// # Safety
+///
+/// `path` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_open_domain_history(path: *const c_char, learning_secs: i64) -> i64 {
+    let mut inspector = INSPECTOR.lock().unwrap();
+    if path.is_null() {
+        inspector.set_domain_history(None);
+        return 0;
+    }
+    let path = unsafe { CStr::from_ptr(path) }.to_string_lossy().into_owned();
+    let mut config = DomainHistoryConfig::default();
+    if learning_secs >= 0 { config.learning_ms = learning_secs as u64 * 1000; }
+    match DomainHistory::open(std::path::Path::new(&path), config, now_ms()) {
+        Ok(history) => {
+            let count = history.len() as i64;
+            inspector.set_domain_history(Some(history));
+            count
+        }
+        Err(_) => -1,
+    }
+}
+
+// Flush the first-seen domain filter to disk, e.g. when the VPN service stops. Returns 0 or -1.
+#[no_mangle]
+pub extern "C" fn rust_save_domain_history() -> c_int {
+    match INSPECTOR.lock().unwrap().save_domain_history() {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Feed decrypted HTTP/2 bytes the app recovered itself; `tag` identifies the connection and
+/// `from_client` is non-zero for the client-to-server direction. Returns the verdict.
+///
+/// # Safety
+///
+/// `data` must be null or point to `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rust_inspect_http2_stream(
+    tag: i64,
+    from_client: c_int,
+    data: *const u8,
+    length: c_int,
+) -> c_int {
+    if data.is_null() || length <= 0 { return 0; }
+    let data = unsafe { std::slice::from_raw_parts(data, length as usize) };
+    INSPECTOR.lock().unwrap().inspect_http2_stream(tag as u64, from_client != 0, data) as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn rust_close_http2_stream(tag: i64) {
+    INSPECTOR.lock().unwrap().close_http2_stream(tag as u64);
+}
+
+// Relay all TUN traffic through the userspace stack on a background thread, inspecting it
+// inline. Takes ownership of `tun_fd`; `protect` must call VpnService.protect(fd) and
+// return non-zero on success. Returns -1 if the stack is already running, the fd is bad or
+// `protect` is null, since sockets it does not protect would route back into the VPN.
 #[no_mangle]
-pub extern "C" fn rust_inspect_packet(_packet: *const u8, _length: c_int) -> c_int {
+pub extern "C" fn rust_netstack_start(tun_fd: c_int, protect: Option<extern "C" fn(c_int) -> c_int>) -> c_int {
+    if tun_fd < 0 { return -1; }
+    let Some(protect) = protect else { return -1 };
+    let mut running = NETSTACK.lock().unwrap();
+    if running.is_some() { return -1; }
+    let Ok(device) = TunDevice::from_fd(unsafe { OwnedFd::from_raw_fd(tun_fd) }) else { return -1 };
+
+    let mut stack = NetStack::new(device, NetStackConfig::default(), Box::new(move |fd| protect(fd) != 0));
+    stack.set_inspector(Box::new(|packet| INSPECTOR.lock().unwrap().analyze(packet)));
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = stop.clone();
+    let handle = std::thread::spawn(move || {
+        let _ = stack.run(&flag);
+    });
+    *running = Some((stop, handle));
     0
 }
 
+// Stop the stack and close the TUN fd; blocks until the relay thread has exited
+#[no_mangle]
+pub extern "C" fn rust_netstack_stop() {
+    let running = NETSTACK.lock().unwrap().take();
+    if let Some((stop, handle)) = running {
+        stop.store(true, Ordering::Relaxed);
+        let _ = handle.join();
+    }
+}
+
+// JNI init call made by HypervisorVpnService when the VPN starts. Zeek-style logs go into
+// `log_dir`: format 0 TSV, 1 JSON. Non-positive size/interval/file count keep the defaults
+// (16 MiB, one hour, eight rotated files per log). A null or empty dir turns logging off.
+// Returns 0 or -1.
+#[no_mangle]
+pub extern "system" fn Java_com_fortress_hypervisor_HypervisorVpnService_nativeInit(
+    mut env: JNIEnv,
+    _class: JClass,
+    log_dir: JString,
+    log_format: jint,
+    max_log_bytes: jlong,
+    rotate_secs: jlong,
+    max_log_files: jint,
+) -> jint {
+    let dir: String = if log_dir.is_null() {
+        String::new()
+    } else {
+        match env.get_string(&log_dir) {
+            Ok(dir) => dir.into(),
+            Err(_) => return -1,
+        }
+    };
+    let mut inspector = INSPECTOR.lock().unwrap();
+    if dir.trim().is_empty() {
+        inspector.set_logger(None);
+        return 0;
+    }
+    let format = match log_format {
+        0 => LogFormat::Tsv,
+        1 => LogFormat::Json,
+        _ => return -1,
+    };
+    let mut config = LogConfig::new(dir.trim(), format);
+    if max_log_bytes > 0 { config.max_bytes = max_log_bytes as u64; }
+    if rotate_secs > 0 { config.rotate_interval_ms = rotate_secs as u64 * 1000; }
+    if max_log_files > 0 { config.max_rotated_files = max_log_files as usize; }
+    match ZeekLogger::new(config) {
+        Ok(logger) => {
+            inspector.set_logger(Some(logger));
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Flow export: format 0 disables, 1 IPFIX, 2 NetFlow v9; target is "udp://host:port" or a file path.
+/// A UDP collector's socket is passed to `protect`, which must call VpnService.protect(fd) and
+/// return non-zero on success, as for rust_netstack_start. Returns 0 on success, -1 on a bad
+/// argument, a UDP target without `protect`, or if the target can't be opened or protected.
+///
+/// # Safety
+///
+/// `target` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_configure_flow_export(
+    format: c_int,
+    target: *const c_char,
+    protect: Option<extern "C" fn(c_int) -> c_int>,
+) -> c_int {
+    let mut inspector = INSPECTOR.lock().unwrap();
+    let format = match format {
+        0 => {
+            inspector.set_flow_exporter(None);
+            return 0;
+        }
+        1 => ExportFormat::Ipfix,
+        2 => ExportFormat::NetflowV9,
+        _ => return -1,
+    };
+    if target.is_null() { return -1; }
+    let target = unsafe { CStr::from_ptr(target) }.to_string_lossy();
+    let Some(target) = ExportTarget::parse(&target) else { return -1 };
+    let mut protect = |fd| protect.is_some_and(|protect| protect(fd) != 0);
+    match FlowExporter::new(format, &target, now_ms(), &mut protect) {
+        Ok(exporter) => {
+            inspector.set_flow_exporter(Some(exporter));
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+// Export running totals of all live flows; returns messages written or -1 on I/O error
+#[no_mangle]
+pub extern "C" fn rust_export_active_flows() -> c_int {
+    match INSPECTOR.lock().unwrap().export_active_flows(now_ms()) {
+        Ok(messages) => messages as c_int,
+        Err(_) => -1,
+    }
+}
+
+/// Load trusted roots from a certificate directory; returns how many were added
+///
+/// # Safety
+///
+/// `dir` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_load_trusted_roots(dir: *const c_char) -> c_int {
+    if dir.is_null() { return 0; }
+    let dir = unsafe { CStr::from_ptr(dir) }.to_string_lossy().into_owned();
+    INSPECTOR.lock().unwrap().load_trusted_roots(std::path::Path::new(&dir)) as c_int
+}
+
+/// Lexical URL score of a raw HTTP request (or bare URL) as
+/// {"malicious", "threat_type", "confidence", "explanation", ...}; free with rust_free_string
+///
+/// # Safety
+///
+/// `request` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_analyze_http_request(request: *const c_char) -> *mut c_char {
+    if request.is_null() { return std::ptr::null_mut(); }
+    let request = unsafe { CStr::from_ptr(request) }.to_string_lossy();
+    let analysis = packet_inspection::analyze_http_request(&request);
+    match serde_json::to_string(&analysis).ok().and_then(|json| CString::new(json).ok()) {
+        Some(s) => s.into_raw(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+// Pending inspection events as a JSON array; free with rust_free_string
+#[no_mangle]
+pub extern "C" fn rust_poll_events() -> *mut c_char {
+    let events = INSPECTOR.lock().unwrap().drain_events();
+    let json = serde_json::to_string(&events).unwrap_or_else(|_| "[]".to_string());
+    match CString::new(json) {
+        Ok(s) => s.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `ips` and `domains` must each be null or point to a NUL-terminated string.
 #[no_mangle]
-pub extern "C" fn rust_init_threat_db(_ips: *const c_char, _domains: *const c_char) {
+pub unsafe extern "C" fn rust_init_threat_db(ips: *const c_char, domains: *const c_char) {
+    let mut inspector = INSPECTOR.lock().unwrap();
+    for ip in c_str_list(ips) {
+        if let Ok(addr) = ip.parse::<Ipv4Addr>() {
+            inspector.add_threat_ip(addr);
+        }
+    }
+    for domain in c_str_list(domains) {
+        inspector.add_threat_domain(&domain);
+    }
+}
+
+/// Import the indicators of a STIX 2.1 bundle / MISP event export into the IP, domain, URL,
+/// JA3, certificate and file hash tables. Expired indicators are skipped now and dropped
+/// later as their windows end. Returns how many were imported, or -1 if the JSON is unusable.
+///
+/// # Safety
+///
+/// `json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_import_stix(json: *const c_char) -> c_int {
+    import_feed(json, threat_intel::parse_stix)
+}
+
+/// # Safety
+///
+/// `json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_import_misp(json: *const c_char) -> c_int {
+    import_feed(json, threat_intel::parse_misp)
+}
+
+unsafe fn import_feed(json: *const c_char, parse: fn(&str) -> Option<threat_intel::ParsedFeed>) -> c_int {
+    if json.is_null() { return -1; }
+    let json = unsafe { CStr::from_ptr(json) }.to_string_lossy();
+    let Some(feed) = parse(&json) else { return -1 };
+    INSPECTOR.lock().unwrap().import_indicators(feed.indicators, now_ms()) as c_int
+}
+
+/// Confidence (1-100) of an active threat intel indicator for an MD5/SHA-1/SHA-256 file hash, or 0
+///
+/// # Safety
+///
+/// `hash` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_check_file_hash(hash: *const c_char) -> c_int {
+    if hash.is_null() { return 0; }
+    let hash = unsafe { CStr::from_ptr(hash) }.to_string_lossy();
+    let inspector = INSPECTOR.lock().unwrap();
+    inspector
+        .threat_intel()
+        .match_hash(threat_intel::IndicatorKind::FileHash, &hash, now_ms())
+        .map_or(0, |indicator| indicator.confidence.max(1) as c_int)
 }
 
 #[no_mangle]
@@ -28,14 +501,52 @@ pub extern "C" fn rust_analyze_memory(_address: u64, _size: u64) -> c_int {
     0
 }
 
+// Live inspection throughput, latency histogram and allocations per packet, plus process
+// CPU (since the previous call) and resident memory, as JSON
+#[no_mangle]
+pub extern "C" fn hypervisor_get_performance_stats() -> *mut c_char {
+    let inspection = INSPECTOR.lock().unwrap().performance(now_ms());
+    let process = perf::process_stats();
+    let stats = serde_json::json!({
+        "packet_processing_rate": inspection.packets_per_sec,
+        "cpu_usage_percent": process.cpu_usage_percent,
+        "memory_usage_mb": process.memory_usage_mb,
+        "inspection": inspection,
+    });
+    match CString::new(stats.to_string()) {
+        Ok(s) => s.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn rust_get_version() -> *mut c_char {
     let s = CString::new("1.0.0").unwrap();
     s.into_raw()
 }
 
+/// # Safety
+///
+/// `s` must be null or a string returned by this library that has not been freed yet.
 #[no_mangle]
-pub extern "C" fn rust_free_string(s: *mut c_char) {
+pub unsafe extern "C" fn rust_free_string(s: *mut c_char) {
     if s.is_null() { return; }
-    unsafe { CString::from_raw(s); }
+    unsafe { drop(CString::from_raw(s)); }
+}
+
+// Internals the criterion benchmarks drive directly; not part of the FFI surface
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::dns::parse_message;
+    pub use crate::flow_table::{parse_packet, FlowKey};
+    pub use crate::http::parse_request;
+    pub use crate::http2::Http2Connection;
+    pub use crate::lookalike::LookalikeDetector;
+    pub use crate::netstack::{tcp_packet, udp_packet};
+    pub use crate::packet_inspection::PacketInspector;
+    pub use crate::pcap::{read_packets, CapturedPacket};
+    pub use crate::perf::{thread_allocations, PerfSnapshot};
+    pub use crate::threat_intel::{Indicator, IndicatorKind, ThreatIntel};
+    pub use crate::tls::{parse_client_hello, HelloTransport};
+    pub use crate::url_analysis::{analyze_url, Url};
 }
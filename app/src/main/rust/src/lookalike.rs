@@ -0,0 +1,398 @@
+use ahash::AHashMap;
+use serde::Serialize;
+
+// Commonly phished brands protected out of the box; the host can replace the list
+pub const DEFAULT_PROTECTED_DOMAINS: &[&str] = &[
+    "paypal.com", "google.com", "apple.com", "icloud.com", "microsoft.com", "office.com",
+    "outlook.com", "live.com", "amazon.com", "facebook.com", "instagram.com", "whatsapp.com",
+    "netflix.com", "linkedin.com", "dropbox.com", "chase.com", "wellsfargo.com",
+    "bankofamerica.com", "citibank.com", "coinbase.com", "binance.com", "steampowered.com",
+];
+
+// Second-level suffixes under which the registrable label sits one level deeper
+const TWO_LEVEL_SUFFIXES: &[&str] = &[
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "com.au", "net.au", "org.au", "co.jp", "ne.jp", "or.jp",
+    "com.br", "com.cn", "com.mx", "co.in", "co.za", "co.nz", "com.tr", "com.sg", "com.hk", "co.kr",
+];
+
+// Labels shorter than this match too many unrelated words
+const MIN_BRAND_LABEL_LEN: usize = 4;
+// An edit away from a shorter brand label is often just another word (love.com and live.com),
+// so only homographs or edits to labels at least this long are worth blocking
+const BLOCKING_LABEL_LEN: usize = 8;
+// Typosquat confidence scales down with the brand label's length below BLOCKING_LABEL_LEN, and
+// matches under this are not reported at all
+const MIN_REPORTED_CONFIDENCE: f32 = 0.55;
+const MAX_CACHED_DOMAINS: usize = 4096;
+
+// RFC 3492 parameters
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// How a domain imitates a protected brand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LookalikeTechnique {
+    /// Renders the same as the brand (confusable Unicode or ASCII like `rn` for `m`)
+    Homograph,
+    /// One key replaced by a neighbouring one on a QWERTY keyboard
+    KeyboardTypo,
+    /// Small edit distance: dropped, doubled, swapped or substituted characters
+    Typosquat,
+}
+
+/// A domain judged to impersonate one of the protected brands
+#[derive(Debug, Clone, Serialize)]
+pub struct LookalikeMatch {
+    /// Domain as seen on the wire (ASCII, punycode)
+    pub domain: String,
+    /// Punycode-decoded form shown to users
+    pub unicode: String,
+    /// Protected domain it resembles
+    pub brand: String,
+    pub technique: LookalikeTechnique,
+    /// Edit distance between the skeletons of the two labels
+    pub distance: usize,
+    pub confidence: f32,
+    /// Close enough to the brand to block rather than only report
+    pub blocking: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ProtectedDomain {
+    domain: String,
+    label: String,
+    skeleton: String,
+}
+
+/// Matches domains against protected brands by confusable skeleton, edit distance and key adjacency
+pub struct LookalikeDetector {
+    protected: Vec<ProtectedDomain>,
+    cache: AHashMap<String, Option<LookalikeMatch>>,
+}
+
+impl Default for LookalikeDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LookalikeDetector {
+    pub fn new() -> Self {
+        let mut detector = Self { protected: Vec::new(), cache: AHashMap::new() };
+        detector.set_protected_domains(DEFAULT_PROTECTED_DOMAINS.iter().copied());
+        detector
+    }
+
+    /// Replace the protected list; entries without a usable registrable label are skipped
+    pub fn set_protected_domains<'a>(&mut self, domains: impl IntoIterator<Item = &'a str>) -> usize {
+        self.protected = domains
+            .into_iter()
+            .filter_map(|domain| {
+                let domain = domain.trim().trim_end_matches('.').to_lowercase();
+                let label = registrable_label(&domain)?;
+                if label.chars().count() < MIN_BRAND_LABEL_LEN {
+                    return None;
+                }
+                let label = label.to_string();
+                Some(ProtectedDomain { skeleton: skeleton(&label), domain, label })
+            })
+            .collect();
+        self.cache.clear();
+        self.protected.len()
+    }
+
+    pub fn protected_count(&self) -> usize {
+        self.protected.len()
+    }
+
+    /// Check a domain; the second value is true the first time a match is reported
+    pub fn check(&mut self, domain: &str) -> (Option<&LookalikeMatch>, bool) {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let fresh = !self.cache.contains_key(&domain);
+        if fresh {
+            if self.cache.len() >= MAX_CACHED_DOMAINS {
+                self.cache.clear();
+            }
+            let result = self.evaluate(&domain);
+            self.cache.insert(domain.clone(), result);
+        }
+        let result = self.cache.get(&domain).and_then(|r| r.as_ref());
+        (result, fresh && result.is_some())
+    }
+
+    fn evaluate(&self, domain: &str) -> Option<LookalikeMatch> {
+        // The brands' own domains and their subdomains are never lookalikes
+        if self.protected.iter().any(|p| domain == p.domain || domain.ends_with(&format!(".{}", p.domain))) {
+            return None;
+        }
+
+        let unicode = to_unicode(domain)?;
+        let label = registrable_label(&unicode)?;
+        let label_skeleton = skeleton(label);
+
+        let mut best: Option<LookalikeMatch> = None;
+        for brand in &self.protected {
+            let Some((technique, distance, confidence)) = compare(label, &label_skeleton, brand) else { continue };
+            if confidence < MIN_REPORTED_CONFIDENCE {
+                continue;
+            }
+            if best.as_ref().is_none_or(|b| confidence > b.confidence) {
+                let long = brand.skeleton.chars().count() >= BLOCKING_LABEL_LEN;
+                best = Some(LookalikeMatch {
+                    domain: domain.to_string(),
+                    unicode: unicode.clone(),
+                    brand: brand.domain.clone(),
+                    technique,
+                    distance,
+                    confidence,
+                    blocking: technique == LookalikeTechnique::Homograph || long,
+                });
+            }
+        }
+        best
+    }
+}
+
+// Judge one registrable label against one brand
+fn compare(label: &str, label_skeleton: &str, brand: &ProtectedDomain) -> Option<(LookalikeTechnique, usize, f32)> {
+    if label == brand.label {
+        // Same name under another TLD; brands register these themselves too often to flag
+        return None;
+    }
+    if label_skeleton == brand.skeleton {
+        return Some((LookalikeTechnique::Homograph, 0, 0.95));
+    }
+
+    let distance = edit_distance(label_skeleton, &brand.skeleton);
+    let brand_len = brand.skeleton.chars().count();
+    let allowed = if brand_len >= 9 { 2 } else { 1 };
+    if distance > allowed {
+        return None;
+    }
+    let scale = (brand_len as f32 / BLOCKING_LABEL_LEN as f32).min(1.0);
+    if distance == 1 && is_keyboard_substitution(label, &brand.label) {
+        return Some((LookalikeTechnique::KeyboardTypo, 1, 0.85 * scale));
+    }
+    let confidence = if distance == 1 { 0.8 } else { 0.6 };
+    Some((LookalikeTechnique::Typosquat, distance, confidence * scale))
+}
+
+/// Registered domain: the public suffix plus the one label above it
+pub fn registrable_domain(domain: &str) -> Option<&str> {
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 || labels.iter().any(|l| l.is_empty()) {
+        return None;
+    }
+    let last_two = labels[labels.len() - 2..].join(".");
+    let suffix_labels = if labels.len() >= 3 && TWO_LEVEL_SUFFIXES.contains(&last_two.as_str()) { 2 } else { 1 };
+    let kept: usize = labels[labels.len() - suffix_labels - 1..].iter().map(|l| l.len() + 1).sum();
+    Some(&domain[domain.len() + 1 - kept..])
+}
+
+// Label registered directly under the public suffix
+fn registrable_label(domain: &str) -> Option<&str> {
+    registrable_domain(domain)?.split('.').next()
+}
+
+/// UTS #39 skeleton, folded to lowercase so `0` and `o` collide as they do on screen
+pub fn skeleton(s: &str) -> String {
+    unicode_security::skeleton(s).collect::<String>().to_lowercase()
+}
+
+/// Decode every `xn--` label of a domain
+pub fn to_unicode(domain: &str) -> Option<String> {
+    let labels: Option<Vec<String>> = domain
+        .split('.')
+        .map(|label| match label.strip_prefix("xn--") {
+            Some(encoded) => punycode_decode(encoded),
+            None => Some(label.to_string()),
+        })
+        .collect();
+    Some(labels?.join("."))
+}
+
+/// RFC 3492 Punycode decoding of a single label (without the ACE prefix)
+pub fn punycode_decode(input: &str) -> Option<String> {
+    if !input.is_ascii() {
+        return None;
+    }
+    let (basic, encoded) = match input.rfind('-') {
+        Some(dash) => (&input[..dash], &input[dash + 1..]),
+        None => ("", input),
+    };
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = encoded.bytes().peekable();
+
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut weight: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = match digits.next()? {
+                b @ b'a'..=b'z' => (b - b'a') as u32,
+                b @ b'A'..=b'Z' => (b - b'A') as u32,
+                b @ b'0'..=b'9' => (b - b'0') as u32 + 26,
+                _ => return None,
+            };
+            i = i.checked_add(digit.checked_mul(weight)?)?;
+            let t = if k <= bias { T_MIN } else if k >= bias + T_MAX { T_MAX } else { k - bias };
+            if digit < t {
+                break;
+            }
+            weight = weight.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let length = output.len() as u32 + 1;
+        bias = adapt(i - old_i, length, old_i == 0);
+        n = n.checked_add(i / length)?;
+        i %= length;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and adjacent swaps
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+// Equal-length labels differing in exactly one character that sits next to the original key
+fn is_keyboard_substitution(label: &str, brand: &str) -> bool {
+    let label: Vec<char> = label.chars().collect();
+    let brand: Vec<char> = brand.chars().collect();
+    if label.len() != brand.len() {
+        return false;
+    }
+    let mut differences = label.iter().zip(&brand).filter(|(a, b)| a != b);
+    match (differences.next(), differences.next()) {
+        (Some((&typed, &intended)), None) => keys_adjacent(typed, intended),
+        _ => false,
+    }
+}
+
+fn key_position(c: char) -> Option<(i32, i32)> {
+    KEYBOARD_ROWS
+        .iter()
+        .enumerate()
+        .find_map(|(row, keys)| keys.find(c).map(|col| (row as i32, col as i32)))
+}
+
+fn keys_adjacent(a: char, b: char) -> bool {
+    match (key_position(a), key_position(b)) {
+        (Some((ra, ca)), Some((rb, cb))) => (ra - rb).abs() <= 1 && (ca - cb).abs() <= 1 && (ra, ca) != (rb, cb),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punycode_vectors() {
+        assert_eq!(punycode_decode("bcher-kva").as_deref(), Some("bücher"));
+        assert_eq!(punycode_decode("pypal-4ve").as_deref(), Some("pаypal"));
+        assert_eq!(punycode_decode("80ak6aa92e").as_deref(), Some("аррӏе"));
+        // RFC 3492 7.1 (B): Chinese (simplified)
+        assert_eq!(punycode_decode("ihqwcrb4cv8a8dqg056pqjye").as_deref(), Some("他们为什么不说中文"));
+        assert!(punycode_decode("ü-abc").is_none());
+        assert_eq!(to_unicode("www.xn--bcher-kva.example").as_deref(), Some("www.bücher.example"));
+    }
+
+    #[test]
+    fn test_homographs_and_typos_name_the_brand() {
+        let mut detector = LookalikeDetector::new();
+
+        let (hit, fresh) = detector.check("xn--l-7sbq6ba.com");
+        let hit = hit.unwrap();
+        assert!(fresh);
+        assert_eq!(hit.brand, "apple.com");
+        assert_eq!(hit.technique, LookalikeTechnique::Homograph);
+        assert_eq!(hit.unicode, "аррlе.com");
+
+        // Cached: still matched, but not reported again
+        let (hit, fresh) = detector.check("XN--L-7SBQ6BA.com.");
+        assert!(hit.is_some() && !fresh);
+
+        let hit = detector.check("login.paypa1.com").0.unwrap();
+        assert_eq!((hit.brand.as_str(), hit.technique), ("paypal.com", LookalikeTechnique::Homograph));
+        assert_eq!(detector.check("rnicrosoft.co.uk").0.unwrap().technique, LookalikeTechnique::Homograph);
+
+        let hit = detector.check("gooogle.com").0.unwrap();
+        assert_eq!((hit.technique, hit.distance), (LookalikeTechnique::Typosquat, 1));
+        assert_eq!(detector.check("netfix.com").0.unwrap().technique, LookalikeTechnique::Typosquat);
+        assert_eq!(detector.check("amazpn.com").0.unwrap().technique, LookalikeTechnique::KeyboardTypo);
+        assert_eq!(detector.check("bankofamreica.com").0.unwrap().brand, "bankofamerica.com");
+
+        assert!(detector.check("mail.google.com").0.is_none());
+        assert!(detector.check("google.de").0.is_none());
+        assert!(detector.check("example.com").0.is_none());
+        assert!(detector.check("applied.com").0.is_none());
+
+        // Edits to long labels block; to short ones they are only reported
+        assert!(detector.check("bankofamreica.com").0.unwrap().blocking);
+        assert!(detector.check("login.paypa1.com").0.unwrap().blocking);
+        assert!(!detector.check("gooogle.com").0.unwrap().blocking);
+    }
+
+    #[test]
+    fn test_common_words_near_short_brands_are_not_blocked() {
+        let mut detector = LookalikeDetector::new();
+        for word in ["love.com", "life.com", "give.com", "olive.com", "hive.com", "lives.com", "apply.com"] {
+            assert!(detector.check(word).0.is_none(), "{}", word);
+        }
+        for (word, brand) in [("finance.com", "binance.com"), ("cloud.com", "icloud.com")] {
+            let hit = detector.check(word).0.unwrap();
+            assert_eq!(hit.brand, brand);
+            assert!(!hit.blocking, "{}", word);
+        }
+    }
+
+    #[test]
+    fn test_custom_protected_list() {
+        let mut detector = LookalikeDetector::new();
+        assert_eq!(detector.set_protected_domains(["mybank.example", "x.com", ""]), 1);
+        assert_eq!(detector.check("rnybank.net").0.unwrap().brand, "mybank.example");
+        assert!(detector.check("paypa1.com").0.is_none());
+    }
+}
@@ -2,6 +2,9 @@ use std::collections::HashMap;
 use std::sync::Mutex;
 use lazy_static::lazy_static;
 
+use crate::scanner;
+use crate::yara::RuleMatch;
+
 // Memory analysis structures
 #[derive(Debug, Clone)]
 pub struct MemoryRegion {
@@ -64,29 +67,26 @@ pub extern "C" fn hypervisor_analyze_memory() -> *mut MemoryAnalysisResult {
 
 // Read memory maps from /proc/self/maps
 fn read_memory_maps() -> Vec<MemoryRegion> {
-    let mut regions = Vec::new();
-
     // In a real implementation, this would read and parse /proc/self/maps
     // For this example, we'll create mock data
-    regions.push(MemoryRegion {
-        start_address: 0x400000,
-        size: 0x1000,
-        permissions: "r-xp".to_string(),
-        mapped_file: Some("/system/bin/app_process64".to_string()),
-        is_executable: true,
-        entropy: 6.5,
-    });
-
-    regions.push(MemoryRegion {
-        start_address: 0x7fff0000,
-        size: 0x2000,
-        permissions: "rw-p".to_string(),
-        mapped_file: None,
-        is_executable: false,
-        entropy: 4.2,
-    });
-
-    regions
+    vec![
+        MemoryRegion {
+            start_address: 0x400000,
+            size: 0x1000,
+            permissions: "r-xp".to_string(),
+            mapped_file: Some("/system/bin/app_process64".to_string()),
+            is_executable: true,
+            entropy: 6.5,
+        },
+        MemoryRegion {
+            start_address: 0x7fff0000,
+            size: 0x2000,
+            permissions: "rw-p".to_string(),
+            mapped_file: None,
+            is_executable: false,
+            entropy: 4.2,
+        },
+    ]
 }
 
 // Analyze memory regions for suspicious patterns
@@ -150,8 +150,7 @@ fn detect_hooks(regions: &[MemoryRegion]) -> Vec<String> {
 
     // Check for PLT/GOT hooks (simplified detection)
     for region in regions {
-        if region.permissions.contains('w') && region.mapped_file.is_some() {
-            let file_name = region.mapped_file.as_ref().unwrap();
+        if let (true, Some(file_name)) = (region.permissions.contains('w'), region.mapped_file.as_ref()) {
             if file_name.contains("libc") || file_name.contains("libdl") {
                 // Check for modifications to critical functions
                 // In a real implementation, this would involve checking specific addresses
@@ -199,6 +198,22 @@ pub extern "C" fn hypervisor_dump_memory(address: u64, size: usize) -> *mut Vec<
     Box::into_raw(Box::new(buffer))
 }
 
+// Run the loaded YARA rules over memory read from `base_address`; hit offsets are addresses.
+// `filesize` conditions never hold here, as in YARA's process scanning.
+pub fn scan_memory(data: &[u8], base_address: u64) -> Vec<RuleMatch> {
+    scanner::yara_rules().map_or_else(Vec::new, |rules| rules.scan(data, None, base_address))
+}
+
+// Scan a memory dump (e.g. from hypervisor_dump_memory) with the loaded YARA rules
+#[no_mangle]
+pub extern "C" fn hypervisor_scan_memory_rules(data: *const u8, data_len: usize, base_address: u64) -> *mut Vec<RuleMatch> {
+    if data.is_null() {
+        return std::ptr::null_mut();
+    }
+    let data = unsafe { std::slice::from_raw_parts(data, data_len) };
+    Box::into_raw(Box::new(scan_memory(data, base_address)))
+}
+
 // Calculate memory entropy for a region
 fn calculate_memory_entropy(data: &[u8]) -> f64 {
     if data.is_empty() {
@@ -225,15 +240,11 @@ fn calculate_memory_entropy(data: &[u8]) -> f64 {
 
 // Detect DLL injection patterns
 fn detect_dll_injection() -> Vec<String> {
-    let mut injected_modules = Vec::new();
-
     // In Windows, this would check loaded modules
     // For Android/Linux, check loaded shared libraries
 
     // Mock detection
-    injected_modules.push("Potential injected library detected".to_string());
-
-    injected_modules
+    vec!["Potential injected library detected".to_string()]
 }
 
 // Analyze heap allocations
@@ -249,14 +260,10 @@ fn analyze_heap_allocations() -> HashMap<String, usize> {
 
 // Detect anti-debugging techniques
 fn detect_anti_debugging() -> Vec<String> {
-    let mut techniques = Vec::new();
-
     // Check for debugger detection patterns
     // This would involve checking for common anti-debugging tricks
 
-    techniques.push("Debugger detection pattern found".to_string());
-
-    techniques
+    vec!["Debugger detection pattern found".to_string()]
 }
 
 // Memory forensics - find sensitive data in memory
@@ -363,6 +370,14 @@ pub extern "C" fn hypervisor_free_memory_dump(dump: *mut Vec<u8>) {
     }
 }
 
+// Clean up YARA memory scan matches
+#[no_mangle]
+pub extern "C" fn hypervisor_free_rule_matches(matches: *mut Vec<RuleMatch>) {
+    if !matches.is_null() {
+        unsafe { Box::from_raw(matches) };
+    }
+}
+
 // Clean up string
 #[no_mangle]
 pub extern "C" fn hypervisor_free_string(string: *mut String) {
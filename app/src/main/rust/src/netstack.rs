@@ -0,0 +1,1031 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use ahash::AHashMap;
+use serde::Serialize;
+
+use crate::flow_table::{self, FlowKey, PacketMeta};
+use crate::tun::PacketDevice;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+// Bytes buffered towards the remote per connection; also the receive window we advertise
+const TCP_BUFFER: usize = 65_535;
+// MSS assumed when the app's SYN does not carry one (RFC 1122)
+const DEFAULT_MSS: usize = 536;
+// Unacknowledged data towards the app is resent after this long
+const TCP_RTO_MS: u64 = 1_000;
+// Device packets handled per poll so socket events are not starved
+const MAX_PACKETS_PER_POLL: usize = 256;
+// A device without a pollable descriptor is checked at least this often
+const MEMORY_POLL_MS: i32 = 10;
+const RUN_POLL_MS: i32 = 100;
+
+/// Called with every outbound socket before it connects, so it bypasses the VPN
+/// (`VpnService.protect`). Returning false abandons the flow. Required: an unprotected socket's
+/// traffic would loop back into the tunnel.
+pub type ProtectFn = Box<dyn FnMut(RawFd) -> bool + Send>;
+
+/// Inline inspection of each relayed packet, in either direction; returns a verdict code
+pub type InspectFn = Box<dyn FnMut(&[u8]) -> u8 + Send>;
+
+#[derive(Debug, Clone)]
+pub struct NetStackConfig {
+    pub mtu: usize,
+    pub tcp_idle_ms: u64,
+    pub udp_idle_ms: u64,
+    pub connect_timeout_ms: u64,
+    pub max_tcp_connections: usize,
+    pub max_udp_sessions: usize,
+    /// Verdicts that reset a TCP connection or drop a UDP datagram; others only report
+    pub blocking_verdicts: Vec<u8>,
+}
+
+impl Default for NetStackConfig {
+    fn default() -> Self {
+        Self {
+            mtu: 1500,
+            tcp_idle_ms: 300_000,
+            udp_idle_ms: 60_000,
+            connect_timeout_ms: 10_000,
+            max_tcp_connections: 1024,
+            max_udp_sessions: 512,
+            // MALICIOUS, ENCRYPTED_DNS_BLOCKED/REJECT, PORT_SCAN, GEO_POLICY, PHISHING_LOOKALIKE, MALICIOUS_URL
+            blocking_verdicts: vec![1, 4, 5, 6, 7, 8, 9],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct NetStackStats {
+    pub packets_from_device: u64,
+    pub packets_to_device: u64,
+    pub bytes_to_remote: u64,
+    pub bytes_from_remote: u64,
+    pub tcp_opened: u64,
+    pub udp_opened: u64,
+    pub blocked: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TcpState {
+    // Waiting for the outbound socket to connect before answering the app's SYN
+    Connecting,
+    // SYN-ACK sent, waiting for the app to acknowledge it
+    SynReceived,
+    Established,
+}
+
+// One app connection terminated here and mirrored on a real socket
+struct TcpConnection {
+    // Direction app -> remote
+    key: FlowKey,
+    state: TcpState,
+    socket: TcpStream,
+    // Next sequence number expected from the app, i.e. our ACK number
+    client_next: u32,
+    // Next sequence number we send, and the oldest one the app has not acknowledged
+    our_next: u32,
+    client_acked: u32,
+    client_window: u32,
+    mss: usize,
+    to_remote: Vec<u8>,
+    // Data sent to the app from `client_acked` on, kept for retransmission
+    unacked: VecDeque<u8>,
+    client_fin: bool,
+    remote_shutdown: bool,
+    remote_eof: bool,
+    fin_sent: bool,
+    // Blocked by inspection or failed; torn down at the next opportunity
+    reset: bool,
+    opened_ms: u64,
+    last_activity_ms: u64,
+    last_send_ms: u64,
+}
+
+impl TcpConnection {
+    // Sequence space sent but not acknowledged, SYN and FIN included
+    fn in_flight(&self) -> u32 {
+        self.our_next.wrapping_sub(self.client_acked)
+    }
+
+    fn send_window(&self) -> usize {
+        self.client_window.saturating_sub(self.in_flight()) as usize
+    }
+
+    fn receive_window(&self) -> u16 {
+        (TCP_BUFFER - self.to_remote.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn poll_events(&self) -> i16 {
+        if self.state == TcpState::Connecting {
+            return libc::POLLOUT;
+        }
+        let mut events = 0;
+        if self.state == TcpState::Established && !self.remote_eof && self.send_window() > 0 {
+            events |= libc::POLLIN;
+        }
+        if !self.to_remote.is_empty() {
+            events |= libc::POLLOUT;
+        }
+        events
+    }
+
+    // Both sides closed and our FIN acknowledged
+    fn finished(&self) -> bool {
+        self.client_fin && self.fin_sent && self.in_flight() == 0
+    }
+}
+
+struct UdpSession {
+    socket: UdpSocket,
+    last_activity_ms: u64,
+}
+
+// Fields of a TCP header the stack acts on
+struct TcpSegment<'a> {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &'a [u8],
+}
+
+fn parse_tcp<'a>(packet: &'a [u8], meta: &PacketMeta) -> Option<TcpSegment<'a>> {
+    let header = packet.get(meta.transport_offset..meta.payload_offset)?;
+    if header.len() < 20 {
+        return None;
+    }
+    let mut mss = None;
+    let mut options = &header[20..];
+    while let Some(&kind) = options.first() {
+        match kind {
+            0 => break,
+            1 => options = &options[1..],
+            _ => {
+                let len = *options.get(1)? as usize;
+                if len < 2 || len > options.len() {
+                    break;
+                }
+                if kind == 2 && len == 4 {
+                    mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                }
+                options = &options[len..];
+            }
+        }
+    }
+    Some(TcpSegment {
+        seq: u32::from_be_bytes(header[4..8].try_into().unwrap()),
+        ack: u32::from_be_bytes(header[8..12].try_into().unwrap()),
+        flags: header[13],
+        window: u16::from_be_bytes([header[14], header[15]]),
+        mss,
+        payload: meta.payload(packet),
+    })
+}
+
+#[derive(Clone, Copy)]
+enum SocketOwner {
+    Device,
+    Tcp(FlowKey),
+    Udp(FlowKey),
+}
+
+/// Userspace TCP/UDP stack: terminates the app flows read from a packet device and
+/// relays their bytes over real (protected) sockets, inspecting every packet inline
+pub struct NetStack<D: PacketDevice> {
+    device: D,
+    config: NetStackConfig,
+    protect: ProtectFn,
+    inspect: Option<InspectFn>,
+    tcp: AHashMap<FlowKey, TcpConnection>,
+    udp: AHashMap<FlowKey, UdpSession>,
+    stats: NetStackStats,
+    clock: Instant,
+    buf: Vec<u8>,
+}
+
+impl<D: PacketDevice> NetStack<D> {
+    pub fn new(device: D, config: NetStackConfig, protect: ProtectFn) -> Self {
+        Self {
+            device,
+            config,
+            protect,
+            inspect: None,
+            tcp: AHashMap::new(),
+            udp: AHashMap::new(),
+            stats: NetStackStats::default(),
+            clock: Instant::now(),
+            buf: vec![0; u16::MAX as usize],
+        }
+    }
+
+    pub fn set_inspector(&mut self, inspect: InspectFn) {
+        self.inspect = Some(inspect);
+    }
+
+    pub fn stats(&self) -> NetStackStats {
+        self.stats
+    }
+
+    pub fn tcp_connections(&self) -> usize {
+        self.tcp.len()
+    }
+
+    pub fn udp_sessions(&self) -> usize {
+        self.udp.len()
+    }
+
+    /// Relay until `stop` is set or the device fails
+    pub fn run(&mut self, stop: &AtomicBool) -> io::Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            self.poll(RUN_POLL_MS)?;
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout_ms` for the device or a socket, then handle whatever is ready
+    pub fn poll(&mut self, timeout_ms: i32) -> io::Result<()> {
+        let mut fds = Vec::with_capacity(1 + self.tcp.len() + self.udp.len());
+        let mut owners = Vec::with_capacity(fds.capacity());
+        let timeout_ms = match self.device.raw_fd() {
+            Some(fd) => {
+                fds.push(libc::pollfd { fd, events: libc::POLLIN, revents: 0 });
+                owners.push(SocketOwner::Device);
+                timeout_ms
+            }
+            None => timeout_ms.min(MEMORY_POLL_MS),
+        };
+        for (key, conn) in &self.tcp {
+            fds.push(libc::pollfd { fd: conn.socket.as_raw_fd(), events: conn.poll_events(), revents: 0 });
+            owners.push(SocketOwner::Tcp(*key));
+        }
+        for (key, session) in &self.udp {
+            fds.push(libc::pollfd { fd: session.socket.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+            owners.push(SocketOwner::Udp(*key));
+        }
+
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::Interrupted { Ok(()) } else { Err(err) };
+        }
+
+        self.drain_device()?;
+        for (fd, owner) in fds.iter().zip(owners) {
+            if fd.revents == 0 {
+                continue;
+            }
+            match owner {
+                SocketOwner::Device => {}
+                SocketOwner::Tcp(key) => self.tcp_socket_ready(key, fd.revents),
+                SocketOwner::Udp(key) => self.udp_socket_ready(key),
+            }
+        }
+        self.run_timers();
+        Ok(())
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.clock.elapsed().as_millis() as u64
+    }
+
+    fn drain_device(&mut self) -> io::Result<()> {
+        let mut buf = std::mem::take(&mut self.buf);
+        let mut result = Ok(());
+        for _ in 0..MAX_PACKETS_PER_POLL {
+            match self.device.recv(&mut buf) {
+                Ok(Some(n)) => self.handle_device_packet(&buf[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.buf = buf;
+        result
+    }
+
+    fn inspect_packet(&mut self, packet: &[u8]) -> bool {
+        let verdict = self.inspect.as_mut().map_or(0, |inspect| inspect(packet));
+        let blocked = self.config.blocking_verdicts.contains(&verdict);
+        if blocked {
+            self.stats.blocked += 1;
+        }
+        !blocked
+    }
+
+    // Hand a packet to the app after inspection; false if inspection blocked it
+    fn relay_to_client(&mut self, packet: &[u8]) -> bool {
+        if !self.inspect_packet(packet) {
+            return false;
+        }
+        self.write_device(packet);
+        true
+    }
+
+    fn write_device(&mut self, packet: &[u8]) {
+        // A failing device is noticed on the next read
+        if self.device.send(packet).is_ok() {
+            self.stats.packets_to_device += 1;
+        }
+    }
+
+    fn handle_device_packet(&mut self, packet: &[u8]) {
+        self.stats.packets_from_device += 1;
+        let Some(meta) = flow_table::parse_packet(packet) else { return };
+        let allowed = self.inspect_packet(packet);
+        match meta.key.protocol {
+            6 => self.handle_tcp_segment(packet, &meta, allowed),
+            17 if allowed => self.handle_udp_datagram(meta.key, meta.payload(packet)),
+            // ICMP and the rest have no socket to be relayed through
+            _ => {}
+        }
+    }
+
+    // --- TCP ---
+
+    fn handle_tcp_segment(&mut self, packet: &[u8], meta: &PacketMeta, allowed: bool) {
+        let Some(segment) = parse_tcp(packet, meta) else { return };
+        let key = meta.key;
+        if segment.flags & RST != 0 {
+            self.tcp.remove(&key);
+            return;
+        }
+        if !allowed {
+            match self.tcp.remove(&key) {
+                Some(mut conn) => self.abort(&mut conn),
+                None => self.reset_segment(&key, &segment),
+            }
+            return;
+        }
+        let now = self.now_ms();
+        let Some(mut conn) = self.tcp.remove(&key) else {
+            if segment.flags & (SYN | ACK) == SYN {
+                self.open_tcp(key, &segment, now);
+            } else {
+                self.reset_segment(&key, &segment);
+            }
+            return;
+        };
+        if self.client_segment(&mut conn, &segment, now) {
+            self.tcp.insert(key, conn);
+        }
+    }
+
+    fn open_tcp(&mut self, key: FlowKey, syn: &TcpSegment, now: u64) {
+        if self.tcp.len() >= self.config.max_tcp_connections {
+            self.reset_segment(&key, syn);
+            return;
+        }
+        let socket = match self.connect_tcp(SocketAddr::new(key.dst, key.dst_port)) {
+            Ok(socket) => socket,
+            Err(_) => {
+                self.reset_segment(&key, syn);
+                return;
+            }
+        };
+        let isn: u32 = rand::random();
+        let mss = syn.mss.map_or(DEFAULT_MSS, usize::from).min(self.our_mss(&key) as usize);
+        self.stats.tcp_opened += 1;
+        self.tcp.insert(key, TcpConnection {
+            key,
+            state: TcpState::Connecting,
+            socket,
+            client_next: syn.seq.wrapping_add(1),
+            our_next: isn,
+            client_acked: isn,
+            client_window: syn.window as u32,
+            mss,
+            to_remote: Vec::new(),
+            unacked: VecDeque::new(),
+            client_fin: false,
+            remote_shutdown: false,
+            remote_eof: false,
+            fin_sent: false,
+            reset: false,
+            opened_ms: now,
+            last_activity_ms: now,
+            last_send_ms: now,
+        });
+    }
+
+    // Start a non-blocking connect on a socket protected from the VPN
+    fn connect_tcp(&mut self, dst: SocketAddr) -> io::Result<TcpStream> {
+        let domain = if dst.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+        let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Owns the descriptor from here, so every early return closes it
+        let stream = unsafe { TcpStream::from_raw_fd(fd) };
+        self.protect_socket(fd)?;
+
+        let (addr, len) = socket_addr(dst);
+        let rc = unsafe { libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len) };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(err);
+            }
+        }
+        Ok(stream)
+    }
+
+    fn protect_socket(&mut self, fd: RawFd) -> io::Result<()> {
+        if !(self.protect)(fd) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "socket protection refused"));
+        }
+        Ok(())
+    }
+
+    fn our_mss(&self, key: &FlowKey) -> u16 {
+        let headers = if key.dst.is_ipv4() { 40 } else { 60 };
+        self.config.mtu.saturating_sub(headers).clamp(DEFAULT_MSS, u16::MAX as usize) as u16
+    }
+
+    // Process a segment from the app; returns false once the connection is gone
+    fn client_segment(&mut self, conn: &mut TcpConnection, segment: &TcpSegment, now: u64) -> bool {
+        conn.last_activity_ms = now;
+        if segment.flags & SYN != 0 {
+            // The app retransmitted its SYN; repeat our answer if we already gave one
+            if conn.state == TcpState::SynReceived {
+                let isn = conn.client_acked;
+                self.send_segment(conn, isn, SYN | ACK, &[]);
+            }
+            return self.keep(conn);
+        }
+
+        if segment.flags & ACK != 0 {
+            let acked = segment.ack.wrapping_sub(conn.client_acked);
+            if acked > 0 && acked <= conn.in_flight() {
+                if conn.state == TcpState::SynReceived {
+                    conn.state = TcpState::Established;
+                }
+                let data = (acked as usize).min(conn.unacked.len());
+                conn.unacked.drain(..data);
+                conn.client_acked = segment.ack;
+            }
+            conn.client_window = segment.window as u32;
+        }
+        if conn.state != TcpState::Established {
+            return self.keep(conn);
+        }
+
+        let carries_data = !segment.payload.is_empty() || segment.flags & FIN != 0;
+        if carries_data && segment.seq == conn.client_next && !conn.client_fin {
+            // Out-of-order segments are dropped and recovered by the app's retransmission
+            let take = segment.payload.len().min(TCP_BUFFER - conn.to_remote.len());
+            conn.to_remote.extend_from_slice(&segment.payload[..take]);
+            conn.client_next = conn.client_next.wrapping_add(take as u32);
+            if take == segment.payload.len() && segment.flags & FIN != 0 {
+                conn.client_fin = true;
+                conn.client_next = conn.client_next.wrapping_add(1);
+            }
+        }
+        if !self.flush_to_remote(conn) {
+            conn.reset = true;
+        }
+        if carries_data {
+            self.send_ack(conn);
+        }
+        self.keep(conn)
+    }
+
+    // Write buffered app bytes to the socket; false if the socket failed
+    fn flush_to_remote(&mut self, conn: &mut TcpConnection) -> bool {
+        while !conn.to_remote.is_empty() {
+            match conn.socket.write(&conn.to_remote) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    conn.to_remote.drain(..n);
+                    self.stats.bytes_to_remote += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+        if conn.client_fin && conn.to_remote.is_empty() && !conn.remote_shutdown {
+            let _ = conn.socket.shutdown(Shutdown::Write);
+            conn.remote_shutdown = true;
+        }
+        true
+    }
+
+    fn tcp_socket_ready(&mut self, key: FlowKey, revents: i16) {
+        let Some(mut conn) = self.tcp.remove(&key) else { return };
+        let now = self.now_ms();
+        if self.socket_event(&mut conn, revents, now) {
+            self.tcp.insert(key, conn);
+        }
+    }
+
+    fn socket_event(&mut self, conn: &mut TcpConnection, revents: i16, now: u64) -> bool {
+        if conn.state == TcpState::Connecting {
+            let connected = matches!(conn.socket.take_error(), Ok(None)) && conn.socket.peer_addr().is_ok();
+            if !connected {
+                conn.reset = true;
+                return self.keep(conn);
+            }
+            conn.state = TcpState::SynReceived;
+            let isn = conn.our_next;
+            conn.our_next = isn.wrapping_add(1);
+            self.send_segment(conn, isn, SYN | ACK, &[]);
+            conn.last_send_ms = now;
+            return self.keep(conn);
+        }
+
+        if revents & libc::POLLOUT != 0 && !conn.to_remote.is_empty() {
+            let buffered = conn.to_remote.len();
+            if !self.flush_to_remote(conn) {
+                conn.reset = true;
+            } else if conn.to_remote.len() < buffered {
+                // Reopen the window the app may be waiting on
+                self.send_ack(conn);
+            }
+        }
+        if revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            self.read_remote(conn, now);
+        }
+        self.keep(conn)
+    }
+
+    // Forward what the remote sent, as far as the app's window allows
+    fn read_remote(&mut self, conn: &mut TcpConnection, now: u64) {
+        let mut buf = std::mem::take(&mut self.buf);
+        while conn.state == TcpState::Established && !conn.remote_eof && !conn.reset {
+            let window = conn.send_window().min(buf.len());
+            if window == 0 {
+                break;
+            }
+            match conn.socket.read(&mut buf[..window]) {
+                Ok(0) => conn.remote_eof = true,
+                Ok(n) => {
+                    self.stats.bytes_from_remote += n as u64;
+                    for chunk in buf[..n].chunks(conn.mss) {
+                        let seq = conn.our_next;
+                        self.send_segment(conn, seq, PSH | ACK, chunk);
+                        conn.our_next = seq.wrapping_add(chunk.len() as u32);
+                        conn.unacked.extend(chunk);
+                    }
+                    conn.last_send_ms = now;
+                    conn.last_activity_ms = now;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => conn.reset = true,
+            }
+        }
+        self.buf = buf;
+
+        if conn.remote_eof && !conn.fin_sent && !conn.reset {
+            let seq = conn.our_next;
+            self.send_segment(conn, seq, FIN | ACK, &[]);
+            conn.our_next = seq.wrapping_add(1);
+            conn.fin_sent = true;
+            conn.last_send_ms = now;
+        }
+    }
+
+    fn send_ack(&mut self, conn: &mut TcpConnection) {
+        let seq = conn.our_next;
+        self.send_segment(conn, seq, ACK, &[]);
+    }
+
+    // Send a segment to the app; if inspection blocks it the connection is reset
+    fn send_segment(&mut self, conn: &mut TcpConnection, seq: u32, flags: u8, payload: &[u8]) {
+        let mss = (flags & SYN != 0).then(|| self.our_mss(&conn.key));
+        let packet =
+            tcp_packet(&conn.key.reversed(), seq, conn.client_next, flags, conn.receive_window(), mss, payload);
+        if !self.relay_to_client(&packet) {
+            conn.reset = true;
+        }
+    }
+
+    // Tear down a reset or finished connection; returns whether it lives on
+    fn keep(&mut self, conn: &mut TcpConnection) -> bool {
+        if conn.reset {
+            self.abort(conn);
+            return false;
+        }
+        !conn.finished()
+    }
+
+    // Reset the app's side and close the remote one
+    fn abort(&mut self, conn: &mut TcpConnection) {
+        let packet = tcp_packet(&conn.key.reversed(), conn.our_next, conn.client_next, RST | ACK, 0, None, &[]);
+        self.write_device(&packet);
+        let _ = conn.socket.shutdown(Shutdown::Both);
+    }
+
+    // Refuse a segment that belongs to no connection (RFC 793 reset generation)
+    fn reset_segment(&mut self, key: &FlowKey, segment: &TcpSegment) {
+        let reply = key.reversed();
+        let packet = if segment.flags & ACK != 0 {
+            tcp_packet(&reply, segment.ack, 0, RST, 0, None, &[])
+        } else {
+            let len = segment.payload.len() as u32 + (segment.flags & SYN != 0) as u32 + (segment.flags & FIN != 0) as u32;
+            tcp_packet(&reply, 0, segment.seq.wrapping_add(len), RST | ACK, 0, None, &[])
+        };
+        self.write_device(&packet);
+    }
+
+    fn run_timers(&mut self) {
+        let now = self.now_ms();
+        let keys: Vec<FlowKey> = self.tcp.keys().copied().collect();
+        for key in keys {
+            let Some(mut conn) = self.tcp.remove(&key) else { continue };
+            if self.tcp_timers(&mut conn, now) {
+                self.tcp.insert(key, conn);
+            }
+        }
+
+        let idle_ms = self.config.udp_idle_ms;
+        self.udp.retain(|_, session| now.saturating_sub(session.last_activity_ms) < idle_ms);
+    }
+
+    fn tcp_timers(&mut self, conn: &mut TcpConnection, now: u64) -> bool {
+        let timed_out = match conn.state {
+            TcpState::Connecting => now.saturating_sub(conn.opened_ms) >= self.config.connect_timeout_ms,
+            _ => now.saturating_sub(conn.last_activity_ms) >= self.config.tcp_idle_ms,
+        };
+        if timed_out {
+            conn.reset = true;
+            return self.keep(conn);
+        }
+
+        if conn.in_flight() > 0 && now.saturating_sub(conn.last_send_ms) >= TCP_RTO_MS {
+            // Go back to the oldest unacknowledged segment
+            let seq = conn.client_acked;
+            if conn.state == TcpState::SynReceived {
+                self.send_segment(conn, seq, SYN | ACK, &[]);
+            } else if !conn.unacked.is_empty() {
+                let chunk: Vec<u8> = conn.unacked.iter().take(conn.mss).copied().collect();
+                self.send_segment(conn, seq, PSH | ACK, &chunk);
+            } else if conn.fin_sent {
+                self.send_segment(conn, seq, FIN | ACK, &[]);
+            }
+            conn.last_send_ms = now;
+        }
+        self.keep(conn)
+    }
+
+    // --- UDP ---
+
+    fn handle_udp_datagram(&mut self, key: FlowKey, payload: &[u8]) {
+        let now = self.now_ms();
+        if !self.udp.contains_key(&key) {
+            if self.udp.len() >= self.config.max_udp_sessions {
+                return;
+            }
+            let Ok(socket) = self.open_udp(SocketAddr::new(key.dst, key.dst_port)) else { return };
+            self.stats.udp_opened += 1;
+            self.udp.insert(key, UdpSession { socket, last_activity_ms: now });
+        }
+        let Some(session) = self.udp.get_mut(&key) else { return };
+        session.last_activity_ms = now;
+        if session.socket.send(payload).is_ok() {
+            self.stats.bytes_to_remote += payload.len() as u64;
+        }
+    }
+
+    fn open_udp(&mut self, dst: SocketAddr) -> io::Result<UdpSocket> {
+        let any: SocketAddr = match dst {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(any)?;
+        socket.set_nonblocking(true)?;
+        self.protect_socket(socket.as_raw_fd())?;
+        socket.connect(dst)?;
+        Ok(socket)
+    }
+
+    fn udp_socket_ready(&mut self, key: FlowKey) {
+        let Some(mut session) = self.udp.remove(&key) else { return };
+        let mut buf = std::mem::take(&mut self.buf);
+        let reply = key.reversed();
+        loop {
+            match session.socket.recv(&mut buf) {
+                Ok(n) => {
+                    self.stats.bytes_from_remote += n as u64;
+                    session.last_activity_ms = self.now_ms();
+                    // A blocked datagram is dropped; the session stays for the app to time out
+                    self.relay_to_client(&udp_packet(&reply, &buf[..n]));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // Would block, or an ICMP error such as port unreachable
+                Err(_) => break,
+            }
+        }
+        self.buf = buf;
+        self.udp.insert(key, session);
+    }
+}
+
+fn socket_addr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_scope_id = v6.scope_id();
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+// --- packet construction ---
+
+// Sum of big-endian 16-bit words, odd trailing byte padded with zero
+fn checksum_add(mut sum: u64, data: &[u8]) -> u64 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u64) << 8;
+    }
+    sum
+}
+
+fn checksum_fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+// Wrap a transport segment in an IPv4 or IPv6 header, filling the checksum at `checksum_at`
+fn ip_packet(key: &FlowKey, mut segment: Vec<u8>, checksum_at: usize) -> Vec<u8> {
+    let len = segment.len();
+    let mut packet = Vec::with_capacity(40 + len);
+    let pseudo = match (key.src, key.dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + len) as u16).to_be_bytes());
+            // No identification needed: don't-fragment is set
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, key.protocol, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let header = checksum_fold(checksum_add(0, &packet));
+            packet[10..12].copy_from_slice(&header.to_be_bytes());
+            checksum_add(checksum_add(0, &src.octets()), &dst.octets())
+        }
+        (src, dst) => {
+            let (src, dst) = (to_v6(src), to_v6(dst));
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(len as u16).to_be_bytes());
+            packet.extend_from_slice(&[key.protocol, 64]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            checksum_add(checksum_add(0, &src.octets()), &dst.octets())
+        }
+    };
+    let mut checksum = checksum_fold(checksum_add(pseudo + key.protocol as u64 + len as u64, &segment));
+    // Zero means "no checksum" for UDP
+    if key.protocol == 17 && checksum == 0 {
+        checksum = 0xFFFF;
+    }
+    segment[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&segment);
+    packet
+}
+
+/// Build a TCP/IP packet from `key.src` to `key.dst`
+pub fn tcp_packet(
+    key: &FlowKey,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let header_len = if mss.is_some() { 24 } else { 20 };
+    let mut segment = Vec::with_capacity(header_len + payload.len());
+    segment.extend_from_slice(&key.src_port.to_be_bytes());
+    segment.extend_from_slice(&key.dst_port.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[((header_len / 4) << 4) as u8, flags]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    if let Some(mss) = mss {
+        segment.extend_from_slice(&[2, 4]);
+        segment.extend_from_slice(&mss.to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+    ip_packet(key, segment, 16)
+}
+
+/// Build a UDP/IP packet from `key.src` to `key.dst`
+pub fn udp_packet(key: &FlowKey, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(8 + payload.len());
+    segment.extend_from_slice(&key.src_port.to_be_bytes());
+    segment.extend_from_slice(&key.dst_port.to_be_bytes());
+    segment.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(payload);
+    ip_packet(key, segment, 6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tun::MemoryPipe;
+    use std::net::{TcpListener, UdpSocket};
+
+    fn app_key(dst: SocketAddr, protocol: u8) -> FlowKey {
+        FlowKey { src: "10.0.0.2".parse().unwrap(), dst: dst.ip(), src_port: 40_000, dst_port: dst.port(), protocol }
+    }
+
+    // Transport checksum over the pseudo-header verifies to zero
+    fn checksum_ok(packet: &[u8]) -> bool {
+        let meta = flow_table::parse_packet(packet).unwrap();
+        let segment = &packet[meta.transport_offset..];
+        let pseudo = match (meta.key.src, meta.key.dst) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                if checksum_fold(checksum_add(0, &packet[..20])) != 0 {
+                    return false;
+                }
+                checksum_add(checksum_add(0, &s.octets()), &d.octets())
+            }
+            (s, d) => checksum_add(checksum_add(0, &to_v6(s).octets()), &to_v6(d).octets()),
+        };
+        checksum_fold(checksum_add(pseudo + meta.key.protocol as u64 + segment.len() as u64, segment)) == 0
+    }
+
+    // Poll until the app side receives a packet matching `want`
+    fn expect<D: PacketDevice>(stack: &mut NetStack<D>, app: &mut MemoryPipe, want: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+        let mut buf = vec![0u8; 65_535];
+        for _ in 0..500 {
+            stack.poll(10).unwrap();
+            while let Some(n) = app.recv(&mut buf).unwrap() {
+                assert!(checksum_ok(&buf[..n]));
+                if want(&buf[..n]) {
+                    return buf[..n].to_vec();
+                }
+            }
+        }
+        panic!("expected packet never arrived");
+    }
+
+    fn segment_of(packet: &[u8]) -> (u32, u32, u8, Vec<u8>) {
+        let meta = flow_table::parse_packet(packet).unwrap();
+        let segment = parse_tcp(packet, &meta).unwrap();
+        (segment.seq, segment.ack, segment.flags, segment.payload.to_vec())
+    }
+
+    #[test]
+    fn test_tcp_relay_through_memory_pipe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut data = Vec::new();
+            socket.read_to_end(&mut data).unwrap();
+            socket.write_all(&data).unwrap();
+        });
+
+        let (device, mut app) = MemoryPipe::pair();
+        let protected = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = protected.clone();
+        let protect = Box::new(move |_| counter.fetch_add(1, Ordering::Relaxed) < usize::MAX);
+        let mut stack = NetStack::new(device, NetStackConfig::default(), protect);
+        let inspected = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = inspected.clone();
+        stack.set_inspector(Box::new(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            0
+        }));
+
+        let key = app_key(server, 6);
+        app.send(&tcp_packet(&key, 1_000, 0, SYN, 65_535, Some(1_460), &[])).unwrap();
+        let syn_ack = expect(&mut stack, &mut app, |p| segment_of(p).2 == SYN | ACK);
+        let (isn, ack, _, _) = segment_of(&syn_ack);
+        assert_eq!(ack, 1_001);
+        assert_eq!(protected.load(Ordering::Relaxed), 1);
+
+        // Handshake ACK, request bytes and our FIN so the echo server replies and closes
+        let isn = isn.wrapping_add(1);
+        app.send(&tcp_packet(&key, 1_001, isn, ACK, 65_535, None, &[])).unwrap();
+        app.send(&tcp_packet(&key, 1_001, isn, PSH | ACK, 65_535, None, b"hello netstack")).unwrap();
+        app.send(&tcp_packet(&key, 1_015, isn, FIN | ACK, 65_535, None, &[])).unwrap();
+
+        let data = expect(&mut stack, &mut app, |p| !segment_of(p).3.is_empty());
+        let (seq, ack, _, payload) = segment_of(&data);
+        assert_eq!((seq, ack), (isn, 1_016));
+        assert_eq!(payload, b"hello netstack");
+
+        let fin = expect(&mut stack, &mut app, |p| segment_of(p).2 & FIN != 0);
+        assert_eq!(segment_of(&fin).0, isn.wrapping_add(14));
+        app.send(&tcp_packet(&key, 1_016, isn.wrapping_add(15), ACK, 65_535, None, &[])).unwrap();
+        stack.poll(10).unwrap();
+        assert_eq!(stack.tcp_connections(), 0);
+        assert!(inspected.load(Ordering::Relaxed) >= 6);
+        assert_eq!(stack.stats().bytes_to_remote, 14);
+        assert_eq!(stack.stats().bytes_from_remote, 14);
+    }
+
+    #[test]
+    fn test_udp_relay_and_blocked_syn() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (n, from) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..n], from).unwrap();
+        });
+
+        let (device, mut app) = MemoryPipe::pair();
+        let mut stack = NetStack::new(device, NetStackConfig::default(), Box::new(|_| true));
+        // Anything to port 4444 is "malicious"
+        stack.set_inspector(Box::new(|packet| {
+            let meta = flow_table::parse_packet(packet).unwrap();
+            (meta.key.dst_port == 4_444) as u8
+        }));
+
+        let key = app_key(server_addr, 17);
+        app.send(&udp_packet(&key, b"ping")).unwrap();
+        let reply = expect(&mut stack, &mut app, |p| flow_table::parse_packet(p).unwrap().key.protocol == 17);
+        let meta = flow_table::parse_packet(&reply).unwrap();
+        assert_eq!(meta.key, key.reversed());
+        assert_eq!(meta.payload(&reply), b"ping");
+        assert_eq!(stack.udp_sessions(), 1);
+
+        // Blocked before any socket is opened, and refused with a reset
+        let blocked = app_key("127.0.0.1:4444".parse().unwrap(), 6);
+        app.send(&tcp_packet(&blocked, 7, 0, SYN, 65_535, None, &[])).unwrap();
+        let rst = expect(&mut stack, &mut app, |p| segment_of(p).2 & RST != 0);
+        assert_eq!(segment_of(&rst).1, 8);
+        assert_eq!(stack.tcp_connections(), 0);
+        assert_eq!(stack.stats().blocked, 1);
+    }
+
+    #[test]
+    fn test_refused_protection_abandons_the_flow() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (device, mut app) = MemoryPipe::pair();
+        let mut stack = NetStack::new(device, NetStackConfig::default(), Box::new(|_| false));
+
+        let key = app_key(listener.local_addr().unwrap(), 6);
+        app.send(&tcp_packet(&key, 1_000, 0, SYN, 65_535, None, &[])).unwrap();
+        let rst = expect(&mut stack, &mut app, |p| segment_of(p).2 & RST != 0);
+        assert_eq!(segment_of(&rst).1, 1_001);
+        assert_eq!(stack.tcp_connections(), 0);
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn test_ipv6_packets_parse_back() {
+        let key = FlowKey {
+            src: "2001:db8::1".parse().unwrap(),
+            dst: "2001:db8::2".parse().unwrap(),
+            src_port: 5_353,
+            dst_port: 53,
+            protocol: 17,
+        };
+        let packet = udp_packet(&key, b"query");
+        assert!(checksum_ok(&packet));
+        let meta = flow_table::parse_packet(&packet).unwrap();
+        assert_eq!((meta.key, meta.payload(&packet)), (key, &b"query"[..]));
+
+        let key = FlowKey { protocol: 6, ..key };
+        let packet = tcp_packet(&key, 1, 2, SYN | ACK, 1_000, Some(1_440), b"");
+        assert!(checksum_ok(&packet));
+        let meta = flow_table::parse_packet(&packet).unwrap();
+        let segment = parse_tcp(&packet, &meta).unwrap();
+        assert_eq!((segment.seq, segment.ack, segment.flags, segment.mss), (1, 2, SYN | ACK, Some(1_440)));
+    }
+}
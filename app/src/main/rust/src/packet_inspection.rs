@@ -1,32 +1,360 @@
 use std::net::{Ipv4Addr};
-use ahash::AHashSet;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use ahash::{AHashMap, AHashSet};
+
+use crate::certificate::CertificateInspector;
+use crate::dns;
+use crate::domain_history::DomainHistory;
+use crate::encrypted_dns::{EncryptedDnsDetector, EncryptedDnsPolicy};
+use crate::events::{EventQueue, InspectionEvent};
+use crate::flow_export::{FlowEndReason, FlowExporter};
+use crate::flow_table::{self, FlowKey, FlowRecord, FlowTable, TcpReassembly};
+use crate::geoip::{GeoAction, GeoIp, GeoInfo, GeoRule, MmdbReader};
+use crate::http;
+use crate::http2::{self, Http2Connection, Http2Event};
+use crate::icmp_tunnel::{IcmpAssessment, IcmpTunnelDetector};
+use crate::lookalike::LookalikeDetector;
+use crate::perf::{self, InspectionCounters, PerfSnapshot};
+use crate::quic::{self, QuicHelloReassembler};
+use crate::scan_detection::{ScanDetector, ScanSource, ScanThresholds};
+use crate::threat_intel::{Indicator, IndicatorKind, ThreatIntel};
+use crate::tls::{self, ClientHello, HelloTransport, ServerHandshakeStream};
+use crate::url_analysis::{self, UrlAnalysis};
+use crate::zeek_log::ZeekLogger;
+
+// Concurrent server handshakes buffered for certificate inspection
+const MAX_SERVER_HANDSHAKES: usize = 512;
+
+// Cleartext HTTP/2 connections decoded at once, plus streams handed over by the host
+const MAX_HTTP2_CONNECTIONS: usize = 256;
+
+// Out-of-order bytes held per direction of a cleartext HTTP/2 connection before it is dropped
+const MAX_HTTP2_EARLY_BYTES: usize = 256 * 1024;
+
+// How often idle flows are swept out of the flow table
+const FLOW_EXPIRY_INTERVAL_MS: u64 = 1_000;
+
+// A cleartext HTTP/2 connection seen on the wire, decoded once each direction is back in order
+#[derive(Default)]
+struct TcpHttp2 {
+    connection: Http2Connection,
+    client: TcpReassembly,
+    server: TcpReassembly,
+}
 
 pub struct PacketInspector {
     threat_ips: AHashSet<u32>,
+    threat_domains: AHashSet<String>,
+    threat_ja3: AHashSet<String>,
+    threat_ja4: AHashSet<String>,
+    threat_ja3s: AHashSet<String>,
+    certificates: CertificateInspector,
+    server_handshakes: AHashMap<FlowKey, ServerHandshakeStream>,
+    http2: AHashMap<FlowKey, TcpHttp2>,
+    tagged_http2: AHashMap<u64, Http2Connection>,
+    quic_hellos: QuicHelloReassembler,
+    flows: FlowTable,
+    encrypted_dns: EncryptedDnsDetector,
+    scans: ScanDetector,
+    icmp_tunnels: IcmpTunnelDetector,
+    geo: GeoIp,
+    geo_rules: Vec<GeoRule>,
+    lookalikes: LookalikeDetector,
+    intel: ThreatIntel,
+    domain_history: Option<DomainHistory>,
+    exporter: Option<FlowExporter>,
+    logger: Option<ZeekLogger>,
+    last_expiry_ms: u64,
+    events: EventQueue,
+    perf: InspectionCounters,
+    // Flow, direction and clock of the packet currently being inspected
+    current_flow: Option<FlowKey>,
+    current_outbound: bool,
+    now_ms: u64,
+}
+
+impl Default for PacketInspector {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PacketInspector {
     pub fn new() -> Self {
         Self {
             threat_ips: AHashSet::new(),
+            threat_domains: AHashSet::new(),
+            threat_ja3: AHashSet::new(),
+            threat_ja4: AHashSet::new(),
+            threat_ja3s: AHashSet::new(),
+            certificates: CertificateInspector::new(),
+            server_handshakes: AHashMap::new(),
+            http2: AHashMap::new(),
+            tagged_http2: AHashMap::new(),
+            quic_hellos: QuicHelloReassembler::new(),
+            flows: FlowTable::new(),
+            encrypted_dns: EncryptedDnsDetector::new(),
+            scans: ScanDetector::default(),
+            icmp_tunnels: IcmpTunnelDetector::new(),
+            geo: GeoIp::new(),
+            geo_rules: Vec::new(),
+            lookalikes: LookalikeDetector::new(),
+            intel: ThreatIntel::new(),
+            domain_history: None,
+            exporter: None,
+            logger: None,
+            last_expiry_ms: 0,
+            events: EventQueue::default(),
+            perf: InspectionCounters::new(),
+            current_flow: None,
+            current_outbound: false,
+            now_ms: 0,
+        }
+    }
+
+    pub fn add_threat_ip(&mut self, ip: Ipv4Addr) {
+        self.threat_ips.insert(u32::from(ip));
+    }
+
+    pub fn add_threat_domain(&mut self, domain: &str) {
+        self.threat_domains.insert(domain.trim_end_matches('.').to_ascii_lowercase());
+    }
+
+    /// Register a malicious JA3 hash or JA4 fingerprint
+    pub fn add_threat_fingerprint(&mut self, fingerprint: &str) {
+        if fingerprint.len() == 32 && fingerprint.bytes().all(|b| b.is_ascii_hexdigit()) {
+            self.threat_ja3.insert(fingerprint.to_ascii_lowercase());
+        } else {
+            self.threat_ja4.insert(fingerprint.to_string());
+        }
+    }
+
+    /// Register a malicious server JA3S hash
+    pub fn add_threat_ja3s(&mut self, ja3s: &str) {
+        self.threat_ja3s.insert(ja3s.to_ascii_lowercase());
+    }
+
+    /// Register a known-bad server certificate by SHA-1 or SHA-256 fingerprint
+    pub fn add_threat_certificate(&mut self, fingerprint: &str) {
+        self.certificates.add_bad_fingerprint(fingerprint);
+    }
+
+    /// Load trusted roots (e.g. /system/etc/security/cacerts) on top of the bundled set
+    pub fn load_trusted_roots(&mut self, dir: &Path) -> usize {
+        self.certificates.trust.load_dir(dir)
+    }
+
+    /// Blocklist lookup that also matches any parent domain of `domain`
+    pub fn is_domain_blocked(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let mut candidate = domain.as_str();
+        loop {
+            if self.threat_domains.contains(candidate) {
+                return true;
+            }
+            match candidate.find('.') {
+                Some(dot) => candidate = &candidate[dot + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    pub fn set_encrypted_dns_policy(&mut self, policy: EncryptedDnsPolicy) {
+        self.encrypted_dns.policy = policy;
+    }
+
+    /// Add a DoH/DoT resolver hostname or IP to the bundled list
+    pub fn add_encrypted_dns_resolver(&mut self, resolver: &str) {
+        self.encrypted_dns.add_resolver(resolver);
+    }
+
+    /// Replace the port scan / host sweep thresholds
+    pub fn set_scan_thresholds(&mut self, thresholds: ScanThresholds) {
+        self.scans.thresholds = thresholds;
+    }
+
+    /// Start (or with `None`, stop) exporting finished flows as IPFIX / NetFlow v9
+    pub fn set_flow_exporter(&mut self, exporter: Option<FlowExporter>) {
+        self.exporter = exporter;
+    }
+
+    /// Load (or with `None`, unload) the MMDB country and ASN databases
+    pub fn set_geoip_databases(&mut self, country: Option<MmdbReader>, asn: Option<MmdbReader>) {
+        self.geo.set_country_db(country);
+        self.geo.set_asn_db(asn);
+    }
+
+    /// Replace the country/ASN policy rules
+    pub fn set_geo_rules(&mut self, rules: Vec<GeoRule>) {
+        self.geo_rules = rules;
+    }
+
+    /// Replace the brand domains that lookalike detection protects; returns how many were usable
+    pub fn set_protected_domains<'a>(&mut self, domains: impl IntoIterator<Item = &'a str>) -> usize {
+        self.lookalikes.set_protected_domains(domains)
+    }
+
+    /// Route imported STIX/MISP indicators into the matching tables; returns how many were kept
+    pub fn import_indicators(&mut self, indicators: Vec<Indicator>, now_ms: u64) -> usize {
+        self.intel.import(indicators, now_ms)
+    }
+
+    pub fn threat_intel(&self) -> &ThreatIntel {
+        &self.intel
+    }
+
+    /// Track first-seen domains; the previous history is saved before it is replaced
+    pub fn set_domain_history(&mut self, history: Option<DomainHistory>) {
+        if let Some(old) = &mut self.domain_history {
+            let _ = old.save();
         }
+        self.domain_history = history;
+    }
+
+    pub fn save_domain_history(&mut self) -> std::io::Result<()> {
+        match &mut self.domain_history {
+            Some(history) => history.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Start (or with `None`, stop) writing Zeek-style conn/dns/http/ssl logs
+    pub fn set_logger(&mut self, logger: Option<ZeekLogger>) {
+        self.logger = logger;
+    }
+
+    /// Export every live flow with its running totals; returns the messages written
+    pub fn export_active_flows(&mut self, now_ms: u64) -> std::io::Result<usize> {
+        match &mut self.exporter {
+            Some(exporter) => exporter.export(self.flows.iter(), FlowEndReason::ActiveTimeout, now_ms),
+            None => Ok(0),
+        }
+    }
+
+    /// Take every event raised since the last call
+    pub fn drain_events(&mut self) -> Vec<InspectionEvent> {
+        self.events.drain()
+    }
+
+    pub fn flows(&self) -> &FlowTable {
+        &self.flows
+    }
+
+    pub fn analyze(&mut self, packet: &[u8]) -> u8 {
+        self.analyze_for_uid(packet, None)
     }
 
-    pub fn analyze(&self, packet: &[u8]) -> u8 {
+    /// Analyze a packet the VPN service has attributed to an app UID
+    pub fn analyze_for_uid(&mut self, packet: &[u8], uid: Option<u32>) -> u8 {
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let allocations = perf::thread_allocations();
+        let started = Instant::now();
+        let verdict = self.inspect(packet, uid, now_ms);
+        let ns = started.elapsed().as_nanos() as u64;
+        self.perf.record(packet.len(), ns, perf::thread_allocations() - allocations, now_ms);
+        verdict
+    }
+
+    /// Throughput, per-packet latency and allocation counters of `analyze`
+    pub fn performance(&self, now_ms: u64) -> PerfSnapshot {
+        self.perf.snapshot(now_ms)
+    }
+
+    pub(crate) fn inspect(&mut self, packet: &[u8], uid: Option<u32>, now_ms: u64) -> u8 {
         if packet.len() < 20 {
             return 0; // Invalid packet
         }
 
+        self.now_ms = now_ms;
+        let meta = flow_table::parse_packet(packet);
+        self.current_flow = None;
+        if let Some(meta) = meta {
+            let (key, evicted) = self.flows.record(&meta, packet.len(), uid, now_ms);
+            self.current_flow = Some(key);
+            self.close_flows(evicted.idle, FlowEndReason::IdleTimeout, now_ms);
+            self.close_flows(evicted.oldest.into_iter().collect(), FlowEndReason::LackOfResources, now_ms);
+        }
+        self.current_outbound = matches!((meta, self.current_flow), (Some(meta), Some(key)) if meta.key == key);
+        // A flow whose first packet is this one, sent from our side
+        let opened_flow = match (meta, self.current_flow.and_then(|key| self.flows.get(&key))) {
+            (Some(meta), Some(flow)) => flow.key == meta.key && flow.packets_out == 1 && flow.packets_in == 0,
+            _ => false,
+        };
+        if opened_flow {
+            self.enrich_flow();
+        }
+
         let ip_version = (packet[0] >> 4) & 0x0F;
 
-        match ip_version {
-            4 => self.analyze_ipv4(packet),
-            6 => self.analyze_ipv6(packet),
-            _ => 0,
+        let mut verdict = meta.map_or(0, |meta| self.check_intel_addresses(&meta.key, opened_flow));
+
+        if verdict == 0 {
+            verdict = match ip_version {
+                4 => self.analyze_ipv4(packet),
+                6 => self.analyze_ipv6(packet),
+                _ => 0,
+            };
+        }
+
+        if verdict == 0 {
+            verdict = self.check_encrypted_dns();
         }
+
+        if verdict == 0 && opened_flow {
+            verdict = self.check_scan();
+        }
+
+        if verdict == 0 {
+            verdict = self.check_geo_policy();
+        }
+
+        if verdict != 0 {
+            if let Some(flow) = self.current_flow.and_then(|key| self.flows.get_mut(&key)) {
+                flow.verdict = verdict;
+            }
+        }
+
+        if now_ms.saturating_sub(self.last_expiry_ms) >= FLOW_EXPIRY_INTERVAL_MS {
+            self.last_expiry_ms = now_ms;
+            self.expire_flows(now_ms);
+            self.intel.expire(now_ms);
+            if let Some(history) = &mut self.domain_history {
+                let _ = history.maybe_save(now_ms);
+            }
+            // Log lines are buffered; write them out once per tick rather than per line
+            if let Some(logger) = &mut self.logger {
+                let _ = logger.flush();
+            }
+        }
+
+        verdict
+    }
+
+    fn expire_flows(&mut self, now_ms: u64) {
+        let expired = self.flows.expire(now_ms);
+        self.close_flows(expired, FlowEndReason::IdleTimeout, now_ms);
     }
 
-    fn analyze_ipv4(&self, packet: &[u8]) -> u8 {
+    // Log, export and forget flows the table has dropped
+    fn close_flows(&mut self, closed: Vec<FlowRecord>, reason: FlowEndReason, now_ms: u64) {
+        if closed.is_empty() {
+            return;
+        }
+        for flow in &closed {
+            self.server_handshakes.remove(&flow.key);
+            self.http2.remove(&flow.key);
+            if let Some(logger) = &mut self.logger {
+                let _ = logger.flow_closed(flow, now_ms);
+            }
+        }
+        if let Some(exporter) = &mut self.exporter {
+            // Telemetry is best effort; a collector outage must not affect inspection
+            let _ = exporter.export(&closed, reason, now_ms);
+        }
+    }
+
+    fn analyze_ipv4(&mut self, packet: &[u8]) -> u8 {
         if packet.len() < 20 { return 0; }
 
         let protocol = packet[9];
@@ -45,72 +373,113 @@ impl PacketInspector {
         }
     }
 
-    fn analyze_ipv6(&self, _packet: &[u8]) -> u8 {
-        // Simplified IPv6 handling for now
-        0
+    fn analyze_ipv6(&mut self, packet: &[u8]) -> u8 {
+        match flow_table::parse_packet(packet).map(|meta| meta.key.protocol) {
+            Some(6) => self.analyze_tcp(packet),
+            Some(17) => self.analyze_udp(packet),
+            Some(58) => self.analyze_icmp(packet),
+            _ => 0,
+        }
+    }
+
+    // Offset of the TCP/UDP header, past IPv4 options or IPv6 extension headers
+    fn transport_offset(packet: &[u8]) -> usize {
+        match packet[0] >> 4 {
+            6 => flow_table::parse_packet(packet).map_or(packet.len(), |meta| meta.transport_offset),
+            _ => (packet[0] & 0x0F) as usize * 4,
+        }
     }
 
-    fn analyze_tcp(&self, packet: &[u8]) -> u8 {
-        if packet.len() < 40 {
+    fn analyze_tcp(&mut self, packet: &[u8]) -> u8 {
+        let tcp_offset = Self::transport_offset(packet);
+        if packet.len() < tcp_offset + 20 {
             return 0;
         }
 
-        // TCP header offset assumes no IP options; offset 20..
-        let src_port = u16::from_be_bytes([packet[20], packet[21]]);
-        let dst_port = u16::from_be_bytes([packet[22], packet[23]]);
+        let src_port = u16::from_be_bytes([packet[tcp_offset], packet[tcp_offset + 1]]);
+        let dst_port = u16::from_be_bytes([packet[tcp_offset + 2], packet[tcp_offset + 3]]);
 
         // Check for common data-exfiltration and malware ports
         if Self::is_known_c2_port(dst_port) {
             return 1; // MALICIOUS (C2)
         }
 
+        // Cleartext HTTP/2 on any port, recognised by the client preface
+        if let Some(analysis) = self.inspect_http2(packet) {
+            return analysis;
+        }
+
         // HTTP
         if src_port == 80 || dst_port == 80 {
-            match self.inspect_http(packet) {
-                Some(analysis) => return analysis,
-                None => {}
+            if let Some(analysis) = self.inspect_http(packet) {
+                return analysis;
             }
         }
 
-        // TLS/HTTPS
-        if src_port == 443 || dst_port == 443 {
-            match self.inspect_tls(packet) {
-                Some(analysis) => return analysis,
-                None => {}
+        // TLS/HTTPS and DNS-over-TLS
+        if matches!(src_port, 443 | 853) {
+            if let Some(analysis) = self.inspect_tls_server(packet) {
+                return analysis;
+            }
+        } else if matches!(dst_port, 443 | 853) {
+            if let Some(analysis) = self.inspect_tls(packet) {
+                return analysis;
             }
         }
 
         0 // Allow by default
     }
 
-    fn analyze_udp(&self, packet: &[u8]) -> u8 {
+    fn analyze_udp(&mut self, packet: &[u8]) -> u8 {
         if packet.len() < 28 { return 0; }
 
-        let dst_port = u16::from_be_bytes([packet[22], packet[23]]);
+        let ihl = Self::transport_offset(packet);
+        if packet.len() < ihl + 8 { return 0; }
+        let src_port = u16::from_be_bytes([packet[ihl], packet[ihl + 1]]);
+        let dst_port = u16::from_be_bytes([packet[ihl + 2], packet[ihl + 3]]);
 
-        // DNS (53) or mDNS (5353)
-        if dst_port == 53 || dst_port == 5353 {
+        // DNS (53) or mDNS (5353), queries and responses alike
+        if matches!(dst_port, 53 | 5353) || matches!(src_port, 53 | 5353) {
             return self.inspect_dns(packet);
         }
 
+        // HTTP/3: decrypt client Initials to recover the ClientHello
+        let payload = &packet[ihl + 8..];
+        if quic::is_long_header_initial(payload) {
+            return self.inspect_quic(payload).unwrap_or(0);
+        }
+
+        // QUIC always sets the fixed bit; don't treat HTTP/3 as a tunnel just for its size
+        let looks_like_quic = (src_port == 443 || dst_port == 443) && payload.first().is_some_and(|b| b & 0x40 != 0);
+
         // Large UDP packets may indicate tunneling
-        if packet.len() > 512 {
+        if packet.len() > 512 && !looks_like_quic {
             return 3; // SUSPICIOUS_LARGE_UDP
         }
 
         0
     }
 
-    fn analyze_icmp(&self, _packet: &[u8]) -> u8 {
-        // ICMP generally not used for exfiltration; monitor for odd sizes
-        0
+    fn analyze_icmp(&mut self, packet: &[u8]) -> u8 {
+        let Some(meta) = flow_table::parse_packet(packet) else { return 0 };
+        let Some(icmp) = packet.get(meta.transport_offset..) else { return 0 };
+
+        match self.icmp_tunnels.observe(meta.key.src, meta.key.dst, icmp, self.now_ms) {
+            IcmpAssessment::Clean => 0,
+            IcmpAssessment::Tunnel => 3, // ICMP_TUNNEL
+            IcmpAssessment::NewTunnel(detection) => {
+                let uid = self.current_flow.and_then(|key| self.flows.get(&key)).and_then(|f| f.uid);
+                self.events.push(InspectionEvent::IcmpTunnel { timestamp_ms: self.now_ms, uid, detection });
+                3 // ICMP_TUNNEL
+            }
+        }
     }
 
     /// Inspect HTTP payload for sensitive strings or large uploads
     /// returns Some(code) if action required, None for no decision
-    fn inspect_http(&self, packet: &[u8]) -> Option<u8> {
+    fn inspect_http(&mut self, packet: &[u8]) -> Option<u8> {
         // Calculate TCP header offset (IP header length may vary)
-        let ihl = (packet[0] & 0x0F) as usize * 4;
+        let ihl = Self::transport_offset(packet);
         if packet.len() <= ihl + 20 { return None; }
 
         // TCP header length (data offset nibble)
@@ -119,7 +488,43 @@ impl PacketInspector {
         let payload_offset = ihl + data_offset;
         if packet.len() <= payload_offset { return None; }
 
+        // Parsed once for both the URL check and the log; payloads that don't start with a
+        // method are turned away before anything is allocated
         let payload = &packet[payload_offset..];
+        let request = http::parse_request(payload);
+        let verdict = Self::http_payload_verdict(payload).or_else(|| request.as_ref().and_then(|r| self.check_url(r)));
+        if self.logger.is_some() {
+            // Requests travel in the flow's direction; responses come back from port 80
+            match request {
+                Some(request) => self.log_http_message(Some(request), None),
+                None => self.log_http_message(None, http::parse_response(payload)),
+            }
+        }
+        verdict
+    }
+
+    // Threat intel URLs, then lexical URL scoring; only malicious URLs raise an event
+    fn check_url(&mut self, request: &http::HttpRequest) -> Option<u8> {
+        // A request without a usable URL scores clean
+        let url = url_analysis::Url::from_request(request)?;
+        if !self.intel.is_empty() {
+            let text = url.to_string();
+            if let Some(indicator) = self.intel.match_url(&text, self.now_ms).cloned() {
+                self.report_intel_match(indicator, text);
+                return Some(9); // MALICIOUS_URL
+            }
+        }
+        let analysis = url_analysis::analyze_url(&url);
+        if !analysis.malicious {
+            return None;
+        }
+        let uid = self.current_flow.and_then(|key| self.flows.get(&key)).and_then(|f| f.uid);
+        self.events.push(InspectionEvent::UrlThreat { timestamp_ms: self.now_ms, uid, analysis });
+        Some(9) // MALICIOUS_URL
+    }
+
+    // Keyword DLP and upload size rules shared by every HTTP version
+    fn http_payload_verdict(payload: &[u8]) -> Option<u8> {
         // Inspect a bounded prefix to avoid heavy parsing
         let sample = &payload[..payload.len().min(2048)];
 
@@ -138,10 +543,67 @@ impl PacketInspector {
         None
     }
 
+    fn inspect_http2(&mut self, packet: &[u8]) -> Option<u8> {
+        let key = self.current_flow?;
+        let meta = flow_table::parse_packet(packet)?;
+        let (seq, payload) = (meta.tcp_seq(packet)?, meta.payload(packet));
+        if !self.http2.contains_key(&key) {
+            let starts_h2 = self.current_outbound && payload.starts_with(http2::CLIENT_PREFACE);
+            if !starts_h2 || self.http2.len() >= MAX_HTTP2_CONNECTIONS {
+                return None;
+            }
+            self.http2.insert(key, TcpHttp2::default());
+        }
+        let state = self.http2.get_mut(&key)?;
+        let stream = if self.current_outbound { &mut state.client } else { &mut state.server };
+        let Some(payload) = stream.push(seq, payload, MAX_HTTP2_EARLY_BYTES) else {
+            self.http2.remove(&key);
+            return None;
+        };
+        if payload.is_empty() {
+            return None;
+        }
+        let events = state.connection.feed(self.current_outbound, &payload);
+        Some(self.handle_http2_events(events))
+    }
+
+    /// Decode HTTP/2 plaintext the host recovered itself (e.g. from its own TLS endpoint),
+    /// one connection per `tag`, and apply the same rules as cleartext traffic
+    pub fn inspect_http2_stream(&mut self, tag: u64, from_client: bool, data: &[u8]) -> u8 {
+        if !self.tagged_http2.contains_key(&tag) && self.tagged_http2.len() >= MAX_HTTP2_CONNECTIONS {
+            return 0;
+        }
+        let events = self.tagged_http2.entry(tag).or_default().feed(from_client, data);
+        // Not tied to a packet, so nothing to log against
+        self.current_flow = None;
+        self.handle_http2_events(events)
+    }
+
+    pub fn close_http2_stream(&mut self, tag: u64) {
+        self.tagged_http2.remove(&tag);
+    }
+
+    fn handle_http2_events(&mut self, events: Vec<Http2Event>) -> u8 {
+        let mut verdict = 0;
+        for event in events {
+            match event {
+                Http2Event::Request(message) => {
+                    let Some(request) = message.to_request() else { continue };
+                    if let Some(v) = Self::http_payload_verdict(&request.to_wire()).or_else(|| self.check_url(&request)) {
+                        verdict = verdict.max(v);
+                    }
+                    self.log_http_message(Some(request), None);
+                }
+                Http2Event::Response(message) => self.log_http_message(None, message.to_response()),
+            }
+        }
+        verdict
+    }
+
     /// Inspect TLS client hello fingerprint for known malicious JA3-like patterns
-    fn inspect_tls(&self, packet: &[u8]) -> Option<u8> {
+    fn inspect_tls(&mut self, packet: &[u8]) -> Option<u8> {
         // Very simplified: look for "Client Hello" marker and a small prefix
-        let ihl = (packet[0] & 0x0F) as usize * 4;
+        let ihl = Self::transport_offset(packet);
         if packet.len() <= ihl + 20 { return None; }
 
         let tcp_offset = ihl;
@@ -153,20 +615,338 @@ impl PacketInspector {
 
         // TLS records start with 0x16 for handshake, then version
         if payload[0] == 0x16 && payload.len() > 5 {
-            // crude fingerprint extraction
-            let fingerprint = Self::calculate_tls_fingerprint(payload);
-            if Self::is_malicious_fingerprint(&fingerprint) {
-                return Some(1); // MALICIOUS_TLS
+            // Only a ClientHello that fits in this segment is fingerprinted
+            let record_len = u16::from_be_bytes([payload[3], payload[4]]) as usize;
+            let record = &payload[5..payload.len().min(5 + record_len)];
+            if let Some(hello) = tls::parse_client_hello(record) {
+                return self.evaluate_client_hello(&hello, HelloTransport::Tcp);
             }
         }
 
         None
     }
 
-    fn inspect_dns(&self, packet: &[u8]) -> u8 {
+    /// Follow the server's cleartext handshake flight for JA3S and certificate checks.
+    /// Only TLS 1.2 and earlier send the Certificate message unencrypted.
+    fn inspect_tls_server(&mut self, packet: &[u8]) -> Option<u8> {
+        let key = self.current_flow?;
+        if self.flows.get(&key)?.tls_server_inspected {
+            return None;
+        }
+
+        let ihl = Self::transport_offset(packet);
+        if packet.len() < ihl + 20 { return None; }
+        let seq = u32::from_be_bytes([packet[ihl + 4], packet[ihl + 5], packet[ihl + 6], packet[ihl + 7]]);
+        let data_offset = ((packet[ihl + 12] >> 4) as usize) * 4;
+        let payload = packet.get(ihl + data_offset..).unwrap_or(&[]);
+
+        if !self.server_handshakes.contains_key(&key) {
+            if payload.is_empty() {
+                return None;
+            }
+            if payload[0] != 0x16 || self.server_handshakes.len() >= MAX_SERVER_HANDSHAKES {
+                self.flows.get_mut(&key)?.tls_server_inspected = true;
+                return None;
+            }
+        }
+
+        let stream = self.server_handshakes.entry(key).or_default();
+        if !stream.push(seq, payload) {
+            self.server_handshakes.remove(&key);
+            self.flows.get_mut(&key)?.tls_server_inspected = true;
+            return None;
+        }
+
+        let (messages, finished) = stream.messages();
+        let server_hello = messages
+            .iter()
+            .find(|(ty, _)| *ty == tls::HANDSHAKE_SERVER_HELLO)
+            .and_then(|(_, body)| tls::parse_server_hello(body));
+        let tls13 = server_hello.as_ref().is_some_and(|h| h.is_tls13());
+        if !finished && !tls13 {
+            return None;
+        }
+        self.server_handshakes.remove(&key);
+
+        let chain = messages
+            .iter()
+            .find(|(ty, _)| *ty == tls::HANDSHAKE_CERTIFICATE)
+            .and_then(|(_, body)| tls::parse_certificate_list(body))
+            .filter(|chain| !chain.is_empty());
+
+        let ja3s = server_hello.as_ref().map(|h| h.ja3s_hash());
+        let flow = self.flows.get_mut(&key)?;
+        flow.tls_server_inspected = true;
+        flow.ja3s = ja3s.clone();
+        if let Some(hello) = &server_hello {
+            flow.tls_version = Some(hello.selected_version.unwrap_or(hello.legacy_version));
+            flow.tls_cipher = Some(hello.cipher_suite);
+        }
+
+        let mut verdict = None;
+        if ja3s.as_ref().is_some_and(|h| self.threat_ja3s.contains(h)) {
+            verdict = Some(1); // MALICIOUS_TLS
+        }
+
+        if let Some(chain) = chain {
+            let report = self.certificates.analyze_chain(&chain, flow.sni.as_deref(), (self.now_ms / 1000) as i64);
+            flow.certificate = Some(report.clone());
+            let intel_hit = [&report.sha256, &report.sha1]
+                .into_iter()
+                .find_map(|fp| self.intel.match_hash(IndicatorKind::CertificateHash, fp, self.now_ms))
+                .cloned();
+            if let Some(indicator) = intel_hit {
+                verdict = Some(1); // MALICIOUS_CERTIFICATE
+                let observed = report.sha256.clone();
+                self.events.push(InspectionEvent::ThreatIntel { timestamp_ms: self.now_ms, uid: flow.uid, observed, indicator });
+            }
+            if !report.flags.is_empty() {
+                if verdict.is_none() {
+                    verdict = Some(if report.is_malicious() { 1 } else { 3 }); // MALICIOUS / SUSPICIOUS_CERTIFICATE
+                }
+                let event = InspectionEvent::CertificateAnomaly {
+                    timestamp_ms: self.now_ms,
+                    uid: flow.uid,
+                    sni: flow.sni.clone(),
+                    ja3s,
+                    certificate: report,
+                };
+                self.events.push(event);
+            }
+        }
+
+        verdict
+    }
+
+    /// Decrypt QUIC Initial packets and check the reassembled ClientHello
+    fn inspect_quic(&mut self, payload: &[u8]) -> Option<u8> {
+        let hello = self.quic_hellos.process_datagram(payload)?;
+        self.evaluate_client_hello(&hello, HelloTransport::Quic)
+    }
+
+    /// Domain and fingerprint checks shared by TLS over TCP and QUIC
+    fn evaluate_client_hello(&mut self, hello: &ClientHello, transport: HelloTransport) -> Option<u8> {
+        let ja3 = hello.ja3_hash();
+        let ja4 = hello.ja4(transport);
+        let ja3_indicator = self.intel.match_hash(IndicatorKind::Ja3, &ja3, self.now_ms).cloned();
+        let malicious_fingerprint = Self::is_malicious_fingerprint(hello)
+            || self.threat_ja3.contains(&ja3)
+            || self.threat_ja4.contains(&ja4);
+
+        if let Some(flow) = self.current_flow.and_then(|key| self.flows.get_mut(&key)) {
+            flow.sni = hello.server_name.clone();
+            flow.alpn = hello.alpn.first().cloned();
+            flow.ja3 = Some(ja3);
+            flow.ja4 = Some(ja4);
+        }
+
+        if let Some(sni) = &hello.server_name {
+            if self.is_domain_blocked(sni) {
+                return Some(1); // MALICIOUS_DOMAIN
+            }
+            if let Some(verdict) = self.check_intel_domain(sni) {
+                return Some(verdict);
+            }
+            let source = match transport {
+                HelloTransport::Tcp => "tls",
+                HelloTransport::Quic => "quic",
+            };
+            let lookalike = self.check_lookalike(sni, source);
+            self.observe_domain(sni, source);
+            if lookalike.is_some() {
+                return lookalike;
+            }
+        }
+
+        if let Some(indicator) = ja3_indicator {
+            let observed = indicator.value.clone();
+            self.report_intel_match(indicator, observed);
+            return Some(1); // MALICIOUS_TLS
+        }
+
+        if malicious_fingerprint {
+            return Some(1); // MALICIOUS_TLS
+        }
+
+        None
+    }
+
+    fn log_dns(&mut self, message: dns::DnsMessage) {
+        let Some(logger) = &mut self.logger else { return };
+        let Some(flow) = self.current_flow.and_then(|key| self.flows.get(&key)) else { return };
+        let _ = logger.dns_message(flow, message, self.now_ms);
+    }
+
+    fn log_http_message(&mut self, request: Option<http::HttpRequest>, response: Option<http::HttpResponse>) {
+        let Some(logger) = &mut self.logger else { return };
+        let Some(flow) = self.current_flow.and_then(|key| self.flows.get(&key)) else { return };
+        if let Some(request) = request {
+            let _ = logger.http_request(flow, request, self.now_ms);
+        }
+        if let Some(response) = response {
+            let _ = logger.http_response(flow, response, self.now_ms);
+        }
+    }
+
+    /// Classify the current flow as DoT/DoH/DoQ and apply the encrypted DNS policy
+    fn check_encrypted_dns(&mut self) -> u8 {
+        let Some(key) = self.current_flow else { return 0 };
+        if !matches!(key.protocol, 6 | 17) {
+            return 0;
+        }
+        let Some(flow) = self.flows.get_mut(&key) else { return 0 };
+
+        if flow.encrypted_dns.is_none() {
+            let Some(detection) = self.encrypted_dns.classify(flow) else { return 0 };
+            flow.encrypted_dns = Some(detection.kind);
+            let uid = flow.uid;
+            self.events.push(InspectionEvent::EncryptedDns {
+                timestamp_ms: self.now_ms,
+                uid,
+                detection,
+                action: self.encrypted_dns.policy,
+            });
+        }
+
+        match self.encrypted_dns.policy {
+            EncryptedDnsPolicy::Monitor => 0,
+            EncryptedDnsPolicy::Block => 4, // ENCRYPTED_DNS_BLOCKED
+            EncryptedDnsPolicy::ForcePlainDns => 5, // ENCRYPTED_DNS_REJECT: reset so the client retries on port 53
+        }
+    }
+
+    // Attach country and ASN of the remote end to a new flow
+    fn enrich_flow(&mut self) {
+        if !self.geo.is_loaded() {
+            return;
+        }
+        let Some(key) = self.current_flow else { return };
+        let info = self.geo.lookup(key.dst);
+        if let Some(flow) = self.flows.get_mut(&key) {
+            flow.country = info.country;
+            flow.asn = info.asn;
+            flow.as_org = info.as_org;
+        }
+    }
+
+    // Either endpoint inside a threat intel network; reported once, when the flow opens
+    fn check_intel_addresses(&mut self, key: &FlowKey, report: bool) -> u8 {
+        let hit = [key.dst, key.src]
+            .into_iter()
+            .find_map(|ip| self.intel.match_ip(ip, self.now_ms).map(|indicator| (ip, indicator.clone())));
+        let Some((ip, indicator)) = hit else { return 0 };
+        if report {
+            self.report_intel_match(indicator, ip.to_string());
+        }
+        1 // MALICIOUS_IP
+    }
+
+    fn check_intel_domain(&mut self, domain: &str) -> Option<u8> {
+        let indicator = self.intel.match_domain(domain, self.now_ms)?.clone();
+        self.report_intel_match(indicator, domain.trim_end_matches('.').to_ascii_lowercase());
+        Some(1) // MALICIOUS_DOMAIN
+    }
+
+    fn report_intel_match(&mut self, indicator: Indicator, observed: String) {
+        let uid = self.current_flow.and_then(|key| self.flows.get(&key)).and_then(|f| f.uid);
+        self.events.push(InspectionEvent::ThreatIntel { timestamp_ms: self.now_ms, uid, observed, indicator });
+    }
+
+    fn check_geo_policy(&mut self) -> u8 {
+        if self.geo_rules.is_empty() {
+            return 0;
+        }
+        let Some(flow) = self.current_flow.and_then(|key| self.flows.get_mut(&key)) else { return 0 };
+        if flow.country.is_none() && flow.asn.is_none() {
+            return 0;
+        }
+
+        let info = GeoInfo { country: flow.country.clone(), asn: flow.asn, as_org: flow.as_org.clone() };
+        let Some(rule) = self
+            .geo_rules
+            .iter()
+            .filter(|r| r.matches(&info, flow.uid, flow.bytes_out))
+            .max_by_key(|r| r.action == GeoAction::Block)
+        else {
+            return 0;
+        };
+
+        // One event per flow; blocking continues for every later packet
+        if !flow.geo_alerted {
+            flow.geo_alerted = true;
+            self.events.push(InspectionEvent::GeoPolicy {
+                timestamp_ms: self.now_ms,
+                uid: flow.uid,
+                remote: flow.key.dst,
+                geo: info,
+                bytes_out: flow.bytes_out,
+                rule: rule.clone(),
+            });
+        }
+
+        match rule.action {
+            GeoAction::Block => 7, // GEO_POLICY_BLOCKED
+            GeoAction::Alert => 0,
+        }
+    }
+
+    // Homograph and typosquat check shared by DNS queries and SNI; every match is reported, but
+    // only homographs and edits to long brand labels are blocked
+    fn check_lookalike(&mut self, domain: &str, source: &'static str) -> Option<u8> {
+        let (lookalike, fresh) = self.lookalikes.check(domain);
+        let lookalike = lookalike?.clone();
+        let blocking = lookalike.blocking;
+        if fresh {
+            let uid = self.current_flow.and_then(|key| self.flows.get(&key)).and_then(|f| f.uid);
+            self.events.push(InspectionEvent::LookalikeDomain { timestamp_ms: self.now_ms, uid, source, lookalike });
+        }
+        blocking.then_some(8) // PHISHING_LOOKALIKE
+    }
+
+    // Remember the domain and report the first contact with it
+    fn observe_domain(&mut self, domain: &str, source: &'static str) {
+        let Some(history) = &mut self.domain_history else { return };
+        let Some(registered) = history.observe(domain, self.now_ms) else { return };
+        let uid = self.current_flow.and_then(|key| self.flows.get(&key)).and_then(|f| f.uid);
+        // Already cached by check_lookalike, so this doesn't re-raise its event
+        let lookalike_of = self.lookalikes.check(domain).0.map(|m| m.brand.clone());
+        self.events.push(InspectionEvent::NewDomain {
+            timestamp_ms: self.now_ms,
+            uid,
+            source,
+            domain: registered,
+            query: domain.to_string(),
+            lookalike_of,
+        });
+    }
+
+    fn check_scan(&mut self) -> u8 {
+        let Some(key) = self.current_flow else { return 0 };
+        let uid = self.flows.get(&key).and_then(|f| f.uid);
+        let source = match uid {
+            Some(uid) => ScanSource::Uid(uid),
+            None => ScanSource::Address(key.src),
+        };
+
+        // Sources already caught scanning may not open new connections until the window passes
+        if self.scans.is_flagged(&source, self.now_ms) {
+            self.scans.observe_new_flow(key, uid, &self.flows, self.now_ms);
+            return 6; // PORT_SCAN
+        }
+
+        match self.scans.observe_new_flow(key, uid, &self.flows, self.now_ms) {
+            Some(scan) => {
+                self.events.push(InspectionEvent::Scan { timestamp_ms: self.now_ms, uid, scan });
+                6 // PORT_SCAN
+            }
+            None => 0,
+        }
+    }
+
+    fn inspect_dns(&mut self, packet: &[u8]) -> u8 {
         // Very simple: locate DNS payload after UDP header
-        // IP header length
-        let ihl = (packet[0] & 0x0F) as usize * 4;
+        // IP header length, including any IPv6 extension headers
+        let ihl = Self::transport_offset(packet);
         let udp_offset = ihl;
         if packet.len() <= udp_offset + 8 { return 0; }
 
@@ -174,8 +954,22 @@ impl PacketInspector {
         if packet.len() <= dns_offset { return 0; }
 
         let query = &packet[dns_offset..];
-        // If query is very long or contains base64-like content, mark as tunneling
-        if query.len() > 100 || Self::looks_like_base64(query) {
+        let mut lookalike = None;
+        let mut response = false;
+        if let Some(message) = dns::parse_message(query) {
+            response = message.response;
+            if let Some(name) = message.query_name().filter(|_| !message.response) {
+                lookalike = self.check_intel_domain(name).or_else(|| self.check_lookalike(name, "dns"));
+                self.observe_domain(name, "dns");
+            }
+            self.log_dns(message);
+        }
+        if let Some(verdict) = lookalike {
+            return verdict;
+        }
+        // If query is very long or contains base64-like content, mark as tunneling; answers are
+        // routinely longer and carry no data of ours
+        if !response && (query.len() > 100 || Self::looks_like_base64(query)) {
             return 3; // DNS_TUNNELING
         }
 
@@ -189,9 +983,7 @@ impl PacketInspector {
 
     fn bytes_contains_case_insensitive(hay: &[u8], needle: &[u8]) -> bool {
         if needle.is_empty() || hay.len() < needle.len() { return false; }
-        let lower_hay: Vec<u8> = hay.iter().map(|b| b.to_ascii_lowercase()).collect();
-        let lower_needle: Vec<u8> = needle.iter().map(|b| b.to_ascii_lowercase()).collect();
-        lower_hay.windows(lower_needle.len()).any(|w| w == lower_needle.as_slice())
+        hay.windows(needle.len()).any(|w| w.eq_ignore_ascii_case(needle))
     }
 
     fn looks_like_base64(slice: &[u8]) -> bool {
@@ -200,40 +992,517 @@ impl PacketInspector {
         let s = &slice[..sample_len];
         let mut alpha = 0usize;
         for &c in s {
-            if (b'A'..=b'Z').contains(&c) || (b'a'..=b'z').contains(&c) || (b'0'..=b'9').contains(&c) || c == b'+' || c == b'/' || c == b'=' || c == b'-' || c == b'_' {
+            if c.is_ascii_alphanumeric() || c == b'+' || c == b'/' || c == b'=' || c == b'-' || c == b'_' {
                 alpha += 1;
             }
         }
         alpha * 100 / sample_len > 90 // >90% base64-like chars
     }
 
-    fn calculate_tls_fingerprint(payload: &[u8]) -> String {
-        // Very simplified pseudo-JA3: collect first bytes of ClientHello extensions
-        // This is a placeholder: real parsing requires TLS parsing
-        let mut parts: Vec<String> = Vec::new();
-        for i in 0..payload.len().min(32) {
-            parts.push(format!("{}", payload[i]));
+    fn is_malicious_fingerprint(hello: &ClientHello) -> bool {
+        // Known bad JA3 version and leading cipher suites (simplified), compared without building the string
+        const MAL_CIPHER_PREFIXES: [[u16; 4]; 2] = [
+            [52393, 52392, 49195, 49199],
+            [49195, 49199, 49196, 49200],
+        ];
+        if hello.legacy_version != 771 {
+            return false;
         }
-        parts.join("-")
+        MAL_CIPHER_PREFIXES.iter().any(|prefix| {
+            let mut ciphers = hello.cipher_suites.iter().filter(|c| !tls::is_grease(**c));
+            prefix.iter().all(|expected| ciphers.next() == Some(expected))
+        })
     }
+}
 
-    fn is_malicious_fingerprint(fp: &str) -> bool {
-        // Compare against a small set of known bad substrings (simplified)
-        const MAL_FPS: [&str; 2] = [
-            "771-52393-52392-49195-49199",
-            "771-49195-49199-49196-49200",
-        ];
-        MAL_FPS.iter().any(|m| fp.contains(m))
+/// Score the URL of a raw HTTP request, as the app's JNI layer asks for it
+pub fn analyze_http_request(request: &str) -> UrlAnalysis {
+    match http::parse_request(request.as_bytes()) {
+        Some(request) => url_analysis::analyze_request(&request),
+        // Not a request head: treat the text as a bare URL
+        None => match url_analysis::Url::parse(request) {
+            Some(url) => url_analysis::analyze_url(&url),
+            None => UrlAnalysis::clean(request.trim().to_string()),
+        },
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sha2::Digest;
+    use crate::flow_export::{ExportFormat, ExportTarget};
 
     #[test]
     fn test_base64_detector() {
         let s = b"c29tZWJhc2U2NHN0cmluZw==";
         assert!(PacketInspector::looks_like_base64(s));
     }
+
+    fn ipv4_udp(dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        udp_datagram(([10, 0, 0, 2], 50000), ([93, 184, 216, 34], dst_port), payload)
+    }
+
+    fn udp_datagram(src: ([u8; 4], u16), dst: ([u8; 4], u16), payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0];
+        packet.extend_from_slice(&src.0);
+        packet.extend_from_slice(&dst.0);
+        let total = (28 + payload.len()) as u16;
+        packet[2..4].copy_from_slice(&total.to_be_bytes());
+        packet.extend_from_slice(&src.1.to_be_bytes());
+        packet.extend_from_slice(&dst.1.to_be_bytes());
+        packet.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn tcp_segment(src: ([u8; 4], u16), dst: ([u8; 4], u16), seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0];
+        packet.extend_from_slice(&src.0);
+        packet.extend_from_slice(&dst.0);
+        packet.extend_from_slice(&src.1.to_be_bytes());
+        packet.extend_from_slice(&dst.1.to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        packet.extend_from_slice(payload);
+        let total = packet.len() as u16;
+        packet[2..4].copy_from_slice(&total.to_be_bytes());
+        packet
+    }
+
+    fn ipv4_tcp(dst: [u8; 4], dst_port: u16, flags: u8, payload: &[u8]) -> Vec<u8> {
+        tcp_segment(([10, 0, 0, 2], 50000), (dst, dst_port), 1, flags, payload)
+    }
+
+    fn handshake_record(messages: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (ty, msg) in messages {
+            body.push(*ty);
+            body.extend_from_slice(&(msg.len() as u32).to_be_bytes()[1..]);
+            body.extend_from_slice(msg);
+        }
+        let mut record = vec![0x16, 0x03, 0x03];
+        record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        record.extend_from_slice(&body);
+        record
+    }
+
+    #[test]
+    fn test_tls12_server_certificate_is_inspected() {
+        let weak = include_bytes!("../testdata/weak.der");
+        let mut server_hello = vec![0x03, 0x03];
+        server_hello.extend_from_slice(&[0x22; 32]);
+        server_hello.extend_from_slice(&[0x00, 0xc0, 0x2f, 0x00]); // no session id, cipher, null compression
+        server_hello.extend_from_slice(&[0x00, 0x04, 0xff, 0x01, 0x00, 0x00]); // renegotiation_info
+        let mut certificate = (weak.len() as u32 + 3).to_be_bytes()[1..].to_vec();
+        certificate.extend_from_slice(&(weak.len() as u32).to_be_bytes()[1..]);
+        certificate.extend_from_slice(weak);
+        let flight = handshake_record(&[(2, server_hello), (11, certificate), (14, Vec::new())]);
+
+        let server = ([198, 51, 100, 7], 443);
+        let client = ([10, 0, 0, 2], 50000);
+        let mut inspector = PacketInspector::new();
+        assert_eq!(inspector.inspect(&tcp_segment(client, server, 1, 0x02, &[]), Some(10500), 1_748_736_000_000), 0);
+
+        // The flight arrives in two segments; nothing is decided until it is complete
+        let (first, second) = flight.split_at(300);
+        assert_eq!(inspector.inspect(&tcp_segment(server, client, 5000, 0x18, first), None, 1_748_736_000_010), 0);
+        assert_eq!(inspector.inspect(&tcp_segment(server, client, 5300, 0x18, second), None, 1_748_736_000_020), 3);
+
+        let events = inspector.drain_events();
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["event"], "certificate_anomaly");
+        assert_eq!(json["uid"], 10500);
+        assert_eq!(json["ja3s"], format!("{:x}", md5::Md5::digest(b"771,49199,65281")));
+        let flags = json["certificate"]["flags"].as_array().unwrap();
+        assert!(flags.contains(&serde_json::json!("self_signed")));
+        assert!(flags.contains(&serde_json::json!("weak_key")));
+    }
+
+    #[test]
+    fn test_doh_sni_raises_event_and_applies_policy() {
+        let hello = crate::tls::tests::build_client_hello(Some("dns.quad9.net"), &["h2"]);
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        record.extend_from_slice(&hello);
+
+        let mut inspector = PacketInspector::new();
+        inspector.set_encrypted_dns_policy(EncryptedDnsPolicy::ForcePlainDns);
+        let dst = [203, 0, 113, 53];
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 443, 0x02, &[]), Some(10234), 1_000), 0);
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 443, 0x18, &record), None, 1_050), 5);
+
+        let events = inspector.drain_events();
+        assert_eq!(events.len(), 1);
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["event"], "encrypted_dns");
+        assert_eq!(json["uid"], 10234);
+        assert_eq!(json["resolver"], "dns.quad9.net");
+        assert_eq!(json["kind"], "doh");
+
+        // Later packets on the flow keep the verdict without repeating the event
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 443, 0x10, &[]), None, 1_100), 5);
+        assert!(inspector.drain_events().is_empty());
+    }
+
+    #[test]
+    fn test_quic_initial_sni_uses_domain_blocklist() {
+        let hello = crate::tls::tests::build_client_hello(Some("cdn.evil.example"), &["h3"]);
+        let initial = crate::quic::tests::build_initial(crate::quic::QUIC_V1, &[7; 8], 0, &[(0, &hello)]);
+        let packet = ipv4_udp(443, &initial);
+
+        let mut inspector = PacketInspector::new();
+        assert_eq!(inspector.analyze(&packet), 0); // large, but recognised as QUIC
+
+        inspector.add_threat_domain("evil.example");
+        assert_eq!(inspector.analyze(&packet), 1);
+    }
+
+    #[test]
+    fn test_lookalike_domains_in_dns_and_sni() {
+        let mut inspector = PacketInspector::new();
+        let query = crate::dns::tests::build_message(7, "xn--l-7sbq6ba.com", crate::dns::TYPE_A, &[]);
+        assert_eq!(inspector.inspect(&ipv4_udp(53, &query), Some(10400), 1_000), 8);
+        let legit = crate::dns::tests::build_message(8, "www.apple.com", crate::dns::TYPE_A, &[]);
+        assert_eq!(inspector.inspect(&ipv4_udp(53, &legit), Some(10400), 1_010), 0);
+
+        let hello = crate::tls::tests::build_client_hello(Some("secure.paypa1.com"), &["h2"]);
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        record.extend_from_slice(&hello);
+        let dst = [198, 51, 100, 9];
+        inspector.inspect(&ipv4_tcp(dst, 443, 0x02, &[]), Some(10400), 1_020);
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 443, 0x18, &record), None, 1_030), 8);
+
+        let events: Vec<_> = inspector.drain_events().iter().map(|e| serde_json::to_value(e).unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "lookalike_domain");
+        assert_eq!(events[0]["source"], "dns");
+        assert_eq!(events[0]["brand"], "apple.com");
+        assert_eq!(events[0]["unicode"], "аррlе.com");
+        assert_eq!(events[1]["source"], "tls");
+        assert_eq!(events[1]["uid"], 10400);
+        assert_eq!(events[1]["brand"], "paypal.com");
+        assert_eq!(events[1]["technique"], "homograph");
+
+        // Words an edit away from a short brand pass; the closer ones are still reported
+        for (id, word) in [(9, "love.com"), (10, "cloud.com"), (11, "finance.com")] {
+            let query = crate::dns::tests::build_message(id, word, crate::dns::TYPE_A, &[]);
+            assert_eq!(inspector.inspect(&ipv4_udp(53, &query), Some(10400), 1_040), 0, "{}", word);
+        }
+        let events: Vec<_> = inspector.drain_events().iter().map(|e| serde_json::to_value(e).unwrap()).collect();
+        let brands: Vec<&str> = events.iter().map(|e| e["brand"].as_str().unwrap()).collect();
+        assert_eq!(brands, ["icloud.com", "binance.com"]);
+        assert!(events.iter().all(|e| e["blocking"] == false));
+    }
+
+    #[test]
+    fn test_dns_response_completes_the_logged_query() {
+        use crate::zeek_log::{LogConfig, LogFormat};
+
+        let dir = std::env::temp_dir().join(format!("fortress-dns-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut inspector = PacketInspector::new();
+        inspector.set_logger(Some(ZeekLogger::new(LogConfig::new(&dir, LogFormat::Json)).unwrap()));
+
+        let (client, resolver) = (([10, 0, 0, 2], 41000), ([9, 9, 9, 9], 53));
+        let name = "www.example.com";
+        let query = crate::dns::tests::build_message(21, name, crate::dns::TYPE_A, &[]);
+        let addresses = [[93, 184, 216, 34], [93, 184, 216, 35]];
+        let answer = crate::dns::tests::build_message(21, name, crate::dns::TYPE_A, &addresses);
+        assert_eq!(inspector.inspect(&udp_datagram(client, resolver, &query), Some(10321), 5_000), 0);
+        assert_eq!(inspector.inspect(&udp_datagram(resolver, client, &answer), None, 5_030), 0);
+        inspector.set_logger(None);
+
+        let log = std::fs::read_to_string(dir.join("dns.log")).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let entry: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["query"], name);
+        assert_eq!(entry["trans_id"], 21);
+        assert_eq!(entry["id.orig_p"], 41000);
+        assert_eq!(entry["id.resp_h"], "9.9.9.9");
+        assert_eq!(entry["rtt"], 0.03);
+        assert_eq!(entry["rcode_name"], "NOERROR");
+        assert_eq!(entry["answers"], serde_json::json!(["93.184.216.34", "93.184.216.35"]));
+        assert_eq!(entry["app_uid"], 10321);
+    }
+
+    #[test]
+    fn test_first_contact_with_domain_raises_event() {
+        use crate::domain_history::{DomainHistory, DomainHistoryConfig};
+
+        let mut inspector = PacketInspector::new();
+        let config = DomainHistoryConfig { learning_ms: 0, ..Default::default() };
+        inspector.set_domain_history(Some(DomainHistory::new(config, 0)));
+
+        let query = |id, name| ipv4_udp(53, &crate::dns::tests::build_message(id, name, crate::dns::TYPE_A, &[]));
+        inspector.inspect(&query(1, "www.fresh-domain.example"), Some(10600), 1_000);
+        inspector.inspect(&query(2, "cdn.fresh-domain.example"), Some(10600), 1_010);
+        inspector.inspect(&query(3, "netfix.com"), Some(10601), 1_020);
+
+        let events: Vec<_> = inspector.drain_events().iter().map(|e| serde_json::to_value(e).unwrap()).collect();
+        let new_domains: Vec<_> = events.iter().filter(|e| e["event"] == "new_domain").collect();
+        assert_eq!(new_domains.len(), 2);
+        assert_eq!(new_domains[0]["domain"], "fresh-domain.example");
+        assert_eq!(new_domains[0]["query"], "www.fresh-domain.example");
+        assert_eq!(new_domains[0]["uid"], 10600);
+        assert!(new_domains[0]["lookalike_of"].is_null());
+        assert_eq!(new_domains[1]["lookalike_of"], "netflix.com");
+    }
+
+    #[test]
+    fn test_h2c_request_body_hits_dlp_rules() {
+        let mut inspector = PacketInspector::new();
+        let dst = [198, 51, 100, 20];
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 8000, 0x02, &[]), Some(10700), 1_000), 0);
+
+        let request = crate::http2::tests::post_request("upload.example", "/collect", b"{\"api_key\":\"abc\"}");
+        let (first, second) = request.split_at(60);
+        let (app, server) = (([10, 0, 0, 2], 50000), (dst, 8000));
+        assert_eq!(inspector.inspect(&tcp_segment(app, server, 2, 0x18, first), None, 1_010), 0);
+        assert_eq!(inspector.inspect(&tcp_segment(app, server, 62, 0x18, second), None, 1_020), 2);
+
+        let benign = crate::http2::tests::post_request("upload.example", "/collect", b"{}");
+        assert_eq!(inspector.inspect_http2_stream(7, true, &benign), 0);
+        assert_eq!(inspector.inspect_http2_stream(8, true, &request), 2);
+    }
+
+    #[test]
+    fn test_h2c_segments_are_decoded_in_sequence_order() {
+        let mut inspector = PacketInspector::new();
+        let (app, server) = (([10, 0, 0, 2], 50001), ([198, 51, 100, 21], 8000));
+        assert_eq!(inspector.inspect(&tcp_segment(app, server, 100, 0x02, &[]), Some(10700), 1_000), 0);
+
+        let request = crate::http2::tests::post_request("upload.example", "/collect", b"{\"api_key\":\"abc\"}");
+        let (head, rest) = request.split_at(40);
+        let (middle, tail) = rest.split_at(30);
+        let segment = |seq: u32, payload: &[u8]| tcp_segment(app, server, seq, 0x18, payload);
+        assert_eq!(inspector.inspect(&segment(101, head), None, 1_010), 0);
+        // The tail arrives before the middle, and the head is retransmitted in between
+        assert_eq!(inspector.inspect(&segment(171, tail), None, 1_020), 0);
+        assert_eq!(inspector.inspect(&segment(101, head), None, 1_030), 0);
+        assert_eq!(inspector.inspect(&segment(141, middle), None, 1_040), 2);
+        // A retransmission of bytes already decoded is not decoded again
+        assert_eq!(inspector.inspect(&segment(141, middle), None, 1_050), 0);
+    }
+
+    #[test]
+    fn test_phishing_kit_request_is_blocked() {
+        let mut inspector = PacketInspector::new();
+        let request = b"GET /wp-content/plugins/login/office365/verify.php HTTP/1.1\r\nHost: 203.0.113.80\r\n\r\n";
+        assert_eq!(inspector.inspect(&ipv4_tcp([203, 0, 113, 80], 80, 0x18, request), Some(10800), 1_000), 9);
+        let events = inspector.drain_events();
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["event"], "url_threat");
+        assert_eq!(json["threat_type"], "phishing");
+        assert!(json["explanation"].as_array().unwrap().len() >= 2);
+
+        // Same request over IPv6, as the userspace stack relays it
+        let key = FlowKey {
+            src: "2001:db8::2".parse().unwrap(),
+            dst: "2001:db8::80".parse().unwrap(),
+            src_port: 40_000,
+            dst_port: 80,
+            protocol: 6,
+        };
+        let packet = crate::netstack::tcp_packet(&key, 1, 1, 0x18, 65_535, None, request);
+        assert_eq!(inspector.inspect(&packet, None, 2_000), 9);
+
+        let analysis = analyze_http_request("GET /index.html HTTP/1.1\r\nHost: www.example.com\r\n\r\n");
+        assert!(!analysis.malicious);
+        assert_eq!(analysis.url, "http://www.example.com/index.html");
+    }
+
+    #[test]
+    fn test_threat_intel_indicators_match_traffic_until_expiry() {
+        use crate::threat_intel::{Indicator, IndicatorKind};
+        let mut inspector = PacketInspector::new();
+        let mut network = Indicator::new(IndicatorKind::Ip, "198.51.100.0/24", "indicator--net").unwrap();
+        network.valid_until_ms = Some(10_000);
+        let domain = Indicator::new(IndicatorKind::Domain, "c2.example.org", "misp-attr-1").unwrap();
+        assert_eq!(inspector.import_indicators(vec![network, domain], 1_000), 2);
+
+        let query = crate::dns::tests::build_message(9, "beacon.c2.example.org", crate::dns::TYPE_A, &[]);
+        assert_eq!(inspector.inspect(&ipv4_udp(53, &query), Some(10500), 1_000), 1);
+
+        let dst = [198, 51, 100, 77];
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 443, 0x02, &[]), Some(10500), 1_010), 1);
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 443, 0x10, &[]), Some(10500), 1_020), 1);
+
+        let events: Vec<_> = inspector.drain_events().iter().map(|e| serde_json::to_value(e).unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "threat_intel");
+        assert_eq!(events[0]["kind"], "domain");
+        assert_eq!(events[0]["observed"], "beacon.c2.example.org");
+        assert_eq!(events[1]["kind"], "ip");
+        assert_eq!(events[1]["value"], "198.51.100.0/24");
+        assert_eq!(events[1]["source_id"], "indicator--net");
+
+        // The network indicator's window has closed; the expiry sweep drops it
+        assert_eq!(inspector.inspect(&ipv4_tcp(dst, 443, 0x10, &[]), Some(10500), 12_000), 0);
+        assert_eq!(inspector.threat_intel().len(), 1);
+    }
+
+    #[test]
+    fn test_unanswered_host_sweep_is_flagged() {
+        let mut inspector = PacketInspector::new();
+        let mut verdicts = Vec::new();
+        for host in 1..=40u8 {
+            let syn = ipv4_tcp([192, 168, 0, host], 445, 0x02, &[]);
+            verdicts.push(inspector.inspect(&syn, Some(10900), host as u64 * 250));
+        }
+
+        let first = verdicts.iter().position(|&v| v == 6).expect("sweep should be flagged");
+        assert!(verdicts[..first].iter().all(|&v| v == 0));
+        assert!(verdicts[first..].iter().all(|&v| v == 6));
+
+        let events = inspector.drain_events();
+        assert_eq!(events.len(), 1);
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["event"], "scan");
+        assert_eq!(json["kind"], "host_sweep");
+        assert_eq!(json["source"]["uid"], 10900);
+        assert_eq!(json["ports"], serde_json::json!([445]));
+    }
+
+    #[test]
+    fn test_icmpv6_tunnel_flagged_but_neighbor_discovery_is_not() {
+        fn ipv6_icmp(src: &str, dst: &str, icmp: &[u8]) -> Vec<u8> {
+            let mut packet = vec![0x60, 0, 0, 0];
+            packet.extend_from_slice(&(icmp.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[58, 255]);
+            packet.extend_from_slice(&src.parse::<std::net::Ipv6Addr>().unwrap().octets());
+            packet.extend_from_slice(&dst.parse::<std::net::Ipv6Addr>().unwrap().octets());
+            packet.extend_from_slice(icmp);
+            packet
+        }
+
+        let mut inspector = PacketInspector::new();
+        let mut solicitation = vec![135, 0, 0, 0, 0, 0, 0, 0];
+        solicitation.extend_from_slice(&"fe80::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        solicitation.extend_from_slice(&[1, 1, 0x02, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        for i in 0..10 {
+            assert_eq!(inspector.inspect(&ipv6_icmp("fe80::2", "ff02::1:ff00:1", &solicitation), None, i), 0);
+        }
+
+        // Encrypted-looking echo data that the far end never echoes back
+        let mut verdict = 0;
+        for seq in 0..6u16 {
+            let mut request = vec![128, 0, 0, 0, 0x0b, 0xad];
+            request.extend_from_slice(&seq.to_be_bytes());
+            request.extend((0..300u32).map(|i| (i.wrapping_mul(2_654_435_761).wrapping_add(seq as u32 * 97) >> 13) as u8));
+            verdict = inspector.inspect(&ipv6_icmp("2001:db8::2", "2001:db8::66", &request), Some(10300), 100 + seq as u64);
+        }
+        assert_eq!(verdict, 3);
+        let events = inspector.drain_events();
+        assert_eq!(events.len(), 1);
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["event"], "icmp_tunnel");
+        assert_eq!(json["uid"], 10300);
+        assert_eq!(json["requester"], "2001:db8::2");
+    }
+
+    #[test]
+    fn test_geo_enrichment_and_policy() {
+        let mut inspector = PacketInspector::new();
+        inspector.set_geoip_databases(Some(crate::geoip::tests::country_db()), Some(crate::geoip::tests::asn_db()));
+        inspector.set_geo_rules(vec![
+            GeoRule::parse("block country=RU uid=10400").unwrap(),
+            GeoRule::parse("alert asn=15133 upload>=3000").unwrap(),
+        ]);
+
+        // Another app may reach the same country
+        assert_eq!(inspector.inspect(&ipv4_tcp([203, 0, 113, 7], 443, 0x02, &[]), Some(10401), 1_000), 0);
+        let blocked = tcp_segment(([10, 0, 0, 2], 50001), ([203, 0, 113, 7], 443), 1, 0x02, &[]);
+        assert_eq!(inspector.inspect(&blocked, Some(10400), 1_001), 7);
+
+        let upload = ([10, 0, 0, 2], 50002);
+        let server = ([93, 184, 216, 34], 443);
+        for i in 0..3 {
+            assert_eq!(inspector.inspect(&tcp_segment(upload, server, 1 + i * 1400, 0x18, &[0u8; 1400]), Some(10402), 1_010 + i as u64), 0);
+        }
+        let flow = inspector.flows().iter().find(|f| f.key.src_port == 50002).unwrap();
+        assert_eq!(flow.country.as_deref(), Some("US"));
+        assert_eq!(flow.as_org.as_deref(), Some("Edgecast"));
+
+        let events = inspector.drain_events();
+        assert_eq!(events.len(), 2);
+        let alert = serde_json::to_value(&events[1]).unwrap();
+        assert_eq!(alert["event"], "geo_policy");
+        assert_eq!(alert["asn"], 15133);
+        assert_eq!(alert["rule"]["action"], "alert");
+    }
+
+    #[test]
+    fn test_flows_evicted_from_a_full_table_are_exported() {
+        let path = std::env::temp_dir().join(format!("fortress-evicted-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut inspector = PacketInspector::new();
+        inspector.flows = FlowTable::with_limits(2, 60_000);
+        let target = ExportTarget::File(path.clone());
+        let exporter = FlowExporter::new(ExportFormat::Ipfix, &target, 1_000, &mut |_| true).unwrap();
+        inspector.set_flow_exporter(Some(exporter));
+
+        for (i, port) in [443, 8443, 9443].into_iter().enumerate() {
+            inspector.inspect(&tcp_segment(([10, 0, 0, 2], 50000), ([93, 184, 216, 34], port), 1, 0x02, &[]), None, 1_000 + i as u64);
+        }
+        assert_eq!(inspector.flows.len(), 2);
+        assert_eq!(inspector.exporter.as_ref().unwrap().records_exported(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_steady_state_inspection_does_not_allocate() {
+        let mut inspector = PacketInspector::new();
+        let server = [93, 184, 216, 34];
+        let hello = crate::tls::tests::build_client_hello(Some("example.com"), &["h2"]);
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        record.extend_from_slice(&hello);
+        let mut application_data = vec![0x17, 0x03, 0x03, 0x04, 0x00];
+        application_data.extend_from_slice(&[0x5a; 1024]);
+        let upload = b"--boundary\r\nContent-Disposition: form-data; name=\"photo\"\r\n\r\nJFIF....";
+        let mut quic_short = vec![0x43];
+        quic_short.extend_from_slice(&[0x7e; 1200]);
+
+        let flows = [
+            ipv4_tcp(server, 443, 0x18, &application_data),
+            ipv4_tcp(server, 80, 0x18, upload),
+            ipv4_udp(443, &quic_short),
+            ipv4_udp(5000, &[0x01; 200]),
+        ];
+        assert_eq!(inspector.inspect(&ipv4_tcp(server, 443, 0x18, &record), None, 1_000), 0);
+        for packet in &flows {
+            inspector.inspect(packet, None, 1_000);
+        }
+
+        let before = perf::thread_allocations();
+        for i in 0..100 {
+            for packet in &flows {
+                assert_eq!(inspector.inspect(packet, None, 1_001 + i), 0);
+            }
+        }
+        assert_eq!(perf::thread_allocations() - before, 0);
+
+        // A real request is parsed once for the URL check and, with logging off, not again
+        let request = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl/8\r\n\r\n";
+        let get = ipv4_tcp(server, 80, 0x18, request);
+        let before = perf::thread_allocations();
+        let parsed = http::parse_request(request).unwrap();
+        assert!(!url_analysis::analyze_request(&parsed).malicious);
+        drop(parsed);
+        let one_parse = perf::thread_allocations() - before;
+        inspector.inspect(&get, None, 1_200);
+        let before = perf::thread_allocations();
+        assert_eq!(inspector.inspect(&get, None, 1_201), 0);
+        assert_eq!(perf::thread_allocations() - before, one_parse);
+
+        inspector.analyze(&flows[0]);
+        let snapshot = inspector.performance(u64::MAX / 2);
+        assert_eq!(snapshot.packets, 1);
+        assert!(snapshot.max_ns > 0);
+    }
 }
@@ -0,0 +1,220 @@
+// Link layer header types (tcpdump.org/linktypes.html)
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+// pcapng block types
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+/// An IP packet out of a capture file, link layer header stripped
+#[derive(Debug, Clone)]
+pub struct CapturedPacket {
+    pub timestamp_us: u64,
+    pub data: Vec<u8>,
+}
+
+/// Read the IP packets of a classic pcap or pcapng capture. Frames that are not IPv4/IPv6
+/// (ARP, truncated frames, unknown link types) are skipped; `None` if the format is unknown.
+pub fn read_packets(file: &[u8]) -> Option<Vec<CapturedPacket>> {
+    match file.get(..4)? {
+        [0x0A, 0x0D, 0x0D, 0x0A] => read_pcapng(file),
+        _ => read_pcap(file),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+}
+
+fn read_pcap(file: &[u8]) -> Option<Vec<CapturedPacket>> {
+    let magic = u32::from_le_bytes(file.get(..4)?.try_into().ok()?);
+    let (big_endian, nanos) = match magic {
+        0xA1B2_C3D4 => (false, false),
+        0xA1B2_3C4D => (false, true),
+        0xD4C3_B2A1 => (true, false),
+        0x4D3C_B2A1 => (true, true),
+        _ => return None,
+    };
+    let r = Reader { data: file, big_endian };
+    // The top bits of the link type field carry FCS information
+    let link_type = r.u32(20)? & 0x0FFF_FFFF;
+
+    let mut packets = Vec::new();
+    let mut at = 24;
+    while let (Some(secs), Some(frac), Some(captured)) = (r.u32(at), r.u32(at + 4), r.u32(at + 8)) {
+        let Some(frame) = file.get(at + 16..at + 16 + captured as usize) else { break };
+        let frac_us = if nanos { frac as u64 / 1000 } else { frac as u64 };
+        if let Some(ip) = strip_link_layer(link_type, frame, big_endian) {
+            packets.push(CapturedPacket { timestamp_us: secs as u64 * 1_000_000 + frac_us, data: ip.to_vec() });
+        }
+        at += 16 + captured as usize;
+    }
+    Some(packets)
+}
+
+fn read_pcapng(file: &[u8]) -> Option<Vec<CapturedPacket>> {
+    let mut packets = Vec::new();
+    // Link type of each interface in the current section
+    let mut interfaces: Vec<u32> = Vec::new();
+    let mut r = Reader { data: file, big_endian: false };
+    let mut at = 0;
+    while at + 12 <= file.len() {
+        if file[at..at + 4] == [0x0A, 0x0D, 0x0D, 0x0A] {
+            // The byte order magic decides the endianness of everything in this section
+            r.big_endian = file.get(at + 8..at + 12)? == [0x1A, 0x2B, 0x3C, 0x4D];
+            interfaces.clear();
+        }
+        let block_type = r.u32(at)?;
+        let block_len = r.u32(at + 4)? as usize;
+        if block_len < 12 || at + block_len > file.len() {
+            break;
+        }
+        let body = at + 8;
+        match block_type {
+            PCAPNG_SECTION_HEADER => {}
+            PCAPNG_INTERFACE => interfaces.push(r.u16(body)? as u32),
+            PCAPNG_ENHANCED_PACKET => {
+                let link_type = interfaces.get(r.u32(body)? as usize).copied();
+                // Timestamps default to microsecond resolution
+                let timestamp_us = ((r.u32(body + 4)? as u64) << 32) | r.u32(body + 8)? as u64;
+                let captured = r.u32(body + 12)? as usize;
+                let frame = file.get(body + 20..(body + 20 + captured).min(at + block_len - 4));
+                if let Some(ip) = link_type.zip(frame).and_then(|(lt, frame)| strip_link_layer(lt, frame, r.big_endian)) {
+                    packets.push(CapturedPacket { timestamp_us, data: ip.to_vec() });
+                }
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let frame = file.get(body + 4..at + block_len - 4);
+                if let Some(ip) = interfaces.first().zip(frame).and_then(|(&lt, frame)| strip_link_layer(lt, frame, r.big_endian)) {
+                    packets.push(CapturedPacket { timestamp_us: 0, data: ip.to_vec() });
+                }
+            }
+            _ => {}
+        }
+        at += block_len;
+    }
+    Some(packets)
+}
+
+// The IP packet inside a link layer frame, if it carries one
+fn strip_link_layer(link_type: u32, frame: &[u8], big_endian: bool) -> Option<&[u8]> {
+    let ip = match link_type {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => frame,
+        LINKTYPE_NULL => {
+            // Address family in the capturing host's byte order: 2 = AF_INET, 24/28/30 = AF_INET6
+            let family = Reader { data: frame, big_endian }.u32(0)?;
+            if !matches!(family, 2 | 24 | 28 | 30) {
+                return None;
+            }
+            frame.get(4..)?
+        }
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            let mut ether_type = u16::from_be_bytes(frame.get(at..at + 2)?.try_into().ok()?);
+            // 802.1Q and 802.1ad tags, possibly stacked
+            while matches!(ether_type, 0x8100 | 0x88A8) {
+                at += 4;
+                ether_type = u16::from_be_bytes(frame.get(at..at + 2)?.try_into().ok()?);
+            }
+            if !matches!(ether_type, 0x0800 | 0x86DD) {
+                return None;
+            }
+            frame.get(at + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => {
+            let protocol = u16::from_be_bytes(frame.get(14..16)?.try_into().ok()?);
+            if !matches!(protocol, 0x0800 | 0x86DD) {
+                return None;
+            }
+            frame.get(16..)?
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            let protocol = u16::from_be_bytes(frame.get(0..2)?.try_into().ok()?);
+            if !matches!(protocol, 0x0800 | 0x86DD) {
+                return None;
+            }
+            frame.get(20..)?
+        }
+        _ => return None,
+    };
+    matches!(ip.first()? >> 4, 4 | 6).then_some(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_pcap_and_pcapng_frames() {
+        let ipv4 = [0x45u8, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        let mut ethernet = vec![0u8; 12];
+        ethernet.extend_from_slice(&[0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        ethernet.extend_from_slice(&ipv4);
+        let mut arp = vec![0u8; 12];
+        arp.extend_from_slice(&[0x08, 0x06, 0, 1]);
+
+        // Big-endian nanosecond pcap over Ethernet: a VLAN-tagged IPv4 frame and an ARP frame
+        let mut pcap = vec![0xA1, 0xB2, 0x3C, 0x4D, 0, 2, 0, 4];
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&65_535u32.to_be_bytes());
+        pcap.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+        for frame in [&ethernet, &arp] {
+            pcap.extend_from_slice(&10u32.to_be_bytes());
+            pcap.extend_from_slice(&5_000u32.to_be_bytes());
+            pcap.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            pcap.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            pcap.extend_from_slice(frame);
+        }
+        let packets = read_packets(&pcap).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, ipv4);
+        assert_eq!(packets[0].timestamp_us, 10_000_005);
+
+        // Little-endian pcapng: section header, raw IP interface, one enhanced packet block
+        let mut pcapng = Vec::new();
+        let mut block = |block_type: u32, body: &[u8]| {
+            let len = 12 + body.len().div_ceil(4) * 4;
+            pcapng.extend_from_slice(&block_type.to_le_bytes());
+            pcapng.extend_from_slice(&(len as u32).to_le_bytes());
+            pcapng.extend_from_slice(body);
+            pcapng.resize(pcapng.len() + (len - 12 - body.len()), 0);
+            pcapng.extend_from_slice(&(len as u32).to_le_bytes());
+        };
+        block(PCAPNG_SECTION_HEADER, &[0x4D, 0x3C, 0x2B, 0x1A, 1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        block(PCAPNG_INTERFACE, &[LINKTYPE_RAW as u8, 0, 0, 0, 0, 0, 0, 0]);
+        let mut epb = vec![0u8; 4];
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&42u32.to_le_bytes());
+        epb.extend_from_slice(&(ipv4.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(ipv4.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&ipv4);
+        block(PCAPNG_ENHANCED_PACKET, &epb);
+        let packets = read_packets(&pcapng).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, ipv4);
+        assert_eq!(packets[0].timestamp_us, 42);
+
+        assert!(read_packets(b"not a capture").is_none());
+    }
+}
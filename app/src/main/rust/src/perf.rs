@@ -0,0 +1,226 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::Serialize;
+
+// Log2 buckets of inspection time: bucket i counts packets that took [2^i, 2^(i+1)) ns
+const BUCKETS: usize = 32;
+// Packets per second is measured over windows at least this long
+const RATE_WINDOW_MS: u64 = 1_000;
+
+thread_local! {
+    // Const-initialized and without a destructor, so touching it never allocates
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// System allocator that counts the allocations each thread makes
+pub struct CountingAllocator;
+
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Allocations (including reallocations) made so far by the calling thread
+pub fn thread_allocations() -> u64 {
+    ALLOCATIONS.try_with(Cell::get).unwrap_or(0)
+}
+
+/// Live inspection counters: throughput, per-packet latency and allocations
+#[derive(Debug, Clone, Default)]
+pub struct InspectionCounters {
+    packets: u64,
+    bytes: u64,
+    total_ns: u64,
+    max_ns: u64,
+    allocations: u64,
+    histogram: [u64; BUCKETS],
+    last_packet_ms: u64,
+    window_start_ms: u64,
+    window_packets: u64,
+    packets_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramBucket {
+    /// Exclusive upper bound of the bucket
+    pub lt_ns: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PerfSnapshot {
+    pub packets: u64,
+    pub bytes: u64,
+    pub packets_per_sec: f64,
+    pub mean_ns: u64,
+    /// Percentiles are the upper bound of the histogram bucket they fall in
+    pub p50_ns: u64,
+    pub p99_ns: u64,
+    pub max_ns: u64,
+    pub allocations_per_packet: f64,
+    /// Non-empty buckets only
+    pub ns_histogram: Vec<HistogramBucket>,
+}
+
+impl InspectionCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, bytes: usize, ns: u64, allocations: u64, now_ms: u64) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+        self.total_ns += ns;
+        self.max_ns = self.max_ns.max(ns);
+        self.allocations += allocations;
+        let bucket = (u64::BITS - ns.leading_zeros()).saturating_sub(1) as usize;
+        self.histogram[bucket.min(BUCKETS - 1)] += 1;
+
+        self.last_packet_ms = now_ms;
+        if self.window_packets == 0 {
+            self.window_start_ms = now_ms;
+        }
+        self.window_packets += 1;
+        let elapsed = now_ms.saturating_sub(self.window_start_ms);
+        if elapsed >= RATE_WINDOW_MS {
+            self.packets_per_sec = self.window_packets as f64 * 1000.0 / elapsed as f64;
+            self.window_packets = 0;
+        }
+    }
+
+    fn percentile(&self, fraction: f64) -> u64 {
+        let target = (self.packets as f64 * fraction).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &count) in self.histogram.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return 1 << (i + 1);
+            }
+        }
+        0
+    }
+
+    pub fn snapshot(&self, now_ms: u64) -> PerfSnapshot {
+        // Nothing inspected for a couple of windows: the last rate no longer holds
+        let idle = now_ms.saturating_sub(self.last_packet_ms) >= 2 * RATE_WINDOW_MS;
+        let per_packet = |total: u64| if self.packets == 0 { 0.0 } else { total as f64 / self.packets as f64 };
+        PerfSnapshot {
+            packets: self.packets,
+            bytes: self.bytes,
+            packets_per_sec: if idle { 0.0 } else { self.packets_per_sec },
+            mean_ns: per_packet(self.total_ns) as u64,
+            p50_ns: self.percentile(0.5),
+            p99_ns: self.percentile(0.99),
+            max_ns: self.max_ns,
+            allocations_per_packet: per_packet(self.allocations),
+            ns_histogram: self
+                .histogram
+                .iter()
+                .enumerate()
+                .filter(|(_, &count)| count > 0)
+                .map(|(i, &count)| HistogramBucket { lt_ns: 1 << (i + 1), count })
+                .collect(),
+        }
+    }
+}
+
+/// CPU and memory use of this process
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ProcessStats {
+    /// Share of one core used since the previous call (0 on the first)
+    pub cpu_usage_percent: f64,
+    pub memory_usage_mb: f64,
+}
+
+// Previous CPU time sample: wall clock and user+system seconds
+static LAST_CPU_SAMPLE: Mutex<Option<(Instant, f64)>> = Mutex::new(None);
+
+pub fn process_stats() -> ProcessStats {
+    let mut stats = ProcessStats::default();
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as f64;
+    if let Some(resident) = fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<f64>().ok())
+    {
+        stats.memory_usage_mb = resident * page_size / (1024.0 * 1024.0);
+    }
+
+    let Some(cpu_secs) = cpu_time_secs() else { return stats };
+    let now = Instant::now();
+    let mut last = LAST_CPU_SAMPLE.lock().unwrap();
+    if let Some((at, secs)) = *last {
+        let wall = now.duration_since(at).as_secs_f64();
+        if wall > 0.0 {
+            stats.cpu_usage_percent = (cpu_secs - secs) / wall * 100.0;
+        }
+    }
+    *last = Some((now, cpu_secs));
+    stats
+}
+
+// utime + stime from /proc/self/stat; the command name may contain spaces, so split after ')'
+fn cpu_time_secs() -> Option<f64> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    let mut fields = stat.get(stat.rfind(')')? + 2..)?.split_whitespace();
+    // State is field 3; utime and stime are fields 14 and 15
+    let utime: f64 = fields.nth(11)?.parse().ok()?;
+    let stime: f64 = fields.next()?.parse().ok()?;
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+    Some((utime + stime) / ticks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_histogram_and_rate() {
+        let mut counters = InspectionCounters::new();
+        for i in 0..1_000u64 {
+            // Mostly ~300 ns, a few slow packets
+            let ns = if i % 100 == 0 { 40_000 } else { 300 };
+            counters.record(100, ns, (i % 2 == 0) as u64, i * 2);
+        }
+        let snapshot = counters.snapshot(2_000);
+        assert_eq!(snapshot.packets, 1_000);
+        assert_eq!(snapshot.p50_ns, 512);
+        assert_eq!(snapshot.p99_ns, 512);
+        assert_eq!(snapshot.max_ns, 40_000);
+        assert_eq!(snapshot.allocations_per_packet, 0.5);
+        assert!((snapshot.packets_per_sec - 500.0).abs() < 2.0, "{}", snapshot.packets_per_sec);
+        assert_eq!(snapshot.ns_histogram.len(), 2);
+        assert_eq!(counters.snapshot(10_000).packets_per_sec, 0.0);
+
+        let before = thread_allocations();
+        let boxed = Box::new(7u64);
+        assert_eq!(thread_allocations() - before, 1);
+        drop(boxed);
+
+        let process = process_stats();
+        assert!(process.memory_usage_mb > 0.0);
+    }
+}
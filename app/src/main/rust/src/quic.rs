@@ -0,0 +1,434 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use ahash::AHashMap;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::tls::{self, ClientHello};
+
+pub const QUIC_V1: u32 = 0x0000_0001;
+pub const QUIC_V2: u32 = 0x6b33_43cf;
+
+// RFC 9001 section 5.2 and RFC 9369 section 3.3.1
+const INITIAL_SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb,
+    0x7f, 0x0a,
+];
+const INITIAL_SALT_V2: [u8; 20] = [
+    0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe, 0x6e, 0x26, 0x9d, 0xcb, 0xf9, 0xbd,
+    0x2e, 0xd9,
+];
+
+// Reassembly limits; a post-quantum ClientHello is a few KB, anything bigger is abuse
+const MAX_CRYPTO_STREAM_BYTES: u64 = 64 * 1024;
+const MAX_PENDING_STREAMS: usize = 256;
+
+/// Client Initial packet protection keys derived from the original DCID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitialKeys {
+    pub key: [u8; 16],
+    pub iv: [u8; 12],
+    pub hp: [u8; 16],
+}
+
+/// Decrypted contents of one client Initial packet
+#[derive(Debug, Clone)]
+pub struct InitialPacket {
+    pub version: u32,
+    pub dcid: Vec<u8>,
+    pub scid: Vec<u8>,
+    pub packet_number: u64,
+    /// CRYPTO frames as (stream offset, data), in the order they appeared
+    pub crypto_frames: Vec<(u64, Vec<u8>)>,
+}
+
+/// HKDF-Expand-Label from TLS 1.3 with an empty context
+fn hkdf_expand_label(hk: &Hkdf<Sha256>, label: &str, out: &mut [u8]) -> Option<()> {
+    let full_label = format!("tls13 {}", label);
+    let mut info = Vec::with_capacity(4 + full_label.len());
+    info.extend_from_slice(&(out.len() as u16).to_be_bytes());
+    info.push(full_label.len() as u8);
+    info.extend_from_slice(full_label.as_bytes());
+    info.push(0);
+    hk.expand(&info, out).ok()
+}
+
+/// Derive the client Initial keys for `version` as described in RFC 9001 section 5.2
+pub fn derive_client_initial_keys(version: u32, dcid: &[u8]) -> Option<InitialKeys> {
+    let (salt, key_label, iv_label, hp_label) = match version {
+        QUIC_V1 => (&INITIAL_SALT_V1, "quic key", "quic iv", "quic hp"),
+        QUIC_V2 => (&INITIAL_SALT_V2, "quicv2 key", "quicv2 iv", "quicv2 hp"),
+        _ => return None,
+    };
+
+    let initial = Hkdf::<Sha256>::new(Some(salt), dcid);
+    let mut client_secret = [0u8; 32];
+    hkdf_expand_label(&initial, "client in", &mut client_secret)?;
+
+    let client = Hkdf::<Sha256>::from_prk(&client_secret).ok()?;
+    let mut keys = InitialKeys { key: [0; 16], iv: [0; 12], hp: [0; 16] };
+    hkdf_expand_label(&client, key_label, &mut keys.key)?;
+    hkdf_expand_label(&client, iv_label, &mut keys.iv)?;
+    hkdf_expand_label(&client, hp_label, &mut keys.hp)?;
+    Some(keys)
+}
+
+/// Quick check used by the UDP path before attempting any crypto
+pub fn is_long_header_initial(datagram: &[u8]) -> bool {
+    if datagram.len() < 7 || datagram[0] & 0xc0 != 0xc0 {
+        return false;
+    }
+    let version = u32::from_be_bytes([datagram[1], datagram[2], datagram[3], datagram[4]]);
+    let packet_type = (datagram[0] >> 4) & 0x03;
+    matches!((version, packet_type), (QUIC_V1, 0) | (QUIC_V2, 1))
+}
+
+/// Read a QUIC variable-length integer (RFC 9000 section 16)
+pub fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let first = *data.get(*pos)?;
+    let len = 1usize << (first >> 6);
+    let bytes = data.get(*pos..*pos + len)?;
+    let mut value = (first & 0x3f) as u64;
+    for b in &bytes[1..] {
+        value = (value << 8) | *b as u64;
+    }
+    *pos += len;
+    Some(value)
+}
+
+/// Remove header protection and decrypt every client Initial packet in a UDP datagram
+pub fn decrypt_initial_packets(datagram: &[u8]) -> Vec<InitialPacket> {
+    let mut packets = Vec::new();
+    let mut rest = datagram;
+
+    // Initial packets may be coalesced with 0-RTT or Handshake packets
+    while is_long_header_initial(rest) {
+        match decrypt_one(rest) {
+            Some((packet, consumed)) => {
+                packets.push(packet);
+                rest = &rest[consumed..];
+            }
+            None => break,
+        }
+    }
+
+    packets
+}
+
+fn decrypt_one(data: &[u8]) -> Option<(InitialPacket, usize)> {
+    let version = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+    let mut pos = 5;
+
+    let dcid_len = *data.get(pos)? as usize;
+    if dcid_len > 20 {
+        return None;
+    }
+    let dcid = data.get(pos + 1..pos + 1 + dcid_len)?.to_vec();
+    pos += 1 + dcid_len;
+
+    let scid_len = *data.get(pos)? as usize;
+    if scid_len > 20 {
+        return None;
+    }
+    let scid = data.get(pos + 1..pos + 1 + scid_len)?.to_vec();
+    pos += 1 + scid_len;
+
+    let token_len = read_varint(data, &mut pos)? as usize;
+    pos = pos.checked_add(token_len)?;
+    let length = read_varint(data, &mut pos)? as usize;
+    let pn_offset = pos;
+    let end = pn_offset.checked_add(length)?;
+    if end > data.len() || length < 20 {
+        return None;
+    }
+
+    let keys = derive_client_initial_keys(version, &dcid)?;
+
+    // Header protection: the sample starts 4 bytes past the packet number offset
+    let mut mask = [0u8; 16];
+    mask.copy_from_slice(&data[pn_offset + 4..pn_offset + 20]);
+    let hp = Aes128::new_from_slice(&keys.hp).ok()?;
+    hp.encrypt_block((&mut mask).into());
+
+    let mut header = data[..pn_offset + 4].to_vec();
+    header[0] ^= mask[0] & 0x0f;
+    let pn_len = (header[0] & 0x03) as usize + 1;
+    header.truncate(pn_offset + pn_len);
+
+    let mut packet_number = 0u64;
+    for i in 0..pn_len {
+        header[pn_offset + i] ^= mask[1 + i];
+        packet_number = (packet_number << 8) | header[pn_offset + i] as u64;
+    }
+
+    let mut nonce = keys.iv;
+    for (i, b) in packet_number.to_be_bytes().iter().enumerate() {
+        nonce[4 + i] ^= b;
+    }
+
+    let aead = Aes128Gcm::new_from_slice(&keys.key).ok()?;
+    let plaintext = aead
+        .decrypt(
+            &Nonce::from(nonce),
+            Payload { msg: &data[pn_offset + pn_len..end], aad: &header },
+        )
+        .ok()?;
+
+    let crypto_frames = parse_crypto_frames(&plaintext)?;
+    Some((InitialPacket { version, dcid, scid, packet_number, crypto_frames }, end))
+}
+
+/// Walk the frames permitted in an Initial packet and collect CRYPTO data
+fn parse_crypto_frames(payload: &[u8]) -> Option<Vec<(u64, Vec<u8>)>> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+
+    while pos < payload.len() {
+        let frame_type = read_varint(payload, &mut pos)?;
+        match frame_type {
+            0x00 | 0x01 => {} // PADDING, PING
+            0x02 | 0x03 => {
+                // ACK: largest, delay, range count, first range, ranges, optional ECN counts
+                read_varint(payload, &mut pos)?;
+                read_varint(payload, &mut pos)?;
+                let ranges = read_varint(payload, &mut pos)?;
+                read_varint(payload, &mut pos)?;
+                for _ in 0..ranges.saturating_mul(2) {
+                    read_varint(payload, &mut pos)?;
+                }
+                if frame_type == 0x03 {
+                    for _ in 0..3 {
+                        read_varint(payload, &mut pos)?;
+                    }
+                }
+            }
+            0x06 => {
+                let offset = read_varint(payload, &mut pos)?;
+                let len = read_varint(payload, &mut pos)? as usize;
+                let data = payload.get(pos..pos.checked_add(len)?)?;
+                frames.push((offset, data.to_vec()));
+                pos += len;
+            }
+            0x1c | 0x1d => {
+                read_varint(payload, &mut pos)?;
+                if frame_type == 0x1c {
+                    read_varint(payload, &mut pos)?;
+                }
+                let reason_len = read_varint(payload, &mut pos)? as usize;
+                pos = pos.checked_add(reason_len)?;
+            }
+            // Any other frame type is a protocol violation in an Initial packet
+            _ => return None,
+        }
+    }
+
+    Some(frames)
+}
+
+// CRYPTO stream fragments for one connection, keyed by stream offset
+struct CryptoStream {
+    fragments: BTreeMap<u64, Vec<u8>>,
+    buffered: u64,
+    started: Instant,
+}
+
+impl CryptoStream {
+    fn insert(&mut self, offset: u64, data: Vec<u8>) -> bool {
+        let end = offset.saturating_add(data.len() as u64);
+        if end > MAX_CRYPTO_STREAM_BYTES || self.buffered + data.len() as u64 > MAX_CRYPTO_STREAM_BYTES {
+            return false;
+        }
+        self.buffered += data.len() as u64;
+        self.fragments.insert(offset, data);
+        true
+    }
+
+    // Contiguous bytes from offset zero; fragments may overlap on retransmission
+    fn contiguous(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (&offset, data) in &self.fragments {
+            let have = out.len() as u64;
+            if offset > have {
+                break;
+            }
+            let skip = (have - offset) as usize;
+            if skip < data.len() {
+                out.extend_from_slice(&data[skip..]);
+            }
+        }
+        out
+    }
+}
+
+/// Reassembles CRYPTO frames across Initial packets until a full ClientHello is available
+pub struct QuicHelloReassembler {
+    streams: AHashMap<Vec<u8>, CryptoStream>,
+}
+
+impl QuicHelloReassembler {
+    pub fn new() -> Self {
+        Self { streams: AHashMap::new() }
+    }
+
+    /// Feed one UDP payload; returns the ClientHello once all of it has been seen
+    pub fn process_datagram(&mut self, datagram: &[u8]) -> Option<ClientHello> {
+        for packet in decrypt_initial_packets(datagram) {
+            if let Some(hello) = self.process_packet(packet) {
+                return Some(hello);
+            }
+        }
+        None
+    }
+
+    fn process_packet(&mut self, packet: InitialPacket) -> Option<ClientHello> {
+        if packet.crypto_frames.is_empty() {
+            return None;
+        }
+
+        if !self.streams.contains_key(&packet.dcid) && self.streams.len() >= MAX_PENDING_STREAMS {
+            self.evict_oldest();
+        }
+
+        let stream = self.streams.entry(packet.dcid.clone()).or_insert_with(|| CryptoStream {
+            fragments: BTreeMap::new(),
+            buffered: 0,
+            started: Instant::now(),
+        });
+
+        for (offset, data) in packet.crypto_frames {
+            if !stream.insert(offset, data) {
+                self.streams.remove(&packet.dcid);
+                return None;
+            }
+        }
+
+        let assembled = stream.contiguous();
+        let total = tls::handshake_message_len(&assembled)?;
+        if assembled.len() < total {
+            return None;
+        }
+
+        self.streams.remove(&packet.dcid);
+        tls::parse_client_hello(&assembled[..total])
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self.streams.iter().min_by_key(|(_, s)| s.started).map(|(k, _)| k.clone());
+        if let Some(key) = oldest {
+            self.streams.remove(&key);
+        }
+    }
+
+    pub fn pending_streams(&self) -> usize {
+        self.streams.len()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tls::tests::build_client_hello;
+
+    /// Protect a client Initial packet carrying the given CRYPTO frames (test-side encoder)
+    pub(crate) fn build_initial(version: u32, dcid: &[u8], pn: u8, frames: &[(u64, &[u8])]) -> Vec<u8> {
+        let keys = derive_client_initial_keys(version, dcid).unwrap();
+
+        let mut payload = Vec::new();
+        for (offset, data) in frames {
+            payload.push(0x06);
+            push_varint(&mut payload, *offset);
+            push_varint(&mut payload, data.len() as u64);
+            payload.extend_from_slice(data);
+        }
+        payload.resize(payload.len().max(1162), 0); // pad to a 1200-byte datagram
+
+        let type_bits = if version == QUIC_V2 { 0x10 } else { 0x00 };
+        let mut header = vec![0xc0 | type_bits];
+        header.extend_from_slice(&version.to_be_bytes());
+        header.push(dcid.len() as u8);
+        header.extend_from_slice(dcid);
+        header.push(0); // empty SCID
+        header.push(0); // empty token
+        let length = 1 + payload.len() + 16;
+        header.extend_from_slice(&((length as u16) | 0x4000).to_be_bytes());
+        let pn_offset = header.len();
+        header.push(pn);
+
+        let mut nonce = keys.iv;
+        nonce[11] ^= pn;
+        let aead = Aes128Gcm::new_from_slice(&keys.key).unwrap();
+        let ciphertext = aead
+            .encrypt(&Nonce::from(nonce), Payload { msg: &payload, aad: &header })
+            .unwrap();
+
+        let mut packet = header;
+        packet.extend_from_slice(&ciphertext);
+
+        let mut mask = [0u8; 16];
+        mask.copy_from_slice(&packet[pn_offset + 4..pn_offset + 20]);
+        Aes128::new_from_slice(&keys.hp).unwrap().encrypt_block((&mut mask).into());
+        packet[0] ^= mask[0] & 0x0f;
+        packet[pn_offset] ^= mask[1];
+        packet
+    }
+
+    fn push_varint(out: &mut Vec<u8>, v: u64) {
+        if v < 64 {
+            out.push(v as u8);
+        } else {
+            out.extend_from_slice(&((v as u16) | 0x4000).to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_rfc9001_initial_keys() {
+        let dcid = hex::decode("8394c8f03e515708").unwrap();
+        let keys = derive_client_initial_keys(QUIC_V1, &dcid).unwrap();
+        assert_eq!(hex::encode(keys.key), "1f369613dd76d5467730efcbe3b1a22d");
+        assert_eq!(hex::encode(keys.iv), "fa044b2f42a3fd3b46fb255c");
+        assert_eq!(hex::encode(keys.hp), "9f50449e04a0e810283a1e9933adedd2");
+    }
+
+    #[test]
+    fn test_rfc9369_initial_keys() {
+        let dcid = hex::decode("8394c8f03e515708").unwrap();
+        let keys = derive_client_initial_keys(QUIC_V2, &dcid).unwrap();
+        assert_eq!(hex::encode(keys.key), "8b1a0bc121284290a29e0971b5cd045d");
+        assert_eq!(hex::encode(keys.iv), "91f73e2351d8fa91660e909f");
+        assert_eq!(hex::encode(keys.hp), "45b95e15235d6f45a6b19cbcb0294ba9");
+    }
+
+    #[test]
+    fn test_reassembles_hello_split_across_packets() {
+        let hello = build_client_hello(Some("video.example.net"), &["h3"]);
+        let (first, second) = hello.split_at(hello.len() / 2);
+        let dcid = [0x42u8; 8];
+
+        for version in [QUIC_V1, QUIC_V2] {
+            let mut reassembler = QuicHelloReassembler::new();
+            // Deliver the tail first, as Chrome's CRYPTO frame shuffling can
+            let tail = build_initial(version, &dcid, 1, &[(first.len() as u64, second)]);
+            assert!(reassembler.process_datagram(&tail).is_none());
+            assert_eq!(reassembler.pending_streams(), 1);
+
+            let head = build_initial(version, &dcid, 0, &[(0, first)]);
+            let parsed = reassembler.process_datagram(&head).unwrap();
+            assert_eq!(parsed.server_name.as_deref(), Some("video.example.net"));
+            assert_eq!(reassembler.pending_streams(), 0);
+        }
+    }
+
+    #[test]
+    fn test_rejects_tampered_packet() {
+        let hello = build_client_hello(Some("example.org"), &[]);
+        let mut packet = build_initial(QUIC_V1, &[1, 2, 3, 4], 0, &[(0, &hello)]);
+        let last = packet.len() - 1;
+        packet[last] ^= 0xff;
+        assert!(decrypt_initial_packets(&packet).is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+
+use ahash::AHashMap;
+use serde::Serialize;
+
+use crate::flow_table::{FlowKey, FlowTable};
+
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+// Memory bounds: probes remembered per source and sources tracked at once
+const MAX_PROBES_PER_SOURCE: usize = 4096;
+const MAX_SOURCES: usize = 1024;
+
+/// Tunables for scan detection
+#[derive(Debug, Clone, Copy)]
+pub struct ScanThresholds {
+    /// Sliding window over which probes are counted
+    pub window_ms: u64,
+    /// Distinct destination ports on one host that make a port scan
+    pub ports_per_host: usize,
+    /// Distinct destination hosts that make a host sweep
+    pub hosts: usize,
+    /// Share of answered-or-not probes that must have failed (SYN-only, RST, no reply)
+    pub min_failed_ratio: f32,
+    /// Probes younger than this haven't had a chance to be answered and are not judged
+    pub response_grace_ms: u64,
+}
+
+impl Default for ScanThresholds {
+    fn default() -> Self {
+        Self {
+            window_ms: 60_000,
+            ports_per_host: 20,
+            hosts: 30,
+            min_failed_ratio: 0.7,
+            response_grace_ms: 1_500,
+        }
+    }
+}
+
+/// Who is scanning: the owning app when known, else the local address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanSource {
+    Uid(u32),
+    Address(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanKind {
+    /// Many ports on one host
+    PortScan,
+    /// Many hosts, usually on the same few ports
+    HostSweep,
+}
+
+/// A detected scan and the targets it touched
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanVerdict {
+    pub source: ScanSource,
+    pub kind: ScanKind,
+    pub targets: Vec<IpAddr>,
+    pub ports: Vec<u16>,
+    pub probes: usize,
+    pub failed_ratio: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    key: FlowKey,
+    at_ms: u64,
+}
+
+#[derive(Default)]
+struct SourceActivity {
+    probes: VecDeque<Probe>,
+    // Reference counts of live probes, for O(1) distinct counts
+    hosts: AHashMap<IpAddr, u32>,
+    ports_by_host: AHashMap<IpAddr, AHashMap<u16, u32>>,
+    last_seen_ms: u64,
+    flagged_until_ms: u64,
+}
+
+impl SourceActivity {
+    fn add(&mut self, probe: Probe) {
+        *self.hosts.entry(probe.key.dst).or_default() += 1;
+        *self.ports_by_host.entry(probe.key.dst).or_default().entry(probe.key.dst_port).or_default() += 1;
+        self.probes.push_back(probe);
+        if self.probes.len() > MAX_PROBES_PER_SOURCE {
+            self.pop_oldest();
+        }
+    }
+
+    fn prune(&mut self, oldest_ms: u64) {
+        while self.probes.front().is_some_and(|p| p.at_ms < oldest_ms) {
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        let Some(probe) = self.probes.pop_front() else { return };
+        let dst = probe.key.dst;
+        if let Some(count) = self.hosts.get_mut(&dst) {
+            *count -= 1;
+            if *count == 0 {
+                self.hosts.remove(&dst);
+            }
+        }
+        if let Some(ports) = self.ports_by_host.get_mut(&dst) {
+            if let Some(count) = ports.get_mut(&probe.key.dst_port) {
+                *count -= 1;
+                if *count == 0 {
+                    ports.remove(&probe.key.dst_port);
+                }
+            }
+            if ports.is_empty() {
+                self.ports_by_host.remove(&dst);
+            }
+        }
+    }
+}
+
+/// Counts distinct ports per host and distinct hosts per source over new outbound flows
+pub struct ScanDetector {
+    pub thresholds: ScanThresholds,
+    sources: AHashMap<ScanSource, SourceActivity>,
+}
+
+impl Default for ScanDetector {
+    fn default() -> Self {
+        Self::new(ScanThresholds::default())
+    }
+}
+
+impl ScanDetector {
+    pub fn new(thresholds: ScanThresholds) -> Self {
+        Self { thresholds, sources: AHashMap::new() }
+    }
+
+    /// Whether new connection attempts from this source should be refused
+    pub fn is_flagged(&self, source: &ScanSource, now_ms: u64) -> bool {
+        self.sources.get(source).is_some_and(|s| now_ms < s.flagged_until_ms)
+    }
+
+    /// Record a newly opened outbound flow and check the source against the thresholds
+    pub fn observe_new_flow(&mut self, key: FlowKey, uid: Option<u32>, flows: &FlowTable, now_ms: u64) -> Option<ScanVerdict> {
+        // Only connection-oriented probing is counted; ICMP has its own detector
+        if !matches!(key.protocol, 6 | 17) {
+            return None;
+        }
+
+        let source = match uid {
+            Some(uid) => ScanSource::Uid(uid),
+            None => ScanSource::Address(key.src),
+        };
+
+        if !self.sources.contains_key(&source) && self.sources.len() >= MAX_SOURCES {
+            let idle = self.sources.iter().min_by_key(|(_, a)| a.last_seen_ms).map(|(s, _)| *s);
+            if let Some(idle) = idle {
+                self.sources.remove(&idle);
+            }
+        }
+
+        let thresholds = self.thresholds;
+        let activity = self.sources.entry(source).or_default();
+        activity.last_seen_ms = now_ms;
+        activity.prune(now_ms.saturating_sub(thresholds.window_ms));
+        activity.add(Probe { key, at_ms: now_ms });
+
+        if now_ms < activity.flagged_until_ms {
+            return None; // already reported for this window
+        }
+
+        let port_scan_target = activity
+            .ports_by_host
+            .iter()
+            .filter(|(_, ports)| ports.len() >= thresholds.ports_per_host)
+            .max_by_key(|(_, ports)| ports.len())
+            .map(|(host, _)| *host);
+
+        let (kind, probes): (ScanKind, Vec<Probe>) = if let Some(host) = port_scan_target {
+            (ScanKind::PortScan, activity.probes.iter().filter(|p| p.key.dst == host).copied().collect())
+        } else if activity.hosts.len() >= thresholds.hosts {
+            (ScanKind::HostSweep, activity.probes.iter().copied().collect())
+        } else {
+            return None;
+        };
+
+        // Judge only probes old enough to have been answered
+        let judged: Vec<&Probe> =
+            probes.iter().filter(|p| now_ms.saturating_sub(p.at_ms) >= thresholds.response_grace_ms).collect();
+        let minimum = match kind {
+            ScanKind::PortScan => thresholds.ports_per_host / 2,
+            ScanKind::HostSweep => thresholds.hosts / 2,
+        };
+        if judged.len() < minimum.max(1) {
+            return None;
+        }
+
+        let failed = judged.iter().filter(|p| Self::probe_failed(flows, &p.key)).count();
+        let failed_ratio = failed as f32 / judged.len() as f32;
+        if failed_ratio < thresholds.min_failed_ratio {
+            return None;
+        }
+
+        activity.flagged_until_ms = now_ms + thresholds.window_ms;
+
+        let mut targets: Vec<IpAddr> = probes.iter().map(|p| p.key.dst).collect();
+        targets.sort();
+        targets.dedup();
+        let mut ports: Vec<u16> = probes.iter().map(|p| p.key.dst_port).collect();
+        ports.sort_unstable();
+        ports.dedup();
+
+        Some(ScanVerdict { source, kind, targets, ports, probes: probes.len(), failed_ratio })
+    }
+
+    // SYN that was never answered with SYN-ACK, was reset, or a UDP probe with no reply
+    fn probe_failed(flows: &FlowTable, key: &FlowKey) -> bool {
+        let Some(flow) = flows.get(key) else { return true };
+        match key.protocol {
+            6 => flow.tcp_flags_in & TCP_RST != 0 || flow.tcp_flags_in & TCP_SYN == 0,
+            _ => flow.packets_in == 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_table::parse_packet;
+
+    fn syn(dst: [u8; 4], port: u16, flags: u8, reverse: bool) -> Vec<u8> {
+        let (src, dst, sport, dport) = if reverse {
+            (dst, [192, 168, 1, 20], port, 40000)
+        } else {
+            ([192, 168, 1, 20], dst, 40000, port)
+        };
+        let mut p = vec![0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0];
+        p.extend_from_slice(&src);
+        p.extend_from_slice(&dst);
+        p.extend_from_slice(&sport.to_be_bytes());
+        p.extend_from_slice(&dport.to_be_bytes());
+        p.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        p
+    }
+
+    fn open(table: &mut FlowTable, detector: &mut ScanDetector, dst: [u8; 4], port: u16, now: u64) -> Option<ScanVerdict> {
+        let packet = syn(dst, port, TCP_SYN, false);
+        let (key, _) = table.record(&parse_packet(&packet).unwrap(), packet.len(), Some(10777), now);
+        detector.observe_new_flow(key, Some(10777), table, now)
+    }
+
+    #[test]
+    fn test_vertical_port_scan_with_resets() {
+        let mut table = FlowTable::new();
+        let mut detector = ScanDetector::default();
+        let target = [192, 168, 1, 1];
+
+        let mut verdict = None;
+        for (i, port) in (1..=40u16).enumerate() {
+            let now = i as u64 * 100;
+            // The target answers closed ports with RST
+            verdict = verdict.or(open(&mut table, &mut detector, target, port, now));
+            let rst = syn(target, port, TCP_RST | 0x10, true);
+            table.record(&parse_packet(&rst).unwrap(), rst.len(), None, now + 5);
+        }
+
+        let verdict = verdict.expect("scan should be detected");
+        assert_eq!(verdict.kind, ScanKind::PortScan);
+        assert_eq!(verdict.source, ScanSource::Uid(10777));
+        assert_eq!(verdict.targets, vec![IpAddr::from(target)]);
+        assert!(verdict.failed_ratio >= 0.99);
+        assert!(detector.is_flagged(&ScanSource::Uid(10777), 5_000));
+    }
+
+    #[test]
+    fn test_answered_connections_are_not_a_sweep() {
+        let mut table = FlowTable::new();
+        let mut detector = ScanDetector::default();
+
+        for i in 0..60u8 {
+            let now = i as u64 * 200;
+            let dst = [203, 0, 113, i];
+            assert!(open(&mut table, &mut detector, dst, 443, now).is_none());
+            let synack = syn(dst, 443, TCP_SYN | 0x10, true);
+            table.record(&parse_packet(&synack).unwrap(), synack.len(), None, now + 20);
+        }
+    }
+}
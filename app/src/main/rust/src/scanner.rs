@@ -0,0 +1,1096 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
+use serde_json::Value;
+
+use crate::axml;
+use crate::clamav;
+use crate::dex::{self, Dex};
+use crate::elf;
+use crate::cloud_reputation::{CloudConfig, CloudReputation, HttpJsonProvider, ReputationProvider};
+use crate::fuzzy_hash::{FuzzyDatabase, FuzzyHashes, FuzzyMatch};
+use crate::hash_reputation::{self, DeltaError, HashReputation, Reputation, Verdict};
+use crate::signatures::{BodySignature, SignatureDatabase, SignatureMatch};
+use crate::yara::{RuleMatch, YaraRules};
+use crate::zip::ZipArchive;
+
+// Malware signature database
+lazy_static! {
+    static ref MALWARE_SIGNATURES: Mutex<SignatureDatabase> = Mutex::new(SignatureDatabase::new());
+    // Compiled YARA rules shared with memory analysis, and why the last load failed
+    static ref YARA_RULES: Mutex<Option<Arc<YaraRules>>> = Mutex::new(None);
+    static ref YARA_LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+    // Local hash reputation store, once opened, and the cloud lookups consulted after it
+    static ref HASH_REPUTATION: Mutex<Option<HashReputation>> = Mutex::new(None);
+    static ref CLOUD_REPUTATION: Mutex<Option<Arc<CloudReputation>>> = Mutex::new(None);
+    // Known-bad ssdeep and TLSH digests; loads copy on write so scans never wait on them
+    static ref FUZZY_HASHES: Mutex<Arc<FuzzyDatabase>> = Mutex::new(Arc::new(FuzzyDatabase::new()));
+}
+
+// Files read ahead in a directory scan so their cloud lookups go out as one batch, within a
+// memory budget since APKs can be large
+const CLOUD_PREFETCH_FILES: usize = 32;
+const CLOUD_PREFETCH_BYTES: usize = 128 << 20;
+// Directory levels a directory scan descends, counting the one it starts in
+const MAX_DIRECTORY_DEPTH: usize = 16;
+
+// Extensions a directory scan always picks up; other files only when their magic is ZIP, DEX or ELF
+const SCANNED_EXTENSIONS: [&str; 15] =
+    ["apk", "apks", "xapk", "jar", "zip", "dex", "odex", "so", "exe", "dll", "bat", "cmd", "ps1", "vbs", "js"];
+
+// Confidence from which a result counts as malicious
+const MALICIOUS_THRESHOLD: f32 = 0.5;
+
+// How far into an archive a scan goes: nesting depth, bytes per entry and in total
+const MAX_ARCHIVE_DEPTH: usize = 3;
+const MAX_ENTRY_SIZE: usize = 64 << 20;
+const MAX_ARCHIVE_BYTES: usize = 512 << 20;
+// ZIP tricks only malware bothers with, when they show up in an APK
+const ZIP_ANOMALY_CONFIDENCE: f32 = 0.6;
+// Entries the scan could not fully cover, and damage outside an APK: reported, but below the
+// malicious threshold since large assets and corrupt downloads are common
+const ZIP_UNSCANNED_CONFIDENCE: f32 = 0.3;
+
+// How much of the pipeline applies to a piece of content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentScope {
+    // A file on its own: every check
+    File,
+    // An archive, whose compressed body makes entropy and string checks meaningless
+    Archive,
+    // A member of an archive: structural heuristics only, and no cloud lookup per entry
+    Entry,
+}
+
+// A detection inside an archive, and the entry that triggered it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryDetection {
+    // Path within the archive; nested archives are joined with "!/"
+    pub entry: String,
+    pub threat_name: String,
+    pub confidence: f32,
+}
+
+// Malware scanning result
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub file_path: String,
+    pub is_malicious: bool,
+    pub threat_name: Option<String>,
+    pub confidence: f32,
+    pub scan_time_ms: u64,
+    // Every body signature found, with the offset of its first byte
+    pub signature_matches: Vec<SignatureMatch>,
+    pub rule_matches: Vec<RuleMatch>,
+    // What the local hash reputation store or a cloud provider says about the file, if known
+    pub reputation: Option<Reputation>,
+    // The file's ssdeep and TLSH digests, and the closest known-bad digest within its threshold
+    pub fuzzy_hashes: Option<FuzzyHashes>,
+    pub fuzzy_match: Option<FuzzyMatch>,
+    // Framework methods a DEX file references, for the behavior analyzer; for an APK, those of
+    // all its DEX files
+    pub api_calls: Vec<String>,
+    // For archives, what each entry triggered
+    pub detections: Vec<EntryDetection>,
+}
+
+// Initialize the malware scanner
+#[no_mangle]
+pub extern "C" fn hypervisor_scanner_init() -> i32 {
+    let mut signatures = MALWARE_SIGNATURES.lock().unwrap();
+
+    // Load known malware signatures (simplified example)
+    // In a real implementation, this would load from a secure database
+    let defaults = [
+        BodySignature::literal("trojan_example", &[0x90, 0x90, 0x90]), // NOP sled example
+        BodySignature::literal("ransomware_pattern", &[0xE8, 0x00, 0x00, 0x00, 0x00]), // CALL pattern
+    ];
+    for signature in defaults.into_iter().flatten() {
+        signatures.insert(signature);
+    }
+
+    0 // Success
+}
+
+// Scan a file for malware
+#[no_mangle]
+pub extern "C" fn hypervisor_scan_file(file_path: *const u8, path_len: usize) -> *mut ScanResult {
+    let path_bytes = unsafe { std::slice::from_raw_parts(file_path, path_len) };
+    let path_str = match std::str::from_utf8(path_bytes) {
+        Ok(s) => s,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    let start_time = std::time::Instant::now();
+
+    let result = match scan_file_internal(path_str) {
+        Ok(scan_result) => scan_result,
+        Err(_) => ScanResult {
+            file_path: path_str.to_string(),
+            is_malicious: false,
+            threat_name: None,
+            confidence: 0.0,
+            scan_time_ms: start_time.elapsed().as_millis() as u64,
+            signature_matches: Vec::new(),
+            rule_matches: Vec::new(),
+            reputation: None,
+            fuzzy_hashes: None,
+            fuzzy_match: None,
+            api_calls: Vec::new(),
+            detections: Vec::new(),
+        },
+    };
+
+    Box::into_raw(Box::new(result))
+}
+
+// Internal file scanning implementation
+fn scan_file_internal(file_path: &str) -> Result<ScanResult, Box<dyn std::error::Error>> {
+    let start_time = std::time::Instant::now();
+    let path = Path::new(file_path);
+
+    // Check if file exists
+    if !path.exists() {
+        return Ok(ScanResult {
+            file_path: file_path.to_string(),
+            is_malicious: false,
+            threat_name: Some("FILE_NOT_FOUND".to_string()),
+            confidence: 0.0,
+            scan_time_ms: start_time.elapsed().as_millis() as u64,
+            signature_matches: Vec::new(),
+            rule_matches: Vec::new(),
+            reputation: None,
+            fuzzy_hashes: None,
+            fuzzy_match: None,
+            api_calls: Vec::new(),
+            detections: Vec::new(),
+        });
+    }
+
+    // Read file content
+    let content = fs::read(path)?;
+    Ok(scan_content(file_path, &content, start_time))
+}
+
+fn scan_content(file_path: &str, content: &[u8], start_time: std::time::Instant) -> ScanResult {
+    let Some(archive) = ZipArchive::parse(content) else {
+        return analyze_content(file_path, content, start_time, ContentScope::File);
+    };
+    let mut result = analyze_content(file_path, content, start_time, ContentScope::Archive);
+    // A known-clean package is not unpacked
+    if result.reputation.as_ref().is_some_and(|r| r.verdict == Verdict::Clean) {
+        return result;
+    }
+
+    let mut budget = MAX_ARCHIVE_BYTES;
+    scan_archive(&mut result, &archive, "", 1, &mut budget);
+    result.api_calls.sort_unstable();
+    result.api_calls.dedup();
+
+    // The package is as bad as its worst entry; a verdict on the package itself keeps its name
+    let worst = result.detections.iter().max_by(|a, b| a.confidence.total_cmp(&b.confidence));
+    if let Some(worst) = worst.filter(|worst| worst.confidence >= MALICIOUS_THRESHOLD) {
+        if !result.is_malicious {
+            result.is_malicious = true;
+            result.threat_name = Some(worst.threat_name.clone());
+        }
+        result.confidence = result.confidence.max(worst.confidence);
+    }
+    result.scan_time_ms = start_time.elapsed().as_millis() as u64;
+    result
+}
+
+// Scan every entry of an archive, descending into nested APKs and JARs, and add what each one
+// triggers to the outer result under the entry's path. Entries past the size limits have their
+// start scanned; none is skipped without a detection saying so.
+fn scan_archive(result: &mut ScanResult, archive: &ZipArchive, prefix: &str, depth: usize, budget: &mut usize) {
+    let apk = archive.is_apk();
+    let anomaly_confidence = if apk { ZIP_ANOMALY_CONFIDENCE } else { ZIP_UNSCANNED_CONFIDENCE };
+    if apk {
+        for anomaly in &archive.anomalies {
+            result.detections.push(EntryDetection {
+                entry: format!("{}{}", prefix, anomaly.entry),
+                threat_name: anomaly.kind.finding().to_string(),
+                confidence: ZIP_ANOMALY_CONFIDENCE,
+            });
+        }
+    }
+
+    for entry in &archive.entries {
+        let path = format!("{}{}", prefix, entry.name);
+        let read = if *budget > 0 { archive.read(entry, MAX_ENTRY_SIZE.min(*budget)).ok() } else { None };
+        let Some(read) = read else {
+            result.detections.push(EntryDetection {
+                entry: path,
+                threat_name: "ZIP_ENTRY_NOT_SCANNED".to_string(),
+                confidence: ZIP_UNSCANNED_CONFIDENCE,
+            });
+            continue;
+        };
+        if !read.complete {
+            result.detections.push(EntryDetection {
+                entry: path.clone(),
+                threat_name: "ZIP_ENTRY_PARTIALLY_SCANNED".to_string(),
+                confidence: ZIP_UNSCANNED_CONFIDENCE,
+            });
+        } else if !read.crc_valid {
+            // Altered after it was packed, so what an analyser and the platform see may differ
+            result.detections.push(EntryDetection {
+                entry: path.clone(),
+                threat_name: "ZIP_CRC_MISMATCH".to_string(),
+                confidence: anomaly_confidence,
+            });
+        }
+        let content = read.data;
+        *budget -= content.len();
+
+        let nested = if depth < MAX_ARCHIVE_DEPTH { ZipArchive::parse(&content) } else { None };
+        let scope = if nested.is_some() { ContentScope::Archive } else { ContentScope::Entry };
+        let entry_result = analyze_content(&path, &content, std::time::Instant::now(), scope);
+        if entry_result.is_malicious {
+            result.detections.push(EntryDetection {
+                entry: path.clone(),
+                threat_name: entry_result.threat_name.unwrap_or_default(),
+                confidence: entry_result.confidence,
+            });
+        }
+        result.api_calls.extend(entry_result.api_calls);
+        if let Some(nested) = &nested {
+            scan_archive(result, nested, &format!("{}!/", path), depth + 1, budget);
+        }
+    }
+}
+
+fn analyze_content(file_path: &str, content: &[u8], start_time: std::time::Instant, scope: ContentScope) -> ScanResult {
+    // Look the file's hashes up in the reputation store
+    let reputation = lookup_reputation(content, scope != ContentScope::Entry);
+
+    // Perform signature-based scanning
+    let signature_matches = scan_with_signatures(content);
+    let rule_matches = yara_rules().map_or_else(Vec::new, |rules| rules.scan(content, Some(content.len() as u64), 0));
+    let fuzzy_hashes = FuzzyHashes::of(content);
+    let fuzzy_database = FUZZY_HASHES.lock().unwrap().clone();
+    let fuzzy_match = fuzzy_database.best_match(&fuzzy_hashes);
+    let signature_result = reputation
+        .as_ref()
+        .filter(|r| r.verdict == Verdict::Malicious)
+        .map(|r| (r.family.clone(), 0.99)) // Known-bad hash
+        .or_else(|| signature_matches.first().map(|m| (m.name.clone(), 0.95))) // High confidence for signature match
+        .or_else(|| rule_matches.first().map(|m| (m.rule.clone(), 0.9)))
+        .or_else(|| fuzzy_match.as_ref().map(|m| (m.family.clone(), 0.85))); // Variant of a known family
+
+    // Perform heuristic analysis
+    let dex = dex::parse(content).ok();
+    let mut heuristic_result = match scope {
+        ContentScope::File => perform_heuristic_analysis(content, dex.as_ref()),
+        _ => structural_heuristics(content, dex.as_ref()).unwrap_or_default(),
+    };
+
+    // Perform entropy analysis
+    let mut entropy_result = if scope == ContentScope::File { analyze_entropy(content) } else { 0.0 };
+
+    // A known-clean hash overrides heuristics, but not signatures; a suspicious one counts as one
+    match &reputation {
+        Some(r) if r.verdict == Verdict::Clean => {
+            heuristic_result.clear();
+            entropy_result = 0.0;
+        }
+        Some(r) if r.verdict == Verdict::Suspicious => heuristic_result.push((r.family.clone(), 0.7)),
+        _ => {}
+    }
+
+    // Combine results
+    let (is_malicious, threat_name, confidence) = combine_scan_results(
+        signature_result,
+        heuristic_result,
+        entropy_result,
+    );
+
+    ScanResult {
+        file_path: file_path.to_string(),
+        is_malicious,
+        threat_name,
+        confidence,
+        scan_time_ms: start_time.elapsed().as_millis() as u64,
+        signature_matches,
+        rule_matches,
+        reputation,
+        fuzzy_hashes: Some(fuzzy_hashes),
+        fuzzy_match,
+        api_calls: dex.map_or_else(Vec::new, |dex| dex.framework_methods()),
+        detections: Vec::new(),
+    }
+}
+
+// The local store answers first; cloud providers only for hashes it does not know, and only when
+// asked. Hashing happens before the store lock is taken, and cloud calls never hold it.
+fn lookup_reputation(content: &[u8], use_cloud: bool) -> Option<Reputation> {
+    let cloud = CLOUD_REPUTATION.lock().unwrap().clone().filter(|_| use_cloud);
+    if HASH_REPUTATION.lock().unwrap().is_none() && cloud.is_none() {
+        return None;
+    }
+    let digests = hash_reputation::file_digests(content);
+    let local = HASH_REPUTATION.lock().unwrap().as_ref().and_then(|store| store.lookup_digests(&digests));
+    local.or_else(|| cloud?.lookup(&hex::encode(&digests[0]), crate::now_ms()))
+}
+
+// Warm the cloud cache for a set of files with one batched request per provider
+fn prefetch_cloud_reputation<'a>(contents: impl Iterator<Item = &'a [u8]>) {
+    let Some(cloud) = CLOUD_REPUTATION.lock().unwrap().clone() else { return };
+    let hashes: Vec<String> = contents.map(|content| hex::encode(&hash_reputation::file_digests(content)[0])).collect();
+    cloud.lookup_many(&hashes, crate::now_ms());
+}
+
+// Signature-based malware detection: a whole-file hash match first, then every body signature
+// in one pass over the content. The lock is only held to fetch the compiled automaton and hash
+// set, so scans run concurrently.
+fn scan_with_signatures(content: &[u8]) -> Vec<SignatureMatch> {
+    let (matcher, hashes) = {
+        let mut signatures = MALWARE_SIGNATURES.lock().unwrap();
+        (signatures.matcher(), signatures.hashes())
+    };
+    let mut matches: Vec<SignatureMatch> =
+        hashes.lookup(content).map(|name| SignatureMatch { name, offset: 0 }).into_iter().collect();
+    matches.extend(matcher.scan(content));
+    matches
+}
+
+// The loaded YARA rules, if any; callers scan after the lock is released
+pub(crate) fn yara_rules() -> Option<Arc<YaraRules>> {
+    YARA_RULES.lock().unwrap().clone()
+}
+
+// Compile YARA rule source and replace the loaded rules. Returns the number of rules, or -1
+// if the source does not compile (the previous rules stay loaded; see hypervisor_yara_last_error).
+#[no_mangle]
+pub extern "C" fn hypervisor_load_yara_rules(source: *const u8, source_len: usize) -> i32 {
+    let source = unsafe { std::slice::from_raw_parts(source, source_len) };
+    let compiled = match std::str::from_utf8(source) {
+        Ok(source) => YaraRules::compile(source).map_err(|e| e.to_string()),
+        Err(_) => Err("rule source is not valid UTF-8".to_string()),
+    };
+    match compiled {
+        Ok(rules) => {
+            let count = rules.len() as i32;
+            *YARA_RULES.lock().unwrap() = Some(Arc::new(rules));
+            *YARA_LAST_ERROR.lock().unwrap() = None;
+            count
+        }
+        Err(message) => {
+            *YARA_LAST_ERROR.lock().unwrap() = Some(message);
+            -1
+        }
+    }
+}
+
+// "line N: message" for the last rule source that failed to compile, or null
+#[no_mangle]
+pub extern "C" fn hypervisor_yara_last_error() -> *mut String {
+    match YARA_LAST_ERROR.lock().unwrap().clone() {
+        Some(message) => Box::into_raw(Box::new(message)),
+        None => std::ptr::null_mut(),
+    }
+}
+
+// Findings from parsing native libraries and DEX files; None for any other content
+fn structural_heuristics(content: &[u8], dex: Option<&Dex>) -> Option<Vec<(String, f32)>> {
+    let elf = elf::parse(content).ok();
+    if elf.is_none() && dex.is_none() {
+        return None;
+    }
+    let mut findings = Vec::new();
+    if let Some(elf) = &elf {
+        findings.extend(elf.heuristics(content));
+    }
+    if let Some(dex) = dex {
+        findings.extend(dex.heuristics());
+    }
+    Some(findings)
+}
+
+fn perform_heuristic_analysis(content: &[u8], dex: Option<&Dex>) -> Vec<(String, f32)> {
+    // Native libraries and DEX files get structural checks; the Windows command strings and PE
+    // packer stubs below only apply to other content
+    let structural = structural_heuristics(content, dex);
+    let structured = structural.is_some();
+    let mut suspicious_patterns = structural.unwrap_or_default();
+
+    // Check for suspicious strings
+    let suspicious_strings = [
+        "cmd.exe",
+        "powershell.exe",
+        "net user",
+        "reg add",
+        "schtasks",
+        "bitsadmin",
+        "certutil",
+        "mshta",
+        "rundll32",
+        "wscript",
+        "cscript",
+    ];
+
+    let content_str = String::from_utf8_lossy(content);
+    for suspicious in suspicious_strings.iter().filter(|_| !structured) {
+        if content_str.contains(suspicious) {
+            suspicious_patterns.push((format!("SUSPICIOUS_COMMAND_{}", suspicious), 0.7));
+        }
+    }
+
+    // Check for encryption-like patterns (high entropy sections)
+    if content.len() > 1024 {
+        let mut high_entropy_count = 0;
+        for chunk in content.chunks(256) {
+            if calculate_entropy(chunk) > 7.5 {
+                high_entropy_count += 1;
+            }
+        }
+
+        if high_entropy_count > content.len() / 1024 {
+            suspicious_patterns.push(("HIGH_ENTROPY_CONTENT".to_string(), 0.6));
+        }
+    }
+
+    // Check for packer signatures
+    let packer_signatures = [
+        &[0x60, 0xE8, 0x00, 0x00, 0x00, 0x00], // UPX
+        &[0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00], // PE header + UPX
+    ];
+
+    for (i, signature) in packer_signatures.iter().enumerate().filter(|_| !structured) {
+        if content.windows(signature.len()).any(|window| window == *signature) {
+            suspicious_patterns.push((format!("PACKER_DETECTED_{}", i), 0.8));
+        }
+    }
+
+    suspicious_patterns
+}
+
+// Calculate Shannon entropy of data
+pub(crate) fn calculate_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut frequencies = [0u32; 256];
+    for &byte in data {
+        frequencies[byte as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+    let mut entropy = 0.0;
+
+    for &freq in &frequencies {
+        if freq > 0 {
+            let p = freq as f64 / len;
+            entropy -= p * p.log2();
+        }
+    }
+
+    entropy
+}
+
+// Analyze overall entropy
+fn analyze_entropy(content: &[u8]) -> f32 {
+    if content.len() < 256 {
+        return 0.0;
+    }
+
+    let entropy = calculate_entropy(content);
+    let normalized_entropy = (entropy / 8.0) as f32; // Normalize to 0-1 range
+
+    // High entropy might indicate encryption or compression
+    if normalized_entropy > 0.8 {
+        0.9 // High confidence of suspicious content
+    } else if normalized_entropy > 0.6 {
+        0.5 // Moderate confidence
+    } else {
+        0.0 // Low confidence
+    }
+}
+
+// Combine multiple scanning results
+fn combine_scan_results(
+    signature_result: Option<(String, f32)>,
+    heuristic_results: Vec<(String, f32)>,
+    entropy_score: f32,
+) -> (bool, Option<String>, f32) {
+    let mut max_confidence = 0.0f32;
+    let mut threat_name = None;
+    let mut is_malicious = false;
+
+    // Signature results have highest priority
+    if let Some((name, confidence)) = signature_result {
+        max_confidence = max_confidence.max(confidence);
+        threat_name = Some(name);
+        is_malicious = true;
+    }
+
+    // Combine heuristic results
+    for (name, confidence) in heuristic_results {
+        if confidence > max_confidence {
+            max_confidence = confidence;
+            if threat_name.is_none() {
+                threat_name = Some(name);
+            }
+            is_malicious = true;
+        }
+    }
+
+    // Factor in entropy analysis
+    if entropy_score > max_confidence {
+        max_confidence = entropy_score;
+        if threat_name.is_none() {
+            threat_name = Some("HIGH_ENTROPY_SUSPICIOUS".to_string());
+        }
+        is_malicious = true;
+    }
+
+    // Apply threshold
+    if max_confidence >= MALICIOUS_THRESHOLD {
+        (true, threat_name, max_confidence)
+    } else {
+        (false, None, max_confidence)
+    }
+}
+
+// Scan directory recursively
+#[no_mangle]
+pub extern "C" fn hypervisor_scan_directory(dir_path: *const u8, path_len: usize) -> *mut Vec<ScanResult> {
+    let path_bytes = unsafe { std::slice::from_raw_parts(dir_path, path_len) };
+    let path_str = match std::str::from_utf8(path_bytes) {
+        Ok(s) => s,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    let mut paths = Vec::new();
+    let mut skipped = Vec::new();
+    collect_scannable(Path::new(path_str), 0, &mut paths, &mut skipped);
+
+    // Directories too deep to enter are reported rather than silently left out
+    let mut results: Vec<ScanResult> = skipped
+        .into_iter()
+        .map(|dir| ScanResult {
+            file_path: dir.to_string_lossy().into_owned(),
+            is_malicious: false,
+            threat_name: Some("DIRECTORY_NOT_SCANNED".to_string()),
+            confidence: 0.0,
+            scan_time_ms: 0,
+            signature_matches: Vec::new(),
+            rule_matches: Vec::new(),
+            reputation: None,
+            fuzzy_hashes: None,
+            fuzzy_match: None,
+            api_calls: Vec::new(),
+            detections: Vec::new(),
+        })
+        .collect();
+
+    // Read a few files at a time so the cloud sees one batched lookup for them
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut pending_bytes = 0;
+    for (i, path) in paths.iter().enumerate() {
+        if let Ok(content) = fs::read(path) {
+            pending_bytes += content.len();
+            files.push((path.to_string_lossy().into_owned(), content));
+        }
+        let last = i + 1 == paths.len();
+        if last || files.len() >= CLOUD_PREFETCH_FILES || pending_bytes >= CLOUD_PREFETCH_BYTES {
+            prefetch_cloud_reputation(files.iter().map(|(_, content)| content.as_slice()));
+            for (file_path, content) in files.drain(..) {
+                results.push(scan_content(&file_path, &content, std::time::Instant::now()));
+            }
+            pending_bytes = 0;
+        }
+    }
+
+    Box::into_raw(Box::new(results))
+}
+
+// Files worth scanning under a directory, and the subdirectories below the depth limit. Symbolic
+// links are not followed, so a link back up the tree cannot loop.
+fn collect_scannable(dir: &Path, depth: usize, paths: &mut Vec<PathBuf>, skipped: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else { continue };
+        let path = entry.path();
+        if file_type.is_file() {
+            // Packages, code and scripts, whatever they are named
+            if is_scannable(&path) {
+                paths.push(path);
+            }
+        } else if file_type.is_dir() {
+            if depth + 1 < MAX_DIRECTORY_DEPTH {
+                collect_scannable(&path, depth + 1, paths, skipped);
+            } else {
+                skipped.push(path);
+            }
+        }
+    }
+}
+
+fn is_scannable(path: &Path) -> bool {
+    let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    if extension.is_some_and(|ext| SCANNED_EXTENSIONS.contains(&ext.as_str())) {
+        return true;
+    }
+    let mut magic = [0u8; 8];
+    let read = fs::File::open(path).and_then(|mut file| std::io::Read::read(&mut file, &mut magic)).unwrap_or(0);
+    let magic = &magic[..read];
+    crate::zip::is_zip(magic) || dex::is_dex(magic) || elf::is_elf(magic)
+}
+
+// Update malware signatures
+#[no_mangle]
+pub extern "C" fn hypervisor_update_signatures(signature_data: *const u8, data_len: usize) -> i32 {
+    let data = unsafe { std::slice::from_raw_parts(signature_data, data_len) };
+    let signature_str = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+
+    // Parse signature data (JSON format expected): name -> byte array, or name -> ClamAV-style
+    // hex pattern with wildcards and gaps. Returns how many were rejected.
+    let new_signatures = match serde_json::from_str::<HashMap<String, Value>>(signature_str) {
+        Ok(new_signatures) => new_signatures,
+        Err(_) => return -1, // Parse error
+    };
+    let mut signatures = MALWARE_SIGNATURES.lock().unwrap();
+    let mut rejected = 0;
+    for (name, value) in new_signatures {
+        let signature = match value {
+            Value::String(pattern) => BodySignature::parse(&name, &pattern).ok(),
+            bytes => serde_json::from_value::<Vec<u8>>(bytes).ok().and_then(|b| BodySignature::literal(&name, &b).ok()),
+        };
+        match signature {
+            Some(signature) => signatures.insert(signature),
+            None => rejected += 1,
+        }
+    }
+    rejected
+}
+
+// Load a ClamAV database file (.hdb, .hsb, .ndb, .ldb) into the signature database. Returns a
+// JSON report {"loaded": n, "unsupported": [{"line", "name", "reason"}]} of the lines that were
+// skipped, or null if the file cannot be read or is not a supported database type.
+#[no_mangle]
+pub extern "C" fn hypervisor_load_clamav_database(file_path: *const u8, path_len: usize) -> *mut String {
+    let path_bytes = unsafe { std::slice::from_raw_parts(file_path, path_len) };
+    let Ok(path_str) = std::str::from_utf8(path_bytes) else { return std::ptr::null_mut() };
+    let Ok(text) = fs::read(path_str) else { return std::ptr::null_mut() };
+    let Some(database) = clamav::parse_database(path_str, &String::from_utf8_lossy(&text)) else {
+        return std::ptr::null_mut();
+    };
+
+    let loaded = database.len();
+    let mut signatures = MALWARE_SIGNATURES.lock().unwrap();
+    for hash in database.hashes {
+        // The parser only accepts MD5, SHA-1 and SHA-256 lengths
+        signatures.insert_hash(hash);
+    }
+    for body in database.bodies {
+        signatures.insert(body);
+    }
+    for logical in database.logical {
+        signatures.insert_logical(logical);
+    }
+    drop(signatures);
+
+    let report = serde_json::json!({ "loaded": loaded, "unsupported": database.unsupported });
+    Box::into_raw(Box::new(report.to_string()))
+}
+
+// Parse an ELF file (a native library, usually) and report its structure as JSON: headers,
+// sections and segments with entropy, DT_NEEDED, imports, exports, JNI entry points and the
+// heuristic findings they trigger. Returns null if the data is not a supported ELF file.
+#[no_mangle]
+pub extern "C" fn hypervisor_analyze_elf(data: *const u8, data_len: usize) -> *mut String {
+    let data = unsafe { std::slice::from_raw_parts(data, data_len) };
+    let Ok(elf) = elf::parse(data) else { return std::ptr::null_mut() };
+    let findings: Vec<_> = elf
+        .heuristics(data)
+        .into_iter()
+        .map(|(name, confidence)| serde_json::json!({ "name": name, "confidence": confidence }))
+        .collect();
+    let report = serde_json::json!({ "elf": elf, "jni_exports": elf.jni_exports(), "findings": findings });
+    Box::into_raw(Box::new(report.to_string()))
+}
+
+// Parse a DEX file and report it as JSON: version, whether the checksum and SHA-1 signature
+// verify, table sizes, the classes it defines, every framework method it references
+// ("api_calls", in the form the behavior analyzer scores), the suspicious subset by category and
+// the heuristic findings. Returns null if the data is not a supported DEX file.
+#[no_mangle]
+pub extern "C" fn hypervisor_analyze_dex(data: *const u8, data_len: usize) -> *mut String {
+    let data = unsafe { std::slice::from_raw_parts(data, data_len) };
+    let Ok(dex) = dex::parse(data) else { return std::ptr::null_mut() };
+    let suspicious: Vec<_> = dex
+        .suspicious_apis()
+        .into_iter()
+        .map(|(api, category)| serde_json::json!({ "api": api, "category": category }))
+        .collect();
+    let findings: Vec<_> = dex
+        .heuristics()
+        .into_iter()
+        .map(|(name, confidence)| serde_json::json!({ "name": name, "confidence": confidence }))
+        .collect();
+    let report = serde_json::json!({
+        "version": dex.version,
+        "checksum_valid": dex.checksum_valid,
+        "signature_valid": dex.signature_valid,
+        "strings": dex.strings.len(),
+        "types": dex.types.len(),
+        "fields": dex.fields.len(),
+        "methods": dex.methods.len(),
+        "classes": dex.class_names(),
+        "api_calls": dex.framework_methods(),
+        "suspicious_apis": suspicious,
+        "findings": findings,
+    });
+    Box::into_raw(Box::new(report.to_string()))
+}
+
+// Decode an APK's compiled AndroidManifest.xml, or the manifest itself, into JSON: package,
+// version, SDK levels, requested and declared permissions, and every component with its intent
+// filters, effective exported flag and meta-data. Returns null if no manifest can be decoded.
+#[no_mangle]
+pub extern "C" fn hypervisor_parse_manifest(data: *const u8, data_len: usize) -> *mut String {
+    let data = unsafe { std::slice::from_raw_parts(data, data_len) };
+    let extracted;
+    let manifest = match ZipArchive::parse(data) {
+        Some(archive) => {
+            let Some(entry) = archive.entry("AndroidManifest.xml") else { return std::ptr::null_mut() };
+            let Ok(read) = archive.read(entry, MAX_ENTRY_SIZE) else { return std::ptr::null_mut() };
+            if !read.complete {
+                return std::ptr::null_mut();
+            }
+            extracted = read.data;
+            extracted.as_slice()
+        }
+        None => data,
+    };
+    match axml::parse_manifest(manifest).ok().and_then(|manifest| serde_json::to_string(&manifest).ok()) {
+        Some(json) => Box::into_raw(Box::new(json)),
+        None => std::ptr::null_mut(),
+    }
+}
+
+// Load a feed of known-bad ssdeep and TLSH digests ("digest, family[, threshold]" per line, or
+// ssdeep's CSV output). Returns the number of digests loaded, or -1 if the file cannot be read.
+#[no_mangle]
+pub extern "C" fn hypervisor_load_fuzzy_hashes(file_path: *const u8, path_len: usize) -> i32 {
+    let path_bytes = unsafe { std::slice::from_raw_parts(file_path, path_len) };
+    let Ok(path_str) = std::str::from_utf8(path_bytes) else { return -1 };
+    let Ok(text) = fs::read(path_str) else { return -1 };
+    let mut database = FUZZY_HASHES.lock().unwrap();
+    let (loaded, _rejected) = Arc::make_mut(&mut database).load(&String::from_utf8_lossy(&text));
+    loaded as i32
+}
+
+// ssdeep and TLSH digests of a buffer as JSON {"ssdeep": "...", "tlsh": "T1..." or null}, in the
+// reference tools' formats so they can be shared with existing feeds
+#[no_mangle]
+pub extern "C" fn hypervisor_fuzzy_hash(data: *const u8, data_len: usize) -> *mut String {
+    let data = unsafe { std::slice::from_raw_parts(data, data_len) };
+    match serde_json::to_string(&FuzzyHashes::of(data)) {
+        Ok(json) => Box::into_raw(Box::new(json)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+// Open the hash reputation store in a directory; deltas must be signed by the Ed25519 key whose
+// 32-byte public half is given. Returns the number of entries, or -1.
+#[no_mangle]
+pub extern "C" fn hypervisor_open_hash_reputation(dir: *const u8, dir_len: usize, public_key: *const u8) -> i32 {
+    if dir.is_null() || public_key.is_null() {
+        return -1;
+    }
+    let dir_bytes = unsafe { std::slice::from_raw_parts(dir, dir_len) };
+    let public_key = unsafe { &*(public_key as *const [u8; 32]) };
+    let Ok(dir) = std::str::from_utf8(dir_bytes) else { return -1 };
+    match HashReputation::open(Path::new(dir), public_key) {
+        Ok(store) => {
+            let count = store.len() as i32;
+            *HASH_REPUTATION.lock().unwrap() = Some(store);
+            count
+        }
+        Err(_) => -1,
+    }
+}
+
+// Verify and apply a signed reputation delta. Returns the number of entries it changed, or:
+// -1 no store open, -2 bad signature, -3 out of sequence, -4 malformed, -5 storage error
+#[no_mangle]
+pub extern "C" fn hypervisor_apply_reputation_delta(delta: *const u8, delta_len: usize) -> i32 {
+    let delta = unsafe { std::slice::from_raw_parts(delta, delta_len) };
+    let mut store = HASH_REPUTATION.lock().unwrap();
+    let Some(store) = store.as_mut() else { return -1 };
+    match store.apply_delta(delta) {
+        Ok(applied) => applied as i32,
+        Err(DeltaError::BadSignature) => -2,
+        Err(DeltaError::OutOfSequence { .. }) => -3,
+        Err(DeltaError::Truncated | DeltaError::BadHeader | DeltaError::InvalidEntry(_)) => -4,
+        Err(DeltaError::Io(_)) => -5,
+    }
+}
+
+// Configure cloud reputation lookups from JSON:
+// {"endpoint": "http://host:port/path", "api_key": "...", "batch_size": 100, "timeout_ms": 3000,
+//  "requests_per_minute": 60, "cache_ttl_secs": 86400}. Without an endpoint cloud lookups are
+// turned off. Returns 0, or -1 if the configuration is invalid, which includes an api_key
+// for an endpoint that is not on loopback.
+#[no_mangle]
+pub extern "C" fn hypervisor_configure_cloud_reputation(config: *const u8, config_len: usize) -> i32 {
+    let config = unsafe { std::slice::from_raw_parts(config, config_len) };
+    let Ok(config) = serde_json::from_slice::<Value>(config) else { return -1 };
+    let Some(endpoint) = config["endpoint"].as_str() else {
+        *CLOUD_REPUTATION.lock().unwrap() = None;
+        return 0;
+    };
+    let api_key = config["api_key"].as_str().map(str::to_string);
+    let batch_size = config["batch_size"].as_u64().unwrap_or(100) as usize;
+    let Some(provider) = HttpJsonProvider::new(endpoint, api_key, batch_size) else { return -1 };
+
+    let mut cloud_config = CloudConfig::default();
+    if let Some(timeout_ms) = config["timeout_ms"].as_u64() {
+        cloud_config.timeout = std::time::Duration::from_millis(timeout_ms);
+    }
+    if let Some(limit) = config["requests_per_minute"].as_u64() {
+        cloud_config.requests_per_minute = limit as u32;
+    }
+    if let Some(ttl) = config["cache_ttl_secs"].as_u64() {
+        cloud_config.cache_ttl_ms = ttl * 1000;
+    }
+    let providers: Vec<Box<dyn ReputationProvider>> = vec![Box::new(provider)];
+    *CLOUD_REPUTATION.lock().unwrap() = Some(Arc::new(CloudReputation::new(cloud_config, providers)));
+    0
+}
+
+// Get scanner statistics
+#[no_mangle]
+pub extern "C" fn hypervisor_get_scan_stats() -> *mut HashMap<String, u64> {
+    let mut stats = HashMap::new();
+    stats.insert("total_signatures".to_string(), MALWARE_SIGNATURES.lock().unwrap().len() as u64);
+    stats.insert("yara_rules".to_string(), yara_rules().map_or(0, |rules| rules.len() as u64));
+    stats.insert("fuzzy_hashes".to_string(), FUZZY_HASHES.lock().unwrap().len() as u64);
+    if let Some(cloud) = CLOUD_REPUTATION.lock().unwrap().as_ref() {
+        stats.insert("cloud_reputation_cached".to_string(), cloud.cached() as u64);
+    }
+    if let Some(store) = HASH_REPUTATION.lock().unwrap().as_ref() {
+        stats.insert("hash_reputation_entries".to_string(), store.len() as u64);
+        stats.insert("hash_reputation_sequence".to_string(), store.sequence());
+    }
+    stats.insert("scan_engine_version".to_string(), 1);
+
+    Box::into_raw(Box::new(stats))
+}
+
+// Clean up scan result
+#[no_mangle]
+pub extern "C" fn hypervisor_free_scan_result(result: *mut ScanResult) {
+    if !result.is_null() {
+        unsafe { Box::from_raw(result) };
+    }
+}
+
+// Clean up scan results vector
+#[no_mangle]
+pub extern "C" fn hypervisor_free_scan_results(results: *mut Vec<ScanResult>) {
+    if !results.is_null() {
+        unsafe { Box::from_raw(results) };
+    }
+}
+
+// Clean up statistics
+#[no_mangle]
+pub extern "C" fn hypervisor_free_scan_stats(stats: *mut HashMap<String, u64>) {
+    if !stats.is_null() {
+        unsafe { Box::from_raw(stats) };
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::tests::{build, noise};
+
+    fn scan_entries(archive: &[u8], budget: usize) -> Vec<(String, String)> {
+        let zip = ZipArchive::parse(archive).unwrap();
+        let mut result = analyze_content("test.apk", archive, std::time::Instant::now(), ContentScope::Archive);
+        let mut budget = budget;
+        scan_archive(&mut result, &zip, "", 1, &mut budget);
+        result.detections.into_iter().map(|d| (d.entry, d.threat_name)).collect()
+    }
+
+    #[test]
+    fn test_extension_and_magic_select_files() {
+        let dir = std::env::temp_dir().join(format!("fortress-scannable-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files: [(&str, &[u8]); 5] = [
+            ("loader.js", b"eval(atob(payload))"),
+            ("SETUP.APK", b"not really a zip"),
+            ("update.bin", b"dex\n035\0rest"),
+            ("notes.txt", b"shopping list"),
+            ("photo.jpg", b"\xff\xd8\xff\xe0"),
+        ];
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        let picked: Vec<bool> = files.iter().map(|(name, _)| is_scannable(&dir.join(name))).collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(picked, [true, true, true, false, false]);
+    }
+
+    #[test]
+    fn test_directory_scan_descends_into_subdirectories() {
+        let root = std::env::temp_dir().join(format!("fortress-scan-tree-{}", std::process::id()));
+        let nested = root.join("Download/apps");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("game.apk"), build(&[("classes.dex", b"dex\n035\0", false)])).unwrap();
+        fs::write(root.join("readme.txt"), b"nothing to see").unwrap();
+        let deep = (1..MAX_DIRECTORY_DEPTH).fold(root.clone(), |dir, level| dir.join(level.to_string()));
+        fs::create_dir_all(deep.join("hidden")).unwrap();
+
+        let path = root.to_string_lossy().into_owned();
+        let results = unsafe { Box::from_raw(hypervisor_scan_directory(path.as_ptr(), path.len())) };
+        fs::remove_dir_all(&root).unwrap();
+        let found: Vec<(String, Option<String>)> = results
+            .iter()
+            .map(|result| (result.file_path.strip_prefix(path.as_str()).unwrap().to_string(), result.threat_name.clone()))
+            .collect();
+        let skipped = deep.join("hidden").to_string_lossy().into_owned();
+        assert_eq!(
+            found,
+            [
+                (skipped.strip_prefix(path.as_str()).unwrap().to_string(), Some("DIRECTORY_NOT_SCANNED".to_string())),
+                ("/Download/apps/game.apk".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entries_past_the_budget_and_tampered_entries_are_reported() {
+        let asset = vec![b'a'; 4000];
+        let archive = build(&[
+            ("classes.dex", b"dex\n035\0", false),
+            ("assets/big.bin", &asset, true),
+            ("lib/x.so", b"\x7fELF", false),
+        ]);
+        let detections = scan_entries(&archive, 1000);
+        assert_eq!(
+            detections,
+            [
+                ("assets/big.bin".to_string(), "ZIP_ENTRY_PARTIALLY_SCANNED".to_string()),
+                ("lib/x.so".to_string(), "ZIP_ENTRY_NOT_SCANNED".to_string()),
+            ]
+        );
+        // Reported, but not enough to call the package malicious
+        let result = scan_content("test.apk", &archive, std::time::Instant::now());
+        assert!(!result.is_malicious && result.detections.is_empty());
+
+        let mut tampered = archive.clone();
+        let at = tampered.windows(4).position(|w| w == b"\x7fELF").unwrap();
+        tampered[at + 1] = b'X';
+        let result = scan_content("test.apk", &tampered, std::time::Instant::now());
+        assert_eq!(result.detections[0].entry, "lib/x.so");
+        assert_eq!(result.detections[0].threat_name, "ZIP_CRC_MISMATCH");
+        assert!(result.is_malicious && result.threat_name.as_deref() == Some("ZIP_CRC_MISMATCH"));
+
+        // A partial download is not tampering
+        let cut = build(&[("classes.dex", b"dex\n035\0", false), ("lib/arm64-v8a/libx.so", &noise(20_000), true)]);
+        let result = scan_content("test.apk", &cut[..cut.len() * 9 / 10], std::time::Instant::now());
+        assert_eq!(result.detections[0].entry, "lib/arm64-v8a/libx.so");
+        assert_eq!(result.detections[0].threat_name, "ZIP_ENTRY_PARTIALLY_SCANNED");
+        assert!(!result.is_malicious);
+    }
+
+    fn add_signature(name: &str, bytes: &[u8]) {
+        MALWARE_SIGNATURES.lock().unwrap().insert(BodySignature::literal(name, bytes).unwrap());
+    }
+
+    #[test]
+    fn test_detections_from_engines_are_combined() {
+        // Heuristics alone name the threat; below the threshold they do not make it malicious
+        let heuristics = vec![("SUSPICIOUS_A".to_string(), 0.6), ("SUSPICIOUS_B".to_string(), 0.8)];
+        assert_eq!(combine_scan_results(None, heuristics, 0.0), (true, Some("SUSPICIOUS_A".to_string()), 0.8));
+        assert_eq!(combine_scan_results(None, vec![("WEAK".to_string(), 0.3)], 0.2), (false, None, 0.3));
+        let entropy = combine_scan_results(None, Vec::new(), 0.9);
+        assert_eq!(entropy, (true, Some("HIGH_ENTROPY_SUSPICIOUS".to_string()), 0.9));
+
+        // A signature hit names the threat even when a heuristic is as sure
+        add_signature("Test.Combined", b"fortress-combined-marker");
+        let content = b"start powershell.exe -enc AAAA; fortress-combined-marker";
+        let result = analyze_content("dropper.bat", content, std::time::Instant::now(), ContentScope::File);
+        assert!(result.is_malicious);
+        assert_eq!(result.threat_name.as_deref(), Some("Test.Combined"));
+        assert_eq!(result.confidence, 0.95);
+        assert_eq!(result.signature_matches[0].offset, 32);
+
+        let content = b"start powershell.exe -enc AAAA";
+        let result = analyze_content("dropper.bat", content, std::time::Instant::now(), ContentScope::File);
+        assert_eq!(result.threat_name.as_deref(), Some("SUSPICIOUS_COMMAND_powershell.exe"));
+    }
+
+    #[test]
+    fn test_reputation_verdict_overrides_heuristics() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let trusted = b"rem vendor updater\r\npowershell.exe -File update.ps1 -clean".as_slice();
+        let known_bad = b"rem innocuous\r\necho fortress reputation test".as_slice();
+        let dir = std::env::temp_dir().join(format!("fortress-scanner-reputation-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let signing = SigningKey::from_bytes(&[3; 32]);
+        let mut store = HashReputation::open(&dir, &signing.verifying_key().to_bytes()).unwrap();
+        let delta = format!(
+            "FHRDELTA 1 1\n{}\tclean\tvendor-tool\tplay\n{}\tmalicious\tTest.KnownBad\tvendor-a\n",
+            hex::encode(&hash_reputation::file_digests(trusted)[0]),
+            hex::encode(&hash_reputation::file_digests(known_bad)[0])
+        );
+        let signature = signing.sign(delta.as_bytes()).to_bytes();
+        assert_eq!(store.apply_delta(&[delta.as_bytes(), &signature].concat()), Ok(2));
+        *HASH_REPUTATION.lock().unwrap() = Some(store);
+
+        let now = std::time::Instant::now();
+        let scan = |content: &[u8]| analyze_content("update.bat", content, now, ContentScope::File);
+        let clean = scan(trusted);
+        let unknown = scan(&trusted[..trusted.len() - 1]);
+        let bad = scan(known_bad);
+        *HASH_REPUTATION.lock().unwrap() = None;
+        let _ = fs::remove_dir_all(&dir);
+
+        // The same command line is flagged unless its hash is known to be clean
+        assert!(!clean.is_malicious && clean.threat_name.is_none());
+        assert_eq!(clean.reputation.unwrap().verdict, Verdict::Clean);
+        assert_eq!(unknown.threat_name.as_deref(), Some("SUSPICIOUS_COMMAND_powershell.exe"));
+        assert!(bad.is_malicious && bad.confidence == 0.99);
+        assert_eq!(bad.threat_name.as_deref(), Some("Test.KnownBad"));
+    }
+
+    #[test]
+    fn test_detections_are_attributed_to_nested_entries() {
+        add_signature("Test.NestedPayload", b"fortress-nested-marker");
+        // Deflated so only the entry, not the archives around it, holds the marker
+        let payload = [b"fortress-nested-marker".as_slice(), &[b'x'; 64]].concat();
+        let inner = build(&[
+            ("classes.dex", b"dex\n035\0one", false),
+            ("classes.dex", b"dex\n035\0two", false),
+            ("assets/payload.bin", &payload, true),
+        ]);
+        let outer = build(&[("AndroidManifest.xml", b"\x03\x00\x08\x00", false), ("assets/inner.apk", &inner, false)]);
+
+        let result = scan_content("outer.apk", &outer, std::time::Instant::now());
+        let detections: Vec<(&str, &str)> =
+            result.detections.iter().map(|d| (d.entry.as_str(), d.threat_name.as_str())).collect();
+        assert_eq!(
+            detections,
+            [
+                ("assets/inner.apk!/classes.dex", "ZIP_DUPLICATE_ENTRY"),
+                ("assets/inner.apk!/assets/payload.bin", "Test.NestedPayload"),
+            ]
+        );
+        // The package takes its worst entry's verdict
+        assert!(result.is_malicious && result.confidence == 0.95);
+        assert_eq!(result.threat_name.as_deref(), Some("Test.NestedPayload"));
+    }
+}
@@ -0,0 +1,710 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use aho_corasick::AhoCorasick;
+use ahash::AHashMap;
+use md5::Md5;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+// A run of fixed bytes shorter than this would make the prefilter fire on nearly every offset
+const MIN_ANCHOR_LEN: usize = 2;
+// Matches counted per logical subsignature; counts in expressions are far below this
+const MAX_COUNTED_MATCHES: usize = 256;
+// Segment placements tried per signature and scanned byte while searching gaps. Every anchor
+// hit may search the rest of the file, so without a budget a file full of a short anchor
+// takes quadratic time; past it the signature is given up on for that file
+const GAP_PROBES_PER_BYTE: usize = 16;
+// Budget floor, so small files are always searched exhaustively
+const MIN_GAP_PROBES: usize = 1 << 16;
+
+/// Why a body signature pattern was rejected; positions are character offsets into the pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternError {
+    Empty,
+    InvalidHex(usize),
+    InvalidGap(usize),
+    /// Gaps must sit between byte sequences, not at either end or next to each other
+    MisplacedGap(usize),
+    /// No run of at least two fixed bytes to anchor the automaton on
+    NoAnchor,
+    /// Alternatives, negations and boundary markers: `(aa|bb)`, `!(aa)`, `(B)`
+    Unsupported(usize),
+}
+
+/// File types a signature can be limited to: the ClamAV target types recognisable by magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetType {
+    #[default]
+    Any,
+    Pe,
+    Ole2,
+    Graphics,
+    Elf,
+    MachO,
+    Pdf,
+    Flash,
+    Java,
+}
+
+impl TargetType {
+    /// The ClamAV target type number; None for normalised (HTML, mail, text) and unknown targets
+    pub fn from_clamav(target: u32) -> Option<TargetType> {
+        Some(match target {
+            0 => TargetType::Any,
+            1 => TargetType::Pe,
+            2 => TargetType::Ole2,
+            5 => TargetType::Graphics,
+            6 => TargetType::Elf,
+            9 => TargetType::MachO,
+            10 => TargetType::Pdf,
+            11 => TargetType::Flash,
+            12 => TargetType::Java,
+            _ => return None,
+        })
+    }
+
+    pub fn detect(data: &[u8]) -> TargetType {
+        let magic = |m: &[u8]| data.starts_with(m);
+        if magic(b"MZ") {
+            TargetType::Pe
+        } else if magic(b"\x7fELF") {
+            TargetType::Elf
+        } else if magic(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]) {
+            TargetType::Ole2
+        } else if magic(b"\x89PNG") || magic(b"GIF8") || magic(&[0xFF, 0xD8, 0xFF]) {
+            TargetType::Graphics
+        } else if magic(&[0xFE, 0xED, 0xFA, 0xCE]) || magic(&[0xFE, 0xED, 0xFA, 0xCF])
+            || magic(&[0xCE, 0xFA, 0xED, 0xFE]) || magic(&[0xCF, 0xFA, 0xED, 0xFE])
+        {
+            TargetType::MachO
+        } else if magic(&[0xCA, 0xFE, 0xBA, 0xBE]) {
+            // Fat Mach-O binaries share the Java class magic; they follow it with a small arch count
+            let arch_count = data.get(4..8).map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+            if arch_count < 45 { TargetType::MachO } else { TargetType::Java }
+        } else if magic(b"%PDF-") {
+            TargetType::Pdf
+        } else if magic(b"FWS") || magic(b"CWS") || magic(b"ZWS") {
+            TargetType::Flash
+        } else {
+            TargetType::Any
+        }
+    }
+
+    fn accepts(self, detected: TargetType) -> bool {
+        self == TargetType::Any || self == detected
+    }
+}
+
+/// Where a match may start: anywhere, `offset` (+ up to `shift`) bytes into the data, or
+/// `distance` bytes before its end (+ up to `shift`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureOffset {
+    #[default]
+    Any,
+    Start { offset: usize, shift: usize },
+    End { distance: usize, shift: usize },
+}
+
+impl SignatureOffset {
+    fn accepts(self, start: usize, len: usize) -> bool {
+        let (from, shift) = match self {
+            SignatureOffset::Any => return true,
+            SignatureOffset::Start { offset, shift } => (offset, shift),
+            SignatureOffset::End { distance, shift } => match len.checked_sub(distance) {
+                Some(from) => (from, shift),
+                None => return false,
+            },
+        };
+        start >= from && start - from <= shift
+    }
+}
+
+// Bytes that must follow each other directly; a mask bit of 1 means the value bit must match
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    values: Vec<u8>,
+    masks: Vec<u8>,
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn matches_at(&self, data: &[u8], at: usize) -> bool {
+        data.get(at..at + self.len()).is_some_and(|window| {
+            window.iter().zip(&self.values).zip(&self.masks).all(|((b, v), m)| b & m == *v)
+        })
+    }
+
+    // Longest run of fully fixed bytes: (start, len)
+    fn longest_fixed_run(&self) -> (usize, usize) {
+        let (mut best, mut start) = ((0, 0), 0);
+        for (i, &mask) in self.masks.iter().chain([&0]).enumerate() {
+            if mask != 0xFF {
+                if i - start > best.1 {
+                    best = (start, i - start);
+                }
+                start = i + 1;
+            }
+        }
+        best
+    }
+}
+
+// Bytes allowed between two segments; `max` of None is ClamAV's `*`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Gap {
+    min: usize,
+    max: Option<usize>,
+}
+
+/// A named ClamAV-style body pattern: hex bytes with `??`/`a?`/`?a` wildcards and
+/// `*`, `{n}`, `{n-m}`, `{-m}`, `{n-}` gaps between them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BodySignature {
+    pub name: String,
+    pub target: TargetType,
+    pub offset: SignatureOffset,
+    segments: Vec<Segment>,
+    // gaps[i] sits between segments[i] and segments[i + 1]
+    gaps: Vec<Gap>,
+}
+
+impl BodySignature {
+    /// An exact byte string
+    pub fn literal(name: &str, bytes: &[u8]) -> Result<BodySignature, PatternError> {
+        let segment = Segment { values: bytes.to_vec(), masks: vec![0xFF; bytes.len()] };
+        Self::build(name, vec![segment], Vec::new())
+    }
+
+    pub fn parse(name: &str, pattern: &str) -> Result<BodySignature, PatternError> {
+        let chars: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+        if chars.is_empty() {
+            return Err(PatternError::Empty);
+        }
+        let mut segments = Vec::new();
+        let mut gaps = Vec::new();
+        let mut current = Segment { values: Vec::new(), masks: Vec::new() };
+        let mut i = 0;
+        while i < chars.len() {
+            let gap = match chars[i] {
+                '*' => {
+                    i += 1;
+                    Some(Gap { min: 0, max: None })
+                }
+                '(' | '!' => return Err(PatternError::Unsupported(i)),
+                // `[n-m]` is ClamAV's short bounded jump; for matching it is the same as `{n-m}`
+                open @ ('{' | '[') => {
+                    let close_char = if open == '{' { '}' } else { ']' };
+                    let close = chars[i..].iter().position(|&c| c == close_char).ok_or(PatternError::InvalidGap(i))? + i;
+                    let gap = parse_gap(&chars[i + 1..close].iter().collect::<String>()).ok_or(PatternError::InvalidGap(i))?;
+                    i = close + 1;
+                    Some(gap)
+                }
+                _ => None,
+            };
+            if let Some(gap) = gap {
+                if current.values.is_empty() {
+                    return Err(PatternError::MisplacedGap(i - 1));
+                }
+                segments.push(std::mem::replace(&mut current, Segment { values: Vec::new(), masks: Vec::new() }));
+                gaps.push(gap);
+                continue;
+            }
+            let (high, low) = (chars[i], *chars.get(i + 1).ok_or(PatternError::InvalidHex(i))?);
+            let (value, mask) = parse_masked_byte(high, low).ok_or(PatternError::InvalidHex(i))?;
+            current.values.push(value);
+            current.masks.push(mask);
+            i += 2;
+        }
+        if current.values.is_empty() {
+            return Err(PatternError::MisplacedGap(chars.len() - 1));
+        }
+        segments.push(current);
+        Self::build(name, segments, gaps)
+    }
+
+    fn build(name: &str, segments: Vec<Segment>, gaps: Vec<Gap>) -> Result<BodySignature, PatternError> {
+        if segments.iter().all(|s| s.values.is_empty()) {
+            return Err(PatternError::Empty);
+        }
+        let signature = BodySignature {
+            name: name.to_string(),
+            target: TargetType::Any,
+            offset: SignatureOffset::Any,
+            segments,
+            gaps,
+        };
+        if signature.anchor().2 < MIN_ANCHOR_LEN {
+            return Err(PatternError::NoAnchor);
+        }
+        Ok(signature)
+    }
+
+    /// The UTF-16LE form of the pattern: every byte followed by a zero byte, gaps doubled
+    pub fn wide(mut self) -> BodySignature {
+        for segment in &mut self.segments {
+            segment.values = segment.values.iter().flat_map(|&v| [v, 0]).collect();
+            segment.masks = segment.masks.iter().flat_map(|&m| [m, 0xFF]).collect();
+        }
+        for gap in &mut self.gaps {
+            gap.min *= 2;
+            gap.max = gap.max.map(|max| max * 2);
+        }
+        self
+    }
+
+    // The longest fixed run over all segments: (segment, offset in segment, len)
+    fn anchor(&self) -> (usize, usize, usize) {
+        self.segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                let (start, len) = segment.longest_fixed_run();
+                (i, start, len)
+            })
+            .fold((0, 0, 0), |best, candidate| if candidate.2 > best.2 { candidate } else { best })
+    }
+
+    // Start offset of a match whose segment `index` begins at `at`, if the other segments fit
+    // around it and the match starts where the signature's offset allows. Each placement tried
+    // in a gap spends one of `probes`; none are found once they run out.
+    fn verify(&self, data: &[u8], index: usize, at: usize, probes: &mut usize) -> Option<usize> {
+        if !self.segments[index].matches_at(data, at) {
+            return None;
+        }
+        if !self.matches_after(data, index, at + self.segments[index].len(), probes) {
+            return None;
+        }
+        self.matches_before(data, index, at, probes)
+    }
+
+    // Segments after `index` fit, the first of them starting within the gap after `end`
+    fn matches_after(&self, data: &[u8], index: usize, end: usize, probes: &mut usize) -> bool {
+        let Some(gap) = self.gaps.get(index) else { return true };
+        let next = &self.segments[index + 1];
+        let last = data.len().saturating_sub(next.len());
+        let max = gap.max.map_or(last, |max| (end + max).min(last));
+        for at in end + gap.min..=max {
+            let Some(left) = probes.checked_sub(1) else { return false };
+            *probes = left;
+            if next.matches_at(data, at) && self.matches_after(data, index + 1, at + next.len(), probes) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Start of the whole match if segments before `index` fit, the closest placement first
+    fn matches_before(&self, data: &[u8], index: usize, start: usize, probes: &mut usize) -> Option<usize> {
+        if index == 0 {
+            return self.offset.accepts(start, data.len()).then_some(start);
+        }
+        let gap = self.gaps[index - 1];
+        let previous = &self.segments[index - 1];
+        let latest = start.checked_sub(gap.min + previous.len())?;
+        let earliest = gap.max.map_or(0, |max| start.saturating_sub(max + previous.len()));
+        for at in (earliest..=latest).rev() {
+            *probes = probes.checked_sub(1)?;
+            if previous.matches_at(data, at) {
+                if let Some(start) = self.matches_before(data, index - 1, at, probes) {
+                    return Some(start);
+                }
+            }
+        }
+        None
+    }
+}
+
+// `n`, `n-m`, `-m` or `n-`
+fn parse_gap(spec: &str) -> Option<Gap> {
+    let gap = match spec.split_once('-') {
+        None => {
+            let n = spec.parse().ok()?;
+            Gap { min: n, max: Some(n) }
+        }
+        Some(("", max)) => Gap { min: 0, max: Some(max.parse().ok()?) },
+        Some((min, "")) => Gap { min: min.parse().ok()?, max: None },
+        Some((min, max)) => Gap { min: min.parse().ok()?, max: Some(max.parse().ok()?) },
+    };
+    (gap.max.is_none_or(|max| max >= gap.min)).then_some(gap)
+}
+
+fn parse_masked_byte(high: char, low: char) -> Option<(u8, u8)> {
+    let nibble = |c: char| match c {
+        '?' => Some((0, 0)),
+        _ => c.to_digit(16).map(|d| (d as u8, 0x0F)),
+    };
+    let (hv, hm) = nibble(high)?;
+    let (lv, lm) = nibble(low)?;
+    Some((hv << 4 | lv, hm << 4 | lm))
+}
+
+/// How a logical signature's subsignature matches combine (ClamAV `.ldb` expressions)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogicalExpr {
+    Sub(usize),
+    All(Vec<LogicalExpr>),
+    Any(Vec<LogicalExpr>),
+    /// `A=X`, `A>X,Y`, `A<X`: matches of A against X, and at least Y distinct subsignatures
+    Count { expr: Box<LogicalExpr>, op: CountOp, matches: usize, distinct: Option<usize> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountOp {
+    Eq,
+    Gt,
+    Lt,
+}
+
+impl LogicalExpr {
+    // (holds, total matches, distinct subsignatures matched)
+    fn eval(&self, counts: &[usize]) -> (bool, usize, usize) {
+        match self {
+            LogicalExpr::Sub(i) => {
+                let count = counts.get(*i).copied().unwrap_or(0);
+                (count > 0, count, (count > 0) as usize)
+            }
+            LogicalExpr::All(items) | LogicalExpr::Any(items) => {
+                let results: Vec<_> = items.iter().map(|item| item.eval(counts)).collect();
+                let holds = match self {
+                    LogicalExpr::All(_) => results.iter().all(|r| r.0),
+                    _ => results.iter().any(|r| r.0),
+                };
+                (holds, results.iter().map(|r| r.1).sum(), results.iter().map(|r| r.2).sum())
+            }
+            LogicalExpr::Count { expr, op, matches, distinct } => {
+                let (_, count, seen) = expr.eval(counts);
+                let holds = match op {
+                    CountOp::Eq => count == *matches,
+                    CountOp::Gt => count > *matches,
+                    CountOp::Lt => count < *matches,
+                } && distinct.is_none_or(|distinct| seen >= distinct);
+                (holds, if holds { count } else { 0 }, if holds { seen } else { 0 })
+            }
+        }
+    }
+
+    // Highest subsignature index referenced
+    pub fn max_sub(&self) -> Option<usize> {
+        match self {
+            LogicalExpr::Sub(i) => Some(*i),
+            LogicalExpr::All(items) | LogicalExpr::Any(items) => items.iter().filter_map(|i| i.max_sub()).max(),
+            LogicalExpr::Count { expr, .. } => expr.max_sub(),
+        }
+    }
+}
+
+/// Body subsignatures combined by a logical expression, limited to a target type and file size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogicalSignature {
+    pub name: String,
+    pub target: TargetType,
+    pub file_size: Option<(u64, u64)>,
+    pub subsignatures: Vec<BodySignature>,
+    pub expression: LogicalExpr,
+}
+
+/// A signature that matched, with the offset of its first byte
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SignatureMatch {
+    pub name: String,
+    pub offset: usize,
+}
+
+// A signature keyed under one anchor string of the automaton
+#[derive(Debug, Clone, Copy)]
+struct AnchorRef {
+    signature: usize,
+    segment: usize,
+    offset: usize,
+}
+
+/// Signatures compiled into one Aho-Corasick automaton over their anchors
+pub struct SignatureMatcher {
+    automaton: Option<AhoCorasick>,
+    // Indexed by automaton pattern ID; signatures sharing an anchor share an entry
+    anchors: Vec<Vec<AnchorRef>>,
+    // Standalone signatures, then the subsignatures of every logical signature in order
+    signatures: Vec<BodySignature>,
+    standalone: usize,
+    // Each logical signature with the indices of its subsignatures in `signatures`
+    logical: Vec<(LogicalSignature, Range<usize>)>,
+}
+
+impl SignatureMatcher {
+    pub fn new(signatures: Vec<BodySignature>, logical: Vec<LogicalSignature>) -> Self {
+        let standalone = signatures.len();
+        let mut all = signatures;
+        let logical: Vec<_> = logical
+            .into_iter()
+            .map(|mut signature| {
+                let first = all.len();
+                all.append(&mut signature.subsignatures);
+                (signature, first..all.len())
+            })
+            .collect();
+
+        let mut patterns: Vec<Vec<u8>> = Vec::new();
+        let mut anchors: Vec<Vec<AnchorRef>> = Vec::new();
+        let mut ids: AHashMap<Vec<u8>, usize> = AHashMap::new();
+        for (index, signature) in all.iter().enumerate() {
+            let (segment, offset, len) = signature.anchor();
+            let bytes = signature.segments[segment].values[offset..offset + len].to_vec();
+            let id = *ids.entry(bytes.clone()).or_insert_with(|| {
+                patterns.push(bytes);
+                anchors.push(Vec::new());
+                patterns.len() - 1
+            });
+            anchors[id].push(AnchorRef { signature: index, segment, offset });
+        }
+        let automaton = if patterns.is_empty() { None } else { AhoCorasick::new(&patterns).ok() };
+        Self { automaton, anchors, signatures: all, standalone, logical }
+    }
+
+    pub fn len(&self) -> usize {
+        self.standalone + self.logical.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every signature found in `data`, each once at its first verified offset, in offset order
+    pub fn scan(&self, data: &[u8]) -> Vec<SignatureMatch> {
+        let Some(automaton) = &self.automaton else { return Vec::new() };
+        let detected = TargetType::detect(data);
+        // Match starts per signature: the first one for standalone signatures, up to
+        // MAX_COUNTED_MATCHES distinct ones for subsignatures, which expressions count
+        let mut found: Vec<Vec<usize>> = vec![Vec::new(); self.signatures.len()];
+        let mut probes = vec![(GAP_PROBES_PER_BYTE * data.len()).max(MIN_GAP_PROBES); self.signatures.len()];
+        for hit in automaton.find_overlapping_iter(data) {
+            for anchor in &self.anchors[hit.pattern().as_usize()] {
+                let limit = if anchor.signature < self.standalone { 1 } else { MAX_COUNTED_MATCHES };
+                let signature = &self.signatures[anchor.signature];
+                if found[anchor.signature].len() >= limit || !signature.target.accepts(detected) {
+                    continue;
+                }
+                let Some(at) = hit.start().checked_sub(anchor.offset) else { continue };
+                if let Some(start) = signature.verify(data, anchor.segment, at, &mut probes[anchor.signature]) {
+                    if !found[anchor.signature].contains(&start) {
+                        found[anchor.signature].push(start);
+                    }
+                }
+            }
+        }
+
+        let mut matches: Vec<SignatureMatch> = found[..self.standalone]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, starts)| Some(SignatureMatch { name: self.signatures[i].name.clone(), offset: *starts.first()? }))
+            .collect();
+        for (signature, subsignatures) in &self.logical {
+            let subs = &found[subsignatures.clone()];
+            let size = data.len() as u64;
+            if !signature.target.accepts(detected)
+                || signature.file_size.is_some_and(|(min, max)| size < min || size > max)
+            {
+                continue;
+            }
+            let counts: Vec<usize> = subs.iter().map(Vec::len).collect();
+            if signature.expression.eval(&counts).0 {
+                let offset = subs.iter().filter_map(|starts| starts.iter().min()).min().copied().unwrap_or(0);
+                matches.push(SignatureMatch { name: signature.name.clone(), offset });
+            }
+        }
+        matches.sort_by_key(|m| m.offset);
+        matches
+    }
+}
+
+/// A whole-file digest (MD5, SHA-1 or SHA-256 by length), optionally with the file size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashSignature {
+    pub name: String,
+    pub digest: Vec<u8>,
+    pub size: Option<u64>,
+}
+
+/// Whole-file digests, looked up by computing only the digest kinds that are loaded
+#[derive(Debug, Clone, Default)]
+pub struct HashSignatures {
+    // Digest -> (size, name); a None size matches any size
+    entries: AHashMap<Vec<u8>, Vec<(Option<u64>, String)>>,
+    // Whether any MD5, SHA-1, SHA-256 digests are loaded
+    kinds: [bool; 3],
+}
+
+impl HashSignatures {
+    /// Add a signature; false if the digest is not MD5, SHA-1 or SHA-256 sized
+    pub fn insert(&mut self, signature: HashSignature) -> bool {
+        let kind = match signature.digest.len() {
+            16 => 0,
+            20 => 1,
+            32 => 2,
+            _ => return false,
+        };
+        self.kinds[kind] = true;
+        let entries = self.entries.entry(signature.digest).or_default();
+        if !entries.iter().any(|(size, name)| *size == signature.size && *name == signature.name) {
+            entries.push((signature.size, signature.name));
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The name of a signature matching the whole of `data`
+    pub fn lookup(&self, data: &[u8]) -> Option<String> {
+        let digests = [
+            self.kinds[0].then(|| Md5::digest(data).to_vec()),
+            self.kinds[1].then(|| Sha1::digest(data).to_vec()),
+            self.kinds[2].then(|| Sha256::digest(data).to_vec()),
+        ];
+        let size = data.len() as u64;
+        digests.iter().flatten().find_map(|digest| {
+            self.entries
+                .get(digest)?
+                .iter()
+                .find(|(expected, _)| expected.is_none_or(|expected| expected == size))
+                .map(|(_, name)| name.clone())
+        })
+    }
+}
+
+/// Named body, logical and hash signatures. The automaton compiled from the first two is rebuilt
+/// only after a change; hash signatures are shared copy-on-write so lookups can run unlocked.
+#[derive(Default)]
+pub struct SignatureDatabase {
+    signatures: Vec<BodySignature>,
+    index: AHashMap<String, usize>,
+    logical: Vec<LogicalSignature>,
+    logical_index: AHashMap<String, usize>,
+    hashes: Arc<HashSignatures>,
+    compiled: Option<Arc<SignatureMatcher>>,
+}
+
+impl SignatureDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a signature, replacing any with the same name
+    pub fn insert(&mut self, signature: BodySignature) {
+        match self.index.get(&signature.name) {
+            Some(&i) if self.signatures[i] == signature => return,
+            Some(&i) => self.signatures[i] = signature,
+            None => {
+                self.index.insert(signature.name.clone(), self.signatures.len());
+                self.signatures.push(signature);
+            }
+        }
+        self.compiled = None;
+    }
+
+    /// Add a logical signature, replacing any with the same name
+    pub fn insert_logical(&mut self, signature: LogicalSignature) {
+        match self.logical_index.get(&signature.name) {
+            Some(&i) if self.logical[i] == signature => return,
+            Some(&i) => self.logical[i] = signature,
+            None => {
+                self.logical_index.insert(signature.name.clone(), self.logical.len());
+                self.logical.push(signature);
+            }
+        }
+        self.compiled = None;
+    }
+
+    pub fn insert_hash(&mut self, signature: HashSignature) -> bool {
+        Arc::make_mut(&mut self.hashes).insert(signature)
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len() + self.logical.len() + self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hashes(&self) -> Arc<HashSignatures> {
+        self.hashes.clone()
+    }
+
+    /// The compiled matcher; scans can run on it after the database lock is released
+    pub fn matcher(&mut self) -> Arc<SignatureMatcher> {
+        self.compiled
+            .get_or_insert_with(|| Arc::new(SignatureMatcher::new(self.signatures.clone(), self.logical.clone())))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards_gaps_and_all_matches_in_one_pass() {
+        let mut db = SignatureDatabase::new();
+        db.insert(BodySignature::literal("Nop.Sled", &[0x90, 0x90, 0x90]).unwrap());
+        db.insert(BodySignature::parse("Call.Masked", "e8 00 ?? ?? ?? 5? c3").unwrap());
+        db.insert(BodySignature::parse("Dropper.Gap", "6576696c{2-4}7061796c6f6164*2f62696e2f7368").unwrap());
+        db.insert(BodySignature::parse("Anchor.Late", "??a?{-3}deadbeef").unwrap());
+        let matcher = db.matcher();
+        assert!(Arc::ptr_eq(&matcher, &db.matcher()), "unchanged database reuses the automaton");
+
+        let mut data = vec![0u8; 16];
+        data.extend_from_slice(&[0x90, 0x90, 0x90, 0x90]); // 16
+        data.extend_from_slice(&[0xE8, 0, 2, 3, 4, 0x5A, 0xC3]); // 20
+        data.extend_from_slice(b"evil..payload and then /bin/sh"); // 27
+        data.extend_from_slice(&[0x11, 0xA7, 0x00, 0xDE, 0xAD, 0xBE, 0xEF]); // 57
+        let matches = matcher.scan(&data);
+        let found: Vec<(&str, usize)> = matches.iter().map(|m| (m.name.as_str(), m.offset)).collect();
+        assert_eq!(found, [("Nop.Sled", 16), ("Call.Masked", 20), ("Dropper.Gap", 27), ("Anchor.Late", 57)]);
+
+        // Gap bounds and nibble masks are enforced
+        assert!(matcher.scan(b"evil.....payload /bin/sh").is_empty());
+        assert!(matcher.scan(&[0xE8, 0, 2, 3, 4, 0x6A, 0xC3]).is_empty());
+        assert!(matcher.scan(&[0xA0, 0, 0, 0, 0, 0xDE, 0xAD, 0xBE, 0xEF]).is_empty());
+
+        db.insert(BodySignature::parse("Call.Masked", "e8 00 ?? ?? ?? 6? c3").unwrap());
+        assert!(!Arc::ptr_eq(&matcher, &db.matcher()));
+        assert_eq!(db.len(), 4);
+
+        assert_eq!(BodySignature::parse("x", "").unwrap_err(), PatternError::Empty);
+        assert_eq!(BodySignature::parse("x", "*aabb").unwrap_err(), PatternError::MisplacedGap(0));
+        assert_eq!(BodySignature::parse("x", "aabb{3-1}cc").unwrap_err(), PatternError::InvalidGap(4));
+        assert_eq!(BodySignature::parse("x", "aab").unwrap_err(), PatternError::InvalidHex(2));
+        assert_eq!(BodySignature::parse("x", "a???b?").unwrap_err(), PatternError::NoAnchor);
+    }
+
+    #[test]
+    fn test_gap_search_stays_linear_on_repeated_anchors() {
+        let mut db = SignatureDatabase::new();
+        db.insert(BodySignature::parse("Short.Anchor", "aabb*0102").unwrap());
+        db.insert(BodySignature::parse("Two.Gaps", "aabbccdd*eeff*1122").unwrap());
+        let matcher = db.matcher();
+
+        // Every anchor hit could search to the end of the file for the next segment
+        let mut data = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF].repeat(16 * 1024);
+        let started = std::time::Instant::now();
+        assert!(matcher.scan(&data).is_empty());
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+
+        // The budget still covers a far gap that does close
+        data.extend_from_slice(&[0x01, 0x02, 0x11, 0x22]);
+        let matches = matcher.scan(&data);
+        let found: Vec<(&str, usize)> = matches.iter().map(|m| (m.name.as_str(), m.offset)).collect();
+        assert_eq!(found, [("Short.Anchor", 0), ("Two.Gaps", 0)]);
+    }
+}
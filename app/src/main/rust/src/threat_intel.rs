@@ -0,0 +1,518 @@
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use ahash::AHashMap;
+use chrono::DateTime;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::url_analysis::Url;
+
+// Confidence given to indicators whose feed does not state one
+const DEFAULT_CONFIDENCE: u8 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndicatorKind {
+    /// Single address or CIDR network, IPv4 or IPv6
+    Ip,
+    Domain,
+    Url,
+    FileHash,
+    Ja3,
+    CertificateHash,
+}
+
+/// One indicator of compromise with the window it is valid for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Indicator {
+    pub kind: IndicatorKind,
+    /// Normalized: `network/len` or address, lowercase domain, canonical URL or lowercase hex
+    pub value: String,
+    pub valid_from_ms: Option<u64>,
+    pub valid_until_ms: Option<u64>,
+    /// 0-100
+    pub confidence: u8,
+    /// STIX indicator id or MISP attribute uuid
+    pub source_id: String,
+}
+
+impl Indicator {
+    /// Validate and normalize `value`; None if it is not a usable indicator of `kind`
+    pub fn new(kind: IndicatorKind, value: &str, source_id: &str) -> Option<Indicator> {
+        let value = value.trim();
+        let value = match kind {
+            IndicatorKind::Ip => {
+                let (network, len) = parse_cidr(value)?;
+                if len == max_prefix(network) { network.to_string() } else { format!("{}/{}", network, len) }
+            }
+            IndicatorKind::Domain => normalize_domain(value)?,
+            IndicatorKind::Url => normalize_url(value)?,
+            IndicatorKind::FileHash => normalize_hex(value, &[32, 40, 56, 64, 96, 128])?,
+            IndicatorKind::Ja3 => normalize_hex(value, &[32])?,
+            IndicatorKind::CertificateHash => normalize_hex(value, &[40, 64])?,
+        };
+        Some(Indicator {
+            kind,
+            value,
+            valid_from_ms: None,
+            valid_until_ms: None,
+            confidence: DEFAULT_CONFIDENCE,
+            source_id: source_id.to_string(),
+        })
+    }
+
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.valid_until_ms.is_some_and(|until| now_ms >= until)
+    }
+
+    pub fn is_active(&self, now_ms: u64) -> bool {
+        !self.is_expired(now_ms) && self.valid_from_ms.is_none_or(|from| now_ms >= from)
+    }
+
+    // Fold a re-import of the same indicator into this one: the widest window and highest confidence win
+    fn merge(&mut self, other: Indicator) {
+        self.valid_from_ms = self.valid_from_ms.zip(other.valid_from_ms).map(|(a, b)| a.min(b));
+        self.valid_until_ms = self.valid_until_ms.zip(other.valid_until_ms).map(|(a, b)| a.max(b));
+        if other.confidence > self.confidence {
+            self.confidence = other.confidence;
+            self.source_id = other.source_id;
+        }
+    }
+}
+
+fn max_prefix(ip: IpAddr) -> u8 {
+    if ip.is_ipv4() { 32 } else { 128 }
+}
+
+fn ip_bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u32::from(v4) as u128,
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+fn mask(bits: u128, len: u8, max: u8) -> u128 {
+    if len == 0 { 0 } else { bits & (u128::MAX << (128 - len as u32)) >> (128 - max as u32) }
+}
+
+// Address or network with its host bits cleared
+fn parse_cidr(value: &str) -> Option<(IpAddr, u8)> {
+    let (addr, len) = match value.split_once('/') {
+        Some((addr, len)) => (addr.parse::<IpAddr>().ok()?, Some(len.parse::<u8>().ok()?)),
+        None => (value.parse::<IpAddr>().ok()?, None),
+    };
+    let max = max_prefix(addr);
+    let len = len.unwrap_or(max);
+    if len > max {
+        return None;
+    }
+    let network = mask(ip_bits(addr), len, max);
+    let network = match addr {
+        IpAddr::V4(_) => IpAddr::from((network as u32).to_be_bytes()),
+        IpAddr::V6(_) => IpAddr::from(network.to_be_bytes()),
+    };
+    Some((network, len))
+}
+
+fn normalize_domain(value: &str) -> Option<String> {
+    let domain = value.trim_end_matches('.').to_ascii_lowercase();
+    let valid = domain.contains('.')
+        && domain.parse::<IpAddr>().is_err()
+        && domain.split('.').all(|label| !label.is_empty() && label.len() <= 63)
+        && domain.bytes().all(|b| b.is_ascii_alphanumeric() || b"-._*".contains(&b));
+    valid.then(|| domain.trim_start_matches("*.").to_string())
+}
+
+// Feeds often omit the scheme; matching ignores the fragment, which never leaves the browser
+fn normalize_url(value: &str) -> Option<String> {
+    let mut url = if value.contains("://") { Url::parse(value)? } else { Url::parse(&format!("http://{}", value))? };
+    url.fragment = None;
+    Some(url.to_string())
+}
+
+fn normalize_hex(value: &str, lengths: &[usize]) -> Option<String> {
+    let hex: String = value.chars().filter(|c| *c != ':').collect();
+    (lengths.contains(&hex.len()) && hex.bytes().all(|b| b.is_ascii_hexdigit())).then(|| hex.to_ascii_lowercase())
+}
+
+/// Active indicators routed by kind: a prefix-length bucketed IP store, a domain
+/// suffix table, and exact tables for URLs and file/JA3/certificate hashes
+#[derive(Debug, Default)]
+pub struct ThreatIntel {
+    // Networks per (is IPv6, prefix length), keyed by masked address bits
+    networks: AHashMap<(bool, u8), AHashMap<u128, Indicator>>,
+    entries: AHashMap<(IndicatorKind, String), Indicator>,
+}
+
+impl ThreatIntel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or refresh indicators; ones already expired are dropped. Returns how many were stored.
+    pub fn import(&mut self, indicators: impl IntoIterator<Item = Indicator>, now_ms: u64) -> usize {
+        let mut stored = 0;
+        for indicator in indicators.into_iter().filter(|i| !i.is_expired(now_ms)) {
+            if indicator.kind == IndicatorKind::Ip {
+                let Some((network, len)) = parse_cidr(&indicator.value) else { continue };
+                let table = self.networks.entry((network.is_ipv6(), len)).or_default();
+                upsert(table, ip_bits(network), indicator);
+            } else {
+                upsert(&mut self.entries, (indicator.kind, indicator.value.clone()), indicator);
+            }
+            stored += 1;
+        }
+        stored
+    }
+
+    /// Drop indicators whose validity window has ended; returns how many went
+    pub fn expire(&mut self, now_ms: u64) -> usize {
+        let before = self.len();
+        for table in self.networks.values_mut() {
+            table.retain(|_, indicator| !indicator.is_expired(now_ms));
+        }
+        self.networks.retain(|_, table| !table.is_empty());
+        self.entries.retain(|_, indicator| !indicator.is_expired(now_ms));
+        before - self.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.networks.values().map(|table| table.len()).sum::<usize>() + self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Most specific active network containing `ip`
+    pub fn match_ip(&self, ip: IpAddr, now_ms: u64) -> Option<&Indicator> {
+        if self.networks.is_empty() {
+            return None;
+        }
+        let (v6, max) = (ip.is_ipv6(), max_prefix(ip));
+        let bits = ip_bits(ip);
+        self.networks
+            .iter()
+            .filter(|((family, _), _)| *family == v6)
+            .filter_map(|(&(_, len), table)| table.get(&mask(bits, len, max)).map(|indicator| (len, indicator)))
+            .filter(|(_, indicator)| indicator.is_active(now_ms))
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, indicator)| indicator)
+    }
+
+    /// Active indicator for `domain` or any parent domain
+    pub fn match_domain(&self, domain: &str, now_ms: u64) -> Option<&Indicator> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let mut candidate = domain.as_str();
+        loop {
+            if let Some(indicator) = self.lookup(IndicatorKind::Domain, candidate, now_ms) {
+                return Some(indicator);
+            }
+            candidate = &candidate[candidate.find('.')? + 1..];
+        }
+    }
+
+    pub fn match_url(&self, url: &str, now_ms: u64) -> Option<&Indicator> {
+        self.lookup(IndicatorKind::Url, &normalize_url(url)?, now_ms)
+    }
+
+    /// Exact lookup of a hash-like value (file hash, JA3, certificate fingerprint)
+    pub fn match_hash(&self, kind: IndicatorKind, hash: &str, now_ms: u64) -> Option<&Indicator> {
+        let hash: String = hash.chars().filter(|c| *c != ':').collect();
+        self.lookup(kind, &hash.to_ascii_lowercase(), now_ms)
+    }
+
+    fn lookup(&self, kind: IndicatorKind, value: &str, now_ms: u64) -> Option<&Indicator> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.entries.get(&(kind, value.to_string())).filter(|indicator| indicator.is_active(now_ms))
+    }
+}
+
+fn upsert<K: std::hash::Hash + Eq>(table: &mut AHashMap<K, Indicator>, key: K, indicator: Indicator) {
+    match table.get_mut(&key) {
+        Some(existing) => existing.merge(indicator),
+        None => {
+            table.insert(key, indicator);
+        }
+    }
+}
+
+/// Indicators recovered from a feed, and how many entries could not be used
+#[derive(Debug, Default)]
+pub struct ParsedFeed {
+    pub indicators: Vec<Indicator>,
+    pub skipped: usize,
+}
+
+impl ParsedFeed {
+    fn push(&mut self, kind: IndicatorKind, value: &str, source_id: &str, window: (Option<u64>, Option<u64>), confidence: u8) {
+        match Indicator::new(kind, value, source_id) {
+            Some(mut indicator) => {
+                (indicator.valid_from_ms, indicator.valid_until_ms) = window;
+                indicator.confidence = confidence.min(100);
+                self.indicators.push(indicator);
+            }
+            None => self.skipped += 1,
+        }
+    }
+}
+
+// RFC 3339 timestamps, or Unix seconds as MISP sometimes writes them
+fn parse_time(value: &Value) -> Option<u64> {
+    let text = match value {
+        Value::Number(n) => return n.as_u64().map(|secs| secs * 1000),
+        Value::String(s) => s.as_str(),
+        _ => return None,
+    };
+    if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) {
+        return text.parse::<u64>().ok().map(|secs| secs * 1000);
+    }
+    let millis = DateTime::parse_from_rfc3339(text).ok()?.timestamp_millis();
+    Some(millis.max(0) as u64)
+}
+
+// MISP mixes booleans, numbers and "0"/"1" strings
+fn truthy(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::Number(n) => Some(n.as_u64() != Some(0)),
+        Value::String(s) => Some(!matches!(s.as_str(), "" | "0" | "false")),
+        _ => None,
+    }
+}
+
+// `object-type:property = 'value'` comparisons; ISSUBSET is how STIX writes CIDR matches
+fn stix_comparison() -> &'static Regex {
+    static COMPARISON: OnceLock<Regex> = OnceLock::new();
+    COMPARISON.get_or_init(|| {
+        Regex::new(r"([a-z0-9-]+):([A-Za-z0-9_.'-]+)\s*(?:=|ISSUBSET)\s*'((?:[^'\\]|\\.)*)'").unwrap()
+    })
+}
+
+fn stix_kind(object: &str, property: &str) -> Option<IndicatorKind> {
+    match object {
+        "ipv4-addr" | "ipv6-addr" if property == "value" => Some(IndicatorKind::Ip),
+        "domain-name" if property == "value" => Some(IndicatorKind::Domain),
+        "url" if property == "value" => Some(IndicatorKind::Url),
+        "file" if property.starts_with("hashes.") => Some(IndicatorKind::FileHash),
+        "x509-certificate" if property.starts_with("hashes.") => Some(IndicatorKind::CertificateHash),
+        // No standard object exists for JA3; feeds use custom objects or extensions
+        _ if object.contains("ja3") || property.contains("ja3") => Some(IndicatorKind::Ja3),
+        _ => None,
+    }
+}
+
+/// Observables of a STIX pattern made only of equality comparisons joined by OR.
+/// AND, FOLLOWEDBY and qualifiers describe behaviour no single observable proves.
+fn stix_pattern(pattern: &str) -> Option<Vec<(IndicatorKind, String)>> {
+    let comparison = stix_comparison();
+    let rest = comparison.replace_all(pattern, " ");
+    if rest.split(|c: char| c.is_whitespace() || "[]()".contains(c)).any(|token| !token.is_empty() && token != "OR") {
+        return None;
+    }
+    let observables: Vec<_> = comparison
+        .captures_iter(pattern)
+        .filter_map(|c| Some((stix_kind(&c[1], &c[2])?, c[3].replace("\\'", "'").replace("\\\\", "\\"))))
+        .collect();
+    (!observables.is_empty()).then_some(observables)
+}
+
+/// Indicators in a STIX 2.1 bundle (or a bare indicator / array of objects)
+pub fn parse_stix(json: &str) -> Option<ParsedFeed> {
+    let root: Value = serde_json::from_str(json).ok()?;
+    let objects = match (&root["objects"], &root) {
+        (Value::Array(objects), _) | (_, Value::Array(objects)) => objects.iter().collect(),
+        (_, Value::Object(_)) => vec![&root],
+        _ => return None,
+    };
+
+    let mut feed = ParsedFeed::default();
+    for object in objects.into_iter().filter(|o| o["type"] == "indicator") {
+        let stix_pattern_type = object["pattern_type"].as_str().is_none_or(|t| t == "stix");
+        let revoked = object["revoked"].as_bool().unwrap_or(false);
+        let observables = object["pattern"].as_str().filter(|_| stix_pattern_type && !revoked).and_then(stix_pattern);
+        let Some(observables) = observables else {
+            feed.skipped += 1;
+            continue;
+        };
+        let window = (parse_time(&object["valid_from"]), parse_time(&object["valid_until"]));
+        let confidence = object["confidence"].as_u64().map_or(DEFAULT_CONFIDENCE, |c| c.min(100) as u8);
+        let id = object["id"].as_str().unwrap_or("");
+        for (kind, value) in observables {
+            feed.push(kind, &value, id, window, confidence);
+        }
+    }
+    Some(feed)
+}
+
+fn misp_observables(kind: &str, value: &str) -> Vec<(IndicatorKind, String)> {
+    let (left, right) = value.split_once('|').unwrap_or((value, ""));
+    let one = |kind, value: &str| vec![(kind, value.to_string())];
+    match kind {
+        "ip-src" | "ip-dst" => one(IndicatorKind::Ip, value),
+        "ip-src|port" | "ip-dst|port" => one(IndicatorKind::Ip, left),
+        "domain" | "hostname" => one(IndicatorKind::Domain, value),
+        "hostname|port" => one(IndicatorKind::Domain, left),
+        "domain|ip" => vec![(IndicatorKind::Domain, left.to_string()), (IndicatorKind::Ip, right.to_string())],
+        "url" => one(IndicatorKind::Url, value),
+        "md5" | "sha1" | "sha224" | "sha256" | "sha384" | "sha512" => one(IndicatorKind::FileHash, value),
+        k if k.starts_with("filename|") => one(IndicatorKind::FileHash, right),
+        "ja3-fingerprint-md5" => one(IndicatorKind::Ja3, value),
+        "x509-fingerprint-sha1" | "x509-fingerprint-sha256" => one(IndicatorKind::CertificateHash, value),
+        _ => Vec::new(),
+    }
+}
+
+// MISP threat levels: 1 high, 2 medium, 3 low, 4 undefined
+fn misp_confidence(event: &Value) -> u8 {
+    let level = match &event["threat_level_id"] {
+        Value::String(s) => s.parse().ok(),
+        v => v.as_u64(),
+    };
+    match level {
+        Some(1) => 90,
+        Some(2) => 70,
+        _ => DEFAULT_CONFIDENCE,
+    }
+}
+
+/// IDS-flagged attributes of MISP events: `{"Event": ...}`, a `{"response": [...]}`
+/// search result, or a list of either
+pub fn parse_misp(json: &str) -> Option<ParsedFeed> {
+    let root: Value = serde_json::from_str(json).ok()?;
+    let items = match (&root["response"], &root) {
+        (Value::Array(items), _) | (_, Value::Array(items)) => items.iter().collect(),
+        (_, Value::Object(_)) => vec![&root],
+        _ => return None,
+    };
+    let events: Vec<&Value> = items.into_iter().map(|item| if item["Event"].is_object() { &item["Event"] } else { item }).collect();
+    if !events.iter().any(|e| e["Attribute"].is_array() || e["Object"].is_array()) {
+        return None;
+    }
+
+    let mut feed = ParsedFeed::default();
+    for event in events {
+        let confidence = misp_confidence(event);
+        let objects = event["Object"].as_array().into_iter().flatten();
+        let attributes = event["Attribute"]
+            .as_array()
+            .into_iter()
+            .chain(objects.filter_map(|object| object["Attribute"].as_array()))
+            .flatten();
+        for attribute in attributes {
+            // Attributes without the IDS flag are context, not detection material
+            let usable = !truthy(&attribute["deleted"]).unwrap_or(false) && truthy(&attribute["to_ids"]).unwrap_or(true);
+            let observables = match (attribute["type"].as_str(), attribute["value"].as_str()) {
+                (Some(kind), Some(value)) if usable => misp_observables(kind, value),
+                _ => Vec::new(),
+            };
+            if observables.is_empty() {
+                feed.skipped += 1;
+                continue;
+            }
+            // first_seen/last_seen bound when the attribute is known to be in use
+            let window = (parse_time(&attribute["first_seen"]), parse_time(&attribute["last_seen"]));
+            let id = attribute["uuid"].as_str().unwrap_or("");
+            for (kind, value) in observables {
+                feed.push(kind, &value, id, window, confidence);
+            }
+        }
+    }
+    Some(feed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY_MS: u64 = 86_400_000;
+    // 2024-01-01T00:00:00Z
+    const NOW: u64 = 1_704_067_200_000;
+
+    #[test]
+    fn test_stix_bundle_patterns_and_windows() {
+        let bundle = r#"{
+            "type": "bundle",
+            "objects": [
+                {"type": "identity", "id": "identity--1", "name": "CTI team"},
+                {"type": "indicator", "id": "indicator--a", "pattern_type": "stix", "confidence": 85,
+                 "pattern": "[ipv4-addr:value ISSUBSET '198.51.100.7/24'] OR [ipv6-addr:value = '2001:db8::bad']",
+                 "valid_from": "2023-12-01T00:00:00Z", "valid_until": "2024-01-15T00:00:00Z"},
+                {"type": "indicator", "id": "indicator--b", "pattern_type": "stix",
+                 "pattern": "[domain-name:value = 'Evil.Example.'] OR [url:value = 'https://Files.Example.net/drop.apk#x']",
+                 "valid_from": "2023-12-01T00:00:00Z"},
+                {"type": "indicator", "id": "indicator--c", "pattern_type": "stix",
+                 "pattern": "[file:hashes.'SHA-256' = 'E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855'] OR [x509-certificate:hashes.'SHA-1' = '00:11:22:33:44:55:66:77:88:99:aa:bb:cc:dd:ee:ff:00:11:22:33'] OR [x-ja3:value = 'e7d705a3286e19ea42f587b344ee6865']",
+                 "valid_from": "2023-12-01T00:00:00Z"},
+                {"type": "indicator", "id": "indicator--d", "pattern_type": "stix",
+                 "pattern": "[ipv4-addr:value = '192.0.2.1' AND network-traffic:dst_port = 4444]", "valid_from": "2023-12-01T00:00:00Z"},
+                {"type": "indicator", "id": "indicator--e", "pattern_type": "yara", "pattern": "rule x { condition: true }"},
+                {"type": "indicator", "id": "indicator--f", "revoked": true, "pattern": "[domain-name:value = 'old.example']"},
+                {"type": "indicator", "id": "indicator--g", "pattern": "[domain-name:value = 'expired.example']",
+                 "valid_from": "2020-01-01T00:00:00Z", "valid_until": "2020-02-01T00:00:00Z"}
+            ]
+        }"#;
+        let feed = parse_stix(bundle).unwrap();
+        assert_eq!(feed.indicators.len(), 8);
+        assert_eq!(feed.skipped, 3);
+
+        let mut intel = ThreatIntel::new();
+        // The 2020 indicator is already over
+        assert_eq!(intel.import(feed.indicators, NOW), 7);
+
+        let hit = intel.match_ip("198.51.100.200".parse().unwrap(), NOW).unwrap();
+        assert_eq!((hit.value.as_str(), hit.confidence, hit.source_id.as_str()), ("198.51.100.0/24", 85, "indicator--a"));
+        assert!(intel.match_ip("198.51.101.1".parse().unwrap(), NOW).is_none());
+        assert!(intel.match_ip("2001:db8::bad".parse().unwrap(), NOW).is_some());
+        assert!(intel.match_domain("cdn.evil.example", NOW).is_some());
+        assert!(intel.match_url("https://files.example.net/drop.apk", NOW).is_some());
+        assert!(intel.match_hash(IndicatorKind::FileHash, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", NOW).is_some());
+        assert!(intel.match_hash(IndicatorKind::CertificateHash, "00112233445566778899aabbccddeeff00112233", NOW).is_some());
+        assert!(intel.match_hash(IndicatorKind::Ja3, "E7D705A3286E19EA42F587B344EE6865", NOW).is_some());
+        // Not yet valid
+        assert!(intel.match_domain("evil.example", NOW - 60 * DAY_MS).is_none());
+
+        // The network indicator lapses on January 15th
+        assert_eq!(intel.expire(NOW + 20 * DAY_MS), 2);
+        assert!(intel.match_ip("198.51.100.7".parse().unwrap(), NOW + 20 * DAY_MS).is_none());
+        assert_eq!(intel.len(), 5);
+    }
+
+    #[test]
+    fn test_misp_event_attributes() {
+        let event = r#"{"response": [{"Event": {
+            "info": "Banking trojan campaign", "threat_level_id": "1",
+            "Attribute": [
+                {"uuid": "a1", "type": "ip-dst|port", "value": "203.0.113.9|8443", "to_ids": true},
+                {"uuid": "a2", "type": "domain|ip", "value": "c2.example.org|203.0.113.10", "to_ids": "1",
+                 "first_seen": "2023-11-01T00:00:00.000000+00:00", "last_seen": "2024-02-01T00:00:00.000000+00:00"},
+                {"uuid": "a3", "type": "sha256", "value": "not-a-hash", "to_ids": true},
+                {"uuid": "a4", "type": "url", "value": "phish.example.com/login", "to_ids": false},
+                {"uuid": "a5", "type": "comment", "value": "seen in the wild"}
+            ],
+            "Object": [{"name": "file", "Attribute": [
+                {"uuid": "a6", "type": "md5", "value": "D41D8CD98F00B204E9800998ECF8427E", "to_ids": true},
+                {"uuid": "a7", "type": "x509-fingerprint-sha256", "deleted": true,
+                 "value": "aa00000000000000000000000000000000000000000000000000000000000000", "to_ids": true}
+            ]}]
+        }}]}"#;
+        let feed = parse_misp(event).unwrap();
+        assert_eq!(feed.indicators.len(), 4);
+        assert_eq!(feed.skipped, 4);
+        assert!(feed.indicators.iter().all(|i| i.confidence == 90));
+
+        let mut intel = ThreatIntel::new();
+        assert_eq!(intel.import(feed.indicators, NOW), 4);
+        assert_eq!(intel.match_ip("203.0.113.9".parse().unwrap(), NOW).unwrap().source_id, "a1");
+        assert!(intel.match_domain("c2.example.org.", NOW).is_some());
+        assert!(intel.match_hash(IndicatorKind::FileHash, "d41d8cd98f00b204e9800998ecf8427e", NOW).is_some());
+        assert_eq!(intel.expire(NOW + 60 * DAY_MS), 2);
+
+        assert!(parse_misp("{\"info\": \"no attributes\"}").is_none());
+        assert!(parse_stix("not json").is_none());
+    }
+}
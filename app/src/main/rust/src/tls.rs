@@ -0,0 +1,530 @@
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+// TLS extension identifiers referenced by the fingerprinting code
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// Parsed view of a TLS ClientHello, shared by the TCP and QUIC paths
+#[derive(Debug, Clone, Default)]
+pub struct ClientHello {
+    pub legacy_version: u16,
+    pub cipher_suites: Vec<u16>,
+    pub extensions: Vec<u16>,
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+    pub supported_versions: Vec<u16>,
+    pub signature_algorithms: Vec<u16>,
+    pub supported_groups: Vec<u16>,
+    pub ec_point_formats: Vec<u8>,
+}
+
+/// Transport the ClientHello was carried over; JA4 encodes it in the first character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelloTransport {
+    Tcp,
+    Quic,
+}
+
+/// GREASE values (RFC 8701) are excluded from every fingerprint
+pub fn is_grease(value: u16) -> bool {
+    (value & 0x0f0f) == 0x0a0a && (value >> 8) == (value & 0xff)
+}
+
+/// Length of a complete handshake message at the start of `data`, if it is all there
+pub fn handshake_message_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 4 {
+        return None;
+    }
+    let body_len = u32::from_be_bytes([0, data[1], data[2], data[3]]) as usize;
+    Some(4 + body_len)
+}
+
+/// Parse a ClientHello handshake message (type byte, 24-bit length, body)
+pub fn parse_client_hello(msg: &[u8]) -> Option<ClientHello> {
+    if msg.first() != Some(&0x01) {
+        return None;
+    }
+    let total = handshake_message_len(msg)?;
+    if msg.len() < total {
+        return None;
+    }
+
+    let mut r = Reader::new(&msg[4..total]);
+    let mut hello = ClientHello {
+        legacy_version: r.u16()?,
+        ..Default::default()
+    };
+    r.skip(32)?; // random
+    let session_id_len = r.u8()? as usize;
+    r.skip(session_id_len)?;
+
+    let mut suites = Reader::new(r.take_u16_prefixed()?);
+    while suites.remaining() >= 2 {
+        hello.cipher_suites.push(suites.u16()?);
+    }
+
+    let compression_len = r.u8()? as usize;
+    r.skip(compression_len)?;
+
+    // Extensions are optional in a pre-TLS 1.3 hello
+    if r.remaining() < 2 {
+        return Some(hello);
+    }
+
+    let mut exts = Reader::new(r.take_u16_prefixed()?);
+    while exts.remaining() >= 4 {
+        let ext_type = exts.u16()?;
+        let data = exts.take_u16_prefixed()?;
+        hello.extensions.push(ext_type);
+
+        match ext_type {
+            EXT_SERVER_NAME => hello.server_name = parse_server_name(data),
+            EXT_ALPN => hello.alpn = parse_alpn(data),
+            EXT_SUPPORTED_VERSIONS => {
+                let mut v = Reader::new(data);
+                let list = v.take_u8_prefixed().unwrap_or(&[]);
+                hello.supported_versions = u16_list(list);
+            }
+            EXT_SIGNATURE_ALGORITHMS => {
+                let mut v = Reader::new(data);
+                hello.signature_algorithms = u16_list(v.take_u16_prefixed().unwrap_or(&[]));
+            }
+            EXT_SUPPORTED_GROUPS => {
+                let mut v = Reader::new(data);
+                hello.supported_groups = u16_list(v.take_u16_prefixed().unwrap_or(&[]));
+            }
+            EXT_EC_POINT_FORMATS => {
+                let mut v = Reader::new(data);
+                hello.ec_point_formats = v.take_u8_prefixed().unwrap_or(&[]).to_vec();
+            }
+            _ => {}
+        }
+    }
+
+    Some(hello)
+}
+
+fn parse_server_name(data: &[u8]) -> Option<String> {
+    let mut r = Reader::new(data);
+    let mut list = Reader::new(r.take_u16_prefixed()?);
+    while list.remaining() >= 3 {
+        let name_type = list.u8()?;
+        let name = list.take_u16_prefixed()?;
+        if name_type == 0 {
+            let host = std::str::from_utf8(name).ok()?;
+            return Some(host.trim_end_matches('.').to_ascii_lowercase());
+        }
+    }
+    None
+}
+
+fn parse_alpn(data: &[u8]) -> Vec<String> {
+    let mut protocols = Vec::new();
+    let mut r = Reader::new(data);
+    let Some(list) = r.take_u16_prefixed() else { return protocols };
+    let mut list = Reader::new(list);
+    while let Some(proto) = list.take_u8_prefixed() {
+        protocols.push(String::from_utf8_lossy(proto).into_owned());
+    }
+    protocols
+}
+
+fn u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect()
+}
+
+fn join_decimal<T: ToString>(values: impl Iterator<Item = T>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join("-")
+}
+
+fn truncated_sha256(input: &str) -> String {
+    let digest = Sha256::digest(input.as_bytes());
+    hex::encode(&digest[..6])
+}
+
+impl ClientHello {
+    /// JA3 string: version,ciphers,extensions,groups,point formats (GREASE removed)
+    pub fn ja3_string(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.legacy_version,
+            join_decimal(self.cipher_suites.iter().filter(|c| !is_grease(**c))),
+            join_decimal(self.extensions.iter().filter(|e| !is_grease(**e))),
+            join_decimal(self.supported_groups.iter().filter(|g| !is_grease(**g))),
+            join_decimal(self.ec_point_formats.iter()),
+        )
+    }
+
+    /// JA3 hash as the lowercase MD5 hex digest of the JA3 string
+    pub fn ja3_hash(&self) -> String {
+        hex::encode(Md5::digest(self.ja3_string().as_bytes()))
+    }
+
+    /// JA4 fingerprint (FoxIO spec) for the given transport
+    pub fn ja4(&self, transport: HelloTransport) -> String {
+        let proto = match transport {
+            HelloTransport::Tcp => 't',
+            HelloTransport::Quic => 'q',
+        };
+
+        let version = self
+            .supported_versions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .max()
+            .unwrap_or(self.legacy_version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            0xfeff => "d1",
+            0xfefd => "d2",
+            0xfefc => "d3",
+            _ => "00",
+        };
+
+        let sni = if self.server_name.is_some() { 'd' } else { 'i' };
+
+        let mut ciphers: Vec<u16> = self.cipher_suites.iter().copied().filter(|c| !is_grease(*c)).collect();
+        let mut extensions: Vec<u16> = self.extensions.iter().copied().filter(|e| !is_grease(*e)).collect();
+
+        let alpn = match self.alpn.first().map(|a| a.as_bytes()) {
+            Some(value) if !value.is_empty() => {
+                let first = value[0];
+                let last = value[value.len() - 1];
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                    format!("{}{}", first as char, last as char)
+                } else {
+                    let hex = hex::encode(value);
+                    format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+                }
+            }
+            _ => "00".to_string(),
+        };
+
+        let ja4_a = format!(
+            "{}{}{}{:02}{:02}{}",
+            proto,
+            version,
+            sni,
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn
+        );
+
+        ciphers.sort_unstable();
+        let ja4_b = if ciphers.is_empty() {
+            "000000000000".to_string()
+        } else {
+            let list: Vec<String> = ciphers.iter().map(|c| format!("{:04x}", c)).collect();
+            truncated_sha256(&list.join(","))
+        };
+
+        extensions.retain(|e| *e != EXT_SERVER_NAME && *e != EXT_ALPN);
+        extensions.sort_unstable();
+        let ja4_c = if extensions.is_empty() {
+            "000000000000".to_string()
+        } else {
+            let mut input = extensions.iter().map(|e| format!("{:04x}", e)).collect::<Vec<_>>().join(",");
+            if !self.signature_algorithms.is_empty() {
+                input.push('_');
+                input.push_str(
+                    &self.signature_algorithms.iter().map(|s| format!("{:04x}", s)).collect::<Vec<_>>().join(","),
+                );
+            }
+            truncated_sha256(&input)
+        };
+
+        format!("{}_{}_{}", ja4_a, ja4_b, ja4_c)
+    }
+}
+
+/// Parsed view of a TLS ServerHello
+#[derive(Debug, Clone, Default)]
+pub struct ServerHello {
+    pub legacy_version: u16,
+    pub cipher_suite: u16,
+    pub extensions: Vec<u16>,
+    /// Version picked through supported_versions; only present for TLS 1.3
+    pub selected_version: Option<u16>,
+}
+
+impl ServerHello {
+    /// JA3S string: version,cipher,extensions
+    pub fn ja3s_string(&self) -> String {
+        format!(
+            "{},{},{}",
+            self.legacy_version,
+            self.cipher_suite,
+            join_decimal(self.extensions.iter().filter(|e| !is_grease(**e))),
+        )
+    }
+
+    pub fn ja3s_hash(&self) -> String {
+        hex::encode(Md5::digest(self.ja3s_string().as_bytes()))
+    }
+
+    /// TLS 1.3 encrypts everything after the ServerHello, including the certificates
+    pub fn is_tls13(&self) -> bool {
+        self.selected_version == Some(0x0304)
+    }
+}
+
+/// Parse a ServerHello handshake message body
+pub fn parse_server_hello(body: &[u8]) -> Option<ServerHello> {
+    let mut r = Reader::new(body);
+    let mut hello = ServerHello {
+        legacy_version: r.u16()?,
+        ..Default::default()
+    };
+    r.skip(32)?; // random
+    let session_id_len = r.u8()? as usize;
+    r.skip(session_id_len)?;
+    hello.cipher_suite = r.u16()?;
+    r.skip(1)?; // compression method
+
+    if r.remaining() >= 2 {
+        let mut exts = Reader::new(r.take_u16_prefixed()?);
+        while exts.remaining() >= 4 {
+            let ext_type = exts.u16()?;
+            let data = exts.take_u16_prefixed()?;
+            hello.extensions.push(ext_type);
+            if ext_type == EXT_SUPPORTED_VERSIONS && data.len() == 2 {
+                hello.selected_version = Some(u16::from_be_bytes([data[0], data[1]]));
+            }
+        }
+    }
+
+    Some(hello)
+}
+
+/// Split a TLS 1.2 Certificate message body into DER certificates, leaf first
+pub fn parse_certificate_list(body: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut r = Reader::new(body);
+    let len = r.u24()? as usize;
+    let mut list = Reader::new(r.take(len)?);
+    let mut certs = Vec::new();
+    while list.remaining() >= 3 {
+        let cert_len = list.u24()? as usize;
+        certs.push(list.take(cert_len)?.to_vec());
+    }
+    Some(certs)
+}
+
+// Cap on buffered server handshake bytes; certificate chains are rarely above 16 KB
+const MAX_SERVER_HANDSHAKE_BYTES: usize = 64 * 1024;
+
+pub const HANDSHAKE_SERVER_HELLO: u8 = 2;
+pub const HANDSHAKE_CERTIFICATE: u8 = 11;
+pub const HANDSHAKE_SERVER_HELLO_DONE: u8 = 14;
+
+/// In-order TCP reassembly of the server's handshake flight
+#[derive(Debug, Default)]
+pub struct ServerHandshakeStream {
+    next_seq: Option<u32>,
+    data: Vec<u8>,
+}
+
+impl ServerHandshakeStream {
+    /// Append a segment; returns false when the stream has a gap or grew too large
+    pub fn push(&mut self, seq: u32, payload: &[u8]) -> bool {
+        if payload.is_empty() {
+            return true;
+        }
+        let skip = match self.next_seq {
+            None => 0,
+            Some(expected) => {
+                let ahead = seq.wrapping_sub(expected) as i32;
+                if ahead > 0 {
+                    return false; // lost segment, give up rather than guess
+                }
+                (-(ahead as i64)) as usize
+            }
+        };
+        if skip >= payload.len() {
+            return true; // pure retransmission
+        }
+        if self.data.len() + payload.len() - skip > MAX_SERVER_HANDSHAKE_BYTES {
+            return false;
+        }
+        self.data.extend_from_slice(&payload[skip..]);
+        self.next_seq = Some(seq.wrapping_add(payload.len() as u32));
+        true
+    }
+
+    /// Complete handshake messages so far as (type, body), and whether the flight has ended
+    pub fn messages(&self) -> (Vec<(u8, Vec<u8>)>, bool) {
+        // Handshake messages may span records, so join the record fragments first
+        let mut handshake = Vec::new();
+        let mut finished = false;
+        let mut pos = 0;
+
+        while pos + 5 <= self.data.len() {
+            let content_type = self.data[pos];
+            let len = u16::from_be_bytes([self.data[pos + 3], self.data[pos + 4]]) as usize;
+            if pos + 5 + len > self.data.len() {
+                break;
+            }
+            if content_type != 0x16 {
+                // ChangeCipherSpec, alert or application data ends the cleartext flight
+                finished = true;
+                break;
+            }
+            handshake.extend_from_slice(&self.data[pos + 5..pos + 5 + len]);
+            pos += 5 + len;
+        }
+
+        let mut messages = Vec::new();
+        let mut pos = 0;
+        while let Some(total) = handshake_message_len(&handshake[pos..]) {
+            if pos + total > handshake.len() {
+                break;
+            }
+            let msg_type = handshake[pos];
+            messages.push((msg_type, handshake[pos + 4..pos + total].to_vec()));
+            if msg_type == HANDSHAKE_SERVER_HELLO_DONE {
+                finished = true;
+            }
+            pos += total;
+        }
+
+        (messages, finished)
+    }
+}
+
+// Bounds-checked big-endian cursor over handshake bytes
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.remaining() < n {
+            return None;
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Some(slice)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<u32> {
+        self.take(3).map(|b| u32::from_be_bytes([0, b[0], b[1], b[2]]))
+    }
+
+    fn take_u8_prefixed(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn take_u16_prefixed(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a ClientHello handshake message for tests in this and other modules
+    pub(crate) fn build_client_hello(sni: Option<&str>, alpn: &[&str]) -> Vec<u8> {
+        let mut exts = Vec::new();
+        let mut push_ext = |ty: u16, data: &[u8]| {
+            exts.extend_from_slice(&ty.to_be_bytes());
+            exts.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            exts.extend_from_slice(data);
+        };
+
+        push_ext(0x0a0a, &[]); // GREASE
+        if let Some(host) = sni {
+            let mut entry = vec![0u8];
+            entry.extend_from_slice(&(host.len() as u16).to_be_bytes());
+            entry.extend_from_slice(host.as_bytes());
+            let mut data = (entry.len() as u16).to_be_bytes().to_vec();
+            data.extend_from_slice(&entry);
+            push_ext(EXT_SERVER_NAME, &data);
+        }
+        push_ext(EXT_SUPPORTED_GROUPS, &[0x00, 0x04, 0x00, 0x1d, 0x00, 0x17]);
+        push_ext(EXT_EC_POINT_FORMATS, &[0x01, 0x00]);
+        push_ext(EXT_SIGNATURE_ALGORITHMS, &[0x00, 0x04, 0x04, 0x03, 0x08, 0x04]);
+        if !alpn.is_empty() {
+            let mut list = Vec::new();
+            for proto in alpn {
+                list.push(proto.len() as u8);
+                list.extend_from_slice(proto.as_bytes());
+            }
+            let mut data = (list.len() as u16).to_be_bytes().to_vec();
+            data.extend_from_slice(&list);
+            push_ext(EXT_ALPN, &data);
+        }
+        push_ext(EXT_SUPPORTED_VERSIONS, &[0x04, 0x03, 0x04, 0x03, 0x03]);
+
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x11; 32]);
+        body.push(0); // session id
+        let suites: [u16; 4] = [0x2a2a, 0x1301, 0xc02b, 0xc02f];
+        body.extend_from_slice(&((suites.len() * 2) as u16).to_be_bytes());
+        for s in suites {
+            body.extend_from_slice(&s.to_be_bytes());
+        }
+        body.extend_from_slice(&[0x01, 0x00]); // null compression
+        body.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+        body.extend_from_slice(&exts);
+
+        let mut msg = vec![0x01];
+        msg.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        msg.extend_from_slice(&body);
+        msg
+    }
+
+    #[test]
+    fn test_parse_client_hello_fields() {
+        let msg = build_client_hello(Some("Example.COM"), &["h3"]);
+        let hello = parse_client_hello(&msg).unwrap();
+        assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+        assert_eq!(hello.alpn, vec!["h3".to_string()]);
+        assert_eq!(hello.cipher_suites, vec![0x2a2a, 0x1301, 0xc02b, 0xc02f]);
+        assert!(parse_client_hello(&msg[..msg.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_ja3_and_ja4_skip_grease() {
+        let hello = parse_client_hello(&build_client_hello(Some("example.com"), &["h2"])).unwrap();
+        assert_eq!(hello.ja3_string(), "771,4865-49195-49199,0-10-11-13-16-43,29-23,0");
+        assert_eq!(hello.ja3_hash().len(), 32);
+
+        let ja4 = hello.ja4(HelloTransport::Quic);
+        assert!(ja4.starts_with("q13d0306h2_"), "{}", ja4);
+        assert_eq!(ja4.len(), 36);
+        assert!(hello.ja4(HelloTransport::Tcp).starts_with("t13d"));
+    }
+}
@@ -0,0 +1,172 @@
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+/// Source and sink of raw IP packets for the userspace stack
+pub trait PacketDevice {
+    /// Read one packet into `buf`; `Ok(None)` when nothing is waiting
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>>;
+
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    /// Descriptor that polls readable when a packet is waiting, if the device has one
+    fn raw_fd(&self) -> Option<RawFd>;
+}
+
+/// A TUN interface carrying bare IP packets (no packet information prefix)
+pub struct TunDevice {
+    file: File,
+}
+
+impl TunDevice {
+    /// Take over a TUN descriptor, e.g. the one `VpnService.Builder.establish()` returned
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        set_nonblocking(fd.as_raw_fd())?;
+        Ok(Self { file: File::from(fd) })
+    }
+}
+
+impl PacketDevice for TunDevice {
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        match self.file.read(buf) {
+            Ok(n) => Ok(Some(n)),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        match self.file.write(packet) {
+            Ok(_) => Ok(()),
+            // The kernel queue is full; dropping is what a real link would do
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.file.as_raw_fd())
+    }
+}
+
+pub(crate) fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+type PacketQueue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// One end of an in-memory packet link: what one end sends, the other receives
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryPipe {
+    inbound: PacketQueue,
+    outbound: PacketQueue,
+}
+
+#[cfg(test)]
+impl MemoryPipe {
+    pub fn pair() -> (MemoryPipe, MemoryPipe) {
+        let a = MemoryPipe::default();
+        let b = MemoryPipe { inbound: a.outbound.clone(), outbound: a.inbound.clone() };
+        (a, b)
+    }
+}
+
+#[cfg(test)]
+impl PacketDevice for MemoryPipe {
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let Some(packet) = self.inbound.lock().unwrap().pop_front() else { return Ok(None) };
+        let n = packet.len().min(buf.len());
+        buf[..n].copy_from_slice(&packet[..n]);
+        Ok(Some(n))
+    }
+
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.outbound.lock().unwrap().push_back(packet.to_vec());
+        Ok(())
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_table::{self, FlowKey};
+    use crate::netstack::{udp_packet, NetStack, NetStackConfig};
+    use std::net::UdpSocket;
+    use std::os::fd::FromRawFd;
+
+    // Start a local UDP echo server; returns the key of an app flow to it
+    fn echo_server() -> FlowKey {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (n, from) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..n], from).unwrap();
+        });
+        FlowKey { src: "10.0.0.2".parse().unwrap(), dst: addr.ip(), src_port: 40_000, dst_port: addr.port(), protocol: 17 }
+    }
+
+    // Poll the stack until the app end receives a packet
+    fn relayed<D: PacketDevice, A: PacketDevice>(stack: &mut NetStack<D>, app: &mut A) -> Vec<u8> {
+        let mut buf = vec![0u8; 65_535];
+        for _ in 0..500 {
+            stack.poll(10).unwrap();
+            if let Some(n) = app.recv(&mut buf).unwrap() {
+                return buf[..n].to_vec();
+            }
+        }
+        panic!("nothing was relayed back");
+    }
+
+    #[test]
+    fn test_memory_pipe_relays_through_netstack() {
+        let (device, mut app) = MemoryPipe::pair();
+        let mut buf = [0u8; 64];
+        assert_eq!(app.recv(&mut buf).unwrap(), None);
+
+        let key = echo_server();
+        let mut stack = NetStack::new(device, NetStackConfig::default(), Box::new(|_| true));
+        app.send(&udp_packet(&key, b"first")).unwrap();
+        let reply = relayed(&mut stack, &mut app);
+        let meta = flow_table::parse_packet(&reply).unwrap();
+        assert_eq!(meta.key, key.reversed());
+        assert_eq!(meta.payload(&reply), b"first");
+        assert_eq!(app.recv(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tun_device_from_fd_relays_through_netstack() {
+        // A seqpacket socket pair keeps packet boundaries, as a TUN descriptor does
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) }, 0);
+        let device = TunDevice::from_fd(unsafe { OwnedFd::from_raw_fd(fds[0]) }).unwrap();
+        let mut app = TunDevice::from_fd(unsafe { OwnedFd::from_raw_fd(fds[1]) }).unwrap();
+        assert!(device.raw_fd().is_some());
+        let mut buf = [0u8; 64];
+        assert_eq!(app.recv(&mut buf).unwrap(), None);
+
+        let key = echo_server();
+        let mut stack = NetStack::new(device, NetStackConfig::default(), Box::new(|_| true));
+        app.send(&udp_packet(&key, b"over a descriptor")).unwrap();
+        let reply = relayed(&mut stack, &mut app);
+        let meta = flow_table::parse_packet(&reply).unwrap();
+        assert_eq!(meta.key, key.reversed());
+        assert_eq!(meta.payload(&reply), b"over a descriptor");
+    }
+}
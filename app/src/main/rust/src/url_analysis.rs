@@ -0,0 +1,399 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::Serialize;
+
+use crate::http::HttpRequest;
+use crate::icmp_tunnel::shannon_entropy;
+
+// Combined confidence at which a URL is reported as malicious
+const MALICIOUS_THRESHOLD: f32 = 0.7;
+
+const LONG_URL: usize = 100;
+const MAX_SUBDOMAIN_LABELS: usize = 4;
+// Random-looking tokens that kits use to make each victim's link unique
+const HIGH_ENTROPY: f32 = 4.5;
+const MIN_ENTROPY_LEN: usize = 40;
+
+// TLDs with a high share of abuse in phishing feeds, or that look like file names
+const SUSPICIOUS_TLDS: &[&str] = &[
+    "zip", "mov", "xyz", "top", "tk", "ml", "ga", "cf", "gq", "buzz", "rest", "country", "kim",
+    "work", "click", "link", "support", "fit", "cam", "icu", "cyou", "monster", "sbs",
+];
+
+// Path fragments left behind by widely sold phishing kits
+const PHISHING_KIT_PATHS: &[&str] = &[
+    "webscr", "cmd=_login-submit", "/wp-includes/login", "/wp-content/plugins/login", "/.well-known/pki-validation/",
+    "/owa/auth/", "/office365/", "/sharepoint/", "/docusign/", "/verify.php", "/auth.php", "/next.php",
+    "/post.php", "/login.php", "/signin/v2/", "/account/verify", "/secure-update", "/mailbox/",
+];
+
+// Query parameters that commonly carry a destination URL
+const REDIRECT_PARAMS: &[&str] = &[
+    "url", "redirect", "redirect_uri", "redirect_url", "return", "returnurl", "return_to", "next", "continue",
+    "dest", "destination", "goto", "target", "rurl", "out", "r", "u",
+];
+
+/// Components of an absolute URL, undecoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub scheme: String,
+    pub userinfo: Option<String>,
+    /// Lowercased; IPv6 literals without brackets
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+    pub query: Option<String>,
+    pub fragment: Option<String>,
+}
+
+impl Url {
+    /// Parse an absolute `scheme://authority/path?query#fragment` URL
+    pub fn parse(input: &str) -> Option<Url> {
+        let input = input.trim();
+        let (scheme, rest) = input.split_once("://")?;
+        if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+            return None;
+        }
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, tail) = rest.split_at(authority_end);
+
+        // The last @ ends the userinfo; browsers ignore everything before it when connecting
+        let (userinfo, host_port) = match authority.rfind('@') {
+            Some(at) => (Some(authority[..at].to_string()), &authority[at + 1..]),
+            None => (None, authority),
+        };
+        let (host, port) = if let Some(v6) = host_port.strip_prefix('[') {
+            let (host, after) = v6.split_once(']')?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match host_port.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let port = match port {
+            Some("") | None => None,
+            Some(port) => Some(port.parse().ok()?),
+        };
+
+        let (tail, fragment) = match tail.split_once('#') {
+            Some((tail, fragment)) => (tail, Some(fragment.to_string())),
+            None => (tail, None),
+        };
+        let (path, query) = match tail.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (tail, None),
+        };
+
+        Some(Url {
+            scheme: scheme.to_ascii_lowercase(),
+            userinfo,
+            host: host.trim_end_matches('.').to_ascii_lowercase(),
+            port,
+            path: if path.is_empty() { "/".to_string() } else { path.to_string() },
+            query,
+            fragment,
+        })
+    }
+
+    /// Full URL of a request, from an absolute-form target or the Host header
+    pub fn from_request(request: &HttpRequest) -> Option<Url> {
+        if request.uri.contains("://") {
+            return Url::parse(&request.uri);
+        }
+        let host = request.host()?;
+        let path = if request.uri.starts_with('/') { request.uri.as_str() } else { "/" };
+        Url::parse(&format!("http://{}{}", host, path))
+    }
+
+    /// Name=value pairs of the query string, percent-decoded
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        self.query
+            .as_deref()
+            .unwrap_or("")
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(name), percent_decode(value))
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://", self.scheme)?;
+        if let Some(userinfo) = &self.userinfo {
+            write!(f, "{}@", userinfo)?;
+        }
+        if self.host.contains(':') {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            write!(f, "{}", self.host)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lexical evidence against a URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlSignal {
+    LongUrl,
+    /// Host is a dotted IPv4 or IPv6 literal
+    IpLiteralHost,
+    /// Host is an IPv4 address written as one integer, in hex or octal
+    ObfuscatedIpHost,
+    /// `brand.com@evil.example`: everything before @ is only userinfo
+    UserinfoTrick,
+    SuspiciousTld,
+    ManySubdomains,
+    HighPathEntropy,
+    PhishingKitPath,
+    /// A redirect parameter pointing at another host
+    OpenRedirect,
+    NonStandardPort,
+}
+
+impl UrlSignal {
+    fn weight(self) -> f32 {
+        match self {
+            UrlSignal::ObfuscatedIpHost => 0.6,
+            UrlSignal::PhishingKitPath => 0.55,
+            UrlSignal::UserinfoTrick => 0.5,
+            UrlSignal::IpLiteralHost => 0.45,
+            UrlSignal::OpenRedirect => 0.3,
+            UrlSignal::SuspiciousTld => 0.25,
+            UrlSignal::HighPathEntropy => 0.2,
+            UrlSignal::LongUrl => 0.15,
+            UrlSignal::ManySubdomains => 0.15,
+            UrlSignal::NonStandardPort => 0.15,
+        }
+    }
+}
+
+/// Score of one URL; `threat_type` and `confidence` are what the app displays
+#[derive(Debug, Clone, Serialize)]
+pub struct UrlAnalysis {
+    pub url: String,
+    pub malicious: bool,
+    pub threat_type: String,
+    pub confidence: f32,
+    pub signals: Vec<UrlSignal>,
+    /// One human-readable line per signal
+    pub explanation: Vec<String>,
+}
+
+impl UrlAnalysis {
+    pub(crate) fn clean(url: String) -> Self {
+        Self { url, malicious: false, threat_type: "none".to_string(), confidence: 0.0, signals: Vec::new(), explanation: Vec::new() }
+    }
+}
+
+/// Score a URL on lexical features alone
+pub fn analyze_url(url: &Url) -> UrlAnalysis {
+    let text = url.to_string();
+    let mut found: Vec<(UrlSignal, String)> = Vec::new();
+
+    if text.len() > LONG_URL {
+        found.push((UrlSignal::LongUrl, format!("URL is {} characters long", text.len())));
+    }
+
+    if url.host.parse::<Ipv4Addr>().is_ok() || url.host.parse::<Ipv6Addr>().is_ok() {
+        found.push((UrlSignal::IpLiteralHost, format!("host is an IP address ({})", url.host)));
+    } else if let Some(ip) = obfuscated_ipv4(&url.host) {
+        found.push((UrlSignal::ObfuscatedIpHost, format!("host {} is the address {} in disguise", url.host, ip)));
+    } else {
+        let labels: Vec<&str> = url.host.split('.').collect();
+        let tld = labels.last().copied().unwrap_or("");
+        if SUSPICIOUS_TLDS.contains(&tld) {
+            found.push((UrlSignal::SuspiciousTld, format!("top-level domain .{} is frequently abused", tld)));
+        }
+        if labels.len() > MAX_SUBDOMAIN_LABELS + 1 {
+            found.push((UrlSignal::ManySubdomains, format!("host has {} labels", labels.len())));
+        }
+    }
+
+    if let Some(userinfo) = &url.userinfo {
+        found.push((UrlSignal::UserinfoTrick, format!("\"{}@\" precedes the real host {}", userinfo, url.host)));
+    }
+
+    let path_and_query = match &url.query {
+        Some(query) => format!("{}?{}", url.path, query),
+        None => url.path.clone(),
+    };
+    if path_and_query.len() >= MIN_ENTROPY_LEN {
+        let entropy = shannon_entropy(path_and_query.as_bytes());
+        if entropy > HIGH_ENTROPY {
+            found.push((UrlSignal::HighPathEntropy, format!("path looks random ({:.1} bits per character)", entropy)));
+        }
+    }
+
+    let lowered = path_and_query.to_ascii_lowercase();
+    if let Some(kit) = PHISHING_KIT_PATHS.iter().find(|kit| lowered.contains(*kit)) {
+        found.push((UrlSignal::PhishingKitPath, format!("path contains phishing kit marker \"{}\"", kit)));
+    }
+
+    let redirect = url.query_pairs().into_iter().find_map(|(name, value)| {
+        let target = Url::parse(&value)?;
+        let param = name.to_ascii_lowercase();
+        (REDIRECT_PARAMS.contains(&param.as_str()) && target.host != url.host).then_some((param, target.host))
+    });
+    if let Some((param, host)) = redirect {
+        found.push((UrlSignal::OpenRedirect, format!("parameter \"{}\" redirects to {}", param, host)));
+    }
+
+    if url.port.is_some_and(|p| !matches!(p, 80 | 443 | 8080)) {
+        found.push((UrlSignal::NonStandardPort, format!("uses port {}", url.port.unwrap_or_default())));
+    }
+
+    if found.is_empty() {
+        return UrlAnalysis::clean(text);
+    }
+
+    let confidence = 1.0 - found.iter().map(|(s, _)| 1.0 - s.weight()).product::<f32>();
+    let signals: Vec<UrlSignal> = found.iter().map(|(s, _)| *s).collect();
+    let threat_type = if signals.iter().any(|s| matches!(s, UrlSignal::PhishingKitPath | UrlSignal::UserinfoTrick)) {
+        "phishing"
+    } else if signals.contains(&UrlSignal::OpenRedirect) {
+        "open_redirect"
+    } else {
+        "suspicious_url"
+    };
+
+    UrlAnalysis {
+        url: text,
+        malicious: confidence >= MALICIOUS_THRESHOLD,
+        threat_type: threat_type.to_string(),
+        confidence,
+        signals,
+        explanation: found.into_iter().map(|(_, why)| why).collect(),
+    }
+}
+
+/// Score the URL of a raw HTTP/1.x request
+pub fn analyze_request(request: &HttpRequest) -> UrlAnalysis {
+    match Url::from_request(request) {
+        Some(url) => analyze_url(&url),
+        None => UrlAnalysis::clean(request.uri.clone()),
+    }
+}
+
+// IPv4 written as a single decimal/hex/octal number or with hex/octal parts (http://0xC0A80001/)
+fn obfuscated_ipv4(host: &str) -> Option<Ipv4Addr> {
+    let parts: Vec<&str> = host.split('.').collect();
+    if parts.is_empty() || parts.len() > 4 {
+        return None;
+    }
+    let numbers: Option<Vec<u64>> = parts
+        .iter()
+        .map(|part| {
+            if let Some(hex) = part.strip_prefix("0x") {
+                u64::from_str_radix(hex, 16).ok()
+            } else if part.len() > 1 && part.starts_with('0') {
+                u64::from_str_radix(part, 8).ok()
+            } else {
+                part.parse().ok()
+            }
+        })
+        .collect();
+    let numbers = numbers?;
+    // Last part fills the remaining bytes, as inet_aton does
+    let (last, leading) = numbers.split_last()?;
+    if leading.iter().any(|&n| n > 255) || *last >= 1u64 << (8 * (4 - leading.len())) {
+        return None;
+    }
+    let value = leading.iter().enumerate().fold(*last, |acc, (i, &n)| acc | n << (8 * (3 - i)));
+    Some(Ipv4Addr::from(value as u32))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = match bytes[i] {
+            b'%' => bytes.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok()),
+            _ => None,
+        };
+        match (decoded, bytes[i]) {
+            (Some(b), _) => {
+                out.push(b);
+                i += 3;
+            }
+            (None, b'+') => {
+                out.push(b' ');
+                i += 1;
+            }
+            (None, b) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_parsing() {
+        let url = Url::parse("HTTPS://user:pw@Example.COM.:8443/a/b?x=1&y=%2F#top").unwrap();
+        assert_eq!(url.scheme, "https");
+        assert_eq!(url.userinfo.as_deref(), Some("user:pw"));
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.port, Some(8443));
+        assert_eq!(url.path, "/a/b");
+        assert_eq!(url.query_pairs()[1], ("y".to_string(), "/".to_string()));
+        assert_eq!(url.fragment.as_deref(), Some("top"));
+
+        let v6 = Url::parse("http://[2001:db8::1]/").unwrap();
+        assert_eq!((v6.host.as_str(), v6.port), ("2001:db8::1", None));
+        assert!(Url::parse("example.com/path").is_none());
+        assert!(Url::parse("http://host:99999/").is_none());
+
+        assert_eq!(obfuscated_ipv4("3232235777"), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(obfuscated_ipv4("0xc0.0250.1.1"), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(obfuscated_ipv4("example.com"), None);
+    }
+
+    #[test]
+    fn test_phishing_urls_score_high_with_explanations() {
+        let url = Url::parse("http://paypal.com-account@192.168.1.10:8443/cgi-bin/webscr?cmd=_login-submit").unwrap();
+        let analysis = analyze_url(&url);
+        assert!(analysis.malicious);
+        assert_eq!(analysis.threat_type, "phishing");
+        assert!(analysis.signals.contains(&UrlSignal::UserinfoTrick));
+        assert!(analysis.signals.contains(&UrlSignal::IpLiteralHost));
+        assert_eq!(analysis.explanation.len(), analysis.signals.len());
+
+        let redirect = analyze_url(&Url::parse("https://shop.example/out?url=https%3A%2F%2Flogin.evil.zip%2F").unwrap());
+        assert_eq!(redirect.threat_type, "open_redirect");
+        assert!(!redirect.malicious);
+        assert!(redirect.explanation[0].contains("login.evil.zip"));
+
+        let obfuscated = analyze_url(&Url::parse("http://0xC0A8010A/secure-update/index.html").unwrap());
+        assert!(obfuscated.signals.contains(&UrlSignal::ObfuscatedIpHost));
+
+        let clean = analyze_url(&Url::parse("https://www.example.com/index.html?page=2").unwrap());
+        assert!(!clean.malicious);
+        assert_eq!(clean.threat_type, "none");
+        assert_eq!(clean.confidence, 0.0);
+    }
+}
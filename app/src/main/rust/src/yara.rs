@@ -0,0 +1,1172 @@
+use std::fmt;
+
+use ahash::AHashMap;
+use regex::bytes::{Regex, RegexBuilder};
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+
+// Matches kept per string; YARA has a similar cap so a string like `00 00` can't exhaust memory
+const MAX_MATCHES_PER_STRING: usize = 10_000;
+// Compiled size limit for any one string's regex
+const REGEX_SIZE_LIMIT: usize = 4 << 20;
+
+/// A rule source error with the line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T, CompileError> {
+    Err(CompileError { line, message: message.into() })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    // `$name`, `$` for anonymous strings, `$name*` for a set wildcard
+    StringId(String),
+    Count(String),
+    Offset(String),
+    Length(String),
+    Text(Vec<u8>),
+    Regex(String, String),
+    Hex(String),
+    Int(i64),
+    Punct(&'static str),
+    Eof,
+}
+
+// Longest first, so `<<` wins over `<`
+const PUNCTUATION: [&str; 27] = [
+    "..", "==", "!=", "<=", ">=", "<<", ">>", "{", "}", "(", ")", "[", "]", ":", "=", ",", "<", ">", "+", "-",
+    "*", "\\", "%", "&", "|", "^", "~",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, CompileError> {
+    let bytes = source.as_bytes();
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let ident_end = |from: usize| from + bytes[from..].iter().take_while(|&&b| is_ident(b)).count();
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        let start_line = line;
+        match b {
+            b'\n' => {
+                line += 1;
+                i += 1;
+                continue;
+            }
+            _ if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i += bytes[i..].iter().take_while(|&&b| b != b'\n').count();
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let Some(len) = source[i + 2..].find("*/") else { return error(line, "unterminated comment") };
+                line += source[i..i + 2 + len].matches('\n').count();
+                i += len + 4;
+                continue;
+            }
+            _ => {}
+        }
+
+        // A hex string is the value of a string definition: `$id = { ... }`
+        let after_definition = matches!(
+            tokens.as_slice(),
+            [.., (Token::StringId(_), _), (Token::Punct("="), _)]
+        );
+        let token = if b == b'{' && after_definition {
+            let Some(len) = source[i..].find('}') else { return error(line, "unterminated hex string") };
+            let body = &source[i + 1..i + len];
+            line += body.matches('\n').count();
+            i += len + 1;
+            Token::Hex(body.to_string())
+        } else if b == b'"' {
+            let mut text = Vec::new();
+            i += 1;
+            loop {
+                match bytes.get(i) {
+                    None | Some(b'\n') => return error(line, "unterminated string"),
+                    Some(b'"') => break,
+                    Some(b'\\') => {
+                        let escaped = match bytes.get(i + 1) {
+                            Some(b'n') => b'\n',
+                            Some(b't') => b'\t',
+                            Some(b'r') => b'\r',
+                            Some(b'"') => b'"',
+                            Some(b'\\') => b'\\',
+                            Some(b'x') => {
+                                let hex = source.get(i + 2..i + 4).and_then(|h| u8::from_str_radix(h, 16).ok());
+                                let Some(value) = hex else { return error(line, "invalid \\x escape") };
+                                i += 2;
+                                value
+                            }
+                            _ => return error(line, "invalid escape sequence"),
+                        };
+                        text.push(escaped);
+                        i += 2;
+                    }
+                    Some(&c) => {
+                        text.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            Token::Text(text)
+        } else if b == b'/' {
+            let mut pattern = String::new();
+            i += 1;
+            loop {
+                match bytes.get(i) {
+                    None | Some(b'\n') => return error(line, "unterminated regular expression"),
+                    Some(b'/') => break,
+                    Some(b'\\') if bytes.get(i + 1) == Some(&b'/') => {
+                        pattern.push('/');
+                        i += 2;
+                    }
+                    Some(b'\\') => {
+                        pattern.push_str(source.get(i..i + 2).unwrap_or("\\"));
+                        i += 2;
+                    }
+                    Some(_) => {
+                        let c = source[i..].chars().next().unwrap();
+                        pattern.push(c);
+                        i += c.len_utf8();
+                    }
+                }
+            }
+            i += 1;
+            let flags_len = bytes[i..].iter().take_while(|&&b| b == b'i' || b == b's').count();
+            let flags = source[i..i + flags_len].to_string();
+            i += flags_len;
+            Token::Regex(pattern, flags)
+        } else if matches!(b, b'$' | b'#' | b'@') || (b == b'!' && bytes.get(i + 1).is_some_and(|&c| is_ident(c))) {
+            let end = ident_end(i + 1);
+            let name = source[i + 1..end].to_string();
+            i = end;
+            match b {
+                b'$' if bytes.get(i) == Some(&b'*') => {
+                    i += 1;
+                    Token::StringId(format!("{name}*"))
+                }
+                b'$' => Token::StringId(name),
+                b'#' => Token::Count(name),
+                b'@' => Token::Offset(name),
+                _ => Token::Length(name),
+            }
+        } else if b.is_ascii_digit() {
+            let end = ident_end(i);
+            let literal = &source[i..end];
+            i = end;
+            let (digits, multiplier) = match literal {
+                l if l.ends_with("KB") => (&l[..l.len() - 2], 1024),
+                l if l.ends_with("MB") => (&l[..l.len() - 2], 1024 * 1024),
+                l => (l, 1),
+            };
+            let value = if let Some(hex) = digits.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(octal) = digits.strip_prefix("0o") {
+                i64::from_str_radix(octal, 8)
+            } else {
+                digits.parse()
+            };
+            match value.ok().and_then(|v| v.checked_mul(multiplier)) {
+                Some(v) => Token::Int(v),
+                None => return error(line, format!("invalid number {literal}")),
+            }
+        } else if is_ident(b) {
+            let end = ident_end(i);
+            let ident = source[i..end].to_string();
+            i = end;
+            Token::Ident(ident)
+        } else if let Some(punct) = PUNCTUATION.iter().find(|p| source[i..].starts_with(**p)) {
+            i += punct.len();
+            Token::Punct(punct)
+        } else {
+            return error(line, format!("unexpected character '{}'", source[i..].chars().next().unwrap()));
+        };
+        tokens.push((token, start_line));
+    }
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+/// A `meta:` value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum MetaValue {
+    Text(String),
+    Int(i64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Quantifier {
+    All,
+    Any,
+    None,
+    AtLeast(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Bool(bool),
+    Int(i64),
+    Filesize,
+    Found(usize),
+    FoundAt(usize, Box<Expr>),
+    FoundIn(usize, Box<Expr>, Box<Expr>),
+    Count(usize),
+    // 1-based match index
+    Offset(usize, Box<Expr>),
+    Length(usize, Box<Expr>),
+    Read { width: usize, signed: bool, big_endian: bool, at: Box<Expr> },
+    Rule(usize),
+    Of(Quantifier, Vec<usize>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    BitNot(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+struct CompiledString {
+    identifier: String,
+    regex: Regex,
+    fullword: bool,
+    private: bool,
+    // Length of a text string in ASCII form, to tell wide matches apart for `fullword`
+    text_len: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    tags: Vec<String>,
+    meta: Vec<(String, MetaValue)>,
+    private: bool,
+    global: bool,
+    strings: Vec<CompiledString>,
+    condition: Expr,
+}
+
+/// One string hit inside a matching rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StringHit {
+    pub identifier: String,
+    pub offset: u64,
+    pub length: usize,
+}
+
+/// A rule whose condition held, with its metadata and the string hits behind it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleMatch {
+    pub rule: String,
+    pub tags: Vec<String>,
+    pub meta: Map<String, JsonValue>,
+    pub strings: Vec<StringHit>,
+}
+
+/// A compiled set of YARA rules (text, hex and regex strings; no modules)
+#[derive(Debug)]
+pub struct YaraRules {
+    rules: Vec<Rule>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    rule_names: AHashMap<String, usize>,
+    // Strings of the rule being parsed and whether the condition uses them
+    strings: Vec<(String, bool)>,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Token::Punct(p) if *p == punct) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Token::Ident(k) if k == keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), CompileError> {
+        if self.eat_punct(punct) {
+            return Ok(());
+        }
+        error(self.line(), format!("expected '{punct}', found {}", describe(self.peek())))
+    }
+
+    fn expect_ident(&mut self) -> Result<String, CompileError> {
+        match self.next() {
+            Token::Ident(name) => Ok(name),
+            other => error(self.tokens[self.pos - 1].1, format!("expected identifier, found {}", describe(&other))),
+        }
+    }
+
+    fn parse_rules(&mut self) -> Result<Vec<Rule>, CompileError> {
+        let mut rules = Vec::new();
+        while *self.peek() != Token::Eof {
+            if self.eat_keyword("import") || self.eat_keyword("include") {
+                let line = self.line();
+                let what = match self.next() {
+                    Token::Text(text) => String::from_utf8_lossy(&text).into_owned(),
+                    _ => String::new(),
+                };
+                return error(line, format!("modules and includes are not supported (\"{what}\")"));
+            }
+            let rule = self.parse_rule()?;
+            self.rule_names.insert(rule.name.clone(), rules.len());
+            rules.push(rule);
+        }
+        Ok(rules)
+    }
+
+    fn parse_rule(&mut self) -> Result<Rule, CompileError> {
+        let (mut private, mut global) = (false, false);
+        loop {
+            if self.eat_keyword("private") {
+                private = true;
+            } else if self.eat_keyword("global") {
+                global = true;
+            } else {
+                break;
+            }
+        }
+        if !self.eat_keyword("rule") {
+            return error(self.line(), format!("expected 'rule', found {}", describe(self.peek())));
+        }
+        let line = self.line();
+        let name = self.expect_ident()?;
+        if self.rule_names.contains_key(&name) {
+            return error(line, format!("duplicate rule '{name}'"));
+        }
+        let mut tags = Vec::new();
+        if self.eat_punct(":") {
+            while let Token::Ident(tag) = self.peek().clone() {
+                self.pos += 1;
+                tags.push(tag);
+            }
+        }
+        self.expect_punct("{")?;
+
+        let mut meta = Vec::new();
+        if self.eat_keyword("meta") {
+            self.expect_punct(":")?;
+            while matches!(self.peek(), Token::Ident(k) if k != "strings" && k != "condition") {
+                let key = self.expect_ident()?;
+                self.expect_punct("=")?;
+                let value = match self.next() {
+                    Token::Text(text) => MetaValue::Text(String::from_utf8_lossy(&text).into_owned()),
+                    Token::Int(n) => MetaValue::Int(n),
+                    Token::Punct("-") => match self.next() {
+                        Token::Int(n) => MetaValue::Int(-n),
+                        _ => return error(self.line(), "expected number after '-'"),
+                    },
+                    Token::Ident(b) if b == "true" || b == "false" => MetaValue::Bool(b == "true"),
+                    other => return error(self.tokens[self.pos - 1].1, format!("invalid meta value {}", describe(&other))),
+                };
+                meta.push((key, value));
+            }
+        }
+
+        self.strings.clear();
+        let mut strings = Vec::new();
+        if self.eat_keyword("strings") {
+            self.expect_punct(":")?;
+            while let Token::StringId(id) = self.peek().clone() {
+                let line = self.line();
+                self.pos += 1;
+                if id.ends_with('*') {
+                    return error(line, format!("invalid string identifier ${id}"));
+                }
+                if !id.is_empty() && self.strings.iter().any(|(existing, _)| *existing == id) {
+                    return error(line, format!("duplicate string identifier ${id}"));
+                }
+                self.expect_punct("=")?;
+                let string = self.parse_string(&id, line)?;
+                // Anonymous strings can only be used through `them`, so they never count as unused
+                self.strings.push((id.clone(), id.is_empty()));
+                strings.push(string);
+            }
+        }
+
+        if !self.eat_keyword("condition") {
+            return error(self.line(), format!("expected 'condition', found {}", describe(self.peek())));
+        }
+        self.expect_punct(":")?;
+        let condition = self.parse_expr()?;
+        self.expect_punct("}")?;
+        if let Some((id, _)) = self.strings.iter().find(|(_, used)| !used) {
+            return error(line, format!("unreferenced string ${id} in rule '{name}'"));
+        }
+        Ok(Rule { name, tags, meta, private, global, strings, condition })
+    }
+
+    fn parse_string(&mut self, id: &str, line: usize) -> Result<CompiledString, CompileError> {
+        let value = self.next();
+        let mut modifiers: Vec<String> = Vec::new();
+        while let Token::Ident(modifier) = self.peek().clone() {
+            if !["nocase", "wide", "ascii", "fullword", "private"].contains(&modifier.as_str()) {
+                if matches!(modifier.as_str(), "xor" | "base64" | "base64wide") {
+                    return error(self.line(), format!("modifier '{modifier}' is not supported"));
+                }
+                // Not a modifier: the next section starts here
+                break;
+            }
+            self.pos += 1;
+            modifiers.push(modifier);
+        }
+        let has = |m: &str| modifiers.iter().any(|x| x == m);
+        let (nocase, wide, fullword, private) = (has("nocase"), has("wide"), has("fullword"), has("private"));
+        let ascii = has("ascii") || !wide;
+
+        let (pattern, text_len) = match value {
+            Token::Text(text) => {
+                if text.is_empty() {
+                    return error(line, format!("empty string ${id}"));
+                }
+                let encode = |wide: bool| {
+                    let mut out = String::new();
+                    for &b in &text {
+                        let byte = format!("\\x{b:02x}");
+                        if nocase && b.is_ascii_alphabetic() {
+                            out.push_str(&format!("(?i:{byte})"));
+                        } else {
+                            out.push_str(&byte);
+                        }
+                        if wide {
+                            out.push_str("\\x00");
+                        }
+                    }
+                    out
+                };
+                let pattern = match (ascii, wide) {
+                    (true, true) => format!("(?:{}|{})", encode(true), encode(false)),
+                    (_, wide) => encode(wide),
+                };
+                (pattern, Some(text.len()))
+            }
+            Token::Hex(body) => {
+                if nocase || wide || has("ascii") || fullword {
+                    return error(line, format!("hex string ${id} only accepts the 'private' modifier"));
+                }
+                (hex_to_regex(&body).map_err(|message| CompileError { line, message })?, None)
+            }
+            Token::Regex(regex, flags) => {
+                if wide {
+                    return error(line, format!("'wide' is not supported on regular expression ${id}"));
+                }
+                let mut prefix = String::from("(?");
+                if nocase || flags.contains('i') {
+                    prefix.push('i');
+                }
+                if flags.contains('s') {
+                    prefix.push('s');
+                }
+                (format!("{prefix}-u:{regex})"), None)
+            }
+            other => return error(line, format!("expected string value for ${id}, found {}", describe(&other))),
+        };
+        let regex = RegexBuilder::new(&format!("(?-u){pattern}"))
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| CompileError { line, message: format!("invalid string ${id}: {e}") })?;
+        let identifier = if id.is_empty() { "$".to_string() } else { format!("${id}") };
+        Ok(CompiledString { identifier, regex, fullword, private, text_len })
+    }
+
+    // Index of a string named in the condition; marks it used
+    fn string_ref(&mut self, name: &str, line: usize) -> Result<usize, CompileError> {
+        match self.strings.iter().position(|(id, _)| id == name && !id.is_empty()) {
+            Some(index) => {
+                self.strings[index].1 = true;
+                Ok(index)
+            }
+            None => error(line, format!("undefined string ${name}")),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, CompileError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, CompileError> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, CompileError> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, CompileError> {
+        let left = self.parse_binary(0)?;
+        let op = match self.peek() {
+            Token::Punct("==") => BinaryOp::Eq,
+            Token::Punct("!=") => BinaryOp::Ne,
+            Token::Punct("<") => BinaryOp::Lt,
+            Token::Punct("<=") => BinaryOp::Le,
+            Token::Punct(">") => BinaryOp::Gt,
+            Token::Punct(">=") => BinaryOp::Ge,
+            Token::Ident(k) if matches!(k.as_str(), "contains" | "matches" | "startswith" | "endswith") => {
+                return error(self.line(), format!("operator '{k}' is not supported"));
+            }
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(self.parse_binary(0)?)))
+    }
+
+    // Integer operators by precedence level, loosest first
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+            &[("|", BinaryOp::BitOr)],
+            &[("^", BinaryOp::BitXor)],
+            &[("&", BinaryOp::BitAnd)],
+            &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[("*", BinaryOp::Mul), ("\\", BinaryOp::Div), ("%", BinaryOp::Mod)],
+        ];
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+        let mut left = self.parse_binary(level + 1)?;
+        'outer: loop {
+            for (punct, op) in LEVELS[level] {
+                if self.eat_punct(punct) {
+                    left = Expr::Binary(*op, Box::new(left), Box::new(self.parse_binary(level + 1)?));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat_punct("-") {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        if self.eat_punct("~") {
+            return Ok(Expr::BitNot(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    // Optional `[index]` after `@a` / `!a`, defaulting to the first match
+    fn parse_index(&mut self) -> Result<Box<Expr>, CompileError> {
+        if self.eat_punct("[") {
+            let index = self.parse_expr()?;
+            self.expect_punct("]")?;
+            return Ok(Box::new(index));
+        }
+        Ok(Box::new(Expr::Int(1)))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        match self.next() {
+            Token::Int(n) => {
+                if self.eat_keyword("of") {
+                    return self.parse_of(Quantifier::AtLeast(Box::new(Expr::Int(n))));
+                }
+                Ok(Expr::Int(n))
+            }
+            Token::Punct("(") => {
+                let inner = self.parse_expr()?;
+                self.expect_punct(")")?;
+                Ok(inner)
+            }
+            Token::StringId(name) if name.is_empty() || name.ends_with('*') => {
+                error(line, format!("${name} can only be used in a string set"))
+            }
+            Token::StringId(name) => {
+                let index = self.string_ref(&name, line)?;
+                if self.eat_keyword("at") {
+                    return Ok(Expr::FoundAt(index, Box::new(self.parse_binary(0)?)));
+                }
+                if self.eat_keyword("in") {
+                    self.expect_punct("(")?;
+                    let low = self.parse_binary(0)?;
+                    self.expect_punct("..")?;
+                    let high = self.parse_binary(0)?;
+                    self.expect_punct(")")?;
+                    return Ok(Expr::FoundIn(index, Box::new(low), Box::new(high)));
+                }
+                Ok(Expr::Found(index))
+            }
+            Token::Count(name) => Ok(Expr::Count(self.string_ref(&name, line)?)),
+            Token::Offset(name) => {
+                let index = self.string_ref(&name, line)?;
+                Ok(Expr::Offset(index, self.parse_index()?))
+            }
+            Token::Length(name) => {
+                let index = self.string_ref(&name, line)?;
+                Ok(Expr::Length(index, self.parse_index()?))
+            }
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "filesize" => Ok(Expr::Filesize),
+                "all" | "any" | "none" => {
+                    if !self.eat_keyword("of") {
+                        return error(self.line(), format!("expected 'of' after '{ident}'"));
+                    }
+                    let quantifier = match ident.as_str() {
+                        "all" => Quantifier::All,
+                        "any" => Quantifier::Any,
+                        _ => Quantifier::None,
+                    };
+                    self.parse_of(quantifier)
+                }
+                "for" | "entrypoint" => error(line, format!("'{ident}' is not supported")),
+                _ => {
+                    if let Some((width, signed, big_endian)) = read_function(&ident) {
+                        self.expect_punct("(")?;
+                        let at = self.parse_binary(0)?;
+                        self.expect_punct(")")?;
+                        return Ok(Expr::Read { width, signed, big_endian, at: Box::new(at) });
+                    }
+                    match self.rule_names.get(&ident) {
+                        Some(&rule) => Ok(Expr::Rule(rule)),
+                        None => error(line, format!("undefined identifier '{ident}'")),
+                    }
+                }
+            },
+            other => error(line, format!("unexpected {} in condition", describe(&other))),
+        }
+    }
+
+    // `them` or `($a, $b*, ...)`
+    fn parse_of(&mut self, quantifier: Quantifier) -> Result<Expr, CompileError> {
+        let line = self.line();
+        if self.eat_keyword("them") {
+            if self.strings.is_empty() {
+                return error(line, "'them' used in a rule without strings");
+            }
+            self.strings.iter_mut().for_each(|(_, used)| *used = true);
+            return Ok(Expr::Of(quantifier, (0..self.strings.len()).collect()));
+        }
+        self.expect_punct("(")?;
+        let mut set = Vec::new();
+        loop {
+            let line = self.line();
+            let Token::StringId(name) = self.next() else { return error(line, "expected string identifier in set") };
+            if let Some(prefix) = name.strip_suffix('*') {
+                let before = set.len();
+                for (i, (id, used)) in self.strings.iter_mut().enumerate() {
+                    if !id.is_empty() && id.starts_with(prefix) {
+                        *used = true;
+                        set.push(i);
+                    }
+                }
+                if set.len() == before {
+                    return error(line, format!("no strings match ${name}"));
+                }
+            } else {
+                set.push(self.string_ref(&name, line)?);
+            }
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(")")?;
+        Ok(Expr::Of(quantifier, set))
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(s) => format!("'{s}'"),
+        Token::StringId(s) => format!("'${s}'"),
+        Token::Count(s) => format!("'#{s}'"),
+        Token::Offset(s) => format!("'@{s}'"),
+        Token::Length(s) => format!("'!{s}'"),
+        Token::Text(_) => "text string".to_string(),
+        Token::Regex(..) => "regular expression".to_string(),
+        Token::Hex(_) => "hex string".to_string(),
+        Token::Int(n) => format!("'{n}'"),
+        Token::Punct(p) => format!("'{p}'"),
+        Token::Eof => "end of input".to_string(),
+    }
+}
+
+// uint8/16/32, int8/16/32 and their big-endian `be` forms: (width, signed, big endian)
+fn read_function(name: &str) -> Option<(usize, bool, bool)> {
+    let (signed, rest) = match name.strip_prefix("uint") {
+        Some(rest) => (false, rest),
+        None => (true, name.strip_prefix("int")?),
+    };
+    let (bits, big_endian) = match rest.strip_suffix("be") {
+        Some(bits) => (bits, true),
+        None => (rest, false),
+    };
+    let width = match bits {
+        "8" if !big_endian => 1,
+        "16" => 2,
+        "32" => 4,
+        _ => return None,
+    };
+    Some((width, signed, big_endian))
+}
+
+// Translate a hex string body (`4D 5A ?? [2-4] (01 | 02 03) ?F`) into a bytes regex
+fn hex_to_regex(body: &str) -> Result<String, String> {
+    let chars: Vec<char> = body.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut out = String::new();
+    let mut depth = 0;
+    let mut bytes = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '(' => {
+                depth += 1;
+                out.push_str("(?:");
+                i += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                out.push(')');
+                i += 1;
+            }
+            '|' if depth > 0 => {
+                out.push('|');
+                i += 1;
+            }
+            '[' => {
+                let close = chars[i..].iter().position(|&c| c == ']').ok_or("unterminated jump")? + i;
+                let spec: String = chars[i + 1..close].iter().collect();
+                let (min, max) = match spec.split_once('-') {
+                    None => (spec.as_str(), spec.as_str()),
+                    Some((min, max)) => (min, max),
+                };
+                let min: usize = if min.is_empty() { 0 } else { min.parse().map_err(|_| format!("invalid jump [{spec}]"))? };
+                let max: Option<usize> = if max.is_empty() { None } else { Some(max.parse().map_err(|_| format!("invalid jump [{spec}]"))?) };
+                if max.is_some_and(|max| max < min) {
+                    return Err(format!("invalid jump [{spec}]"));
+                }
+                match max {
+                    Some(max) => out.push_str(&format!("(?s:.){{{min},{max}}}")),
+                    None => out.push_str(&format!("(?s:.){{{min},}}")),
+                }
+                i = close + 1;
+            }
+            high => {
+                let low = *chars.get(i + 1).ok_or("odd number of hex digits")?;
+                let nibble = |c: char| if c == '?' { Ok(None) } else { c.to_digit(16).map(Some).ok_or(format!("invalid hex digit '{c}'")) };
+                match (nibble(high)?, nibble(low)?) {
+                    (Some(h), Some(l)) => out.push_str(&format!("\\x{:02x}", h << 4 | l)),
+                    (None, None) => out.push_str("(?s:.)"),
+                    (Some(h), None) => out.push_str(&format!("[\\x{:02x}-\\x{:02x}]", h << 4, h << 4 | 0xF)),
+                    (None, Some(l)) => {
+                        out.push('[');
+                        for h in 0..16 {
+                            out.push_str(&format!("\\x{:02x}", h << 4 | l));
+                        }
+                        out.push(']');
+                    }
+                }
+                bytes += 1;
+                i += 2;
+            }
+        }
+    }
+    if depth != 0 {
+        return Err("unbalanced parentheses in hex string".to_string());
+    }
+    if bytes == 0 {
+        return Err("empty hex string".to_string());
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Int(i64),
+    Bool(bool),
+    Undefined,
+}
+
+impl Value {
+    fn truthy(self) -> bool {
+        match self {
+            Value::Bool(b) => b,
+            Value::Int(n) => n != 0,
+            Value::Undefined => false,
+        }
+    }
+
+    fn int(self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(n),
+            Value::Bool(b) => Some(b as i64),
+            Value::Undefined => None,
+        }
+    }
+}
+
+struct Scan<'a> {
+    data: &'a [u8],
+    filesize: Option<u64>,
+    // (offset, length) of each string's matches in the current rule
+    hits: Vec<Vec<(usize, usize)>>,
+    rule_results: &'a [bool],
+}
+
+impl Scan<'_> {
+    fn eval(&self, expr: &Expr) -> Value {
+        let int = |e: &Expr| self.eval(e).int();
+        match expr {
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Int(n) => Value::Int(*n),
+            Expr::Filesize => self.filesize.map_or(Value::Undefined, |size| Value::Int(size as i64)),
+            Expr::Found(s) => Value::Bool(!self.hits[*s].is_empty()),
+            Expr::FoundAt(s, at) => match int(at) {
+                Some(at) => Value::Bool(self.hits[*s].iter().any(|&(offset, _)| offset as i64 == at)),
+                None => Value::Undefined,
+            },
+            Expr::FoundIn(s, low, high) => match (int(low), int(high)) {
+                (Some(low), Some(high)) => {
+                    Value::Bool(self.hits[*s].iter().any(|&(offset, _)| (low..=high).contains(&(offset as i64))))
+                }
+                _ => Value::Undefined,
+            },
+            Expr::Count(s) => Value::Int(self.hits[*s].len() as i64),
+            Expr::Offset(s, index) | Expr::Length(s, index) => {
+                let hit = int(index)
+                    .filter(|&i| i >= 1)
+                    .and_then(|i| self.hits[*s].get(i as usize - 1));
+                match (hit, expr) {
+                    (Some(&(offset, _)), Expr::Offset(..)) => Value::Int(offset as i64),
+                    (Some(&(_, length)), _) => Value::Int(length as i64),
+                    (None, _) => Value::Undefined,
+                }
+            }
+            Expr::Read { width, signed, big_endian, at } => {
+                let bytes = int(at)
+                    .and_then(|at| usize::try_from(at).ok())
+                    .and_then(|at| self.data.get(at..at.checked_add(*width)?));
+                let Some(bytes) = bytes else { return Value::Undefined };
+                let mut raw = [0u8; 8];
+                let value = if *big_endian {
+                    raw[8 - width..].copy_from_slice(bytes);
+                    u64::from_be_bytes(raw)
+                } else {
+                    raw[..*width].copy_from_slice(bytes);
+                    u64::from_le_bytes(raw)
+                };
+                let bits = *width as u32 * 8;
+                if *signed {
+                    // Sign-extend from the read width
+                    Value::Int(((value << (64 - bits)) as i64) >> (64 - bits))
+                } else {
+                    Value::Int(value as i64)
+                }
+            }
+            Expr::Rule(rule) => Value::Bool(self.rule_results[*rule]),
+            Expr::Of(quantifier, set) => {
+                let found = set.iter().filter(|&&s| !self.hits[s].is_empty()).count();
+                let holds = match quantifier {
+                    Quantifier::All => found == set.len(),
+                    Quantifier::Any => found > 0,
+                    Quantifier::None => found == 0,
+                    Quantifier::AtLeast(n) => match int(n) {
+                        Some(n) => found as i64 >= n,
+                        None => return Value::Undefined,
+                    },
+                };
+                Value::Bool(holds)
+            }
+            Expr::Not(inner) => match self.eval(inner) {
+                Value::Undefined => Value::Undefined,
+                value => Value::Bool(!value.truthy()),
+            },
+            Expr::Neg(inner) => int(inner).map_or(Value::Undefined, |n| Value::Int(n.wrapping_neg())),
+            Expr::BitNot(inner) => int(inner).map_or(Value::Undefined, |n| Value::Int(!n)),
+            Expr::Binary(op, left, right) => self.binary(*op, left, right),
+        }
+    }
+
+    fn binary(&self, op: BinaryOp, left: &Expr, right: &Expr) -> Value {
+        match op {
+            BinaryOp::And => return Value::Bool(self.eval(left).truthy() && self.eval(right).truthy()),
+            BinaryOp::Or => return Value::Bool(self.eval(left).truthy() || self.eval(right).truthy()),
+            _ => {}
+        }
+        let (Some(a), Some(b)) = (self.eval(left).int(), self.eval(right).int()) else { return Value::Undefined };
+        let value = match op {
+            BinaryOp::Eq => return Value::Bool(a == b),
+            BinaryOp::Ne => return Value::Bool(a != b),
+            BinaryOp::Lt => return Value::Bool(a < b),
+            BinaryOp::Le => return Value::Bool(a <= b),
+            BinaryOp::Gt => return Value::Bool(a > b),
+            BinaryOp::Ge => return Value::Bool(a >= b),
+            BinaryOp::BitOr => Some(a | b),
+            BinaryOp::BitXor => Some(a ^ b),
+            BinaryOp::BitAnd => Some(a & b),
+            BinaryOp::Shl => u32::try_from(b).ok().and_then(|b| a.checked_shl(b)),
+            BinaryOp::Shr => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)),
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div => a.checked_div(b),
+            BinaryOp::Mod => a.checked_rem(b),
+            BinaryOp::And | BinaryOp::Or => unreachable!(),
+        };
+        value.map_or(Value::Undefined, Value::Int)
+    }
+}
+
+impl CompiledString {
+    // Every match start (YARA reports overlapping matches), capped
+    fn find_all(&self, data: &[u8]) -> Vec<(usize, usize)> {
+        let mut hits = Vec::new();
+        let mut from = 0;
+        while hits.len() < MAX_MATCHES_PER_STRING {
+            let Some(found) = self.regex.find_at(data, from) else { break };
+            if !self.fullword || self.is_whole_word(data, found.start(), found.end()) {
+                hits.push((found.start(), found.len()));
+            }
+            from = found.start() + 1;
+            if from > data.len() {
+                break;
+            }
+        }
+        hits
+    }
+
+    fn is_whole_word(&self, data: &[u8], start: usize, end: usize) -> bool {
+        let wide = self.text_len.is_some_and(|len| end - start == len * 2);
+        let step = if wide { 2 } else { 1 };
+        let before = start.checked_sub(step).map(|i| data[i]);
+        let after = data.get(end).copied();
+        !before.is_some_and(|b| b.is_ascii_alphanumeric()) && !after.is_some_and(|b| b.is_ascii_alphanumeric())
+    }
+}
+
+impl YaraRules {
+    /// Compile rule source; the first error is reported with its line number
+    pub fn compile(source: &str) -> Result<YaraRules, CompileError> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0, rule_names: AHashMap::new(), strings: Vec::new() };
+        Ok(YaraRules { rules: parser.parse_rules()? })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Evaluate every rule against `data`. `filesize` is None for memory, where conditions on it
+    /// are undefined; hit offsets are relative to `base`, e.g. the address a region was read from.
+    pub fn scan(&self, data: &[u8], filesize: Option<u64>, base: u64) -> Vec<RuleMatch> {
+        let mut results = vec![false; self.rules.len()];
+        let mut matches = Vec::new();
+        // Global rules run first: if any fails, nothing matches
+        let order = self.rules.iter().enumerate().filter(|(_, r)| r.global).chain(self.rules.iter().enumerate().filter(|(_, r)| !r.global));
+        for (index, rule) in order {
+            let hits: Vec<_> = rule.strings.iter().map(|s| s.find_all(data)).collect();
+            let scan = Scan { data, filesize, hits, rule_results: &results };
+            let matched = scan.eval(&rule.condition).truthy();
+            if !matched && rule.global {
+                return Vec::new();
+            }
+            if matched && !rule.private {
+                let strings = rule
+                    .strings
+                    .iter()
+                    .zip(&scan.hits)
+                    .filter(|(s, _)| !s.private)
+                    .flat_map(|(s, hits)| {
+                        hits.iter().map(|&(offset, length)| StringHit {
+                            identifier: s.identifier.clone(),
+                            offset: base + offset as u64,
+                            length,
+                        })
+                    })
+                    .collect();
+                let meta = rule
+                    .meta
+                    .iter()
+                    .map(|(key, value)| (key.clone(), serde_json::to_value(value).unwrap_or(JsonValue::Null)))
+                    .collect();
+                matches.push((index, RuleMatch { rule: rule.name.clone(), tags: rule.tags.clone(), meta, strings }));
+            }
+            results[index] = matched;
+        }
+        matches.sort_by_key(|(index, _)| *index);
+        matches.into_iter().map(|(_, m)| m).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+// Sample rules in the subset the engine supports
+private rule IsPE {
+    condition:
+        uint16(0) == 0x5A4D and uint32(uint32(0x3C)) == 0x00004550
+}
+
+rule Dropper : trojan android {
+    meta:
+        author = "fortress"
+        severity = 8
+        active = true
+    strings:
+        $cmd = "powershell" nocase wide ascii
+        $hex = { 6A 40 68 00 30 00 00 [2-4] ( 6A 14 | 6A 15 ) ?F }
+        $url = /https?:\/\/[a-z0-9.]{4,32}\/gate\.php/i
+        $su = "su" fullword
+    condition:
+        IsPE and filesize < 10KB and all of ($cmd, $hex) and $url and #su >= 2 and @su[1] < @su[2]
+}
+
+rule AnyMarker {
+    strings:
+        $m1 = "MARK-A"
+        $m2 = "MARK-B"
+        $ = "MARK-C"
+    condition:
+        2 of them and not $m2 at 0
+}
+"#;
+
+    fn sample() -> Vec<u8> {
+        let mut data = vec![0u8; 0x100];
+        data[0] = b'M';
+        data[1] = b'Z';
+        data[0x3C] = 0x80;
+        data[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let mut tail = Vec::new();
+        tail.extend_from_slice("PowerShell".encode_utf16().flat_map(|u| u.to_le_bytes()).collect::<Vec<_>>().as_slice());
+        tail.extend_from_slice(&[0x6A, 0x40, 0x68, 0x00, 0x30, 0x00, 0x00, 1, 2, 3, 0x6A, 0x15, 0x3F]);
+        tail.extend_from_slice(b" HTTP://c2.example.net/gate.php ");
+        tail.extend_from_slice(b"su -c id; /system/xbin/su; sudo ");
+        tail.extend_from_slice(b"MARK-A MARK-C");
+        data.extend_from_slice(&tail);
+        data
+    }
+
+    #[test]
+    fn test_rules_match_files_and_memory() {
+        let rules = YaraRules::compile(RULES).unwrap();
+        assert_eq!(rules.len(), 3);
+        let data = sample();
+        let matches = rules.scan(&data, Some(data.len() as u64), 0);
+        let names: Vec<&str> = matches.iter().map(|m| m.rule.as_str()).collect();
+        assert_eq!(names, ["Dropper", "AnyMarker"]);
+
+        let dropper = &matches[0];
+        assert_eq!(dropper.tags, ["trojan", "android"]);
+        assert_eq!(dropper.meta["severity"], 8);
+        assert_eq!(dropper.meta["author"], "fortress");
+        let su: Vec<u64> = dropper.strings.iter().filter(|s| s.identifier == "$su").map(|s| s.offset).collect();
+        // "sudo" is not a whole word
+        assert_eq!(su.len(), 2);
+        assert_eq!(dropper.strings.iter().find(|s| s.identifier == "$cmd").unwrap().length, 20);
+
+        // Memory has no filesize: Dropper no longer holds, offsets become addresses
+        let memory = rules.scan(&data, None, 0x7000_0000);
+        assert_eq!(memory.len(), 1);
+        assert_eq!(memory[0].strings[0].offset, 0x7000_0000 + data.windows(6).position(|w| w == b"MARK-A").unwrap() as u64);
+
+        // Breaking the nibble-masked tail byte of the hex string breaks the rule
+        let mut broken = data.clone();
+        let at = broken.windows(2).position(|w| w == [0x15, 0x3F]).unwrap();
+        broken[at + 1] = 0x40;
+        assert_eq!(rules.scan(&broken, Some(broken.len() as u64), 0).len(), 1);
+    }
+
+    #[test]
+    fn test_compile_errors_have_line_numbers() {
+        let cases = [
+            ("rule a {\n condition:\n $x\n}", 3, "undefined string $x"),
+            ("rule a {\n strings:\n  $x = \"abc\"\n  $y = \"def\"\n condition:\n  $x\n}", 1, "unreferenced string $y in rule 'a'"),
+            ("rule a { condition: true }\nrule a { condition: true }", 2, "duplicate rule 'a'"),
+            ("import \"pe\"\nrule a { condition: true }", 1, "modules and includes are not supported (\"pe\")"),
+            ("rule a {\n strings:\n  $x = { 4D 5 }\n condition:\n  $x\n}", 3, "odd number of hex digits"),
+            ("rule a {\n strings:\n  $x = /ab(c/\n condition:\n  $x\n}", 3, "invalid string $x"),
+            ("rule a {\n condition:\n  uint16(0) ==\n}", 4, "unexpected '}' in condition"),
+        ];
+        for (source, line, message) in cases {
+            let err = YaraRules::compile(source).unwrap_err();
+            assert_eq!(err.line, line, "{source}: {err}");
+            assert!(err.message.starts_with(message), "{source}: {err}");
+        }
+    }
+}
@@ -0,0 +1,845 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use chrono::DateTime;
+
+use crate::dns::{self, DnsMessage};
+use crate::flow_table::{FlowKey, FlowRecord};
+use crate::http::{HttpRequest, HttpResponse};
+
+const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+const DEFAULT_ROTATE_INTERVAL_MS: u64 = 60 * 60 * 1000;
+const DEFAULT_MAX_ROTATED_FILES: usize = 8;
+
+// Queries without a response are logged unanswered after this long
+const DNS_TIMEOUT_MS: u64 = 10_000;
+const MAX_PENDING_DNS: usize = 1024;
+const MAX_PENDING_HTTP_PER_FLOW: usize = 16;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+const CONN_FIELDS: &[(&str, &str)] = &[
+    ("ts", "time"),
+    ("uid", "string"),
+    ("id.orig_h", "addr"),
+    ("id.orig_p", "port"),
+    ("id.resp_h", "addr"),
+    ("id.resp_p", "port"),
+    ("proto", "enum"),
+    ("service", "string"),
+    ("duration", "interval"),
+    ("orig_bytes", "count"),
+    ("resp_bytes", "count"),
+    ("conn_state", "string"),
+    ("history", "string"),
+    ("orig_pkts", "count"),
+    ("orig_ip_bytes", "count"),
+    ("resp_pkts", "count"),
+    ("resp_ip_bytes", "count"),
+    ("app_uid", "count"),
+    ("verdict", "count"),
+    ("resp_cc", "string"),
+    ("resp_asn", "count"),
+];
+
+const DNS_FIELDS: &[(&str, &str)] = &[
+    ("ts", "time"),
+    ("uid", "string"),
+    ("id.orig_h", "addr"),
+    ("id.orig_p", "port"),
+    ("id.resp_h", "addr"),
+    ("id.resp_p", "port"),
+    ("proto", "enum"),
+    ("trans_id", "count"),
+    ("rtt", "interval"),
+    ("query", "string"),
+    ("qclass", "count"),
+    ("qtype", "count"),
+    ("qtype_name", "string"),
+    ("rcode", "count"),
+    ("rcode_name", "string"),
+    ("AA", "bool"),
+    ("TC", "bool"),
+    ("RD", "bool"),
+    ("RA", "bool"),
+    ("answers", "vector[string]"),
+    ("TTLs", "vector[interval]"),
+    ("app_uid", "count"),
+];
+
+const HTTP_FIELDS: &[(&str, &str)] = &[
+    ("ts", "time"),
+    ("uid", "string"),
+    ("id.orig_h", "addr"),
+    ("id.orig_p", "port"),
+    ("id.resp_h", "addr"),
+    ("id.resp_p", "port"),
+    ("trans_depth", "count"),
+    ("method", "string"),
+    ("host", "string"),
+    ("uri", "string"),
+    ("referrer", "string"),
+    ("version", "string"),
+    ("user_agent", "string"),
+    ("request_body_len", "count"),
+    ("response_body_len", "count"),
+    ("status_code", "count"),
+    ("status_msg", "string"),
+    ("app_uid", "count"),
+];
+
+const SSL_FIELDS: &[(&str, &str)] = &[
+    ("ts", "time"),
+    ("uid", "string"),
+    ("id.orig_h", "addr"),
+    ("id.orig_p", "port"),
+    ("id.resp_h", "addr"),
+    ("id.resp_p", "port"),
+    ("version", "string"),
+    ("cipher", "string"),
+    ("server_name", "string"),
+    ("next_protocol", "string"),
+    ("established", "bool"),
+    ("ja3", "string"),
+    ("ja3s", "string"),
+    ("ja4", "string"),
+    ("subject", "string"),
+    ("issuer", "string"),
+    ("cert_chain_fps", "vector[string]"),
+    ("validation_status", "string"),
+    ("app_uid", "count"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Zeek's default tab-separated format with #fields/#types headers
+    Tsv,
+    /// One JSON object per line, as with `LogAscii::use_json`
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub dir: PathBuf,
+    pub format: LogFormat,
+    /// Rotate once a file grows past this many bytes
+    pub max_bytes: u64,
+    /// Rotate once a file has been open this long
+    pub rotate_interval_ms: u64,
+    /// Rotated files kept per log; older ones are deleted
+    pub max_rotated_files: usize,
+}
+
+impl LogConfig {
+    pub fn new(dir: impl Into<PathBuf>, format: LogFormat) -> Self {
+        Self {
+            dir: dir.into(),
+            format,
+            max_bytes: DEFAULT_MAX_BYTES,
+            rotate_interval_ms: DEFAULT_ROTATE_INTERVAL_MS,
+            max_rotated_files: DEFAULT_MAX_ROTATED_FILES,
+        }
+    }
+}
+
+// A single log column value
+enum Value {
+    Time(u64),
+    Interval(u64),
+    Str(String),
+    Addr(IpAddr),
+    Port(u16),
+    Count(u64),
+    Bool(bool),
+    Vector(Vec<String>),
+    Unset,
+}
+
+impl Value {
+    fn opt_str(value: Option<&str>) -> Value {
+        value.map_or(Value::Unset, |v| Value::Str(v.to_string()))
+    }
+
+    fn opt_count(value: Option<u64>) -> Value {
+        value.map_or(Value::Unset, Value::Count)
+    }
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{}.{:06}", ms / 1000, (ms % 1000) * 1000)
+}
+
+// Zeek escapes separators and non-printable bytes as \xNN
+fn escape_tsv(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '\t' | '\n' | '\r' | '\\' => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+}
+
+fn date_stamp(ms: u64) -> String {
+    DateTime::from_timestamp_millis(ms as i64)
+        .map(|t| t.format("%Y-%m-%d-%H-%M-%S").to_string())
+        .unwrap_or_default()
+}
+
+/// Stable Zeek-style connection UID ("C" + base62) derived from the flow key and start time
+pub fn connection_uid(key: &FlowKey, first_seen_ms: u64) -> String {
+    const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    first_seen_ms.hash(&mut hasher);
+    let mut n = hasher.finish();
+    let mut uid = String::from("C");
+    for _ in 0..17 {
+        uid.push(ALPHABET[(n % 62) as usize] as char);
+        n /= 62;
+        if n == 0 {
+            break;
+        }
+    }
+    uid
+}
+
+fn proto_name(protocol: u8) -> &'static str {
+    match protocol {
+        6 => "tcp",
+        17 => "udp",
+        1 | 58 => "icmp",
+        _ => "unknown_transport",
+    }
+}
+
+// Zeek conn_state from the flags seen in each direction
+fn conn_state(flow: &FlowRecord) -> &'static str {
+    if flow.key.protocol != 6 {
+        return if flow.packets_in > 0 { "SF" } else { "S0" };
+    }
+    let (orig, resp) = (flow.tcp_flags_out, flow.tcp_flags_in);
+    let handshake = orig & TCP_SYN != 0 && resp & TCP_SYN != 0;
+    match () {
+        _ if orig & TCP_SYN != 0 && flow.packets_in == 0 => "S0",
+        _ if orig & TCP_SYN != 0 && resp & TCP_SYN == 0 && resp & TCP_RST != 0 => "REJ",
+        _ if handshake && orig & TCP_RST != 0 => "RSTO",
+        _ if handshake && resp & TCP_RST != 0 => "RSTR",
+        _ if handshake && orig & TCP_FIN != 0 && resp & TCP_FIN != 0 => "SF",
+        _ if handshake && orig & TCP_FIN != 0 => "S2",
+        _ if handshake && resp & TCP_FIN != 0 => "S3",
+        _ if handshake => "S1",
+        _ => "OTH",
+    }
+}
+
+// Condensed Zeek history: upper case for the originator, lower case for the responder
+fn history(flow: &FlowRecord) -> String {
+    let mut h = String::new();
+    for (flags, upper) in [(flow.tcp_flags_out, true), (flow.tcp_flags_in, false)] {
+        for (bit, letter) in [(TCP_SYN, 's'), (TCP_FIN, 'f'), (TCP_RST, 'r')] {
+            if flags & bit != 0 {
+                h.push(if upper { letter.to_ascii_uppercase() } else { letter });
+            }
+        }
+    }
+    h
+}
+
+fn service(flow: &FlowRecord) -> Option<&'static str> {
+    let port = flow.key.dst_port;
+    match (flow.key.protocol, port) {
+        (17, 443) if flow.ja3.is_some() => Some("quic,ssl"),
+        _ if flow.ja3.is_some() || flow.ja3s.is_some() => Some("ssl"),
+        (_, 53) | (17, 5353) => Some("dns"),
+        (6, 80) if flow.bytes_out > 0 => Some("http"),
+        _ => None,
+    }
+}
+
+fn tls_version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSLv3".to_string(),
+        0x0301 => "TLSv10".to_string(),
+        0x0302 => "TLSv11".to_string(),
+        0x0303 => "TLSv12".to_string(),
+        0x0304 => "TLSv13".to_string(),
+        v => format!("unknown-{}", v),
+    }
+}
+
+// One open log file and its rotation bookkeeping
+struct LogStream {
+    path: &'static str,
+    fields: &'static [(&'static str, &'static str)],
+    writer: Option<BufWriter<File>>,
+    opened_ms: u64,
+    bytes: u64,
+}
+
+impl LogStream {
+    fn new(path: &'static str, fields: &'static [(&'static str, &'static str)]) -> Self {
+        Self { path, fields, writer: None, opened_ms: 0, bytes: 0 }
+    }
+
+    fn file_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.log", self.path))
+    }
+
+    fn open(&mut self, config: &LogConfig, now_ms: u64) -> io::Result<()> {
+        let path = self.file_path(&config.dir);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let existing = file.metadata()?.len();
+        let mut writer = BufWriter::new(file);
+        if config.format == LogFormat::Tsv && existing == 0 {
+            let names: Vec<&str> = self.fields.iter().map(|f| f.0).collect();
+            let types: Vec<&str> = self.fields.iter().map(|f| f.1).collect();
+            let header = format!(
+                "#separator \\x09\n#set_separator\t,\n#empty_field\t(empty)\n#unset_field\t-\n#path\t{}\n#open\t{}\n#fields\t{}\n#types\t{}\n",
+                self.path,
+                date_stamp(now_ms),
+                names.join("\t"),
+                types.join("\t"),
+            );
+            writer.write_all(header.as_bytes())?;
+            self.bytes = header.len() as u64;
+        } else {
+            self.bytes = existing;
+        }
+        self.writer = Some(writer);
+        self.opened_ms = now_ms;
+        Ok(())
+    }
+
+    // Close the current file, move it aside as <path>.<open time>.log and delete the oldest
+    // rotated files beyond the retention limit
+    fn rotate(&mut self, config: &LogConfig, now_ms: u64) -> io::Result<()> {
+        let Some(mut writer) = self.writer.take() else { return Ok(()) };
+        if config.format == LogFormat::Tsv {
+            writer.write_all(format!("#close\t{}\n", date_stamp(now_ms)).as_bytes())?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        let current = self.file_path(&config.dir);
+        let stamp = date_stamp(self.opened_ms);
+        let mut archived = config.dir.join(format!("{}.{}.log", self.path, stamp));
+        let mut n = 1;
+        while archived.exists() {
+            archived = config.dir.join(format!("{}.{}-{}.log", self.path, stamp, n));
+            n += 1;
+        }
+        fs::rename(current, archived)?;
+        self.prune(config)
+    }
+
+    fn prune(&self, config: &LogConfig) -> io::Result<()> {
+        let prefix = format!("{}.", self.path);
+        let current = format!("{}.log", self.path);
+        let mut rotated: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(&config.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                name.starts_with(&prefix) && name.ends_with(".log") && name != current
+            })
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        if rotated.len() <= config.max_rotated_files {
+            return Ok(());
+        }
+        rotated.sort();
+        for (_, path) in &rotated[..rotated.len() - config.max_rotated_files] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, config: &LogConfig, values: &[Value], now_ms: u64) -> io::Result<()> {
+        debug_assert_eq!(values.len(), self.fields.len());
+        if self.writer.is_some()
+            && (self.bytes >= config.max_bytes || now_ms.saturating_sub(self.opened_ms) >= config.rotate_interval_ms)
+        {
+            self.rotate(config, now_ms)?;
+        }
+        if self.writer.is_none() {
+            self.open(config, now_ms)?;
+        }
+
+        let line = match config.format {
+            LogFormat::Tsv => Self::tsv_line(values),
+            LogFormat::Json => Self::json_line(self.fields, values),
+        };
+        let writer = self.writer.as_mut().expect("opened above");
+        writer.write_all(line.as_bytes())?;
+        self.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    fn tsv_line(values: &[Value]) -> String {
+        let mut line = String::new();
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                line.push('\t');
+            }
+            match value {
+                Value::Time(ms) | Value::Interval(ms) => line.push_str(&seconds(*ms)),
+                Value::Str(s) if s.is_empty() => line.push_str("(empty)"),
+                Value::Str(s) => escape_tsv(s, &mut line),
+                Value::Addr(a) => line.push_str(&a.to_string()),
+                Value::Port(p) => line.push_str(&p.to_string()),
+                Value::Count(c) => line.push_str(&c.to_string()),
+                Value::Bool(b) => line.push(if *b { 'T' } else { 'F' }),
+                Value::Vector(items) if items.is_empty() => line.push_str("(empty)"),
+                Value::Vector(items) => {
+                    for (j, item) in items.iter().enumerate() {
+                        if j > 0 {
+                            line.push(',');
+                        }
+                        escape_tsv(&item.replace(',', "\\x2c"), &mut line);
+                    }
+                }
+                Value::Unset => line.push('-'),
+            }
+        }
+        line.push('\n');
+        line
+    }
+
+    fn json_line(fields: &[(&str, &str)], values: &[Value]) -> String {
+        let mut object = serde_json::Map::new();
+        for ((name, ty), value) in fields.iter().zip(values) {
+            let json = match value {
+                Value::Time(ms) | Value::Interval(ms) => serde_json::json!(*ms as f64 / 1000.0),
+                Value::Str(s) => serde_json::json!(s),
+                Value::Addr(a) => serde_json::json!(a.to_string()),
+                Value::Port(p) => serde_json::json!(p),
+                Value::Count(c) => serde_json::json!(c),
+                Value::Bool(b) => serde_json::json!(b),
+                // TTLs are intervals, everything else a string vector
+                Value::Vector(items) if *ty == "vector[interval]" => {
+                    serde_json::json!(items.iter().filter_map(|i| i.parse::<f64>().ok()).collect::<Vec<_>>())
+                }
+                Value::Vector(items) => serde_json::json!(items),
+                Value::Unset => continue,
+            };
+            object.insert(name.to_string(), json);
+        }
+        let mut line = serde_json::Value::Object(object).to_string();
+        line.push('\n');
+        line
+    }
+}
+
+struct PendingDns {
+    ts_ms: u64,
+    key: FlowKey,
+    uid: Option<u32>,
+    first_seen_ms: u64,
+    query: DnsMessage,
+}
+
+struct PendingHttp {
+    ts_ms: u64,
+    depth: u64,
+    request: HttpRequest,
+}
+
+/// Writes conn, dns, http and ssl logs in Zeek's layout
+pub struct ZeekLogger {
+    config: LogConfig,
+    conn: LogStream,
+    dns: LogStream,
+    http: LogStream,
+    ssl: LogStream,
+    pending_dns: AHashMap<(FlowKey, u16), PendingDns>,
+    pending_http: AHashMap<FlowKey, VecDeque<PendingHttp>>,
+    http_depth: AHashMap<FlowKey, u64>,
+}
+
+impl ZeekLogger {
+    pub fn new(config: LogConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(Self {
+            config,
+            conn: LogStream::new("conn", CONN_FIELDS),
+            dns: LogStream::new("dns", DNS_FIELDS),
+            http: LogStream::new("http", HTTP_FIELDS),
+            ssl: LogStream::new("ssl", SSL_FIELDS),
+            pending_dns: AHashMap::new(),
+            pending_http: AHashMap::new(),
+            http_depth: AHashMap::new(),
+        })
+    }
+
+    pub fn config(&self) -> &LogConfig {
+        &self.config
+    }
+
+    /// Write out buffered lines; they are otherwise only written as buffers fill, on rotation
+    /// and when the logger is dropped
+    pub fn flush(&mut self) -> io::Result<()> {
+        for stream in [&mut self.conn, &mut self.dns, &mut self.http, &mut self.ssl] {
+            stream.flush()?;
+        }
+        Ok(())
+    }
+
+    fn id_fields(key: &FlowKey, first_seen_ms: u64) -> [Value; 5] {
+        [
+            Value::Str(connection_uid(key, first_seen_ms)),
+            Value::Addr(key.src),
+            Value::Port(key.src_port),
+            Value::Addr(key.dst),
+            Value::Port(key.dst_port),
+        ]
+    }
+
+    /// Record a DNS message seen on `flow`; queries wait for their response
+    pub fn dns_message(&mut self, flow: &FlowRecord, message: DnsMessage, now_ms: u64) -> io::Result<()> {
+        let pending_key = (flow.key, message.id);
+        if !message.response {
+            if self.pending_dns.len() >= MAX_PENDING_DNS {
+                self.flush_dns(now_ms, true)?;
+            }
+            self.pending_dns.insert(
+                pending_key,
+                PendingDns { ts_ms: now_ms, key: flow.key, uid: flow.uid, first_seen_ms: flow.first_seen_ms, query: message },
+            );
+            return Ok(());
+        }
+
+        match self.pending_dns.remove(&pending_key) {
+            Some(query) => self.write_dns(&query, Some((&message, now_ms)), now_ms),
+            None => {
+                let orphan = PendingDns {
+                    ts_ms: now_ms,
+                    key: flow.key,
+                    uid: flow.uid,
+                    first_seen_ms: flow.first_seen_ms,
+                    query: message.clone(),
+                };
+                self.write_dns(&orphan, Some((&message, now_ms)), now_ms)
+            }
+        }
+    }
+
+    // Log unanswered queries older than the timeout, or all of them with `force`
+    fn flush_dns(&mut self, now_ms: u64, force: bool) -> io::Result<()> {
+        let stale: Vec<(FlowKey, u16)> = self
+            .pending_dns
+            .iter()
+            .filter(|(_, p)| force || now_ms.saturating_sub(p.ts_ms) >= DNS_TIMEOUT_MS)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in stale {
+            if let Some(query) = self.pending_dns.remove(&key) {
+                self.write_dns(&query, None, now_ms)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_dns(&mut self, query: &PendingDns, response: Option<(&DnsMessage, u64)>, now_ms: u64) -> io::Result<()> {
+        let question = query.query.questions.first();
+        let [uid, orig_h, orig_p, resp_h, resp_p] = Self::id_fields(&query.key, query.first_seen_ms);
+        let answers = response.map(|(r, _)| r.answers.as_slice()).unwrap_or(&[]);
+        let values = [
+            Value::Time(query.ts_ms),
+            uid,
+            orig_h,
+            orig_p,
+            resp_h,
+            resp_p,
+            Value::Str(proto_name(query.key.protocol).to_string()),
+            Value::Count(query.query.id as u64),
+            response.map_or(Value::Unset, |(_, at)| Value::Interval(at.saturating_sub(query.ts_ms))),
+            Value::opt_str(question.map(|q| q.name.as_str())),
+            Value::opt_count(question.map(|q| q.qclass as u64)),
+            Value::opt_count(question.map(|q| q.qtype as u64)),
+            Value::opt_str(question.and_then(|q| dns::qtype_name(q.qtype))),
+            response.map_or(Value::Unset, |(r, _)| Value::Count(r.rcode as u64)),
+            response.map_or(Value::Unset, |(r, _)| Value::Str(dns::rcode_name(r.rcode).to_string())),
+            Value::Bool(response.is_some_and(|(r, _)| r.authoritative)),
+            Value::Bool(response.is_some_and(|(r, _)| r.truncated)),
+            Value::Bool(query.query.recursion_desired),
+            Value::Bool(response.is_some_and(|(r, _)| r.recursion_available)),
+            if answers.is_empty() { Value::Unset } else { Value::Vector(answers.iter().map(|a| a.data.clone()).collect()) },
+            if answers.is_empty() {
+                Value::Unset
+            } else {
+                Value::Vector(answers.iter().map(|a| format!("{}.0", a.ttl)).collect())
+            },
+            Value::opt_count(query.uid.map(u64::from)),
+        ];
+        self.dns.write(&self.config, &values, now_ms)
+    }
+
+    /// Record a request; it is logged once its response arrives or the flow ends
+    pub fn http_request(&mut self, flow: &FlowRecord, request: HttpRequest, now_ms: u64) -> io::Result<()> {
+        let depth = self.http_depth.entry(flow.key).or_default();
+        *depth += 1;
+        let depth = *depth;
+        let queue = self.pending_http.entry(flow.key).or_default();
+        queue.push_back(PendingHttp { ts_ms: now_ms, depth, request });
+        if queue.len() > MAX_PENDING_HTTP_PER_FLOW {
+            let oldest = queue.pop_front().expect("non-empty");
+            self.write_http(flow, &oldest, None, now_ms)?;
+        }
+        Ok(())
+    }
+
+    /// Pair a response with the oldest outstanding request on the flow
+    pub fn http_response(&mut self, flow: &FlowRecord, response: HttpResponse, now_ms: u64) -> io::Result<()> {
+        let Some(pending) = self.pending_http.get_mut(&flow.key).and_then(|q| q.pop_front()) else { return Ok(()) };
+        self.write_http(flow, &pending, Some(&response), now_ms)
+    }
+
+    fn write_http(&mut self, flow: &FlowRecord, pending: &PendingHttp, response: Option<&HttpResponse>, now_ms: u64) -> io::Result<()> {
+        let request = &pending.request;
+        let [uid, orig_h, orig_p, resp_h, resp_p] = Self::id_fields(&flow.key, flow.first_seen_ms);
+        let values = [
+            Value::Time(pending.ts_ms),
+            uid,
+            orig_h,
+            orig_p,
+            resp_h,
+            resp_p,
+            Value::Count(pending.depth),
+            Value::Str(request.method.clone()),
+            Value::opt_str(request.host()),
+            Value::Str(request.uri.clone()),
+            Value::opt_str(request.header("referer")),
+            Value::opt_str(Some(request.version.as_str()).filter(|v| !v.is_empty())),
+            Value::opt_str(request.header("user-agent")),
+            Value::Count(request.body_len()),
+            Value::Count(response.map_or(0, |r| r.body_len)),
+            response.map_or(Value::Unset, |r| Value::Count(r.status_code as u64)),
+            response.map_or(Value::Unset, |r| Value::Str(r.reason.clone())),
+            Value::opt_count(flow.uid.map(u64::from)),
+        ];
+        self.http.write(&self.config, &values, now_ms)
+    }
+
+    /// Write conn.log (and ssl.log for TLS flows) for a flow that has ended
+    pub fn flow_closed(&mut self, flow: &FlowRecord, now_ms: u64) -> io::Result<()> {
+        if let Some(pending) = self.pending_http.remove(&flow.key) {
+            for request in pending {
+                self.write_http(flow, &request, None, now_ms)?;
+            }
+        }
+        self.http_depth.remove(&flow.key);
+
+        if flow.sni.is_some() || flow.ja3.is_some() || flow.ja3s.is_some() {
+            self.write_ssl(flow, now_ms)?;
+        }
+
+        let [uid, orig_h, orig_p, resp_h, resp_p] = Self::id_fields(&flow.key, flow.first_seen_ms);
+        let values = [
+            Value::Time(flow.first_seen_ms),
+            uid,
+            orig_h,
+            orig_p,
+            resp_h,
+            resp_p,
+            Value::Str(proto_name(flow.key.protocol).to_string()),
+            Value::opt_str(service(flow)),
+            Value::Interval(flow.last_seen_ms.saturating_sub(flow.first_seen_ms)),
+            // Only IP-level byte counts are tracked
+            Value::Unset,
+            Value::Unset,
+            Value::Str(conn_state(flow).to_string()),
+            Value::Str(history(flow)),
+            Value::Count(flow.packets_out),
+            Value::Count(flow.bytes_out),
+            Value::Count(flow.packets_in),
+            Value::Count(flow.bytes_in),
+            Value::opt_count(flow.uid.map(u64::from)),
+            Value::Count(flow.verdict as u64),
+            Value::opt_str(flow.country.as_deref()),
+            Value::opt_count(flow.asn.map(u64::from)),
+        ];
+        self.conn.write(&self.config, &values, now_ms)?;
+        self.flush_dns(now_ms, false)
+    }
+
+    fn write_ssl(&mut self, flow: &FlowRecord, now_ms: u64) -> io::Result<()> {
+        let certificate = flow.certificate.as_ref();
+        let validation = certificate.map(|c| {
+            if c.flags.is_empty() {
+                "ok".to_string()
+            } else {
+                c.flags.iter().map(|f| format!("{:?}", f)).collect::<Vec<_>>().join(",")
+            }
+        });
+        let [uid, orig_h, orig_p, resp_h, resp_p] = Self::id_fields(&flow.key, flow.first_seen_ms);
+        let values = [
+            Value::Time(flow.first_seen_ms),
+            uid,
+            orig_h,
+            orig_p,
+            resp_h,
+            resp_p,
+            flow.tls_version.map_or(Value::Unset, |v| Value::Str(tls_version_name(v))),
+            flow.tls_cipher.map_or(Value::Unset, |c| Value::Str(format!("0x{:04x}", c))),
+            Value::opt_str(flow.sni.as_deref()),
+            Value::opt_str(flow.alpn.as_deref()),
+            Value::Bool(flow.ja3s.is_some() && flow.packets_in > 0),
+            Value::opt_str(flow.ja3.as_deref()),
+            Value::opt_str(flow.ja3s.as_deref()),
+            Value::opt_str(flow.ja4.as_deref()),
+            Value::opt_str(certificate.map(|c| c.subject.as_str())),
+            Value::opt_str(certificate.map(|c| c.issuer.as_str())),
+            certificate.map_or(Value::Unset, |c| Value::Vector(vec![c.sha256.clone()])),
+            Value::opt_str(validation.as_deref()),
+            Value::opt_count(flow.uid.map(u64::from)),
+        ];
+        self.ssl.write(&self.config, &values, now_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::tests::build_message;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fortress-zeek-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn flow(dst: &str, protocol: u8, dst_port: u16) -> FlowRecord {
+        let key = FlowKey { src: "10.0.0.2".parse().unwrap(), dst: dst.parse().unwrap(), src_port: 40000, dst_port, protocol };
+        let mut flow = FlowRecord::new(key, 1_700_000_000_000);
+        flow.uid = Some(10123);
+        flow.last_seen_ms = 1_700_000_002_500;
+        flow.packets_out = 3;
+        flow.bytes_out = 300;
+        flow.packets_in = 2;
+        flow.bytes_in = 1500;
+        flow
+    }
+
+    #[test]
+    fn test_tsv_conn_and_dns_logs() {
+        let dir = temp_dir("tsv");
+        let mut logger = ZeekLogger::new(LogConfig::new(&dir, LogFormat::Tsv)).unwrap();
+
+        let dns_flow = flow("8.8.8.8", 17, 53);
+        let query = dns::parse_message(&build_message(7, "www.example.com", dns::TYPE_A, &[])).unwrap();
+        let answer = dns::parse_message(&build_message(7, "www.example.com", dns::TYPE_A, &[[93, 184, 216, 34]])).unwrap();
+        logger.dns_message(&dns_flow, query, 1_700_000_000_000).unwrap();
+        logger.dns_message(&dns_flow, answer, 1_700_000_000_040).unwrap();
+
+        let mut tcp = flow("93.184.216.34", 6, 443);
+        tcp.tcp_flags_out = 0x13;
+        tcp.tcp_flags_in = 0x13;
+        logger.flow_closed(&tcp, 1_700_000_130_000).unwrap();
+        logger.flush().unwrap();
+
+        let dns_log = fs::read_to_string(dir.join("dns.log")).unwrap();
+        let fields: Vec<&str> = dns_log.lines().find(|l| l.starts_with("#fields")).unwrap().split('\t').skip(1).collect();
+        let row: Vec<&str> = dns_log.lines().find(|l| !l.starts_with('#')).unwrap().split('\t').collect();
+        let col = |name: &str| row[fields.iter().position(|f| *f == name).unwrap()];
+        assert_eq!(col("query"), "www.example.com");
+        assert_eq!(col("rtt"), "0.040000");
+        assert_eq!(col("answers"), "93.184.216.34");
+        assert_eq!(col("rcode_name"), "NOERROR");
+        assert_eq!(col("id.resp_p"), "53");
+
+        let conn_log = fs::read_to_string(dir.join("conn.log")).unwrap();
+        assert!(conn_log.starts_with("#separator \\x09\n"));
+        let row: Vec<&str> = conn_log.lines().find(|l| !l.starts_with('#')).unwrap().split('\t').collect();
+        assert_eq!(row[0], "1700000000.000000");
+        assert_eq!(row[1], connection_uid(&tcp.key, tcp.first_seen_ms));
+        assert_eq!(row[6], "tcp");
+        assert_eq!(row[8], "2.500000");
+        assert_eq!(row[11], "SF");
+        assert_eq!(row[17], "10123");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_http_ssl_and_size_rotation() {
+        let dir = temp_dir("json");
+        let mut config = LogConfig::new(&dir, LogFormat::Json);
+        config.max_bytes = 600;
+        let mut logger = ZeekLogger::new(config).unwrap();
+
+        let web = flow("93.184.216.34", 6, 80);
+        let request = crate::http::parse_request(b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl/8\r\n\r\n").unwrap();
+        let response = crate::http::parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 1256\r\n\r\n").unwrap();
+        logger.http_request(&web, request, 1_700_000_000_100).unwrap();
+        logger.http_response(&web, response, 1_700_000_000_200).unwrap();
+        logger.flush().unwrap();
+
+        let http_log = fs::read_to_string(dir.join("http.log")).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(http_log.lines().next().unwrap()).unwrap();
+        assert_eq!(entry["host"], "example.com");
+        assert_eq!(entry["status_code"], 200);
+        assert_eq!(entry["response_body_len"], 1256);
+        assert_eq!(entry["id.orig_h"], "10.0.0.2");
+        assert!(entry.get("referrer").is_none());
+
+        let mut tls = flow("93.184.216.34", 6, 443);
+        tls.sni = Some("example.com".to_string());
+        tls.ja3 = Some("e7d705a3286e19ea42f587b344ee6865".to_string());
+        tls.tls_version = Some(0x0303);
+        for i in 0..5 {
+            logger.flow_closed(&tls, 1_700_000_010_000 + i).unwrap();
+        }
+        logger.flush().unwrap();
+
+        let ssl_log = fs::read_to_string(dir.join("ssl.log")).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(ssl_log.lines().last().unwrap()).unwrap();
+        assert_eq!(entry["server_name"], "example.com");
+        assert_eq!(entry["version"], "TLSv12");
+
+        let rotated = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with("conn.20"))
+            .count();
+        assert!(rotated >= 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotated_files_beyond_the_limit_are_deleted() {
+        let dir = temp_dir("retention");
+        let mut config = LogConfig::new(&dir, LogFormat::Tsv);
+        config.max_bytes = 1024;
+        config.max_rotated_files = 3;
+        let mut logger = ZeekLogger::new(config).unwrap();
+        let web = flow("93.184.216.34", 6, 80);
+        for i in 0..200 {
+            logger.flow_closed(&web, 1_700_000_000_000 + i * 1000).unwrap();
+        }
+        drop(logger);
+
+        let mut names: Vec<String> =
+            fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[3], "conn.log");
+        // The newest rotations are the ones kept
+        assert!(names[..3].iter().all(|name| name.starts_with("conn.2023-11-14-22-")));
+        assert!(names[2].as_str() > "conn.2023-11-14-22-15", "{:?}", names);
+    }
+}
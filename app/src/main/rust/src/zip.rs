@@ -0,0 +1,442 @@
+use serde::Serialize;
+
+const LOCAL_SIGNATURE: &[u8; 4] = b"PK\x03\x04";
+const CENTRAL_SIGNATURE: &[u8; 4] = b"PK\x01\x02";
+const END_SIGNATURE: &[u8; 4] = b"PK\x05\x06";
+const LOCAL_HEADER_LEN: usize = 30;
+const CENTRAL_HEADER_LEN: usize = 46;
+const END_RECORD_LEN: usize = 22;
+// The end record sits before a comment of at most this many bytes
+const MAX_COMMENT_LEN: usize = 0xffff;
+const MAX_ENTRIES: usize = 65_535;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+const FLAG_ENCRYPTED: u16 = 1;
+// Sizes and CRC follow the data, and a local header may leave them zero
+const FLAG_DATA_DESCRIPTOR: u16 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZipError {
+    // The entry's data lies outside the file
+    Truncated,
+    Corrupt,
+}
+
+// What reading an entry yielded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryData {
+    pub data: Vec<u8>,
+    // False when `data` is only the entry's start: it holds more than the caller allows, or the
+    // file ends before its data does
+    pub complete: bool,
+    // The data matches the CRC-32 the archive records for it; a partial read cannot be checked
+    // and is never valid
+    pub crc_valid: bool,
+}
+
+// Structural oddities that real build tools never produce but that malware uses to confuse
+// analysers while still installing: Android ignores the encryption flag, falls back on unknown
+// compression methods, and historically trusted whichever of two same-named entries it met first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    FakeEncryption,
+    UnknownCompression,
+    HeaderMismatch,
+    DuplicateEntry,
+    // Bytes before the first entry, as in the Janus (CVE-2017-13156) DEX-prepending attack
+    PrependedData,
+}
+
+impl AnomalyKind {
+    pub fn finding(self) -> &'static str {
+        match self {
+            AnomalyKind::FakeEncryption => "ZIP_FAKE_ENCRYPTION",
+            AnomalyKind::UnknownCompression => "ZIP_UNKNOWN_COMPRESSION",
+            AnomalyKind::HeaderMismatch => "ZIP_HEADER_MISMATCH",
+            AnomalyKind::DuplicateEntry => "ZIP_DUPLICATE_ENTRY",
+            AnomalyKind::PrependedData => "ZIP_PREPENDED_DATA",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Anomaly {
+    pub entry: String,
+    pub kind: AnomalyKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    pub method: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    // Start of the entry's data, past its local header
+    data_offset: usize,
+}
+
+pub struct ZipArchive<'a> {
+    data: &'a [u8],
+    pub entries: Vec<ZipEntry>,
+    pub anomalies: Vec<Anomaly>,
+    // Entries were found by walking local headers because the central directory was unusable
+    pub recovered: bool,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset.checked_add(2)?)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset.checked_add(4)?)?.try_into().ok()?))
+}
+
+fn has_signature(data: &[u8], offset: usize, signature: &[u8; 4]) -> bool {
+    data.get(offset..).is_some_and(|rest| rest.starts_with(signature))
+}
+
+// A local header: (name, method, flags, crc32, compressed size, size, data offset)
+type LocalHeader = (String, u16, u16, u32, u64, u64, usize);
+
+fn read_local_header(data: &[u8], offset: usize) -> Option<LocalHeader> {
+    if !has_signature(data, offset, LOCAL_SIGNATURE) {
+        return None;
+    }
+    let flags = u16_at(data, offset + 6)?;
+    let method = u16_at(data, offset + 8)?;
+    let crc32 = u32_at(data, offset + 14)?;
+    let compressed_size = u32_at(data, offset + 18)? as u64;
+    let size = u32_at(data, offset + 22)? as u64;
+    let name_len = u16_at(data, offset + 26)? as usize;
+    let extra_len = u16_at(data, offset + 28)? as usize;
+    let name_start = offset + LOCAL_HEADER_LEN;
+    let name = data.get(name_start..name_start + name_len)?;
+    let data_offset = name_start + name_len + extra_len;
+    Some((String::from_utf8_lossy(name).into_owned(), method, flags, crc32, compressed_size, size, data_offset))
+}
+
+fn find_end_record(data: &[u8]) -> Option<usize> {
+    let last = data.len().checked_sub(END_RECORD_LEN)?;
+    let first = last.saturating_sub(MAX_COMMENT_LEN);
+    (first..=last).rev().find(|&offset| has_signature(data, offset, END_SIGNATURE))
+}
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(LOCAL_SIGNATURE)
+}
+
+impl<'a> ZipArchive<'a> {
+    // Open an archive from its central directory, or by walking local headers when that is
+    // missing or unreadable. None when neither finds anything to read.
+    pub fn parse(data: &'a [u8]) -> Option<ZipArchive<'a>> {
+        let mut archive = ZipArchive { data, entries: Vec::new(), anomalies: Vec::new(), recovered: false };
+        if let Some(end) = find_end_record(data) {
+            archive.read_central_directory(end);
+        }
+        if archive.entries.is_empty() {
+            if !is_zip(data) {
+                return None;
+            }
+            archive.recovered = true;
+            archive.scan_local_headers();
+        }
+        if archive.entries.is_empty() {
+            return None;
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for entry in &archive.entries {
+            if !seen.insert(entry.name.as_str()) {
+                archive.anomalies.push(Anomaly { entry: entry.name.clone(), kind: AnomalyKind::DuplicateEntry });
+            }
+        }
+        Some(archive)
+    }
+
+    fn read_central_directory(&mut self, end: usize) {
+        let data = self.data;
+        let (Some(size), Some(offset)) = (u32_at(data, end + 12), u32_at(data, end + 16)) else { return };
+        // Offsets are relative to the start of the archive, which may not be the start of the file
+        let mut base = 0;
+        let mut at = offset as usize;
+        if !has_signature(data, at, CENTRAL_SIGNATURE) {
+            let Some(actual) = end.checked_sub(size as usize) else { return };
+            if !has_signature(data, actual, CENTRAL_SIGNATURE) {
+                return;
+            }
+            base = actual.saturating_sub(at);
+            at = actual;
+        }
+
+        // The recorded entry count is not trusted; the directory runs as long as its signatures do
+        let mut first_local = usize::MAX;
+        while has_signature(data, at, CENTRAL_SIGNATURE) && self.entries.len() < MAX_ENTRIES {
+            let header = (|| {
+                let flags = u16_at(data, at + 8)?;
+                let method = u16_at(data, at + 10)?;
+                let crc32 = u32_at(data, at + 16)?;
+                let compressed_size = u32_at(data, at + 20)? as u64;
+                let size = u32_at(data, at + 24)? as u64;
+                let name_len = u16_at(data, at + 28)? as usize;
+                let extra_len = u16_at(data, at + 30)? as usize;
+                let comment_len = u16_at(data, at + 32)? as usize;
+                let local = (u32_at(data, at + 42)? as usize).checked_add(base)?;
+                let name = data.get(at + CENTRAL_HEADER_LEN..at + CENTRAL_HEADER_LEN + name_len)?;
+                let next = at + CENTRAL_HEADER_LEN + name_len + extra_len + comment_len;
+                Some((String::from_utf8_lossy(name).into_owned(), flags, method, crc32, compressed_size, size, local, next))
+            })();
+            let Some((name, flags, method, crc32, compressed_size, size, local, next)) = header else { break };
+            at = next;
+            first_local = first_local.min(local);
+
+            // The central directory is authoritative for sizes and method, as for the platform
+            // installer; the local header only locates the data
+            let Some((local_name, local_method, _, _, _, _, data_offset)) = read_local_header(data, local) else {
+                self.anomalies.push(Anomaly { entry: name, kind: AnomalyKind::HeaderMismatch });
+                continue;
+            };
+            if local_name != name || local_method != method {
+                self.anomalies.push(Anomaly { entry: name.clone(), kind: AnomalyKind::HeaderMismatch });
+            }
+            self.push_entry(ZipEntry { name, method, flags, crc32, compressed_size, size, data_offset });
+        }
+        if first_local != usize::MAX && first_local > 0 && !self.entries.is_empty() {
+            self.anomalies.push(Anomaly { entry: String::new(), kind: AnomalyKind::PrependedData });
+        }
+    }
+
+    // Without a central directory, take every local header in order. Entries that defer their
+    // sizes to a data descriptor are still readable when deflated, since the stream ends itself.
+    fn scan_local_headers(&mut self) {
+        let data = self.data;
+        let mut at = 0;
+        while at + LOCAL_HEADER_LEN <= data.len() && self.entries.len() < MAX_ENTRIES {
+            let Some(next) = data[at..].windows(4).position(|window| window == LOCAL_SIGNATURE) else { break };
+            at += next;
+            let Some((name, method, flags, crc32, compressed_size, size, data_offset)) = read_local_header(data, at) else {
+                at += 4;
+                continue;
+            };
+            self.push_entry(ZipEntry { name, method, flags, crc32, compressed_size, size, data_offset });
+            at = data_offset.saturating_add(compressed_size as usize).max(at + 4);
+        }
+    }
+
+    fn push_entry(&mut self, entry: ZipEntry) {
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            self.anomalies.push(Anomaly { entry: entry.name.clone(), kind: AnomalyKind::FakeEncryption });
+        }
+        if entry.method != METHOD_STORED && entry.method != METHOD_DEFLATED {
+            self.anomalies.push(Anomaly { entry: entry.name.clone(), kind: AnomalyKind::UnknownCompression });
+        }
+        self.entries.push(entry);
+    }
+
+    // Looks like an Android package rather than a plain archive
+    pub fn is_apk(&self) -> bool {
+        self.entries.iter().any(|entry| entry.name == "AndroidManifest.xml" || entry.name == "classes.dex")
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&ZipEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    // An entry's contents, or the first `limit` bytes of a larger one, checked against the
+    // recorded CRC. Encryption flags are ignored as the platform ignores them, and an unknown
+    // method is tried as deflate and then as stored data.
+    pub fn read(&self, entry: &ZipEntry, limit: usize) -> Result<EntryData, ZipError> {
+        let available = self.data.get(entry.data_offset..).ok_or(ZipError::Truncated)?;
+        let compressed = match usize::try_from(entry.compressed_size) {
+            Ok(size) if size > 0 && size <= available.len() => &available[..size],
+            _ => available,
+        };
+        let stored = || {
+            let size = if entry.size > 0 { entry.size } else { entry.compressed_size } as usize;
+            let data = available.get(..size).ok_or(ZipError::Truncated)?;
+            Ok((data[..size.min(limit)].to_vec(), size <= limit))
+        };
+        let (data, complete) = match entry.method {
+            METHOD_STORED => stored(),
+            METHOD_DEFLATED => inflate(compressed, limit),
+            _ => inflate(compressed, limit).or_else(|_| stored()),
+        }?;
+        // A local header written ahead of its data descriptor has nothing to check against
+        let unrecorded = entry.flags & FLAG_DATA_DESCRIPTOR != 0 && entry.crc32 == 0;
+        let crc_valid = complete && (unrecorded || crc32(&data) == entry.crc32);
+        Ok(EntryData { data, complete, crc_valid })
+    }
+}
+
+// Raw deflate of at most `limit` bytes, and whether that was all of it. Whatever came out of a
+// stream that is cut short is kept, and is not the whole entry.
+fn inflate(compressed: &[u8], limit: usize) -> Result<(Vec<u8>, bool), ZipError> {
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+    use miniz_oxide::inflate::TINFLStatus;
+    let mut decompressor = DecompressorOxide::new();
+    let mut out = vec![0; compressed.len().saturating_mul(2).clamp(1, limit.max(1))];
+    let (mut input, mut written) = (compressed, 0);
+    loop {
+        let flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let (status, consumed, produced) = decompress(&mut decompressor, input, &mut out, written, flags);
+        input = &input[consumed..];
+        written += produced;
+        let complete = match status {
+            TINFLStatus::Done => true,
+            TINFLStatus::HasMoreOutput if out.len() < limit => {
+                out.resize(out.len().saturating_mul(2).min(limit), 0);
+                continue;
+            }
+            TINFLStatus::HasMoreOutput => false,
+            // The input ended before the stream did
+            TINFLStatus::NeedsMoreInput | TINFLStatus::FailedCannotMakeProgress if written > 0 => false,
+            // Corrupt midway: what came out is kept, and the CRC check reports it
+            _ if written > 0 => true,
+            _ => return Err(ZipError::Corrupt),
+        };
+        out.truncate(written.min(limit));
+        return Ok((out, complete));
+    }
+}
+
+// CRC-32 as ZIP uses it: reflected polynomial 0xedb88320
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, &byte| TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ crc >> 8)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Build an archive from (name, contents, deflate) entries, with a central directory
+    pub(crate) fn build(entries: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+        for &(name, contents, deflate) in entries {
+            let body = if deflate { miniz_oxide::deflate::compress_to_vec(contents, 6) } else { contents.to_vec() };
+            let method: u16 = if deflate { METHOD_DEFLATED } else { METHOD_STORED };
+            let offset = out.len() as u32;
+            let fields = |header: &mut Vec<u8>| {
+                header.extend_from_slice(&method.to_le_bytes());
+                header.extend_from_slice(&[0; 4]); // time, date
+                header.extend_from_slice(&crc32(contents).to_le_bytes());
+                header.extend_from_slice(&(body.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                header.extend_from_slice(&0u16.to_le_bytes());
+            };
+            out.extend_from_slice(LOCAL_SIGNATURE);
+            out.extend_from_slice(&[20, 0, 0, 0]); // version, flags
+            fields(&mut out);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&body);
+
+            central.extend_from_slice(CENTRAL_SIGNATURE);
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0]); // versions, flags
+            fields(&mut central);
+            central.extend_from_slice(&[0; 10]); // comment length, disk, attributes
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let central_offset = out.len() as u32;
+        out.extend_from_slice(&central);
+        out.extend_from_slice(END_SIGNATURE);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&central_offset.to_le_bytes());
+        out.extend_from_slice(&[0; 2]);
+        out
+    }
+
+    // Bytes that deflate cannot shrink
+    pub(crate) fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reads_entries_through_evasion_tricks() {
+        let manifest = vec![0x03u8; 4000];
+        let archive = build(&[("AndroidManifest.xml", &manifest, true), ("classes.dex", b"dex\n035\0", false)]);
+        let zip = ZipArchive::parse(&archive).unwrap();
+        assert!(zip.is_apk() && zip.anomalies.is_empty() && !zip.recovered);
+        let read = zip.read(zip.entry("AndroidManifest.xml").unwrap(), 1 << 20).unwrap();
+        assert!(read.complete && read.crc_valid && read.data == manifest);
+        let read = zip.read(zip.entry("AndroidManifest.xml").unwrap(), 100).unwrap();
+        assert!(!read.complete && !read.crc_valid && read.data == manifest[..100]);
+        let read = zip.read(zip.entry("classes.dex").unwrap(), 4).unwrap();
+        assert!(!read.complete && read.data == b"dex\n");
+
+        // Fake encryption flag and a bogus method in the local header only
+        let mut tricked = archive.clone();
+        tricked[6] = FLAG_ENCRYPTED as u8;
+        let central = find_end_record(&tricked).map(|end| u32_at(&tricked, end + 16).unwrap() as usize).unwrap();
+        tricked[central + 8] = FLAG_ENCRYPTED as u8;
+        tricked[8] = 0x63;
+        let zip = ZipArchive::parse(&tricked).unwrap();
+        let kinds: Vec<AnomalyKind> = zip.anomalies.iter().map(|anomaly| anomaly.kind).collect();
+        assert_eq!(kinds, [AnomalyKind::HeaderMismatch, AnomalyKind::FakeEncryption]);
+        assert_eq!(zip.read(&zip.entries[0], 1 << 20).unwrap().data, manifest);
+
+        // Janus-style prepended DEX, then no central directory at all
+        let janus = [b"dex\n035\0".as_slice(), &archive].concat();
+        let zip = ZipArchive::parse(&janus).unwrap();
+        assert_eq!(zip.anomalies[0].kind, AnomalyKind::PrependedData);
+        assert_eq!(zip.read(zip.entry("classes.dex").unwrap(), 64).unwrap().data, b"dex\n035\0");
+        let end = find_end_record(&archive).unwrap();
+        let zip = ZipArchive::parse(&archive[..end - 60]).unwrap();
+        assert!(zip.recovered && zip.entries.len() == 2);
+        assert_eq!(zip.read(&zip.entries[0], 1 << 20).unwrap().data, manifest);
+    }
+
+    #[test]
+    fn test_tampered_entry_fails_its_crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+
+        let archive = build(&[("classes.dex", b"dex\n035\0", false)]);
+        let mut tampered = archive.clone();
+        let at = tampered.windows(8).position(|w| w == b"dex\n035\0").unwrap();
+        tampered[at + 4] = b'9';
+        let zip = ZipArchive::parse(&tampered).unwrap();
+        let read = zip.read(&zip.entries[0], 64).unwrap();
+        assert!(read.complete && !read.crc_valid && read.data == b"dex\n935\0");
+        let zip = ZipArchive::parse(&archive).unwrap();
+        assert!(zip.read(&zip.entries[0], 64).unwrap().crc_valid);
+
+        // A download cut off inside a deflated entry is incomplete, not tampered with
+        let noise = noise(20_000);
+        let archive = build(&[("lib/arm64-v8a/libx.so", &noise, true)]);
+        let zip = ZipArchive::parse(&archive[..archive.len() * 9 / 10]).unwrap();
+        assert!(zip.recovered);
+        let read = zip.read(&zip.entries[0], 1 << 20).unwrap();
+        assert!(!read.complete && !read.data.is_empty() && noise.starts_with(&read.data));
+    }
+}